@@ -9,6 +9,8 @@ use spirv_std::num_traits::Float as _;
 use core::{ops::{Add, Div, Mul, Neg, Sub}};
 use super::{Arithmetics, interval::{interval, Interval}};
 
+/// Which operands of a min/max can be the result over a range.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Choice {
     Left,
     Right,
@@ -31,6 +33,19 @@ impl Affine3 {
         }
     }
 
+    /// Creates forms covering the axis-aligned box between `low` and `high`.
+    ///
+    /// Only one noise symbol is shared between forms, so only x gets it.
+    /// Giving it to every axis would tie them together and cover a diagonal
+    /// of the box instead of all of it.
+    pub fn from_box(low: Vec3, high: Vec3) -> Self {
+        Self {
+            x: Affine::from_interval(interval(low.x, high.x)),
+            y: Affine::from_error(interval(low.y, high.y)),
+            z: Affine::from_error(interval(low.z, high.z)),
+        }
+    }
+
     generate_component_wise!(Affine);
 }
 
@@ -77,6 +92,19 @@ impl Affine {
         }
     }
 
+    /// Creates a form covering `interval` that's independent of every other form,
+    /// e.g. for the results of operations that aren't affine.
+    ///
+    /// Using the shared noise symbol instead would make the result appear
+    /// correlated with the inputs, and cancel out with them in later operations.
+    pub const fn from_error(interval: Interval) -> Self {
+        Affine {
+            x0: (interval.high + interval.low) / 2.0,
+            x1: 0.0,
+            x2: (interval.high - interval.low) / 2.0,
+        }
+    }
+
     pub fn into_interval(self) -> Interval {
         let rad = self.rad();
         interval(self.x0 - rad, self.x0 + rad)
@@ -91,7 +119,7 @@ impl Affine {
         } else if y.low > x.high {
             (rhs, Choice::Right)
         } else {
-            (interval(x.low.max(y.low), x.high.max(y.high)).into(), Choice::Both)
+            (Affine::from_error(interval(x.low.max(y.low), x.high.max(y.high))), Choice::Both)
         }
     }
 
@@ -104,7 +132,7 @@ impl Affine {
         } else if y.high < x.low {
             (rhs, Choice::Right)
         } else {
-            (interval(x.low.min(y.low), x.high.min(y.high)).into(), Choice::Both)
+            (Affine::from_error(interval(x.low.min(y.low), x.high.min(y.high))), Choice::Both)
         }
     }
 
@@ -114,7 +142,7 @@ impl Affine {
         } else if self.lower() > 0.0{
             self
         } else {
-            self.into_interval().abs().into()
+            Affine::from_error(self.into_interval().abs())
         }
     }
 
@@ -131,15 +159,32 @@ impl Affine {
         //     x1: self.x1 * alpha,
         //     x2: self.x2 * delta,
         // }
-        self.into_interval().sqrt().into()
+        Affine::from_error(self.into_interval().sqrt())
     }
 
+    /// Follows the tangent at the centre, which is off by at most rad²/2
+    /// since the second derivative is at most 1. Wide ranges fall back to
+    /// the interval bounds, which are tighter once the tangent is that far off.
     pub fn sin(self) -> Self {
-        self.into_interval().sin().into()
+        self.tangent(self.x0.sin(), self.x0.cos(), self.into_interval().sin())
     }
 
     pub fn cos(self) -> Self {
-        self.into_interval().cos().into()
+        self.tangent(self.x0.cos(), -self.x0.sin(), self.into_interval().cos())
+    }
+
+    fn tangent(self, value: f32, slope: f32, bounds: Interval) -> Self {
+        let rad = self.rad();
+        let tangent = Self {
+            x0: value,
+            x1: self.x1 * slope,
+            x2: self.x2 * slope.abs() + rad * rad * 0.5,
+        };
+        if tangent.rad() < (bounds.high - bounds.low) * 0.5 {
+            tangent
+        } else {
+            Affine::from_error(bounds)
+        }
     }
 }
 
@@ -154,7 +199,7 @@ impl Arithmetics for Affine {
         } else if y.low > x.high {
             rhs
         } else {
-            interval(x.low.max(y.low), x.high.max(y.high)).into()
+            Affine::from_error(interval(x.low.max(y.low), x.high.max(y.high)))
         }
     }
 
@@ -167,7 +212,7 @@ impl Arithmetics for Affine {
         } else if y.high < x.low {
             rhs
         } else {
-            interval(x.low.min(y.low), x.high.min(y.high)).into()
+            Affine::from_error(interval(x.low.min(y.low), x.high.min(y.high)))
         }
     }
 
@@ -188,9 +233,9 @@ impl Arithmetics<f32> for Affine {
         if x.high < rhs {
             self
         } else if x.low > rhs {
-            interval(rhs, rhs).into()
+            Affine::from_error(interval(rhs, rhs))
         } else {
-            interval(x.low.min(rhs), x.high.min(rhs)).into()
+            Affine::from_error(interval(x.low.min(rhs), x.high.min(rhs)))
         }
     }
 
@@ -200,9 +245,9 @@ impl Arithmetics<f32> for Affine {
         if x.low > rhs {
             self
         } else if x.high < rhs {
-            interval(rhs, rhs).into()
+            Affine::from_error(interval(rhs, rhs))
         } else {
-            interval(x.low.max(rhs), x.high.max(rhs)).into()
+            Affine::from_error(interval(x.low.max(rhs), x.high.max(rhs)))
         }
     }
 
@@ -314,7 +359,16 @@ impl Div for Affine {
 
     fn div(self, rhs: Self) -> Self::Output {
         let interval_rhs: Interval = rhs.into();
-        let inverted_rhs: Affine = (1.0 / interval_rhs).into();
+        // The quotient is unbounded if the divisor can be zero, and the centre of
+        // an unbounded interval isn't a number.
+        if interval_rhs.low <= 0.0 && interval_rhs.high >= 0.0 {
+            return Affine {
+                x0: 0.0,
+                x1: 0.0,
+                x2: f32::INFINITY,
+            };
+        }
+        let inverted_rhs = Affine::from_error(interval_rhs.recip());
         self * inverted_rhs
     }
 }
@@ -351,4 +405,16 @@ mod tests {
         let f2 = |x: Affine| x * 10.0 - x * x;
         assert_eq!(f2(interval(4.0, 6.0).into()).into_interval(), interval(24.0, 26.0));
     }
+
+    #[test]
+    fn division() {
+        let x: Affine = interval(1.0, 2.0).into();
+        let quotient = (x / Affine::from_interval(interval(2.0, 4.0))).into_interval();
+        assert!(quotient.low <= 0.25 && quotient.high >= 1.0);
+        let quotient = (x / Affine::from_interval(interval(-1.0, 1.0))).into_interval();
+        assert_eq!(quotient, interval(f32::NEG_INFINITY, f32::INFINITY));
+        // Unbounded values still have bounded sines.
+        let sin = (x / Affine::from_interval(interval(0.0, 1.0))).sin().into_interval();
+        assert_eq!(sin, interval(-1.0, 1.0));
+    }
 }
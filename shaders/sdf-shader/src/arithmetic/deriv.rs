@@ -189,6 +189,10 @@ impl Deriv {
         Deriv { v, d }
     }
 
+    pub fn value(self) -> f32 {
+        self.v
+    }
+
     pub fn derivatives(self) -> Vec3 {
         self.d
     }
@@ -201,9 +205,15 @@ impl Deriv {
         }
     }
 
+    /// The derivative at zero is taken to be zero rather than infinite,
+    /// e.g. for the length of a vector that is clamped to zero.
     pub fn sqrt(self) -> Self {
         let a = self.v.sqrt();
-        Self::new_with_deriv(a, self.d / (a * 2.0))
+        if a > 0.0 {
+            Self::new_with_deriv(a, self.d / (a * 2.0))
+        } else {
+            Self::new(a)
+        }
     }
 
     pub fn sin(self) -> Self {
@@ -236,7 +246,7 @@ impl Arithmetics for Deriv {
     }
 
     fn clamp(self, low: Self, high: Self) -> Self {
-        self.min(low).max(high)
+        self.max(low).min(high)
     }
 
     fn lerp(self, rhs: Self::Scalar, mix: Self) -> Self {
@@ -263,7 +273,7 @@ impl Arithmetics<f32> for Deriv {
     }
 
     fn clamp(self, low: f32, high: f32) -> Self {
-        self.min(low).max(high)
+        self.max(low).min(high)
     }
 
     fn lerp(self, rhs: Self::Scalar, mix: f32) -> Self {
@@ -1,6 +1,9 @@
 //! This is an implementation of interval arithmetic.
 
-use core::ops::{Add, Div, Mul, Neg, Sub};
+use core::{
+    f32::consts::{FRAC_PI_2, TAU},
+    ops::{Add, Div, Mul, Neg, Sub},
+};
 
 use spirv_std::num_traits::Float as _;
 
@@ -33,14 +36,32 @@ impl Interval {
         }
     }
 
+    /// `1 / x`, which is unbounded if `x` contains zero.
+    pub fn recip(self) -> Self {
+        if self.low > 0.0 || self.high < 0.0 {
+            interval(1.0 / self.high, 1.0 / self.low)
+        } else {
+            interval(f32::NEG_INFINITY, f32::INFINITY)
+        }
+    }
+
+    /// The extremes are at the ends, unless a peak or a trough is in between.
     pub fn sin(self) -> Self {
-        // TODO: Refine this later.
-        interval(-1.0, 1.0)
+        if self.high - self.low >= TAU {
+            return interval(-1.0, 1.0);
+        }
+
+        // Whether x + 2πk is in the interval for some k.
+        let contains = |x: f32| ((self.low - x) / TAU).ceil() <= ((self.high - x) / TAU).floor();
+        let (a, b) = (self.low.sin(), self.high.sin());
+        interval(
+            if contains(-FRAC_PI_2) { -1.0 } else { a.min(b) },
+            if contains(FRAC_PI_2) { 1.0 } else { a.max(b) },
+        )
     }
 
     pub fn cos(self) -> Self {
-        // TODO: Refine this later.
-        interval(-1.0, 1.0)
+        (self + FRAC_PI_2).sin()
     }
 }
 
@@ -142,15 +163,11 @@ impl Div for Interval {
     type Output = Self;
 
     fn div(self, rhs: Self) -> Self::Output {
-        let x1_y1 = self.low / rhs.low;
-        let x1_y2 = self.low / rhs.high;
-        let x2_y1 = self.high / rhs.low;
-        let x2_y2 = self.high / rhs.high;
-
-        Self {
-            low: x1_y1.min(x1_y2).min(x2_y1).min(x2_y2),
-            high: x1_y1.max(x1_y2).max(x2_y1).max(x2_y2),
+        // The unbounded reciprocal would give NaN when multiplied by zero.
+        if rhs.low <= 0.0 && rhs.high >= 0.0 {
+            return interval(f32::NEG_INFINITY, f32::INFINITY);
         }
+        self * rhs.recip()
     }
 }
 
@@ -188,4 +205,30 @@ mod tests {
         let f2 = |x| x * 10.0 - x * x;
         assert_eq!(f2(interval(4.0, 6.0)), interval(4.0, 44.0));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn trigonometric_bounds() {
+        assert_eq!(interval(0.0, 1.0).sin(), interval(0.0, 1.0f32.sin()));
+        assert_eq!(
+            interval(1.0, 2.0).sin(),
+            interval(1.0f32.sin().min(2.0f32.sin()), 1.0)
+        );
+        assert_eq!(interval(-8.0, -7.0).sin(), interval(-1.0, (-7.0f32).sin()));
+        assert_eq!(interval(3.0, 10.0).sin(), interval(-1.0, 1.0));
+        assert_eq!(interval(-0.5, 0.5).cos().high, 1.0);
+        assert_eq!(interval(3.0, 3.5).cos().low, -1.0);
+    }
+
+    #[test]
+    fn division() {
+        assert_eq!(interval(1.0, 2.0) / interval(2.0, 4.0), interval(0.25, 1.0));
+        assert_eq!(
+            interval(1.0, 2.0) / interval(-4.0, -2.0),
+            interval(-1.0, -0.25)
+        );
+        let unbounded = interval(f32::NEG_INFINITY, f32::INFINITY);
+        assert_eq!(interval(1.0, 2.0) / interval(-1.0, 1.0), unbounded);
+        assert_eq!(interval(0.0, 0.0) / interval(0.0, 1.0), unbounded);
+        assert_eq!(1.0 / interval(-0.5, 0.0), unbounded);
+    }
+}
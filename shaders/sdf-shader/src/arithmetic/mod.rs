@@ -8,7 +8,7 @@ pub use self::deriv::*;
 macro_rules! generate_component_wise {
     ($scalar:ty) => {
         pub fn dot(self, other: Self) -> $scalar {
-            self.x * other.x + self.y * other.y + self.z + other.z
+            self.x * other.x + self.y * other.y + self.z * other.z
         }
     
         pub fn abs(self) -> Self {
@@ -1,75 +1,120 @@
 use core::sync::atomic::AtomicUsize;
 
-use glam::{Mat4, UVec2, UVec3, Vec3, Vec3Swizzles, Vec4Swizzles, uvec2, vec3};
+use glam::{Mat4, UVec2, UVec3, Vec2, Vec3, Vec3Swizzles, Vec4Swizzles, uvec2, vec2, vec3};
 
 use shared::inst::Inst;
 #[cfg(not(target_arch = "spirv"))]
 use spirv_std::macros::spirv;
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float as _;
 
-use crate::{arithmetic::{Affine3, interval}, interpreter};
-
-// #[repr(C)]
-// pub struct ConeTracingParams {
-//     view_mat: Mat4,
-//     eye: Vec3,
-//     resolution: UVec2,
-//     grid_size: UVec2,
-//     neg_z_depth: f32,
-//     cone_multiplier: f32,
-// }
-
-// /// Runs on 64 pixel x 64 pixel tiles and uses the initial tape.
-// /// 
-// #[spirv(compute(threads(8, 8, 1)))]
-// pub fn cone_push_64x64(
-//     #[spirv(global_invocation_id)] global_invocation_id: UVec3,
-//     #[spirv(num_workgroups)] num_workgroups: UVec3,
-//     #[spirv(push_constant)] params: &ConeTracingParams,
-//     #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] initial_tape: &[Inst],
-//     #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] starting_depths: &mut [f32],
-// ) {
-//     let tile_coords = global_invocation_id.xy();
-
-//     if tile_coords.x >= params.grid_size.x || tile_coords.y >= params.grid_size.y {
-//         // If we're off the edge, just return.
-//         // This will happen when the resolution width or height aren't multiples
-//         // of 64 * 8.
-//         return;
-//     }
-
-//     let tile_center = tile_coords * 64 + uvec2(32, 32);
-
-//     let ray_dir = compute_ray_direction(
-//         params.resolution,
-//         params.neg_z_depth,
-//         params.view_mat,
-//         tile_center,
-//     );
-
-//     let idx = tile_coords.y as usize * params.grid_size.x as usize + tile_coords.x as usize;
-//     starting_depths[idx] = cone_march(params.eye, ray_dir, params.cone_multiplier, sdf);
-// }
-
-// fn cone_march(origin: Vec3, ray_dir: Vec3, cone_multiplier: f32, sdf: impl Fn(Vec3) -> f32) -> f32 {
-//     const MAX_STEPS: usize = 64;
-//     const EPSILON: f32 = 0.001;
-
-//     let mut t = 0.0;
-
-//     for _ in 0..MAX_STEPS {
-//         let p = origin + ray_dir * t;
-//         let d = sdf(p);
-
-//         let x = (t + d) * cone_multiplier;
-//         if x - t <= EPSILON {
-//             return t;
-//         }
-
-//         t = x;
-//     }
-
-//     0.0
-// }
+use crate::{arithmetic::{Affine, Affine3, interval}, interpreter};
+
+#[repr(C)]
+pub struct ConeTracingParams {
+    view_mat: Mat4,
+    eye: Vec3,
+    resolution: UVec2,
+    grid_size: UVec2,
+    neg_z_depth: f32,
+    max_distance: f32,
+    /// When this is zero, every tile starts at the eye instead.
+    enabled: u32,
+}
+
+#[cfg(target_arch = "spirv")]
+static_assertions::assert_eq_size!(ConeTracingParams, [u8; 112]);
+
+/// Runs on 64 pixel x 64 pixel tiles and uses the initial tape.
+///
+/// Marches a cone enclosing every ray of the tile, which gives a depth
+/// that all of them can start sphere marching from.
+#[spirv(compute(threads(8, 8, 1)))]
+pub fn cone_push_64x64(
+    #[spirv(global_invocation_id)] global_invocation_id: UVec3,
+    #[spirv(push_constant)] params: &ConeTracingParams,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] initial_tape: &[Inst],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] matrices: &[Mat4],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] starting_depths: &mut [f32],
+) {
+    let tile_coords = global_invocation_id.xy();
+
+    if tile_coords.x >= params.grid_size.x || tile_coords.y >= params.grid_size.y {
+        // If we're off the edge, just return.
+        // This will happen when the resolution width or height aren't multiples
+        // of 64 * 8.
+        return;
+    }
+
+    let idx = tile_coords.y as usize * params.grid_size.x as usize + tile_coords.x as usize;
+
+    if params.enabled == 0 {
+        starting_depths[idx] = 0.0;
+        return;
+    }
+
+    let tile_center = tile_coords * 64 + uvec2(32, 32);
+
+    let ray_dir = compute_ray_direction(
+        params.resolution,
+        params.neg_z_depth,
+        params.view_mat,
+        tile_center,
+    );
+
+    // The rays furthest from the central one go through the corners of the tile.
+    let half_resolution = params.resolution.as_f32() / 2.0;
+    let camera_dir = |coords: Vec2| {
+        (coords - half_resolution)
+            .extend(params.neg_z_depth)
+            .normalize()
+    };
+    let center = tile_center.as_f32();
+    let axis = camera_dir(center);
+    let cos_angle = axis
+        .dot(camera_dir(center + vec2(-32.0, -32.0)))
+        .min(axis.dot(camera_dir(center + vec2(32.0, -32.0))))
+        .min(axis.dot(camera_dir(center + vec2(-32.0, 32.0))))
+        .min(axis.dot(camera_dir(center + vec2(32.0, 32.0))));
+    let spread = (1.0 - cos_angle * cos_angle).sqrt() / cos_angle;
+
+    starting_depths[idx] = cone_march(params.eye, ray_dir, spread, params.max_distance, |p| {
+        interpreter::sdf(initial_tape, 0, matrices, p)
+    });
+}
+
+/// Returns a distance from `origin` that every ray within the cone around
+/// `ray_dir` can start marching from. `spread` is the tangent of the cone's half angle.
+///
+/// A point at distance `x` along any ray is at most `x * spread` from the
+/// point at distance `x` along the axis, so the empty sphere around the point
+/// at `t` covers every ray up to where `x - t + x * spread` reaches its radius.
+fn cone_march(
+    origin: Vec3,
+    ray_dir: Vec3,
+    spread: f32,
+    max_distance: f32,
+    sdf: impl Fn(Vec3) -> f32,
+) -> f32 {
+    const MAX_STEPS: usize = 64;
+
+    let cone_multiplier = 1.0 / (1.0 + spread);
+    let mut t = 0.0;
+
+    for _ in 0..MAX_STEPS {
+        let p = origin + ray_dir * t;
+        let d = sdf(p);
+
+        let x = (t + d) * cone_multiplier;
+        if x - t <= EPSILON * t || x > max_distance {
+            return t;
+        }
+
+        t = x;
+    }
+
+    t
+}
 
 #[repr(C)]
 pub struct RenderParams {
@@ -79,65 +124,245 @@ pub struct RenderParams {
     resolution: UVec2,
     grid_size: UVec2,
     neg_z_depth: f32,
-    // /// The size of the initial instruction tape.
-    // initial_tape_len: usize,
-    // /// The starting index of the space available for
-    // /// the 8x8 tile tape optimizer.
-    // tile8x8_tape_start: usize,
+    /// The size of the initial instruction tape, which is also
+    /// the size of the space for every tile's tape.
+    initial_tape_len: u32,
+    /// The starting index of the space available for
+    /// the 8x8 tile tape optimizer.
+    tile8x8_tape_start: u32,
+    /// Rays are only marched up to this distance from the eye.
+    max_distance: f32,
 }
 
 #[cfg(target_arch = "spirv")]
 static_assertions::assert_eq_size!(RenderParams, [u8; 128]);
 
-// /// The initial tape is stored at 0..params.initial_tape_len.
-// /// This runs on a 64x64 pixel tile.
-// ///
-// /// Because parts of the model could be at any depth within the tile, this evaluates
-// /// the initial tape on the entire beam volume with the x and y intervals
-// /// corresponding to the tiles bounds in world coordinates and the z interval stretching
-// /// from the near plane to the far plane.
-// #[spirv(compute(threads(8, 8, 1)))]
-// pub fn evaluate_ray_volume_64x64_tiles(
-//     #[spirv(global_invocation_id)] global_invocation_id: UVec3,
-//     #[spirv(push_constant)] params: &RenderParams,
-//     #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] global_tapes: &mut [Inst],
-//     // #[spirv(storage_buffer, descriptor_set = 0, binding = 1)]
-// ) {
-//     let tile_coords = global_invocation_id.xy();
-    
-//     if tile_coords.x >= params.grid_size.x || tile_coords.y >= params.grid_size.y {
-//         return;
-//     }
-
-//     let tile_center = tile_coords * 64 + uvec2(32, 32);
-
-//     let ro = params.eye;
-//     let rd = compute_ray_direction(
-//         params.resolution,
-//         params.neg_z_depth,
-//         params.view_mat,
-//         tile_center,
-//     );
-//     // Is this the near and far clip distances?
-//     let t = interval(0.0, 1.0);
-
-//     // This *should* be a skewed bounding box.
-//     let ray_bounds = Affine3 {
-//         x: (t * rd.x + ro.x).into(),
-//         y: (t * rd.y + ro.y).into(),
-//         z: (t * rd.z + ro.z).into(),
-//     };
-
-
-// }
+const EPSILON: f32 = 0.001;
+
+/// The number of depth slices a tile's ray volume is evaluated in.
+const DEPTH_SLICES: u32 = 64;
+/// How much longer each depth slice is than the one in front of it.
+/// Ray volumes widen with depth, and bounds far away are looser anyway,
+/// so the slices are shortest close to the eye.
+const DEPTH_SLICE_GROWTH: f32 = 1.1;
+
+// The status of a tile after evaluating its ray volume.
+/// No surface passes through the ray volume.
+const TILE_EMPTY: u32 = 0;
+/// Every ray starts inside the model.
+const TILE_FILLED: u32 = 1;
+/// The surface may pass through the ray volume.
+const TILE_AMBIGUOUS: u32 = 2;
+
+// The global tape buffer holds the initial tape at `0..initial_tape_len`,
+// followed by space for a tape for every 64x64 tile and then for every 8x8
+// tile, starting at `tile8x8_tape_start`. Pruning never makes a tape longer,
+// so every space is `initial_tape_len` instructions long.
+//
+// The tile status buffer holds a status and the first depth slice that isn't
+// empty for each of the 64x64 tiles, followed by the same for the 8x8 tiles.
+// The choices buffer has space for one tape's worth of choices for every tile
+// of the pass being run.
+
+/// The initial tape is stored at 0..params.initial_tape_len.
+/// This runs on a 64x64 pixel tile.
+///
+/// Because parts of the model could be at any depth within the tile, this evaluates
+/// the initial tape on the entire beam volume, from the eye to `max_distance`.
+#[spirv(compute(threads(8, 8, 1)))]
+pub fn evaluate_ray_volume_64x64_tiles(
+    #[spirv(global_invocation_id)] global_invocation_id: UVec3,
+    #[spirv(push_constant)] params: &RenderParams,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] global_tapes: &mut [Inst],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] matrices: &[Mat4],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] tile_status: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] choices: &mut [u32],
+) {
+    let tile_coords = global_invocation_id.xy();
+
+    if tile_coords.x >= params.grid_size.x || tile_coords.y >= params.grid_size.y {
+        return;
+    }
+
+    let idx = tile_coords.y as usize * params.grid_size.x as usize + tile_coords.x as usize;
+    let len = params.initial_tape_len as usize;
+    let low = tile_coords * 64;
+
+    let (status, first_slice) = evaluate_tile(
+        params,
+        low,
+        low + uvec2(63, 63),
+        0,
+        global_tapes,
+        0,
+        len * (1 + idx),
+        matrices,
+        choices,
+        len * idx,
+    );
+    tile_status[idx * 2] = status;
+    tile_status[idx * 2 + 1] = first_slice;
+}
+
+/// Runs on 8x8 pixel tiles and prunes the tape of the 64x64 tile they're in.
+#[spirv(compute(threads(8, 8, 1)))]
+pub fn evaluate_ray_volume_8x8_tiles(
+    #[spirv(global_invocation_id)] global_invocation_id: UVec3,
+    #[spirv(push_constant)] params: &RenderParams,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] global_tapes: &mut [Inst],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] matrices: &[Mat4],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] tile_status: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] choices: &mut [u32],
+) {
+    let tile_coords = global_invocation_id.xy();
+    let grid_size = (params.resolution + uvec2(7, 7)) / 8;
+
+    if tile_coords.x >= grid_size.x || tile_coords.y >= grid_size.y {
+        return;
+    }
+
+    let parent_coords = tile_coords / 8;
+    let parent_idx =
+        parent_coords.y as usize * params.grid_size.x as usize + parent_coords.x as usize;
+    let idx = tile_coords.y as usize * grid_size.x as usize + tile_coords.x as usize;
+    let status_idx = params.grid_size.x as usize * params.grid_size.y as usize + idx;
+
+    let (status, first_slice) = if tile_status[parent_idx * 2] == TILE_EMPTY {
+        (TILE_EMPTY, DEPTH_SLICES)
+    } else {
+        let len = params.initial_tape_len as usize;
+        let low = tile_coords * 8;
+
+        // The slices in front of the parent's first one are empty for it,
+        // so they're also empty for this tile.
+        evaluate_tile(
+            params,
+            low,
+            low + uvec2(7, 7),
+            tile_status[parent_idx * 2 + 1],
+            global_tapes,
+            len * (1 + parent_idx),
+            params.tile8x8_tape_start as usize + len * idx,
+            matrices,
+            choices,
+            len * idx,
+        )
+    };
+    tile_status[status_idx * 2] = status;
+    tile_status[status_idx * 2 + 1] = first_slice;
+}
+
+/// Classifies the ray volume of the tile covering the pixels from `low` to
+/// `high` inclusive, and prunes the tape at `tape_start` over it into `out_start`.
+///
+/// Bounds over the whole depth range of a tile are too loose to ever classify it,
+/// so the volume is evaluated in slices, starting at `start_slice`. Rays are only
+/// marched from the first slice that isn't empty, and never past the first slice
+/// that's entirely inside the model, so the choices are merged between those.
+///
+/// Returns the status of the tile and the first slice that isn't empty.
+fn evaluate_tile(
+    params: &RenderParams,
+    low: UVec2,
+    high: UVec2,
+    start_slice: u32,
+    global_tapes: &mut [Inst],
+    tape_start: usize,
+    out_start: usize,
+    matrices: &[Mat4],
+    choices: &mut [u32],
+    choices_start: usize,
+) -> (u32, u32) {
+    let mut first_slice = DEPTH_SLICES;
+    let mut filled = false;
+
+    let mut slice = start_slice;
+    while slice < DEPTH_SLICES && !filled {
+        let region = ray_volume(params, low, high, slice);
+        let bounds = interpreter::record_choices(
+            global_tapes,
+            tape_start,
+            matrices,
+            region,
+            choices,
+            choices_start,
+            first_slice < slice,
+        )
+        .into_interval();
+
+        // `sphere_march` counts distances below `EPSILON * t` as hits.
+        if first_slice == DEPTH_SLICES && bounds.low <= EPSILON * params.max_distance {
+            first_slice = slice;
+        }
+        filled = bounds.high < 0.0;
+        slice += 1;
+    }
+
+    if first_slice == DEPTH_SLICES {
+        return (TILE_EMPTY, first_slice);
+    }
+
+    interpreter::prune(global_tapes, tape_start, out_start, choices, choices_start);
+
+    if filled && first_slice == 0 && slice == 1 {
+        (TILE_FILLED, first_slice)
+    } else {
+        (TILE_AMBIGUOUS, first_slice)
+    }
+}
+
+/// Returns the distance from the eye at which a depth slice starts, along the
+/// central ray. `DEPTH_SLICES` is the end of the last slice, at `max_distance`.
+fn slice_start(params: &RenderParams, slice: u32) -> f32 {
+    let growth = DEPTH_SLICE_GROWTH.powi(slice as i32) - 1.0;
+    let total = DEPTH_SLICE_GROWTH.powi(DEPTH_SLICES as i32) - 1.0;
+    params.max_distance * growth / total
+}
+
+/// Returns bounds of the points in a depth slice of the rays through the
+/// pixels from `low` to `high` inclusive.
+///
+/// The points are `eye + view_mat * (s * vec3(x, y, neg_z_depth))`, so this
+/// is a skewed box in camera space before being rotated to world space.
+fn ray_volume(params: &RenderParams, low: UVec2, high: UVec2, slice: u32) -> Affine3 {
+    let half_resolution = params.resolution.as_f32() / 2.0;
+    let low = low.as_f32() - half_resolution;
+    let high = high.as_f32() - half_resolution;
+
+    // The central ray has the shortest direction, so this covers
+    // at least the same distances along every other ray.
+    // The distance along the rays is the largest extent,
+    // so it gets the noise symbol shared between forms.
+    let s = Affine::from_interval(interval(
+        slice_start(params, slice),
+        slice_start(params, slice + 1),
+    )) / -params.neg_z_depth;
+    let p = Affine3 {
+        x: s * Affine::from_error(interval(low.x, high.x)),
+        y: s * Affine::from_error(interval(low.y, high.y)),
+        z: s * params.neg_z_depth,
+    };
+
+    let view_mat = params.view_mat;
+    let camera_to_world = Mat4::from_cols(
+        view_mat.x_axis.xyz().extend(0.0),
+        view_mat.y_axis.xyz().extend(0.0),
+        view_mat.z_axis.xyz().extend(0.0),
+        params.eye.extend(1.0),
+    );
+    interpreter::transform_affine3_by_mat4(&camera_to_world, p)
+}
 
 #[spirv(compute(threads(8, 8, 1)))]
 pub fn render_sdf_final(
     #[spirv(global_invocation_id)] global_invocation_id: UVec3,
     #[spirv(push_constant)] params: &RenderParams,
     #[spirv(descriptor_set = 0, binding = 0)] output_texture: &CustomStorageImage2d,
-    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] tape: &[Inst],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] global_tapes: &[Inst],
     #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] matrices: &[Mat4],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] tile_status: &[u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] starting_depths: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] steps: &mut [u32],
 ) {
     let texture_coords = global_invocation_id.xy();
 
@@ -148,6 +373,15 @@ pub fn render_sdf_final(
         return;
     }
 
+    let tile_coords = texture_coords / 8;
+    let tile_idx = tile_coords.y as usize * ((params.resolution.x as usize + 7) / 8)
+        + tile_coords.x as usize;
+    let status_idx = params.grid_size.x as usize * params.grid_size.y as usize + tile_idx;
+    let status = tile_status[status_idx * 2];
+    let first_slice = tile_status[status_idx * 2 + 1];
+    let tape_start =
+        params.tile8x8_tape_start as usize + params.initial_tape_len as usize * tile_idx;
+
     let ray_dir = compute_ray_direction(
         params.resolution,
         params.neg_z_depth,
@@ -155,14 +389,40 @@ pub fn render_sdf_final(
         texture_coords,
     );
 
-    let intersection = sphere_march(params.eye, ray_dir, |p| interpreter::sdf(tape, matrices, p));
+    let intersection = if status == TILE_EMPTY {
+        Intersection {
+            hit: Vec3::ZERO,
+            depth_ratio: -1.0,
+            steps: 0,
+        }
+    } else if status == TILE_FILLED {
+        // The eye is inside the model.
+        Intersection {
+            hit: params.eye,
+            depth_ratio: 0.0,
+            steps: 0,
+        }
+    } else {
+        // Every point in front of the first slice is at least this far away,
+        // and the cone tracing prepass may have found a farther starting point.
+        let tile64x64_coords = texture_coords / 64;
+        let tile64x64_idx = tile64x64_coords.y as usize * params.grid_size.x as usize
+            + tile64x64_coords.x as usize;
+        let start = slice_start(params, first_slice).max(starting_depths[tile64x64_idx]);
+
+        sphere_march(params.eye, ray_dir, start, params.max_distance, |p| {
+            interpreter::sdf(global_tapes, tape_start, matrices, p)
+        })
+    };
+    steps[texture_coords.y as usize * params.resolution.x as usize + texture_coords.x as usize] =
+        intersection.steps;
 
-    let color = if intersection.depth_ratio > 0.0 {
+    let color = if intersection.depth_ratio >= 0.0 {
         let color = vec3(171.0 / 255.0, 146.0 / 255.0, 103.0 / 255.0);
         let shade = vec3(99.0 / 255.0, 84.0 / 255.0, 59.0 / 255.0);
         let ao = 1.0 - intersection.depth_ratio;
 
-        let normals = interpreter::sdf_deriv(tape, matrices, intersection.hit)
+        let normals = interpreter::sdf_deriv(global_tapes, tape_start, matrices, intersection.hit)
             .derivatives()
             .normalize()
             * 0.5
@@ -196,23 +456,37 @@ struct Intersection {
     /// a percentage from 0 to 1
     /// e.g. the number of steps divided by MAX_STEPS
     depth_ratio: f32,
+    /// The number of times the distance was evaluated.
+    steps: u32,
 }
 
 /// Change this to return an option maybe, when it's supported?
-fn sphere_march(origin: Vec3, ray_dir: Vec3, sdf: impl Fn(Vec3) -> f32) -> Intersection {
+fn sphere_march(
+    origin: Vec3,
+    ray_dir: Vec3,
+    start: f32,
+    max_distance: f32,
+    sdf: impl Fn(Vec3) -> f32,
+) -> Intersection {
     const MAX_STEPS: usize = 64;
-    const EPSILON: f32 = 0.001;
 
-    let mut t = 0.0;
+    let mut t = start;
+    let mut steps = 0;
 
     for i in 0..MAX_STEPS {
+        if t > max_distance {
+            break;
+        }
+
         let p = origin + ray_dir * t;
         let r = sdf(p);
+        steps += 1;
 
         if r < EPSILON * t {
             return Intersection {
                 hit: p,
                 depth_ratio: i as f32 / (MAX_STEPS - 1) as f32,
+                steps,
             };
         }
 
@@ -222,6 +496,7 @@ fn sphere_march(origin: Vec3, ray_dir: Vec3, sdf: impl Fn(Vec3) -> f32) -> Inter
     Intersection {
         hit: Vec3::ZERO,
         depth_ratio: -1.0,
+        steps,
     }
 }
 
@@ -1,31 +1,57 @@
 use crate::{
-    arithmetic::{Deriv, Deriv3, Affine, Affine3},
+    arithmetic::{Affine, Affine3, Choice, Deriv, Deriv3},
     sdf,
 };
 use core::convert::identity;
 use glam::{vec3, Mat4, Vec3};
-use shared::inst::{Inst, Op, RectangularPrism, SmoothUnion, Sphere};
+use shared::inst::{
+    Abs, Bcc, Capsule, Cone, Constant, Coordinate, Cos, Cylinder, Diamond, Ellipsoid, Fcc, Frd,
+    GradedSheet, Gyroid, Inst, Intersection, Iwp, Kelvin, Lidinoid, Negation, Neovius, Octet, Op,
+    Plane, RectangularPrism, SchwarzP, Sin, SmoothIntersection, SmoothSubtraction, SmoothUnion,
+    Sphere, Sqrt, Subtraction, Torus, Union, Voronoi, REGISTER_COUNT,
+};
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float as _;
 
-fn transform_deriv3_by_mat4(mat: &Mat4, a: Deriv3) -> Deriv3 {
-    // Hopefully this works.
-    // First, rotate the real vector, and then rotate each of the 3D derivatives.
-    let v = mat.transform_point3(a.v());
+/// Each component of the transformed point is a linear combination of the
+/// components of `p`, so the derivatives are carried through by the same
+/// combination. This multiplies them by the Jacobian of the transform,
+/// which is the upper 3x3 of `mat`.
+fn transform_deriv3_by_mat4(mat: &Mat4, p: Deriv3) -> Deriv3 {
     Deriv3 {
-        x: Deriv::new_with_deriv(v.x, mat.transform_point3(a.x.derivatives())),
-        y: Deriv::new_with_deriv(v.y, mat.transform_point3(a.y.derivatives())),
-        z: Deriv::new_with_deriv(v.z, mat.transform_point3(a.z.derivatives())),
+        x: p.x * mat.x_axis.x + p.y * mat.y_axis.x + p.z * mat.z_axis.x + mat.w_axis.x,
+        y: p.x * mat.x_axis.y + p.y * mat.y_axis.y + p.z * mat.z_axis.y + mat.w_axis.y,
+        z: p.x * mat.x_axis.z + p.y * mat.y_axis.z + p.z * mat.z_axis.z + mat.w_axis.z,
+    }
+}
+
+/// Affine forms are closed under linear combinations, so this
+/// doesn't widen the bounds beyond what the transform requires.
+pub(crate) fn transform_affine3_by_mat4(mat: &Mat4, p: Affine3) -> Affine3 {
+    Affine3 {
+        x: p.x * mat.x_axis.x + p.y * mat.y_axis.x + p.z * mat.z_axis.x + mat.w_axis.x,
+        y: p.x * mat.x_axis.y + p.y * mat.y_axis.y + p.z * mat.z_axis.y + mat.w_axis.y,
+        z: p.x * mat.x_axis.z + p.y * mat.y_axis.z + p.z * mat.z_axis.z + mat.w_axis.z,
     }
 }
 
 macro_rules! generate_interpreter {
-    ($name:ident<$ty:ty>, $sdf_path:path, $p:expr, $reg_init:expr, $mat_transform:expr) => {
+    (
+        $name:ident<$ty:ty>,
+        $sdf_path:path,
+        $p:expr,
+        $constant:expr,
+        $reg_init:expr,
+        $mat_transform:expr
+    ) => {
+        /// Evaluates the tape starting at `tape[start]`.
         #[inline(always)]
-        pub fn $name(tape: &[Inst], matrices: &[Mat4], p: Vec3) -> $ty {
+        pub fn $name(tape: &[Inst], start: usize, matrices: &[Mat4], p: Vec3) -> $ty {
             use $sdf_path as s;
-            const REG_INIT: [$ty; 2] = $reg_init;
+            const REG_INIT: [$ty; REGISTER_COUNT] = [$reg_init; REGISTER_COUNT];
 
-            let mut i = 0;
-            let mut regs: [$ty; 2] = REG_INIT;
+            let mut i = start;
+            let mut regs: [$ty; REGISTER_COUNT] = REG_INIT;
             let p = $p(p);
 
             loop {
@@ -37,32 +63,183 @@ macro_rules! generate_interpreter {
 
                     // Combinations
                     Op::Union => {
-                        regs[inst.reg()] = s::union(regs[0], regs[1]);
+                        let u = inst.extract::<Union>();
+                        regs[inst.reg()] = s::union(regs[u.lhs], regs[u.rhs]);
                     }
                     Op::Intersection => {
-                        regs[inst.reg()] = s::intersect(regs[0], regs[1]);
+                        let int = inst.extract::<Intersection>();
+                        regs[inst.reg()] = s::intersect(regs[int.lhs], regs[int.rhs]);
                     }
                     Op::Subtraction => {
-                        regs[inst.reg()] = s::subtract(regs[0], regs[1]);
+                        let sub = inst.extract::<Subtraction>();
+                        regs[inst.reg()] = s::subtract(regs[sub.lhs], regs[sub.rhs]);
                     }
                     Op::SmoothUnion => {
                         let su = inst.extract::<SmoothUnion>();
-                        regs[inst.reg()] = s::smooth_union(regs[0], regs[1], su.k);
+                        regs[inst.reg()] = s::smooth_union(regs[su.lhs], regs[su.rhs], su.k);
+                    }
+                    Op::SmoothIntersection => {
+                        let si = inst.extract::<SmoothIntersection>();
+                        regs[inst.reg()] = s::smooth_intersect(regs[si.lhs], regs[si.rhs], si.k);
+                    }
+                    Op::SmoothSubtraction => {
+                        let ss = inst.extract::<SmoothSubtraction>();
+                        regs[inst.reg()] = s::smooth_subtract(regs[ss.lhs], regs[ss.rhs], ss.k);
                     }
-                    Op::SmoothIntersection => {}
-                    Op::SmoothSubtraction => {}
 
                     // Shapes
                     Op::Sphere => {
                         let sphere = inst.extract::<Sphere>();
-                        // let mat = &matrices[0];
+                        let p = $mat_transform(&matrices[sphere.matrix_idx], p);
                         regs[inst.reg()] = s::sphere(p, sphere.radius);
                     }
                     Op::RectangularPrism => {
                         let prism = inst.extract::<RectangularPrism>();
-                        // let mat = &matrices[0];
+                        let p = $mat_transform(&matrices[prism.matrix_idx], p);
                         regs[inst.reg()] = s::rectangular_prism(p, vec3(prism.x, prism.y, prism.z))
                     }
+                    Op::Cylinder => {
+                        let c = inst.extract::<Cylinder>();
+                        let p = $mat_transform(&matrices[c.matrix_idx], p);
+                        regs[inst.reg()] = s::cylinder(p, c.radius, c.half_height);
+                    }
+                    Op::Torus => {
+                        let t = inst.extract::<Torus>();
+                        let p = $mat_transform(&matrices[t.matrix_idx], p);
+                        regs[inst.reg()] = s::torus(p, t.major_radius, t.minor_radius);
+                    }
+                    Op::Capsule => {
+                        let c = inst.extract::<Capsule>();
+                        let p = $mat_transform(&matrices[c.matrix_idx], p);
+                        regs[inst.reg()] = s::capsule(p, c.radius, c.half_height);
+                    }
+                    Op::Cone => {
+                        let c = inst.extract::<Cone>();
+                        let p = $mat_transform(&matrices[c.matrix_idx], p);
+                        regs[inst.reg()] = s::cone(p, c.bottom_radius, c.top_radius, c.half_height);
+                    }
+                    Op::Plane => {
+                        let plane = inst.extract::<Plane>();
+                        let p = $mat_transform(&matrices[plane.matrix_idx], p);
+                        let normal = vec3(plane.normal_x, plane.normal_y, plane.normal_z);
+                        regs[inst.reg()] = s::plane(p, normal, plane.offset);
+                    }
+                    Op::Ellipsoid => {
+                        let e = inst.extract::<Ellipsoid>();
+                        let p = $mat_transform(&matrices[e.matrix_idx], p);
+                        regs[inst.reg()] = s::ellipsoid(p, vec3(e.x, e.y, e.z));
+                    }
+
+                    // Fills
+                    Op::Gyroid => {
+                        let g = inst.extract::<Gyroid>();
+                        let p = $mat_transform(&matrices[g.matrix_idx], p);
+                        regs[inst.reg()] = s::gyroid(p, $constant(g.scale), g.thickness);
+                    }
+                    Op::SchwarzP => {
+                        let sp = inst.extract::<SchwarzP>();
+                        let p = $mat_transform(&matrices[sp.matrix_idx], p);
+                        regs[inst.reg()] = s::schwarz_p(p, $constant(sp.scale), sp.thickness);
+                    }
+                    Op::Diamond => {
+                        let d = inst.extract::<Diamond>();
+                        let p = $mat_transform(&matrices[d.matrix_idx], p);
+                        regs[inst.reg()] = s::diamond(p, $constant(d.scale), d.thickness);
+                    }
+                    Op::Neovius => {
+                        let n = inst.extract::<Neovius>();
+                        let p = $mat_transform(&matrices[n.matrix_idx], p);
+                        regs[inst.reg()] = s::neovius(p, $constant(n.scale), n.thickness);
+                    }
+                    Op::Iwp => {
+                        let iw = inst.extract::<Iwp>();
+                        let p = $mat_transform(&matrices[iw.matrix_idx], p);
+                        regs[inst.reg()] = s::iwp(p, $constant(iw.scale), iw.thickness);
+                    }
+                    Op::Lidinoid => {
+                        let l = inst.extract::<Lidinoid>();
+                        let p = $mat_transform(&matrices[l.matrix_idx], p);
+                        regs[inst.reg()] = s::lidinoid(p, $constant(l.scale), l.thickness);
+                    }
+                    Op::Frd => {
+                        let fr = inst.extract::<Frd>();
+                        let p = $mat_transform(&matrices[fr.matrix_idx], p);
+                        regs[inst.reg()] = s::frd(p, $constant(fr.scale), fr.thickness);
+                    }
+
+                    // Strut fills
+                    Op::Bcc => {
+                        let b = inst.extract::<Bcc>();
+                        let p = $mat_transform(&matrices[b.matrix_idx], p);
+                        regs[inst.reg()] = s::bcc(p, b.cell_size, b.strut_radius);
+                    }
+                    Op::Fcc => {
+                        let f = inst.extract::<Fcc>();
+                        let p = $mat_transform(&matrices[f.matrix_idx], p);
+                        regs[inst.reg()] = s::fcc(p, f.cell_size, f.strut_radius);
+                    }
+                    Op::Octet => {
+                        let o = inst.extract::<Octet>();
+                        let p = $mat_transform(&matrices[o.matrix_idx], p);
+                        regs[inst.reg()] = s::octet(p, o.cell_size, o.strut_radius);
+                    }
+                    Op::Kelvin => {
+                        let k = inst.extract::<Kelvin>();
+                        let p = $mat_transform(&matrices[k.matrix_idx], p);
+                        regs[inst.reg()] = s::kelvin(p, k.cell_size, k.strut_radius);
+                    }
+                    Op::Voronoi => {
+                        let v = inst.extract::<Voronoi>();
+                        let p = $mat_transform(&matrices[v.matrix_idx], p);
+                        regs[inst.reg()] = s::voronoi(p, v.cell_size, v.thickness, v.seed);
+                    }
+
+                    // Values that vary over space
+                    Op::Constant => {
+                        regs[inst.reg()] = $constant(inst.extract::<Constant>().value);
+                    }
+                    Op::Coordinate => {
+                        let c = inst.extract::<Coordinate>();
+                        let p = $mat_transform(&matrices[c.matrix_idx], p);
+                        regs[inst.reg()] = s::coordinate(p, c.axis, c.half_extent);
+                    }
+                    Op::Sum => {
+                        let (lhs, rhs) = inst.operands();
+                        regs[inst.reg()] = regs[lhs] + regs[rhs];
+                    }
+                    Op::Difference => {
+                        let (lhs, rhs) = inst.operands();
+                        regs[inst.reg()] = regs[lhs] - regs[rhs];
+                    }
+                    Op::Product => {
+                        let (lhs, rhs) = inst.operands();
+                        regs[inst.reg()] = regs[lhs] * regs[rhs];
+                    }
+                    Op::Quotient => {
+                        let (lhs, rhs) = inst.operands();
+                        regs[inst.reg()] = regs[lhs] / regs[rhs];
+                    }
+                    Op::Negation => {
+                        regs[inst.reg()] = -regs[inst.extract::<Negation>().operand];
+                    }
+                    Op::Abs => {
+                        regs[inst.reg()] = regs[inst.extract::<Abs>().operand].abs();
+                    }
+                    Op::Sqrt => {
+                        regs[inst.reg()] = regs[inst.extract::<Sqrt>().operand].sqrt();
+                    }
+                    Op::Sin => {
+                        regs[inst.reg()] = regs[inst.extract::<Sin>().operand].sin();
+                    }
+                    Op::Cos => {
+                        regs[inst.reg()] = regs[inst.extract::<Cos>().operand].cos();
+                    }
+                    Op::GradedSheet => {
+                        let g = inst.extract::<GradedSheet>();
+                        let p = $mat_transform(&matrices[g.matrix_idx], p);
+                        regs[inst.reg()] =
+                            s::graded_sheet(g.sheet, p, regs[g.scale], g.half_extents.into());
+                    }
                 }
 
                 i += 1;
@@ -71,21 +248,37 @@ macro_rules! generate_interpreter {
     };
 }
 
-generate_interpreter!(sdf<f32>, sdf, identity, [0.0, 0.0], Mat4::transform_point3);
+generate_interpreter!(
+    sdf<f32>,
+    sdf,
+    identity,
+    identity,
+    0.0,
+    Mat4::transform_point3
+);
 generate_interpreter!(
     sdf_deriv<Deriv>,
     sdf::deriv,
     Deriv3::new_xyz,
-    [Deriv::ZERO, Deriv::ZERO],
+    Deriv::new,
+    Deriv::ZERO,
     transform_deriv3_by_mat4
 );
 
-
+/// Evaluates the tape starting at `tape[start]` over a region of space,
+/// reporting which operands of every combination can affect the result
+/// to `record`.
 #[inline(always)]
-pub fn sdf_affine(tape: &[Inst], matrices: &[Mat4], p: Affine3) -> Affine {
-    const REG_INIT: [Affine; 2] = [Affine::ZERO, Affine::ZERO];
+fn sdf_affine_choices(
+    tape: &[Inst],
+    start: usize,
+    matrices: &[Mat4],
+    p: Affine3,
+    mut record: impl FnMut(usize, Choice),
+) -> Affine {
+    const REG_INIT: [Affine; REGISTER_COUNT] = [Affine::ZERO; REGISTER_COUNT];
 
-    let mut i = 0;
+    let mut i = start;
     let mut regs = REG_INIT;
 
     loop {
@@ -97,37 +290,1103 @@ pub fn sdf_affine(tape: &[Inst], matrices: &[Mat4], p: Affine3) -> Affine {
 
             // Combinations
             Op::Union => {
-                let (d, choice) = sdf::affine::union(regs[0], regs[1]);
+                let u = inst.extract::<Union>();
+                let (d, choice) = sdf::affine::union(regs[u.lhs], regs[u.rhs]);
                 regs[inst.reg()] = d;
+                record(i, choice);
             }
             Op::Intersection => {
-                let (d, choice) = sdf::affine::intersect(regs[0], regs[1]);;
+                let int = inst.extract::<Intersection>();
+                let (d, choice) = sdf::affine::intersect(regs[int.lhs], regs[int.rhs]);
                 regs[inst.reg()] = d;
+                record(i, choice);
             }
             Op::Subtraction => {
-                let (d, choice) = sdf::affine::subtract(regs[0], regs[1]);
+                let sub = inst.extract::<Subtraction>();
+                let (d, choice) = sdf::affine::subtract(regs[sub.lhs], regs[sub.rhs]);
                 regs[inst.reg()] = d;
+                record(i, subtraction_choice(choice));
             }
             Op::SmoothUnion => {
                 let su = inst.extract::<SmoothUnion>();
-                regs[inst.reg()] = sdf::affine::smooth_union(regs[0], regs[1], su.k);
+                let (d, choice) = sdf::affine::smooth_union(regs[su.lhs], regs[su.rhs], su.k);
+                regs[inst.reg()] = d;
+                record(i, choice);
+            }
+            Op::SmoothIntersection => {
+                let si = inst.extract::<SmoothIntersection>();
+                let (d, choice) = sdf::affine::smooth_intersect(regs[si.lhs], regs[si.rhs], si.k);
+                regs[inst.reg()] = d;
+                record(i, choice);
+            }
+            Op::SmoothSubtraction => {
+                let ss = inst.extract::<SmoothSubtraction>();
+                let (d, choice) = sdf::affine::smooth_subtract(regs[ss.lhs], regs[ss.rhs], ss.k);
+                regs[inst.reg()] = d;
+                record(i, subtraction_choice(choice));
             }
-            Op::SmoothIntersection => {}
-            Op::SmoothSubtraction => {}
 
             // Shapes
             Op::Sphere => {
                 let sphere = inst.extract::<Sphere>();
-                // let mat = &matrices[0];
+                let p = transform_affine3_by_mat4(&matrices[sphere.matrix_idx], p);
                 regs[inst.reg()] = sdf::affine::sphere(p, sphere.radius);
             }
             Op::RectangularPrism => {
                 let prism = inst.extract::<RectangularPrism>();
-                // let mat = &matrices[0];
-                regs[inst.reg()] = sdf::affine::rectangular_prism(p, vec3(prism.x, prism.y, prism.z))
+                let p = transform_affine3_by_mat4(&matrices[prism.matrix_idx], p);
+                regs[inst.reg()] =
+                    sdf::affine::rectangular_prism(p, vec3(prism.x, prism.y, prism.z))
+            }
+            Op::Cylinder => {
+                let c = inst.extract::<Cylinder>();
+                let p = transform_affine3_by_mat4(&matrices[c.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::cylinder(p, c.radius, c.half_height);
+            }
+            Op::Torus => {
+                let t = inst.extract::<Torus>();
+                let p = transform_affine3_by_mat4(&matrices[t.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::torus(p, t.major_radius, t.minor_radius);
+            }
+            Op::Capsule => {
+                let c = inst.extract::<Capsule>();
+                let p = transform_affine3_by_mat4(&matrices[c.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::capsule(p, c.radius, c.half_height);
+            }
+            Op::Cone => {
+                let c = inst.extract::<Cone>();
+                let p = transform_affine3_by_mat4(&matrices[c.matrix_idx], p);
+                regs[inst.reg()] =
+                    sdf::affine::cone(p, c.bottom_radius, c.top_radius, c.half_height);
+            }
+            Op::Plane => {
+                let plane = inst.extract::<Plane>();
+                let p = transform_affine3_by_mat4(&matrices[plane.matrix_idx], p);
+                let normal = vec3(plane.normal_x, plane.normal_y, plane.normal_z);
+                regs[inst.reg()] = sdf::affine::plane(p, normal, plane.offset);
+            }
+            Op::Ellipsoid => {
+                let e = inst.extract::<Ellipsoid>();
+                let p = transform_affine3_by_mat4(&matrices[e.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::ellipsoid(p, vec3(e.x, e.y, e.z));
+            }
+
+            // Fills
+            Op::Gyroid => {
+                let g = inst.extract::<Gyroid>();
+                let p = transform_affine3_by_mat4(&matrices[g.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::gyroid(p, Affine::new(g.scale), g.thickness);
+            }
+            Op::SchwarzP => {
+                let sp = inst.extract::<SchwarzP>();
+                let p = transform_affine3_by_mat4(&matrices[sp.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::schwarz_p(p, Affine::new(sp.scale), sp.thickness);
+            }
+            Op::Diamond => {
+                let d = inst.extract::<Diamond>();
+                let p = transform_affine3_by_mat4(&matrices[d.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::diamond(p, Affine::new(d.scale), d.thickness);
+            }
+            Op::Neovius => {
+                let n = inst.extract::<Neovius>();
+                let p = transform_affine3_by_mat4(&matrices[n.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::neovius(p, Affine::new(n.scale), n.thickness);
+            }
+            Op::Iwp => {
+                let iw = inst.extract::<Iwp>();
+                let p = transform_affine3_by_mat4(&matrices[iw.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::iwp(p, Affine::new(iw.scale), iw.thickness);
+            }
+            Op::Lidinoid => {
+                let l = inst.extract::<Lidinoid>();
+                let p = transform_affine3_by_mat4(&matrices[l.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::lidinoid(p, Affine::new(l.scale), l.thickness);
+            }
+            Op::Frd => {
+                let fr = inst.extract::<Frd>();
+                let p = transform_affine3_by_mat4(&matrices[fr.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::frd(p, Affine::new(fr.scale), fr.thickness);
+            }
+
+            // Strut fills
+            Op::Bcc => {
+                let b = inst.extract::<Bcc>();
+                let p = transform_affine3_by_mat4(&matrices[b.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::bcc(p, b.cell_size, b.strut_radius);
+            }
+            Op::Fcc => {
+                let f = inst.extract::<Fcc>();
+                let p = transform_affine3_by_mat4(&matrices[f.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::fcc(p, f.cell_size, f.strut_radius);
+            }
+            Op::Octet => {
+                let o = inst.extract::<Octet>();
+                let p = transform_affine3_by_mat4(&matrices[o.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::octet(p, o.cell_size, o.strut_radius);
+            }
+            Op::Kelvin => {
+                let k = inst.extract::<Kelvin>();
+                let p = transform_affine3_by_mat4(&matrices[k.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::kelvin(p, k.cell_size, k.strut_radius);
+            }
+            Op::Voronoi => {
+                let v = inst.extract::<Voronoi>();
+                let p = transform_affine3_by_mat4(&matrices[v.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::voronoi(p, v.cell_size, v.thickness, v.seed);
+            }
+
+            // Values that vary over space
+            Op::Constant => {
+                regs[inst.reg()] = Affine::new(inst.extract::<Constant>().value);
+            }
+            Op::Coordinate => {
+                let c = inst.extract::<Coordinate>();
+                let p = transform_affine3_by_mat4(&matrices[c.matrix_idx], p);
+                regs[inst.reg()] = sdf::affine::coordinate(p, c.axis, c.half_extent);
+            }
+            Op::Sum => {
+                let (lhs, rhs) = inst.operands();
+                regs[inst.reg()] = regs[lhs] + regs[rhs];
+            }
+            Op::Difference => {
+                let (lhs, rhs) = inst.operands();
+                regs[inst.reg()] = regs[lhs] - regs[rhs];
+            }
+            Op::Product => {
+                let (lhs, rhs) = inst.operands();
+                regs[inst.reg()] = regs[lhs] * regs[rhs];
+            }
+            Op::Quotient => {
+                let (lhs, rhs) = inst.operands();
+                regs[inst.reg()] = regs[lhs] / regs[rhs];
+            }
+            Op::Negation => {
+                regs[inst.reg()] = -regs[inst.extract::<Negation>().operand];
+            }
+            Op::Abs => {
+                regs[inst.reg()] = regs[inst.extract::<Abs>().operand].abs();
+            }
+            Op::Sqrt => {
+                regs[inst.reg()] = regs[inst.extract::<Sqrt>().operand].sqrt();
+            }
+            Op::Sin => {
+                regs[inst.reg()] = regs[inst.extract::<Sin>().operand].sin();
+            }
+            Op::Cos => {
+                regs[inst.reg()] = regs[inst.extract::<Cos>().operand].cos();
+            }
+            Op::GradedSheet => {
+                let g = inst.extract::<GradedSheet>();
+                let p = transform_affine3_by_mat4(&matrices[g.matrix_idx], p);
+                regs[inst.reg()] =
+                    sdf::affine::graded_sheet(g.sheet, p, regs[g.scale], g.half_extents.into());
             }
         }
 
         i += 1;
     }
-}
\ No newline at end of file
+}
+
+/// A subtraction that picks its left side returns it negated, which can't
+/// be expressed by forwarding a register, so that side is never pruned.
+fn subtraction_choice(choice: Choice) -> Choice {
+    match choice {
+        Choice::Left => Choice::Both,
+        choice => choice,
+    }
+}
+
+#[inline(always)]
+pub fn sdf_affine(tape: &[Inst], start: usize, matrices: &[Mat4], p: Affine3) -> Affine {
+    sdf_affine_choices(tape, start, matrices, p, |_, _| {})
+}
+
+/// Evaluates the tape at `tape[tape_start..]` over a region with affine arithmetic,
+/// and records which operands of every combination can win in
+/// `choices[choices_start..]`, for use by [`prune`].
+///
+/// If `merge` is set, the choices are merged with the ones already recorded,
+/// so that the pruned tape is valid over every region that was evaluated.
+/// Returns the bounds of the distance over the region.
+pub fn record_choices(
+    tape: &[Inst],
+    tape_start: usize,
+    matrices: &[Mat4],
+    region: Affine3,
+    choices: &mut [u32],
+    choices_start: usize,
+    merge: bool,
+) -> Affine {
+    sdf_affine_choices(tape, tape_start, matrices, region, |i, choice| {
+        let idx = choices_start + i - tape_start;
+        choices[idx] = if merge && choices[idx] != choice as u32 {
+            Choice::Both as u32
+        } else {
+            choice as u32
+        };
+    })
+}
+
+/// Writes a tape to `tapes[out_start..]` that only contains the instructions of
+/// the tape at `tapes[tape_start..]` that can affect its result, according to
+/// the choices recorded by [`record_choices`].
+///
+/// The tape is walked backwards from `Ret`, keeping track of the registers
+/// that are still needed: a combination that only needs one of its operands
+/// is dropped, and the instruction producing that operand writes directly to
+/// the combination's output register instead. Everything that only fed the
+/// other operand is then never needed and gets dropped as well.
+///
+/// This relies on every register being read once between writes, which
+/// holds for tapes compiled from a tree.
+///
+/// Both tapes live in the same buffer since that's how they're bound on the GPU,
+/// and there must be room for as many instructions as the input tape has.
+/// Returns the length of the pruned tape.
+pub fn prune(
+    tapes: &mut [Inst],
+    tape_start: usize,
+    out_start: usize,
+    choices: &[u32],
+    choices_start: usize,
+) -> usize {
+    let mut ret = tape_start;
+    while tapes[ret].op() != Op::Ret {
+        ret += 1;
+    }
+
+    let mut live = [false; REGISTER_COUNT];
+    // The register each value has to be written to.
+    let mut dest = [0; REGISTER_COUNT];
+    let mut r = 0;
+    while r < REGISTER_COUNT {
+        dest[r] = r;
+        r += 1;
+    }
+
+    // The pruned tape is written backwards from the end of its space,
+    // then moved to the start.
+    let end = out_start + ret + 1 - tape_start;
+    let mut len = 0;
+    let mut i = ret + 1;
+    while i > tape_start {
+        i -= 1;
+        let inst = tapes[i];
+        let reg = inst.reg();
+
+        if inst.op() != Op::Ret && !live[reg] {
+            continue;
+        }
+        // The value in `reg` is defined here, so it's no longer awaited and
+        // the register can be forwarded to one of the operands again.
+        let out_reg = dest[reg];
+        live[reg] = false;
+        dest[reg] = reg;
+
+        let keep = match inst.op() {
+            Op::Ret => {
+                live[reg] = true;
+                true
+            }
+            Op::Sphere
+            | Op::RectangularPrism
+            | Op::Cylinder
+            | Op::Torus
+            | Op::Capsule
+            | Op::Cone
+            | Op::Plane
+            | Op::Ellipsoid
+            | Op::Gyroid
+            | Op::SchwarzP
+            | Op::Diamond
+            | Op::Neovius
+            | Op::Iwp
+            | Op::Lidinoid
+            | Op::Frd
+            | Op::Bcc
+            | Op::Fcc
+            | Op::Octet
+            | Op::Kelvin
+            | Op::Voronoi
+            | Op::Constant
+            | Op::Coordinate => true,
+            Op::Sum | Op::Difference | Op::Product | Op::Quotient => {
+                let (lhs, rhs) = inst.operands();
+                live[lhs] = true;
+                live[rhs] = true;
+                true
+            }
+            Op::Negation | Op::Abs | Op::Sqrt | Op::Sin | Op::Cos => {
+                // These all keep their operand in arg 0.
+                live[inst.extract::<Abs>().operand] = true;
+                true
+            }
+            Op::GradedSheet => {
+                live[inst.extract::<GradedSheet>().scale] = true;
+                true
+            }
+            _ => {
+                let (lhs, rhs) = inst.operands();
+                let choice = choices[choices_start + i - tape_start];
+                if choice == Choice::Left as u32 {
+                    live[lhs] = true;
+                    dest[lhs] = out_reg;
+                    false
+                } else if choice == Choice::Right as u32 {
+                    live[rhs] = true;
+                    dest[rhs] = out_reg;
+                    false
+                } else {
+                    live[lhs] = true;
+                    live[rhs] = true;
+                    true
+                }
+            }
+        };
+
+        if keep {
+            len += 1;
+            tapes[end - len] = inst.with_reg(out_reg);
+        }
+    }
+
+    let mut j = 0;
+    while j < len {
+        tapes[out_start + j] = tapes[end - len + j];
+        j += 1;
+    }
+
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::inst::{Difference, Product, Ret, Sum};
+
+    #[test]
+    fn transformed_shapes() {
+        // Shapes store the inverse of their transform.
+        let matrices = [
+            Mat4::from_translation(vec3(2.0, 0.0, 0.0)).inverse(),
+            (Mat4::from_translation(vec3(0.0, 3.0, 0.0))
+                * Mat4::from_rotation_z(core::f32::consts::FRAC_PI_4))
+            .inverse(),
+        ];
+        let sphere = [
+            Inst::make(
+                0,
+                Sphere {
+                    matrix_idx: 0,
+                    radius: 1.0,
+                },
+            ),
+            Inst::make(0, Ret),
+        ];
+        let prism = [
+            Inst::make(
+                0,
+                RectangularPrism {
+                    matrix_idx: 1,
+                    x: 1.0,
+                    y: 1.0,
+                    z: 1.0,
+                },
+            ),
+            Inst::make(0, Ret),
+        ];
+
+        assert!((sdf(&sphere, 0, &matrices, vec3(2.0, 0.0, 0.0)) + 1.0).abs() < 1e-6);
+        let normal = sdf_deriv(&sphere, 0, &matrices, vec3(2.0, 2.0, 0.0)).derivatives();
+        assert!((normal - vec3(0.0, 1.0, 0.0)).length() < 1e-6);
+
+        // The prism is rotated by 45°, so its face normals are diagonal.
+        let p = vec3(2.0, 5.0, 0.0);
+        let normal = sdf_deriv(&prism, 0, &matrices, p).derivatives();
+        let expected = vec3(1.0, 1.0, 0.0).normalize();
+        assert!((normal - expected).length() < 1e-5);
+        assert!((sdf(&prism, 0, &matrices, p) - (8.0f32.sqrt() - 1.0)).abs() < 1e-5);
+
+        let bounds = sdf_affine(&sphere, 0, &matrices, Affine3::new(vec3(2.0, 0.0, 0.0)));
+        let bounds = bounds.into_interval();
+        assert!((bounds.low + 1.0).abs() < 1e-6 && (bounds.high + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn primitives() {
+        let matrices = [Mat4::IDENTITY];
+        let shapes = [
+            Inst::make(
+                0,
+                Cylinder {
+                    matrix_idx: 0,
+                    radius: 1.0,
+                    half_height: 2.0,
+                },
+            ),
+            Inst::make(
+                0,
+                Torus {
+                    matrix_idx: 0,
+                    major_radius: 2.0,
+                    minor_radius: 0.5,
+                },
+            ),
+            Inst::make(
+                0,
+                Capsule {
+                    matrix_idx: 0,
+                    radius: 0.5,
+                    half_height: 1.0,
+                },
+            ),
+            Inst::make(
+                0,
+                Cone {
+                    matrix_idx: 0,
+                    bottom_radius: 1.0,
+                    top_radius: 0.0,
+                    half_height: 1.0,
+                },
+            ),
+            Inst::make(
+                0,
+                Plane {
+                    matrix_idx: 0,
+                    normal_x: 0.0,
+                    normal_y: 0.6,
+                    normal_z: 0.8,
+                    offset: 0.5,
+                },
+            ),
+            Inst::make(
+                0,
+                Ellipsoid {
+                    matrix_idx: 0,
+                    x: 1.0,
+                    y: 2.0,
+                    z: 0.5,
+                },
+            ),
+        ];
+        // A point with a known distance to each shape.
+        let known = [
+            (vec3(0.0, 3.0, 0.0), 1.0),
+            (vec3(0.0, 0.0, 0.0), 1.5),
+            (vec3(0.0, 2.0, 0.0), 0.5),
+            (vec3(0.0, 2.0, 0.0), 1.0),
+            (vec3(1.0, 1.0, 1.0), 0.9),
+            (vec3(0.0, 0.0, 3.0), 2.5),
+        ];
+        let points = [
+            vec3(0.6, 0.3, -0.2),
+            vec3(1.4, -1.2, 0.9),
+            vec3(-2.1, 0.4, 0.3),
+            vec3(0.2, 1.7, -0.6),
+        ];
+
+        for (&shape, &(p, d)) in shapes.iter().zip(&known) {
+            let tape = [shape, Inst::make(0, Ret)];
+            let f = |p| sdf(&tape, 0, &matrices, p);
+            assert!((f(p) - d).abs() < 1e-5, "{:?}", shape.op());
+
+            for &p in &points {
+                let d = f(p);
+                let deriv = sdf_deriv(&tape, 0, &matrices, p);
+                assert!((deriv.value() - d).abs() < 1e-5);
+
+                let (ex, ey, ez) = (Vec3::X * 1e-3, Vec3::Y * 1e-3, Vec3::Z * 1e-3);
+                let fd = vec3(
+                    f(p + ex) - f(p - ex),
+                    f(p + ey) - f(p - ey),
+                    f(p + ez) - f(p - ez),
+                ) / 2e-3;
+                assert!(
+                    (deriv.derivatives() - fd).length() < 1e-2,
+                    "{:?}",
+                    shape.op()
+                );
+
+                let region = Affine3::from_box(p - Vec3::splat(0.1), p + Vec3::splat(0.1));
+                let bounds = sdf_affine(&tape, 0, &matrices, region).into_interval();
+                // The bounds of the plane are tight, so allow for rounding at the corners.
+                for &offset in &[Vec3::ZERO, Vec3::splat(0.1), vec3(-0.1, 0.05, 0.1)] {
+                    let d = f(p + offset);
+                    assert!(
+                        bounds.low - 1e-5 <= d && d <= bounds.high + 1e-5,
+                        "{:?}",
+                        shape.op()
+                    );
+                }
+            }
+        }
+
+        // The ellipsoid's estimate is `0 / 0` at its centre.
+        let tape = [shapes[5], Inst::make(0, Ret)];
+        assert_eq!(sdf(&tape, 0, &matrices, Vec3::ZERO), -0.5);
+        assert_eq!(sdf_deriv(&tape, 0, &matrices, Vec3::ZERO).value(), -0.5);
+    }
+
+    #[test]
+    fn fills() {
+        let matrices = [Mat4::from_rotation_y(0.3)];
+        let fills = [
+            Inst::make(
+                0,
+                Gyroid {
+                    matrix_idx: 0,
+                    scale: 0.5,
+                    thickness: 0.1,
+                },
+            ),
+            Inst::make(
+                0,
+                SchwarzP {
+                    matrix_idx: 0,
+                    scale: 0.5,
+                    thickness: 0.1,
+                },
+            ),
+            Inst::make(
+                0,
+                Diamond {
+                    matrix_idx: 0,
+                    scale: 0.5,
+                    thickness: 0.1,
+                },
+            ),
+            Inst::make(
+                0,
+                Neovius {
+                    matrix_idx: 0,
+                    scale: 0.5,
+                    thickness: 0.1,
+                },
+            ),
+            Inst::make(
+                0,
+                Iwp {
+                    matrix_idx: 0,
+                    scale: 0.5,
+                    thickness: 0.1,
+                },
+            ),
+            Inst::make(
+                0,
+                Lidinoid {
+                    matrix_idx: 0,
+                    scale: 0.5,
+                    thickness: 0.1,
+                },
+            ),
+            Inst::make(
+                0,
+                Frd {
+                    matrix_idx: 0,
+                    scale: 0.5,
+                    thickness: 0.1,
+                },
+            ),
+        ];
+
+        for &fill in &fills {
+            let tape = [fill, Inst::make(0, Ret)];
+            let f = |p| sdf(&tape, 0, &matrices, p);
+
+            for i in 0..27 {
+                let c = |i: usize| (i % 3) as f32 * 1.3 - 1.1;
+                let p = vec3(c(i), c(i / 3), c(i / 9));
+
+                let deriv = sdf_deriv(&tape, 0, &matrices, p);
+                assert!((deriv.value() - f(p)).abs() < 1e-5);
+                let (ex, ey, ez) = (Vec3::X * 1e-3, Vec3::Y * 1e-3, Vec3::Z * 1e-3);
+                let fd = vec3(
+                    f(p + ex) - f(p - ex),
+                    f(p + ey) - f(p - ey),
+                    f(p + ez) - f(p - ez),
+                ) / 2e-3;
+                assert!((deriv.derivatives() - fd).length() < 1e-2, "{:?}", p);
+                // Sphere tracing relies on the distance never growing faster than 1.
+                assert!(
+                    deriv.derivatives().length() <= 1.0 + 1e-5,
+                    "{:?} at {:?}: {:?}",
+                    fill.op(),
+                    p,
+                    deriv.derivatives()
+                );
+
+                // Small regions follow the tangents of sin and cos, and large
+                // ones their interval bounds.
+                for &size in &[0.02, 0.2, 2.0] {
+                    let region = Affine3::from_box(p - Vec3::splat(size), p + Vec3::splat(size));
+                    let bounds = sdf_affine(&tape, 0, &matrices, region).into_interval();
+                    for j in 0..27 {
+                        let c = |j: usize| ((j % 3) as f32 - 1.0) * size;
+                        let d = f(p + vec3(c(j), c(j / 3), c(j / 9)));
+                        assert!(
+                            bounds.low - 1e-5 <= d && d <= bounds.high + 1e-5,
+                            "{:?} near {:?}: {} isn't in {:?}",
+                            fill.op(),
+                            p,
+                            d,
+                            bounds
+                        );
+                    }
+                    if size == 0.02 {
+                        let width = bounds.high - bounds.low;
+                        assert!(width < 0.2, "{:?}: {:?}", fill.op(), bounds);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn struts() {
+        let matrices = [Mat4::from_rotation_y(0.3)];
+        let identity = [Mat4::IDENTITY];
+        let struts = [
+            Inst::make(
+                0,
+                Bcc {
+                    matrix_idx: 0,
+                    cell_size: 1.0,
+                    strut_radius: 0.1,
+                },
+            ),
+            Inst::make(
+                0,
+                Fcc {
+                    matrix_idx: 0,
+                    cell_size: 1.0,
+                    strut_radius: 0.1,
+                },
+            ),
+            Inst::make(
+                0,
+                Octet {
+                    matrix_idx: 0,
+                    cell_size: 1.0,
+                    strut_radius: 0.1,
+                },
+            ),
+            Inst::make(
+                0,
+                Kelvin {
+                    matrix_idx: 0,
+                    cell_size: 1.0,
+                    strut_radius: 0.1,
+                },
+            ),
+        ];
+        // The distance from the centre of a cell to the closest strut.
+        let centers = [
+            -0.1,
+            0.4,
+            0.5f32.sqrt() * 0.5 - 0.1,
+            0.28125f32.sqrt() - 0.1,
+        ];
+
+        for (&strut, &center) in struts.iter().zip(&centers) {
+            let tape = [strut, Inst::make(0, Ret)];
+            let d = sdf(&tape, 0, &identity, vec3(2.0, -1.0, 0.0));
+            assert!((d - center).abs() < 1e-5, "{:?}: {}", strut.op(), d);
+
+            let f = |p| sdf(&tape, 0, &matrices, p);
+            for i in 0..27 {
+                let c = |i: usize| (i % 3) as f32 * 0.37 - 0.41;
+                let p = vec3(c(i), c(i / 3), c(i / 9));
+                assert!((f(p) - f(-p)).abs() < 1e-5);
+
+                let deriv = sdf_deriv(&tape, 0, &matrices, p);
+                assert!((deriv.value() - f(p)).abs() < 1e-5);
+                let (ex, ey, ez) = (Vec3::X * 1e-3, Vec3::Y * 1e-3, Vec3::Z * 1e-3);
+                let fd = vec3(
+                    f(p + ex) - f(p - ex),
+                    f(p + ey) - f(p - ey),
+                    f(p + ez) - f(p - ez),
+                ) / 2e-3;
+                assert!((deriv.derivatives() - fd).length() < 1e-2, "{:?}", p);
+                assert!((deriv.derivatives().length() - 1.0).abs() < 1e-4);
+
+                for &size in &[0.02, 0.2, 2.0] {
+                    let region = Affine3::from_box(p - Vec3::splat(size), p + Vec3::splat(size));
+                    let bounds = sdf_affine(&tape, 0, &matrices, region).into_interval();
+                    for j in 0..27 {
+                        let c = |j: usize| ((j % 3) as f32 - 1.0) * size;
+                        let d = f(p + vec3(c(j), c(j / 3), c(j / 9)));
+                        assert!(
+                            bounds.low - 1e-5 <= d && d <= bounds.high + 1e-5,
+                            "{:?} near {:?}: {} isn't in {:?}",
+                            strut.op(),
+                            p,
+                            d,
+                            bounds
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn foam() {
+        let matrices = [Mat4::from_rotation_y(0.3)];
+        let foam = Inst::make(
+            0,
+            Voronoi {
+                matrix_idx: 0,
+                cell_size: 0.7,
+                thickness: 0.05,
+                seed: 3,
+            },
+        );
+        let tape = [foam, Inst::make(0, Ret)];
+        let f = |p| sdf(&tape, 0, &matrices, p);
+
+        for i in 0..125 {
+            let c = |i: usize| (i % 5) as f32 * 0.61 - 1.3;
+            let p = vec3(c(i), c(i / 5), c(i / 25));
+
+            // Points are never deeper inside a wall than its thickness.
+            let d = f(p);
+            assert!(d >= -0.05 - 1e-6, "{}", d);
+
+            let deriv = sdf_deriv(&tape, 0, &matrices, p);
+            assert!((deriv.value() - d).abs() < 1e-5);
+            assert!((deriv.derivatives().length() - 1.0).abs() < 1e-4);
+            // The distance can't change faster than the point moves, even
+            // across the middle of a cell, where its gradient flips.
+            for &step in &[Vec3::X, Vec3::Y, Vec3::Z, vec3(0.6, -0.8, 0.0)] {
+                let step = step * 0.05;
+                assert!((f(p + step) - d).abs() <= 0.05 + 1e-5, "at {:?}", p);
+            }
+
+            for &size in &[0.02, 0.2, 1.0] {
+                let region = Affine3::from_box(p - Vec3::splat(size), p + Vec3::splat(size));
+                let bounds = sdf_affine(&tape, 0, &matrices, region).into_interval();
+                for j in 0..27 {
+                    let c = |j: usize| ((j % 3) as f32 - 1.0) * size;
+                    let d = f(p + vec3(c(j), c(j / 3), c(j / 9)));
+                    assert!(
+                        bounds.low - 1e-5 <= d && d <= bounds.high + 1e-5,
+                        "near {:?}: {} isn't in {:?}",
+                        p,
+                        d,
+                        bounds
+                    );
+                }
+            }
+        }
+
+        // The closest seed point to this one is two cubes away.
+        let (d, _) = sdf::voronoi_walls(vec3(17.009, 19.178, -3.357), 1.0, 2);
+        assert!((d - 0.006713).abs() < 1e-5, "{}", d);
+
+        // Other seeds place the cells elsewhere.
+        let other = [
+            Inst::make(
+                0,
+                Voronoi {
+                    matrix_idx: 0,
+                    cell_size: 0.7,
+                    thickness: 0.05,
+                    seed: 4,
+                },
+            ),
+            Inst::make(0, Ret),
+        ];
+        let p = vec3(0.2, 0.4, -0.3);
+        assert_ne!(f(p), sdf(&other, 0, &matrices, p));
+    }
+
+    #[test]
+    fn graded_fill() {
+        let matrices = [Mat4::IDENTITY, Mat4::from_rotation_y(0.3)];
+        let constant = |reg, value| Inst::make(reg, Constant { value });
+        let coordinate = |reg, axis| {
+            Inst::make(
+                reg,
+                Coordinate {
+                    matrix_idx: 1,
+                    axis,
+                    half_extent: 2.0,
+                },
+            )
+        };
+        // A gyroid within a sphere, with a scale of 0.4 + x/20 and a
+        // thickness of 0.05 + 0.02 * sin(y), over the sphere's bounding box.
+        let tape = [
+            Inst::make(
+                0,
+                Sphere {
+                    matrix_idx: 0,
+                    radius: 2.0,
+                },
+            ),
+            coordinate(1, 0),
+            constant(2, 0.05),
+            Inst::make(1, Product { lhs: 1, rhs: 2 }),
+            constant(2, 0.4),
+            Inst::make(1, Sum { lhs: 1, rhs: 2 }),
+            Inst::make(
+                1,
+                GradedSheet {
+                    matrix_idx: 1,
+                    sheet: Op::Gyroid,
+                    scale: 1,
+                    half_extents: [2.0; 3],
+                },
+            ),
+            coordinate(2, 1),
+            Inst::make(2, Sin { operand: 2 }),
+            constant(3, 0.02),
+            Inst::make(2, Product { lhs: 2, rhs: 3 }),
+            constant(3, 0.05),
+            Inst::make(2, Sum { lhs: 2, rhs: 3 }),
+            Inst::make(1, Difference { lhs: 1, rhs: 2 }),
+            Inst::make(0, Intersection { lhs: 0, rhs: 1 }),
+            Inst::make(0, Ret),
+        ];
+        let f = |p| sdf(&tape, 0, &matrices, p);
+
+        for i in 0..27 {
+            let c = |i: usize| (i % 3) as f32 * 1.3 - 1.1;
+            let p = vec3(c(i), c(i / 3), c(i / 9));
+
+            let q = matrices[1].transform_point3(p);
+            let clamped = q.max(Vec3::splat(-2.0)).min(Vec3::splat(2.0));
+            let thickness = 0.05 + 0.02 * clamped.y.sin();
+            let sheet = sdf::graded_sheet(Op::Gyroid, q, 0.4 + clamped.x / 20.0, Vec3::splat(2.0));
+            let expected = (sheet - thickness).max(p.length() - 2.0);
+            assert!((f(p) - expected).abs() < 1e-5, "{:?}", p);
+
+            let deriv = sdf_deriv(&tape, 0, &matrices, p);
+            assert!((deriv.value() - f(p)).abs() < 1e-5);
+            let (ex, ey, ez) = (Vec3::X * 1e-3, Vec3::Y * 1e-3, Vec3::Z * 1e-3);
+            let fd = vec3(
+                f(p + ex) - f(p - ex),
+                f(p + ey) - f(p - ey),
+                f(p + ez) - f(p - ez),
+            ) / 2e-3;
+            assert!((deriv.derivatives() - fd).length() < 1e-2, "{:?}", p);
+
+            for &size in &[0.02, 0.2, 1.0] {
+                let region = Affine3::from_box(p - Vec3::splat(size), p + Vec3::splat(size));
+                let bounds = sdf_affine(&tape, 0, &matrices, region).into_interval();
+                for j in 0..27 {
+                    let c = |j: usize| ((j % 3) as f32 - 1.0) * size;
+                    let d = f(p + vec3(c(j), c(j / 3), c(j / 9)));
+                    assert!(
+                        bounds.low - 1e-5 <= d && d <= bounds.high + 1e-5,
+                        "near {:?}: {} isn't in {:?}",
+                        p,
+                        d,
+                        bounds
+                    );
+                }
+            }
+        }
+
+        // Far outside the sphere, the whole fill is pruned, and well inside it
+        // the sphere is, but every value the fill reads is kept.
+        let mut tapes = [Inst::make(0, Ret); 48];
+        tapes[..16].copy_from_slice(&tape);
+        let mut choices = [0; 16];
+        let outside = Affine3::from_box(vec3(4.5, -0.2, -0.2), vec3(4.9, 0.2, 0.2));
+        record_choices(&tapes, 0, &matrices, outside, &mut choices, 0, false);
+        let len = prune(&mut tapes, 0, 16, &choices, 0);
+        assert_eq!(len, 2);
+        assert_eq!(tapes[16].op(), Op::Sphere);
+
+        let inside = Affine3::from_box(vec3(-0.3, -0.3, -0.3), vec3(0.3, 0.3, 0.3));
+        record_choices(&tapes, 0, &matrices, inside, &mut choices, 0, false);
+        let len = prune(&mut tapes, 0, 16, &choices, 0);
+        assert_eq!(len, 14);
+        assert_eq!(tapes[16].op(), Op::Coordinate);
+        assert_eq!(tapes[28].op(), Op::Difference);
+        for &p in &[vec3(0.1, -0.2, 0.25), vec3(-0.3, 0.3, 0.0), Vec3::ZERO] {
+            assert!((sdf(&tapes, 16, &matrices, p) - f(p)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn smooth_combinations() {
+        let matrices = [Mat4::IDENTITY, Mat4::from_translation(vec3(-1.2, 0.0, 0.0))];
+        let shapes = [
+            Inst::make(
+                0,
+                Sphere {
+                    matrix_idx: 0,
+                    radius: 1.0,
+                },
+            ),
+            Inst::make(
+                1,
+                Sphere {
+                    matrix_idx: 1,
+                    radius: 1.0,
+                },
+            ),
+        ];
+        let combinations = [
+            Inst::make(
+                0,
+                SmoothUnion {
+                    lhs: 0,
+                    rhs: 1,
+                    k: 0.5,
+                },
+            ),
+            Inst::make(
+                0,
+                SmoothIntersection {
+                    lhs: 0,
+                    rhs: 1,
+                    k: 0.5,
+                },
+            ),
+            Inst::make(
+                0,
+                SmoothSubtraction {
+                    lhs: 1,
+                    rhs: 0,
+                    k: 0.5,
+                },
+            ),
+        ];
+        let points = [
+            vec3(0.6, 0.0, 0.0),
+            vec3(0.6, 0.9, 0.0),
+            vec3(-0.3, 0.2, 0.4),
+            vec3(1.9, -0.3, 0.0),
+        ];
+
+        for &combination in &combinations {
+            let tape = [shapes[0], shapes[1], combination, Inst::make(0, Ret)];
+
+            let f = |p| sdf(&tape, 0, &matrices, p);
+
+            for &p in &points {
+                let d = f(p);
+
+                // The derivative evaluator must agree with the regular one,
+                // and its gradient with finite differences.
+                let deriv = sdf_deriv(&tape, 0, &matrices, p);
+                assert!((deriv.value() - d).abs() < 1e-5);
+
+                let (ex, ey, ez) = (Vec3::X * 1e-3, Vec3::Y * 1e-3, Vec3::Z * 1e-3);
+                let fd = vec3(
+                    f(p + ex) - f(p - ex),
+                    f(p + ey) - f(p - ey),
+                    f(p + ez) - f(p - ez),
+                ) / 2e-3;
+                assert!((deriv.derivatives() - fd).length() < 1e-2);
+
+                // The bounds over a region must contain every point in it.
+                let region = Affine3::from_box(p - Vec3::splat(0.05), p + Vec3::splat(0.05));
+                let bounds = sdf_affine(&tape, 0, &matrices, region).into_interval();
+                assert!(bounds.low <= d && d <= bounds.high);
+            }
+        }
+    }
+
+    #[test]
+    fn prune_tape() {
+        let matrices = [
+            Mat4::from_translation(vec3(2.0, 0.0, 0.0)),
+            Mat4::from_translation(vec3(-2.0, 0.0, 0.0)),
+            Mat4::IDENTITY,
+            Mat4::from_translation(vec3(0.0, -1.5, 0.0)),
+        ];
+        let sphere = |reg, matrix_idx, radius| Inst::make(reg, Sphere { matrix_idx, radius });
+        let tape = [
+            sphere(0, 0, 0.8),
+            sphere(1, 1, 0.8),
+            Inst::make(
+                0,
+                SmoothUnion {
+                    lhs: 0,
+                    rhs: 1,
+                    k: 0.3,
+                },
+            ),
+            Inst::make(
+                1,
+                RectangularPrism {
+                    matrix_idx: 2,
+                    x: 0.3,
+                    y: 0.3,
+                    z: 2.0,
+                },
+            ),
+            Inst::make(0, Subtraction { lhs: 1, rhs: 0 }),
+            sphere(1, 3, 0.5),
+            Inst::make(1, Union { lhs: 0, rhs: 1 }),
+            Inst::make(1, Ret),
+        ];
+
+        // The tape, followed by space for a pruned tape and a tape pruned from that.
+        let mut tapes = [Inst::make(0, Ret); 24];
+        tapes[..8].copy_from_slice(&tape);
+        let mut choices = [0; 8];
+
+        // Only the sphere on the left can be the closest around it.
+        let left = Affine3::from_box(vec3(-2.3, -0.3, -0.3), vec3(-1.7, 0.3, 0.3));
+        record_choices(&tapes, 0, &matrices, left, &mut choices, 0, false);
+        let len = prune(&mut tapes, 0, 8, &choices, 0);
+        assert_eq!(len, 2);
+        assert_eq!(tapes[8].op(), Op::Sphere);
+        assert_eq!(tapes[8].reg(), 1);
+        assert_eq!(tapes[9].op(), Op::Ret);
+
+        // Around both spheres, both of them and their union are needed.
+        let right = Affine3::from_box(vec3(1.7, -0.3, -0.3), vec3(2.3, 0.3, 0.3));
+        record_choices(&tapes, 0, &matrices, right, &mut choices, 0, true);
+        let len = prune(&mut tapes, 0, 8, &choices, 0);
+        assert_eq!(len, 4);
+        assert_eq!(tapes[10].op(), Op::SmoothUnion);
+        for &p in &[vec3(-2.1, 0.1, 0.0), vec3(1.9, 0.0, -0.2)] {
+            assert!((sdf(&tapes, 8, &matrices, p) - sdf(&tapes, 0, &matrices, p)).abs() < 1e-6);
+        }
+
+        // Inside every region and subregion, the pruned tapes must give the same distances.
+        let mut pruned_any = false;
+        for x in -2..2 {
+            for y in -2..2 {
+                for z in -2..2 {
+                    let low = vec3(x as f32, y as f32, z as f32);
+                    let region = Affine3::from_box(low, low + Vec3::ONE);
+                    let bounds =
+                        record_choices(&tapes, 0, &matrices, region, &mut choices, 0, false);
+                    let bounds = bounds.into_interval();
+                    let len = prune(&mut tapes, 0, 8, &choices, 0);
+                    pruned_any |= len < tape.len();
+
+                    let high = low + Vec3::splat(0.5);
+                    let subregion = Affine3::from_box(low, high);
+                    record_choices(&tapes, 8, &matrices, subregion, &mut choices, 0, false);
+                    prune(&mut tapes, 8, 16, &choices, 0);
+
+                    let offsets = [
+                        Vec3::ZERO,
+                        Vec3::ONE,
+                        vec3(0.5, 0.25, 0.75),
+                        vec3(1.0, 0.0, 0.3),
+                    ];
+                    for &offset in &offsets {
+                        let p = low + offset;
+                        let d = sdf(&tapes, 0, &matrices, p);
+                        assert!((sdf(&tapes, 8, &matrices, p) - d).abs() < 1e-6);
+                        assert!(bounds.low <= d && d <= bounds.high);
+
+                        let p = low + offset * 0.5;
+                        let d = sdf(&tapes, 0, &matrices, p);
+                        assert!((sdf(&tapes, 16, &matrices, p) - d).abs() < 1e-6);
+                    }
+                }
+            }
+        }
+        assert!(pruned_any);
+    }
+}
@@ -1,7 +1,8 @@
+use crate::arithmetic::{interval, Affine, Affine3, Arithmetics, Choice, Interval};
+use glam::{vec3, Vec3};
+use shared::inst::{Diamond, Frd, Gyroid, Iwp, Lidinoid, Neovius, Op, SchwarzP};
 #[cfg(target_arch = "spirv")]
 use spirv_std::num_traits::Float as _;
-use crate::{arithmetic::{Affine, Affine3, Arithmetics, Choice}};
-use glam::Vec3;
 
 pub fn sphere(p: Affine3, r: f32) -> Affine {
     p.length() - r
@@ -12,20 +13,202 @@ pub fn rectangular_prism(p: Affine3, sides: Vec3) -> Affine {
     q.max(0.0).length() + q.y.max(q.z).max(q.x).min(0.0)
 }
 
-// pub fn cylinder(p: Vec3, h: f32, r: f32) -> f32 {
-//     let d = vec2(p.xz().length(), p.y).abs() - vec2(r, h);
-//     d.x.max(d.y).min(0.0) + d.max(Vec2::ZERO).length()
-// }
+fn length2(x: Affine, y: Affine) -> Affine {
+    (x * x + y * y).sqrt()
+}
+
+pub fn cylinder(p: Affine3, r: f32, h: f32) -> Affine {
+    let dx = length2(p.x, p.z) - r;
+    let dy = p.y.abs() - h;
+    dx.max(dy).min(0.0) + length2(dx.max(0.0), dy.max(0.0))
+}
+
+pub fn torus(p: Affine3, major: f32, minor: f32) -> Affine {
+    length2(length2(p.x, p.z) - major, p.y) - minor
+}
+
+/// `y - clamp(y, -h, h)` never decreases, so it's bounded by its values at the ends.
+pub fn capsule(p: Affine3, r: f32, h: f32) -> Affine {
+    let Interval { low, high } = p.y.into_interval();
+    let y = Affine::from_error(interval(low - low.clamp(-h, h), high - high.clamp(-h, h)));
+    (p.x * p.x + y * y + p.z * p.z).sqrt() - r
+}
+
+/// Returns the centre of the box around `p`, and the radius of the sphere around it.
+fn center_and_radius(p: Affine3) -> (Vec3, f32) {
+    let (x, y, z) = (
+        p.x.into_interval(),
+        p.y.into_interval(),
+        p.z.into_interval(),
+    );
+    let center = vec3(x.low + x.high, y.low + y.high, z.low + z.high) * 0.5;
+    let radius = vec3(x.high - x.low, y.high - y.low, z.high - z.low).length() * 0.5;
+    (center, radius)
+}
+
+/// Bounds an exact distance that can't be followed with affine arithmetic,
+/// such as one that branches a lot or folds space into repeating cells. An
+/// exact distance can't change by more than the radius of the region from the
+/// distance `d` at its centre.
+fn around(d: f32, radius: f32) -> Affine {
+    Affine::from_error(interval(d - radius, d + radius))
+}
+
+/// The cone is bounded [`around`] its centre.
+pub fn cone(p: Affine3, bottom: f32, top: f32, h: f32) -> Affine {
+    let (center, radius) = center_and_radius(p);
+    around(crate::sdf::cone(center, bottom, top, h), radius)
+}
+
+pub fn plane(p: Affine3, normal: Vec3, offset: f32) -> Affine {
+    p.x * normal.x + p.y * normal.y + p.z * normal.z - offset
+}
+
+/// The distance is between the distances to the unit sphere, after scaling
+/// the point to it, scaled by the smallest and largest radii.
+pub fn ellipsoid(p: Affine3, radii: Vec3) -> Affine {
+    let scaled = Affine3 {
+        x: p.x / radii.x,
+        y: p.y / radii.y,
+        z: p.z / radii.z,
+    };
+    let t = scaled.length().into_interval() - 1.0;
+    let (small, large) = (
+        radii.x.min(radii.y).min(radii.z),
+        radii.x.max(radii.y).max(radii.z),
+    );
+    Affine::from_error(interval(
+        (t.low * small).min(t.low * large),
+        (t.high * small).max(t.high * large),
+    ))
+}
+
+fn mul(p: Affine3, factor: f32) -> Affine3 {
+    Affine3 {
+        x: p.x * factor,
+        y: p.y * factor,
+        z: p.z * factor,
+    }
+}
+
+fn div(p: Affine3, scale: Affine) -> Affine3 {
+    Affine3 {
+        x: p.x / scale,
+        y: p.y / scale,
+        z: p.z / scale,
+    }
+}
+
+fn sheet(g: Affine, scale: Affine, thickness: f32, lipschitz: f32) -> Affine {
+    (g.abs() * scale - thickness) / lipschitz
+}
+
+pub fn gyroid(p: Affine3, scale: Affine, thickness: f32) -> Affine {
+    let p = div(p, scale);
+    let zxy = Affine3 {
+        x: p.z,
+        y: p.x,
+        z: p.y,
+    };
+    let g = p.sin().dot(zxy.cos());
+    sheet(g, scale, thickness, Gyroid::LIPSCHITZ)
+}
+
+pub fn schwarz_p(p: Affine3, scale: Affine, thickness: f32) -> Affine {
+    let c = div(p, scale).cos();
+    sheet(c.x + c.y + c.z, scale, thickness, SchwarzP::LIPSCHITZ)
+}
 
-// pub fn gyroid(p: Vec3, scale: f32, thickness: f32) -> f32 {
-//     let p = p * scale;
-//     (p.sin().dot(p.zxy().cos()) / scale - thickness) * 0.6
-// }
+pub fn diamond(p: Affine3, scale: Affine, thickness: f32) -> Affine {
+    let p = div(p, scale);
+    let (s, c) = (p.sin(), p.cos());
+    let g = s.x * s.y * s.z + s.x * c.y * c.z + c.x * s.y * c.z + c.x * c.y * s.z;
+    sheet(g, scale, thickness, Diamond::LIPSCHITZ)
+}
+
+pub fn neovius(p: Affine3, scale: Affine, thickness: f32) -> Affine {
+    let c = div(p, scale).cos();
+    let g = (c.x + c.y + c.z) * 3.0 + c.x * c.y * c.z * 4.0;
+    sheet(g, scale, thickness, Neovius::LIPSCHITZ)
+}
+
+pub fn iwp(p: Affine3, scale: Affine, thickness: f32) -> Affine {
+    let p = div(p, scale);
+    let (c, c2) = (p.cos(), mul(p, 2.0).cos());
+    let g = (c.x * c.y + c.y * c.z + c.z * c.x) * 2.0 - (c2.x + c2.y + c2.z);
+    sheet(g, scale, thickness, Iwp::LIPSCHITZ)
+}
+
+pub fn lidinoid(p: Affine3, scale: Affine, thickness: f32) -> Affine {
+    let p = div(p, scale);
+    let (s, c) = (p.sin(), p.cos());
+    let (s2, c2) = (mul(p, 2.0).sin(), mul(p, 2.0).cos());
+    let g = (s2.x * c.y * s.z + s2.y * c.z * s.x + s2.z * c.x * s.y) * 0.5
+        - (c2.x * c2.y + c2.y * c2.z + c2.z * c2.x) * 0.5
+        + 0.15;
+    sheet(g, scale, thickness, Lidinoid::LIPSCHITZ)
+}
 
-// pub fn schwarz_p(p: Vec3, scale: f32, thickness: f32) -> f32 {
-//     let p = p * scale;
-//     (p.cos().dot(Vec3::ONE).abs() / scale - thickness) * 0.6
-// }
+pub fn frd(p: Affine3, scale: Affine, thickness: f32) -> Affine {
+    let p = div(p, scale);
+    let (c, c2) = (p.cos(), mul(p, 2.0).cos());
+    let g = c.x * c.y * c.z * 4.0 - (c2.x * c2.y + c2.y * c2.z + c2.z * c2.x);
+    sheet(g, scale, thickness, Frd::LIPSCHITZ)
+}
+
+pub fn graded_sheet(sheet: Op, p: Affine3, scale: Affine, half_extents: Vec3) -> Affine {
+    let p = Affine3 {
+        x: p.x.clamp(-half_extents.x, half_extents.x),
+        y: p.y.clamp(-half_extents.y, half_extents.y),
+        z: p.z.clamp(-half_extents.z, half_extents.z),
+    };
+    match sheet {
+        Op::Gyroid => gyroid(p, scale, 0.0),
+        Op::SchwarzP => schwarz_p(p, scale, 0.0),
+        Op::Diamond => diamond(p, scale, 0.0),
+        Op::Neovius => neovius(p, scale, 0.0),
+        Op::Iwp => iwp(p, scale, 0.0),
+        Op::Lidinoid => lidinoid(p, scale, 0.0),
+        _ => frd(p, scale, 0.0),
+    }
+}
+
+// Lattices fold space into cells, so they're bounded [`around`] the centre.
+
+pub fn bcc(p: Affine3, cell: f32, r: f32) -> Affine {
+    let (center, radius) = center_and_radius(p);
+    around(crate::sdf::bcc(center, cell, r), radius)
+}
+
+pub fn fcc(p: Affine3, cell: f32, r: f32) -> Affine {
+    let (center, radius) = center_and_radius(p);
+    around(crate::sdf::fcc(center, cell, r), radius)
+}
+
+pub fn octet(p: Affine3, cell: f32, r: f32) -> Affine {
+    let (center, radius) = center_and_radius(p);
+    around(crate::sdf::octet(center, cell, r), radius)
+}
+
+pub fn kelvin(p: Affine3, cell: f32, r: f32) -> Affine {
+    let (center, radius) = center_and_radius(p);
+    around(crate::sdf::kelvin(center, cell, r), radius)
+}
+
+/// Foams fold space into cells too, so they're bounded [`around`] the centre.
+pub fn voronoi(p: Affine3, cell: f32, thickness: f32, seed: u32) -> Affine {
+    let (center, radius) = center_and_radius(p);
+    around(crate::sdf::voronoi(center, cell, thickness, seed), radius)
+}
+
+pub fn coordinate(p: Affine3, axis: usize, half_extent: f32) -> Affine {
+    let x = match axis {
+        0 => p.x,
+        1 => p.y,
+        _ => p.z,
+    };
+    x.clamp(-half_extent, half_extent)
+}
 
 pub fn union(lhs: Affine, rhs: Affine) -> (Affine, Choice) {
     lhs.min_choice(rhs)
@@ -39,7 +222,32 @@ pub fn subtract(lhs: Affine, rhs: Affine) -> (Affine, Choice) {
     (-lhs).max_choice(rhs)
 }
 
-pub fn smooth_union(lhs: Affine, rhs: Affine, k: f32) -> Affine {
-    let h = ((rhs - lhs) * 0.5 / k + 0.5).clamp(0.0, 1.0);
-    rhs.lerp(lhs, h) - h * (1.0 - h) * k
+/// The smooth combinations only pick a side when the blend is saturated
+/// over the whole range, i.e. when the operands are at least `k` apart.
+pub fn smooth_union(lhs: Affine, rhs: Affine, k: f32) -> (Affine, Choice) {
+    let diff = (rhs - lhs).into_interval();
+    if diff.low >= k {
+        (lhs, Choice::Left)
+    } else if diff.high <= -k {
+        (rhs, Choice::Right)
+    } else {
+        let h = ((rhs - lhs) * 0.5 / k + 0.5).clamp(0.0, 1.0);
+        (rhs.lerp(lhs, h) - h * (1.0 - h) * k, Choice::Both)
+    }
+}
+
+pub fn smooth_intersect(lhs: Affine, rhs: Affine, k: f32) -> (Affine, Choice) {
+    let diff = (lhs - rhs).into_interval();
+    if diff.low >= k {
+        (lhs, Choice::Left)
+    } else if diff.high <= -k {
+        (rhs, Choice::Right)
+    } else {
+        let h = ((lhs - rhs) * 0.5 / k + 0.5).clamp(0.0, 1.0);
+        (rhs.lerp(lhs, h) + h * (1.0 - h) * k, Choice::Both)
+    }
+}
+
+pub fn smooth_subtract(lhs: Affine, rhs: Affine, k: f32) -> (Affine, Choice) {
+    smooth_intersect(-lhs, rhs, k)
 }
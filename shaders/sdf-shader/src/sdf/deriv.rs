@@ -1,8 +1,7 @@
-use glam::Vec3;
+use glam::{vec2, vec3, Vec3};
 
-use crate::{
-    arithmetic::{Arithmetics, Deriv, Deriv3},
-};
+use crate::arithmetic::{Arithmetics, Deriv, Deriv3};
+use shared::inst::{Diamond, Frd, Gyroid, Iwp, Lidinoid, Neovius, Op, SchwarzP};
 
 pub fn sphere(p: Deriv3, r: f32) -> Deriv {
     p.length() - r
@@ -10,18 +9,214 @@ pub fn sphere(p: Deriv3, r: f32) -> Deriv {
 
 pub fn rectangular_prism(p: Deriv3, sides: Vec3) -> Deriv {
     let q = p.abs() - sides;
-    q.max(0.0).length() + q.y.max(q.z.max(q.x.min(0.0)))
+    q.max(0.0).length() + q.x.max(q.y).max(q.z).min(0.0)
 }
 
-// pub fn cylinder(p: DualVec3, h: f32, r: f32) -> f32 {
-//     let d = vec2(p.xz().length(), p.y).abs() - vec2(r, h);
-//     d.x.max(d.y).min(0.0) + d.max(Vec2::ZERO).length()
-// }
+fn length2(x: Deriv, y: Deriv) -> Deriv {
+    (x * x + y * y).sqrt()
+}
+
+pub fn cylinder(p: Deriv3, r: f32, h: f32) -> Deriv {
+    let dx = length2(p.x, p.z) - r;
+    let dy = p.y.abs() - h;
+    dx.max(dy).min(0.0) + length2(dx.max(0.0), dy.max(0.0))
+}
+
+pub fn torus(p: Deriv3, major: f32, minor: f32) -> Deriv {
+    length2(length2(p.x, p.z) - major, p.y) - minor
+}
+
+pub fn capsule(p: Deriv3, r: f32, h: f32) -> Deriv {
+    let y = p.y - p.y.clamp(-h, h);
+    (p.x * p.x + y * y + p.z * p.z).sqrt() - r
+}
+
+pub fn cone(p: Deriv3, bottom: f32, top: f32, h: f32) -> Deriv {
+    let (qx, qy) = (length2(p.x, p.z), p.y);
+    let k2 = vec2(top - bottom, 2.0 * h);
+    let cap_x = qx - qx.min(if qy.value() < 0.0 { bottom } else { top });
+    let cap_y = qy.abs() - h;
+    let t = (((-qx + top) * k2.x + (-qy + h) * k2.y) / k2.dot(k2)).clamp(0.0, 1.0);
+    let side_x = qx - top + t * k2.x;
+    let side_y = qy - h + t * k2.y;
+    let s = if side_x.value() < 0.0 && cap_y.value() < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    (cap_x * cap_x + cap_y * cap_y)
+        .min(side_x * side_x + side_y * side_y)
+        .sqrt()
+        * s
+}
+
+pub fn plane(p: Deriv3, normal: Vec3, offset: f32) -> Deriv {
+    p.x * normal.x + p.y * normal.y + p.z * normal.z - offset
+}
+
+pub fn ellipsoid(p: Deriv3, radii: Vec3) -> Deriv {
+    let k0 = (p / radii).length();
+    let k1 = (p / (radii * radii)).length();
+    if k1.value() == 0.0 {
+        return Deriv::new(-radii.min_element());
+    }
+    k0 * (k0 - 1.0) / k1
+}
+
+fn div(p: Deriv3, scale: Deriv) -> Deriv3 {
+    Deriv3 {
+        x: p.x / scale,
+        y: p.y / scale,
+        z: p.z / scale,
+    }
+}
+
+fn sheet(g: Deriv, scale: Deriv, thickness: f32, lipschitz: f32) -> Deriv {
+    (g.abs() * scale - thickness) / lipschitz
+}
+
+pub fn gyroid(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let p = div(p, scale);
+    let zxy = Deriv3 {
+        x: p.z,
+        y: p.x,
+        z: p.y,
+    };
+    let g = p.sin().dot(zxy.cos());
+    sheet(g, scale, thickness, Gyroid::LIPSCHITZ)
+}
+
+pub fn schwarz_p(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let c = div(p, scale).cos();
+    sheet(c.x + c.y + c.z, scale, thickness, SchwarzP::LIPSCHITZ)
+}
+
+pub fn diamond(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let p = div(p, scale);
+    let (s, c) = (p.sin(), p.cos());
+    let g = s.x * s.y * s.z + s.x * c.y * c.z + c.x * s.y * c.z + c.x * c.y * s.z;
+    sheet(g, scale, thickness, Diamond::LIPSCHITZ)
+}
+
+pub fn neovius(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let c = div(p, scale).cos();
+    let g = (c.x + c.y + c.z) * 3.0 + c.x * c.y * c.z * 4.0;
+    sheet(g, scale, thickness, Neovius::LIPSCHITZ)
+}
+
+pub fn iwp(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let p = div(p, scale);
+    let (c, c2) = (p.cos(), (p * 2.0).cos());
+    let g = (c.x * c.y + c.y * c.z + c.z * c.x) * 2.0 - (c2.x + c2.y + c2.z);
+    sheet(g, scale, thickness, Iwp::LIPSCHITZ)
+}
+
+pub fn lidinoid(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let p = div(p, scale);
+    let (s, c) = (p.sin(), p.cos());
+    let (s2, c2) = ((p * 2.0).sin(), (p * 2.0).cos());
+    let g = (s2.x * c.y * s.z + s2.y * c.z * s.x + s2.z * c.x * s.y) * 0.5
+        - (c2.x * c2.y + c2.y * c2.z + c2.z * c2.x) * 0.5
+        + 0.15;
+    sheet(g, scale, thickness, Lidinoid::LIPSCHITZ)
+}
+
+pub fn frd(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let p = div(p, scale);
+    let (c, c2) = (p.cos(), (p * 2.0).cos());
+    let g = c.x * c.y * c.z * 4.0 - (c2.x * c2.y + c2.y * c2.z + c2.z * c2.x);
+    sheet(g, scale, thickness, Frd::LIPSCHITZ)
+}
+
+pub fn graded_sheet(sheet: Op, p: Deriv3, scale: Deriv, half_extents: Vec3) -> Deriv {
+    let p = Deriv3 {
+        x: p.x.clamp(-half_extents.x, half_extents.x),
+        y: p.y.clamp(-half_extents.y, half_extents.y),
+        z: p.z.clamp(-half_extents.z, half_extents.z),
+    };
+    match sheet {
+        Op::Gyroid => gyroid(p, scale, 0.0),
+        Op::SchwarzP => schwarz_p(p, scale, 0.0),
+        Op::Diamond => diamond(p, scale, 0.0),
+        Op::Neovius => neovius(p, scale, 0.0),
+        Op::Iwp => iwp(p, scale, 0.0),
+        Op::Lidinoid => lidinoid(p, scale, 0.0),
+        _ => frd(p, scale, 0.0),
+    }
+}
+
+/// Rounding is flat between its steps, so the fold keeps the derivatives
+/// of `p`, flipped where `abs` flips them.
+fn fold(p: Deriv3, cell: f32) -> Deriv3 {
+    (p - (p.v() / cell).round() * cell).abs()
+}
+
+fn segment(p: Deriv3, a: Vec3, b: Vec3) -> Deriv {
+    let (pa, ba) = (p - a, b - a);
+    let d = pa * ba;
+    let t = ((d.x + d.y + d.z) / ba.dot(ba)).clamp(0.0, 1.0);
+    let x = pa.x - t * ba.x;
+    let y = pa.y - t * ba.y;
+    let z = pa.z - t * ba.z;
+    (x * x + y * y + z * z).sqrt()
+}
+
+pub fn bcc(p: Deriv3, cell: f32, r: f32) -> Deriv {
+    segment(fold(p, cell), Vec3::ZERO, Vec3::splat(cell * 0.5)) - r
+}
 
-// pub fn schwarz_p(p: Deriv3, scale: f32, thickness: f32) -> Deriv {
-//     let p = p * scale;
-//     (p.cos().dot(Deriv3::one()).abs() / scale - thickness) * 0.6
-// }
+pub fn fcc(p: Deriv3, cell: f32, r: f32) -> Deriv {
+    let (q, h) = (fold(p, cell), cell * 0.5);
+    let corner = Vec3::splat(h);
+    let d = segment(q, vec3(h, 0.0, 0.0), corner)
+        .min(segment(q, vec3(0.0, h, 0.0), corner))
+        .min(segment(q, vec3(0.0, 0.0, h), corner));
+    d - r
+}
+
+pub fn octet(p: Deriv3, cell: f32, r: f32) -> Deriv {
+    let (q, h) = (fold(p, cell), cell * 0.5);
+    let (x, y, z, corner) = (
+        vec3(h, 0.0, 0.0),
+        vec3(0.0, h, 0.0),
+        vec3(0.0, 0.0, h),
+        Vec3::splat(h),
+    );
+    let d = segment(q, x, corner)
+        .min(segment(q, y, corner))
+        .min(segment(q, z, corner))
+        .min(segment(q, x, y))
+        .min(segment(q, y, z))
+        .min(segment(q, z, x));
+    d - r
+}
+
+pub fn kelvin(p: Deriv3, cell: f32, r: f32) -> Deriv {
+    let (q, h, a) = (fold(p, cell), cell * 0.5, cell * 0.25);
+    let d = segment(q, vec3(0.0, a, h), vec3(a, 0.0, h))
+        .min(segment(q, vec3(h, 0.0, a), vec3(h, a, 0.0)))
+        .min(segment(q, vec3(0.0, h, a), vec3(a, h, 0.0)))
+        .min(segment(q, vec3(0.0, a, h), vec3(0.0, h, a)))
+        .min(segment(q, vec3(a, 0.0, h), vec3(h, 0.0, a)))
+        .min(segment(q, vec3(a, h, 0.0), vec3(h, a, 0.0)));
+    d - r
+}
+
+/// The closest wall is flat, so the distance grows along its normal.
+pub fn voronoi(p: Deriv3, cell: f32, thickness: f32, seed: u32) -> Deriv {
+    let (d, direction) = crate::sdf::voronoi_walls(p.v(), cell, seed);
+    let along = p.x * direction.x + p.y * direction.y + p.z * direction.z;
+    along + (d - thickness - along.value())
+}
+
+pub fn coordinate(p: Deriv3, axis: usize, half_extent: f32) -> Deriv {
+    let x = match axis {
+        0 => p.x,
+        1 => p.y,
+        _ => p.z,
+    };
+    x.clamp(-half_extent, half_extent)
+}
 
 pub fn union(lhs: Deriv, rhs: Deriv) -> Deriv {
     lhs.min(rhs)
@@ -39,3 +234,12 @@ pub fn smooth_union(lhs: Deriv, rhs: Deriv, k: f32) -> Deriv {
     let h = ((rhs - lhs) * 0.5 / k + 0.5).clamp(0.0, 1.0);
     rhs.lerp(lhs, h) - h * (-h + 1.0) * k
 }
+
+pub fn smooth_intersect(lhs: Deriv, rhs: Deriv, k: f32) -> Deriv {
+    let h = ((lhs - rhs) * 0.5 / k + 0.5).clamp(0.0, 1.0);
+    rhs.lerp(lhs, h) + h * (-h + 1.0) * k
+}
+
+pub fn smooth_subtract(lhs: Deriv, rhs: Deriv, k: f32) -> Deriv {
+    smooth_intersect(-lhs, rhs, k)
+}
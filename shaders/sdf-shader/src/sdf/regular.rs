@@ -1,8 +1,9 @@
 #[cfg(target_arch = "spirv")]
 use spirv_std::num_traits::Float as _;
 
-use crate::extra::Scalar;
-use glam::Vec3;
+use crate::extra::{Scalar, VectorN};
+use glam::{vec2, vec3, Vec2, Vec3};
+use shared::inst::{Diamond, Frd, Gyroid, Iwp, Lidinoid, Neovius, Op, SchwarzP, Voronoi};
 
 pub fn sphere(p: Vec3, r: f32) -> f32 {
     p.length() - r
@@ -13,20 +14,243 @@ pub fn rectangular_prism(p: Vec3, sides: Vec3) -> f32 {
     q.max(Vec3::ZERO).length() + q.y.max(q.z).max(q.x).min(0.0)
 }
 
-// pub fn cylinder(p: Vec3, h: f32, r: f32) -> f32 {
-//     let d = vec2(p.xz().length(), p.y).abs() - vec2(r, h);
-//     d.x.max(d.y).min(0.0) + d.max(Vec2::ZERO).length()
-// }
+pub fn cylinder(p: Vec3, r: f32, h: f32) -> f32 {
+    let d = vec2(vec2(p.x, p.z).length(), p.y).abs() - vec2(r, h);
+    d.x.max(d.y).min(0.0) + d.max(Vec2::ZERO).length()
+}
+
+pub fn torus(p: Vec3, major: f32, minor: f32) -> f32 {
+    vec2(vec2(p.x, p.z).length() - major, p.y).length() - minor
+}
+
+pub fn capsule(p: Vec3, r: f32, h: f32) -> f32 {
+    vec3(p.x, p.y - p.y.clamp(-h, h), p.z).length() - r
+}
+
+/// The distance is to whichever is closer of the caps and the slanted side.
+pub fn cone(p: Vec3, bottom: f32, top: f32, h: f32) -> f32 {
+    let q = vec2(vec2(p.x, p.z).length(), p.y);
+    let k1 = vec2(top, h);
+    let k2 = vec2(top - bottom, 2.0 * h);
+    let cap = vec2(
+        q.x - q.x.min(if q.y < 0.0 { bottom } else { top }),
+        q.y.abs() - h,
+    );
+    let side = q - k1 + k2 * ((k1 - q).dot(k2) / k2.dot(k2)).clamp(0.0, 1.0);
+    let s = if side.x < 0.0 && cap.y < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    s * cap.dot(cap).min(side.dot(side)).sqrt()
+}
+
+pub fn plane(p: Vec3, normal: Vec3, offset: f32) -> f32 {
+    p.dot(normal) - offset
+}
+
+/// This isn't exact, but it's close to the distance near the surface,
+/// and is between the distances to the spheres with the smallest and
+/// largest radii, scaled to the ellipsoid.
+pub fn ellipsoid(p: Vec3, radii: Vec3) -> f32 {
+    let k0 = (p / radii).length();
+    let k1 = (p / (radii * radii)).length();
+    // The estimate is `0 / 0` at the centre, which is as deep as the smallest radius.
+    if k1 == 0.0 {
+        return -radii.min_element();
+    }
+    k0 * (k0 - 1.0) / k1
+}
+
+/// The sheet where `|g| * scale < thickness`, for the implicit function `g` of a
+/// lattice at `p / scale`, whose cells are `2π * scale` wide. `g` isn't a distance,
+/// so it's divided by its largest gradient to keep it from overestimating one.
+fn sheet(g: f32, scale: f32, thickness: f32, lipschitz: f32) -> f32 {
+    (g.abs() * scale - thickness) / lipschitz
+}
+
+pub fn gyroid(p: Vec3, scale: f32, thickness: f32) -> f32 {
+    let p = p / scale;
+    let g = p.sin().dot(vec3(p.z, p.x, p.y).cos());
+    sheet(g, scale, thickness, Gyroid::LIPSCHITZ)
+}
+
+pub fn schwarz_p(p: Vec3, scale: f32, thickness: f32) -> f32 {
+    let g = (p / scale).cos().dot(Vec3::ONE);
+    sheet(g, scale, thickness, SchwarzP::LIPSCHITZ)
+}
+
+pub fn diamond(p: Vec3, scale: f32, thickness: f32) -> f32 {
+    let p = p / scale;
+    let (s, c) = (p.sin(), p.cos());
+    let g = s.x * s.y * s.z + s.x * c.y * c.z + c.x * s.y * c.z + c.x * c.y * s.z;
+    sheet(g, scale, thickness, Diamond::LIPSCHITZ)
+}
+
+pub fn neovius(p: Vec3, scale: f32, thickness: f32) -> f32 {
+    let c = (p / scale).cos();
+    let g = 3.0 * (c.x + c.y + c.z) + 4.0 * c.x * c.y * c.z;
+    sheet(g, scale, thickness, Neovius::LIPSCHITZ)
+}
+
+pub fn iwp(p: Vec3, scale: f32, thickness: f32) -> f32 {
+    let p = p / scale;
+    let (c, c2) = (p.cos(), (p * 2.0).cos());
+    let g = 2.0 * (c.x * c.y + c.y * c.z + c.z * c.x) - (c2.x + c2.y + c2.z);
+    sheet(g, scale, thickness, Iwp::LIPSCHITZ)
+}
+
+pub fn lidinoid(p: Vec3, scale: f32, thickness: f32) -> f32 {
+    let p = p / scale;
+    let (s, c) = (p.sin(), p.cos());
+    let (s2, c2) = ((p * 2.0).sin(), (p * 2.0).cos());
+    let g = 0.5 * (s2.x * c.y * s.z + s2.y * c.z * s.x + s2.z * c.x * s.y)
+        - 0.5 * (c2.x * c2.y + c2.y * c2.z + c2.z * c2.x)
+        + 0.15;
+    sheet(g, scale, thickness, Lidinoid::LIPSCHITZ)
+}
+
+pub fn frd(p: Vec3, scale: f32, thickness: f32) -> f32 {
+    let p = p / scale;
+    let (c, c2) = (p.cos(), (p * 2.0).cos());
+    let g = 4.0 * c.x * c.y * c.z - (c2.x * c2.y + c2.y * c2.z + c2.z * c2.x);
+    sheet(g, scale, thickness, Frd::LIPSCHITZ)
+}
 
-// pub fn gyroid(p: Vec3, scale: f32, thickness: f32) -> f32 {
-//     let p = p * scale;
-//     (p.sin().dot(p.zxy().cos()) / scale - thickness) * 0.6
-// }
+/// The sheet of the fill `sheet` without its thickness, for a scale that varies over space.
+/// `p` is clamped to the box with the half sides `half_extents` around the filled shape,
+/// where the scale is known to be bounded.
+pub fn graded_sheet(sheet: Op, p: Vec3, scale: f32, half_extents: Vec3) -> f32 {
+    let p = p.max(-half_extents).min(half_extents);
+    match sheet {
+        Op::Gyroid => gyroid(p, scale, 0.0),
+        Op::SchwarzP => schwarz_p(p, scale, 0.0),
+        Op::Diamond => diamond(p, scale, 0.0),
+        Op::Neovius => neovius(p, scale, 0.0),
+        Op::Iwp => iwp(p, scale, 0.0),
+        Op::Lidinoid => lidinoid(p, scale, 0.0),
+        _ => frd(p, scale, 0.0),
+    }
+}
+
+/// Moves `p` into the corner of its cell between the centre, at the origin, and
+/// `(h, h, h)`, where `h` is half the side of the cell. The strut lattices are
+/// symmetric across the middle and the faces of a cell, so the closest strut
+/// to the folded point is as close as the closest one to `p`.
+fn fold(p: Vec3, cell: f32) -> Vec3 {
+    (p - (p / cell).round() * cell).abs()
+}
+
+/// The distance to the segment from `a` to `b`.
+fn segment(p: Vec3, a: Vec3, b: Vec3) -> f32 {
+    let (pa, ba) = (p - a, b - a);
+    (pa - ba * (pa.dot(ba) / ba.dot(ba)).clamp(0.0, 1.0)).length()
+}
+
+pub fn bcc(p: Vec3, cell: f32, r: f32) -> f32 {
+    segment(fold(p, cell), Vec3::ZERO, Vec3::splat(cell * 0.5)) - r
+}
 
-// pub fn schwarz_p(p: Vec3, scale: f32, thickness: f32) -> f32 {
-//     let p = p * scale;
-//     (p.cos().dot(Vec3::ONE).abs() / scale - thickness) * 0.6
-// }
+pub fn fcc(p: Vec3, cell: f32, r: f32) -> f32 {
+    let (q, h) = (fold(p, cell), cell * 0.5);
+    let corner = Vec3::splat(h);
+    let d = segment(q, vec3(h, 0.0, 0.0), corner)
+        .min(segment(q, vec3(0.0, h, 0.0), corner))
+        .min(segment(q, vec3(0.0, 0.0, h), corner));
+    d - r
+}
+
+pub fn octet(p: Vec3, cell: f32, r: f32) -> f32 {
+    let (q, h) = (fold(p, cell), cell * 0.5);
+    let (x, y, z, corner) = (
+        vec3(h, 0.0, 0.0),
+        vec3(0.0, h, 0.0),
+        vec3(0.0, 0.0, h),
+        Vec3::splat(h),
+    );
+    let d = segment(q, x, corner)
+        .min(segment(q, y, corner))
+        .min(segment(q, z, corner))
+        .min(segment(q, x, y))
+        .min(segment(q, y, z))
+        .min(segment(q, z, x));
+    d - r
+}
+
+/// The truncated octahedron has its vertices at the permutations of `(0, ±h/2, ±h)`.
+pub fn kelvin(p: Vec3, cell: f32, r: f32) -> f32 {
+    let (q, h, a) = (fold(p, cell), cell * 0.5, cell * 0.25);
+    let d = segment(q, vec3(0.0, a, h), vec3(a, 0.0, h))
+        .min(segment(q, vec3(h, 0.0, a), vec3(h, a, 0.0)))
+        .min(segment(q, vec3(0.0, h, a), vec3(a, h, 0.0)))
+        .min(segment(q, vec3(0.0, a, h), vec3(0.0, h, a)))
+        .min(segment(q, vec3(a, 0.0, h), vec3(h, 0.0, a)))
+        .min(segment(q, vec3(a, h, 0.0), vec3(h, a, 0.0)));
+    d - r
+}
+
+/// The seed point of the cube whose lowest corner is `cube`, in units of cubes.
+fn seed_point(cube: Vec3, seed: u32) -> Vec3 {
+    let jitter = Voronoi::jitter([cube.x as i32, cube.y as i32, cube.z as i32], seed);
+    cube + Vec3::from(jitter)
+}
+
+/// The offset of the `i`th of the cubes up to `reach` cubes away along each axis.
+fn around(i: u32, reach: u32) -> Vec3 {
+    let side = 2 * reach + 1;
+    let offset = |c: u32| (c % side) as f32 - reach as f32;
+    vec3(offset(i), offset(i / side), offset(i / (side * side)))
+}
+
+/// The distance from `p` to the closest wall of a foam, in cubes with sides of
+/// `cell`, and the direction it grows in.
+///
+/// A seed point three cubes away along an axis is at least two cubes from `p`, farther
+/// than the one in `p`'s own cube can be, so the closest seed point is within two cubes.
+/// Its cell is convex, so the closest wall is the closest of the planes halfway to the
+/// points around it, which are searched for within three cubes.
+pub fn voronoi_walls(p: Vec3, cell: f32, seed: u32) -> (f32, Vec3) {
+    let q = p / cell;
+    let cube = q.floor();
+    let mut nearest = seed_point(cube, seed);
+    for i in 0..125 {
+        let other = seed_point(cube + around(i, 2), seed);
+        if (other - q).length_squared() < (nearest - q).length_squared() {
+            nearest = other;
+        }
+    }
+
+    let (mut distance, mut direction) = (f32::INFINITY, Vec3::ZERO);
+    for i in 0..343 {
+        let other = seed_point(cube + around(i, 3), seed);
+        let between = other - nearest;
+        // The nearest point is placed the same way again, and has no wall with itself.
+        if between != Vec3::ZERO {
+            let normal = between.normalize();
+            let d = ((nearest + other) * 0.5 - q).dot(normal);
+            if d < distance {
+                distance = d;
+                direction = -normal;
+            }
+        }
+    }
+    (distance * cell, direction)
+}
+
+pub fn voronoi(p: Vec3, cell: f32, thickness: f32, seed: u32) -> f32 {
+    voronoi_walls(p, cell, seed).0 - thickness
+}
+
+/// The coordinate of `p` along `axis`, clamped to `±half_extent`.
+/// The coordinate of `p` along `axis`, clamped to `±half_extent`.
+pub fn coordinate(p: Vec3, axis: usize, half_extent: f32) -> f32 {
+    let x = match axis {
+        0 => p.x,
+        1 => p.y,
+        _ => p.z,
+    };
+    x.max(-half_extent).min(half_extent)
+}
 
 pub fn union(lhs: f32, rhs: f32) -> f32 {
     lhs.min(rhs)
@@ -44,3 +268,12 @@ pub fn smooth_union(lhs: f32, rhs: f32, k: f32) -> f32 {
     let h = ((rhs - lhs) * 0.5 / k + 0.5).clamp(0.0, 1.0);
     rhs.lerp(lhs, h) - k * h * (1.0 - h)
 }
+
+pub fn smooth_intersect(lhs: f32, rhs: f32, k: f32) -> f32 {
+    let h = ((lhs - rhs) * 0.5 / k + 0.5).clamp(0.0, 1.0);
+    rhs.lerp(lhs, h) + k * h * (1.0 - h)
+}
+
+pub fn smooth_subtract(lhs: f32, rhs: f32, k: f32) -> f32 {
+    smooth_intersect(-lhs, rhs, k)
+}
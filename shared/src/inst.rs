@@ -1,16 +1,17 @@
 use core::{convert::TryInto, mem};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum Op {
-    /// Return register at index in arg 0.
+    /// Return the output register.
     Ret,
 
-    // These have no arguments (except consuming the distances stored in registers 0 and 1).
+    // These read the registers in arg 0 and arg 1.
     Union,
     Intersection,
     Subtraction,
 
-    // These have one argument in arg 0 (besides registers 0 and 1).
+    // These also have one argument in arg 2.
     SmoothUnion,
     SmoothIntersection,
     SmoothSubtraction,
@@ -20,11 +21,96 @@ pub enum Op {
     /// The radius is stored in arg 1.
     Sphere,
 
-    RectangularPrism, // store the side lengths somehow
+    /// The half side lengths are stored in args 1 to 3.
+    RectangularPrism,
+    /// A capped cylinder around the y axis.
+    /// The radius is stored in arg 1 and the half height in arg 2.
+    Cylinder,
+    /// A torus around the y axis.
+    /// The radius of the ring is stored in arg 1 and of the tube in arg 2.
+    Torus,
+    /// A line segment along the y axis, rounded by a radius.
+    /// The radius is stored in arg 1 and the half length of the segment in arg 2.
+    Capsule,
+    /// A capped cone around the y axis.
+    /// The radius at the bottom is stored in arg 1, at the top in arg 2,
+    /// and the half height in arg 3.
+    Cone,
+    /// The half-space behind a plane.
+    /// The unit normal is stored in args 1 to 3 and the offset along it in arg 4.
+    Plane,
+    /// The radii along each axis are stored in args 1 to 3.
+    Ellipsoid,
 
-                      // ...
+    // Fills
+    // These are the lattice alone, as a sheet through all of space, so they are
+    // intersected with the shape they fill. They have a matrix index in arg 0 like shapes,
+    // the size of a cell over 2π in arg 1 and the thickness of the sheet in arg 2.
+    Gyroid,
+    /// Schwarz's primitive surface.
+    SchwarzP,
+    /// Schwarz's diamond surface.
+    Diamond,
+    Neovius,
+    /// Schoen's I-WP surface.
+    Iwp,
+    Lidinoid,
+    /// Schoen's F-RD surface.
+    Frd,
+
+    // Strut fills
+    // These are beams of a radius between the nodes of a cubic cell, repeated through
+    // all of space. They have a matrix index in arg 0, the side of a cell in arg 1 and
+    // the radius of the struts in arg 2.
+    /// Struts from the centre of the cell to its corners.
+    Bcc,
+    /// Struts along the diagonals of the faces of the cell.
+    Fcc,
+    /// The face diagonals, and the octahedron between the centres of the faces.
+    Octet,
+    /// The edges of a truncated octahedron touching the faces of the cell.
+    Kelvin,
+
+    /// The walls between the cells around seed points, one at a random place in each
+    /// cube of a grid, like a closed-cell foam. It has a matrix index in arg 0, the side
+    /// of a cube in arg 1, how far the walls reach to either side in arg 2, and the seed
+    /// of the random places in arg 3.
+    Voronoi,
+
+    // Values that vary over space
+    // These compute the scale and thickness of graded fills at each point, with the
+    // same registers as distances.
+    /// The value is stored in arg 0.
+    Constant,
+    /// A coordinate of the point, transformed by the matrix in arg 0 like a shape.
+    /// The axis is stored in arg 1, from 0 for x to 2 for z, and the coordinate is
+    /// clamped to the box around the filled shape, whose half side is in arg 2.
+    Coordinate,
+    // These read the registers in arg 0 and arg 1, like combinations.
+    Sum,
+    Difference,
+    Product,
+    Quotient,
+    // These read the register in arg 0.
+    Negation,
+    Abs,
+    Sqrt,
+    Sin,
+    Cos,
+    /// The lattice of a fill, whose scale varies over space, without the thickness of
+    /// its sheet, which is subtracted from it afterwards. The matrix index is stored in
+    /// arg 0, the opcode of the fill in arg 1 and the register of the scale in arg 2.
+    /// The point is clamped to the box around the filled shape, whose half sides are
+    /// in args 3 to 5.
+    GradedSheet,
 }
 
+/// The number of registers in the shader interpreter.
+///
+/// Every register holds one distance, so this bounds how many intermediate
+/// results a tape can keep alive at once.
+pub const REGISTER_COUNT: usize = 16;
+
 pub trait InstData {
     const OP: Op;
 
@@ -37,12 +123,26 @@ pub trait InstData {
 pub struct Inst([u32; 8]);
 
 impl Inst {
+    /// The output register is stored in the high 16 bits of the first word
+    /// and the opcode in the low 16 bits.
     pub fn reg(self) -> usize {
-        (self.0[0] >> 30) as usize
+        (self.0[0] >> 16) as usize
     }
 
     pub fn op(self) -> Op {
-        unsafe { mem::transmute(self.0[0] & 0x3fffffff) }
+        unsafe { mem::transmute(self.0[0] & 0xffff) }
+    }
+
+    /// Returns the same instruction writing to another register.
+    pub fn with_reg(mut self, reg: usize) -> Self {
+        self.0[0] = (self.0[0] & 0xffff) | ((reg as u32) << 16);
+        self
+    }
+
+    /// Returns the (lhs, rhs) registers read by a combination.
+    /// Only meaningful for combinations, which all store them in arg 0 and arg 1.
+    pub fn operands(self) -> (usize, usize) {
+        (self.arg::<0>() as usize, self.arg::<1>() as usize)
     }
 
     fn arg<const N: usize>(self) -> u32
@@ -59,9 +159,9 @@ impl Inst {
 
     #[cfg(not(target_arch = "spirv"))]
     pub fn make<T: InstData>(reg: usize, data: T) -> Self {
-        assert!(reg < 2);
+        assert!(reg < REGISTER_COUNT);
         let mut b = [0; 8];
-        b[0] = (T::OP as u32) | ((reg as u32) << 30);
+        b[0] = (T::OP as u32) | ((reg as u32) << 16);
         T::to_inst(data, (&mut b[1..]).try_into().unwrap());
         Inst(b)
     }
@@ -82,13 +182,69 @@ macro_rules! declare_nonary {
 }
 
 declare_nonary!(Ret, Op::Ret);
-declare_nonary!(Union, Op::Union);
-declare_nonary!(Intersection, Op::Intersection);
-declare_nonary!(Subtraction, Op::Subtraction);
+
+macro_rules! declare_combine {
+    ($name:ident, $op:expr) => {
+        pub struct $name {
+            pub lhs: usize,
+            pub rhs: usize,
+        }
+
+        impl InstData for $name {
+            const OP: Op = $op;
+            fn from_inst(inst: Inst) -> Self {
+                Self {
+                    lhs: inst.arg::<0>() as usize,
+                    rhs: inst.arg::<1>() as usize,
+                }
+            }
+            fn to_inst(self, data: &mut [u32; 7]) {
+                data[0] = self.lhs as u32;
+                data[1] = self.rhs as u32;
+            }
+        }
+    };
+}
+
+declare_combine!(Union, Op::Union);
+declare_combine!(Intersection, Op::Intersection);
+declare_combine!(Subtraction, Op::Subtraction);
+declare_combine!(Sum, Op::Sum);
+declare_combine!(Difference, Op::Difference);
+declare_combine!(Product, Op::Product);
+declare_combine!(Quotient, Op::Quotient);
+
+macro_rules! declare_unary {
+    ($name:ident, $op:expr) => {
+        pub struct $name {
+            pub operand: usize,
+        }
+
+        impl InstData for $name {
+            const OP: Op = $op;
+            fn from_inst(inst: Inst) -> Self {
+                Self {
+                    operand: inst.arg::<0>() as usize,
+                }
+            }
+            fn to_inst(self, data: &mut [u32; 7]) {
+                data[0] = self.operand as u32;
+            }
+        }
+    };
+}
+
+declare_unary!(Negation, Op::Negation);
+declare_unary!(Abs, Op::Abs);
+declare_unary!(Sqrt, Op::Sqrt);
+declare_unary!(Sin, Op::Sin);
+declare_unary!(Cos, Op::Cos);
 
 macro_rules! declare_smooth_combine {
     ($name:ident, $op:expr) => {
         pub struct $name {
+            pub lhs: usize,
+            pub rhs: usize,
             pub k: f32,
         }
 
@@ -96,18 +252,22 @@ macro_rules! declare_smooth_combine {
             const OP: Op = $op;
             fn from_inst(inst: Inst) -> Self {
                 Self {
-                    k: f32::from_bits(inst.arg::<0>()),
+                    lhs: inst.arg::<0>() as usize,
+                    rhs: inst.arg::<1>() as usize,
+                    k: f32::from_bits(inst.arg::<2>()),
                 }
             }
             fn to_inst(self, data: &mut [u32; 7]) {
-                data[0] = self.k.to_bits();
+                data[0] = self.lhs as u32;
+                data[1] = self.rhs as u32;
+                data[2] = self.k.to_bits();
             }
         }
     };
 }
 
 declare_smooth_combine!(SmoothUnion, Op::SmoothUnion);
-declare_smooth_combine!(SmoothIntersection, Op::SmoothSubtraction);
+declare_smooth_combine!(SmoothIntersection, Op::SmoothIntersection);
 declare_smooth_combine!(SmoothSubtraction, Op::SmoothSubtraction);
 
 pub struct Sphere {
@@ -155,3 +315,199 @@ impl InstData for RectangularPrism {
         data[3] = self.z.to_bits();
     }
 }
+
+macro_rules! declare_shape {
+    ($name:ident, $op:expr, $($field:ident),+) => {
+        pub struct $name {
+            pub matrix_idx: usize,
+            $(pub $field: f32,)+
+        }
+
+        impl InstData for $name {
+            const OP: Op = $op;
+            fn from_inst(inst: Inst) -> Self {
+                // The fields follow the matrix index, starting at arg 1.
+                let mut i = 1;
+                $(
+                    i += 1;
+                    let $field = f32::from_bits(inst.0[i]);
+                )+
+                Self {
+                    matrix_idx: inst.arg::<0>() as usize,
+                    $($field,)+
+                }
+            }
+
+            fn to_inst(self, data: &mut [u32; 7]) {
+                data[0] = self.matrix_idx as u32;
+                let mut i = 0;
+                $(
+                    i += 1;
+                    data[i] = self.$field.to_bits();
+                )+
+            }
+        }
+    };
+}
+
+declare_shape!(Cylinder, Op::Cylinder, radius, half_height);
+declare_shape!(Torus, Op::Torus, major_radius, minor_radius);
+declare_shape!(Capsule, Op::Capsule, radius, half_height);
+declare_shape!(Cone, Op::Cone, bottom_radius, top_radius, half_height);
+declare_shape!(Plane, Op::Plane, normal_x, normal_y, normal_z, offset);
+declare_shape!(Ellipsoid, Op::Ellipsoid, x, y, z);
+
+macro_rules! declare_lattice {
+    ($name:ident, $op:expr, $lipschitz:expr, $amplitude:expr) => {
+        declare_shape!($name, $op, scale, thickness);
+
+        impl $name {
+            /// The largest gradient of the lattice's implicit function, rounded up.
+            /// Dividing by it keeps the function from overestimating the distance.
+            pub const LIPSCHITZ: f32 = $lipschitz;
+            /// The largest magnitude of the implicit function, rounded up, which
+            /// bounds how much a scale that varies over space changes the sheet.
+            pub const AMPLITUDE: f32 = $amplitude;
+        }
+    };
+}
+
+// √3 for the first three, 7 for Neovius, 3√3 for I-WP, 1.5√3 for the lidinoid,
+// and 4 max(sin 2x - sin x) for F-RD. The largest magnitudes are 1.5, 3, √2, 13,
+// 5, 1.35 and 7, in the same order.
+declare_lattice!(Gyroid, Op::Gyroid, 1.7321, 1.5);
+declare_lattice!(SchwarzP, Op::SchwarzP, 1.7321, 3.0);
+declare_lattice!(Diamond, Op::Diamond, 1.7321, 1.4143);
+declare_lattice!(Neovius, Op::Neovius, 7.0, 13.0);
+declare_lattice!(Iwp, Op::Iwp, 5.1962, 5.0);
+declare_lattice!(Lidinoid, Op::Lidinoid, 2.5981, 1.35);
+declare_lattice!(Frd, Op::Frd, 7.0407, 7.0);
+
+declare_shape!(Bcc, Op::Bcc, cell_size, strut_radius);
+declare_shape!(Fcc, Op::Fcc, cell_size, strut_radius);
+declare_shape!(Octet, Op::Octet, cell_size, strut_radius);
+declare_shape!(Kelvin, Op::Kelvin, cell_size, strut_radius);
+
+pub struct Voronoi {
+    pub matrix_idx: usize,
+    pub cell_size: f32,
+    pub thickness: f32,
+    pub seed: u32,
+}
+
+impl InstData for Voronoi {
+    const OP: Op = Op::Voronoi;
+    fn from_inst(inst: Inst) -> Self {
+        Self {
+            matrix_idx: inst.arg::<0>() as usize,
+            cell_size: f32::from_bits(inst.arg::<1>()),
+            thickness: f32::from_bits(inst.arg::<2>()),
+            seed: inst.arg::<3>(),
+        }
+    }
+
+    fn to_inst(self, data: &mut [u32; 7]) {
+        data[0] = self.matrix_idx as u32;
+        data[1] = self.cell_size.to_bits();
+        data[2] = self.thickness.to_bits();
+        data[3] = self.seed;
+    }
+}
+
+impl Voronoi {
+    /// Where the seed point of the cube at `cell` is, from 0 to 1 along each axis.
+    ///
+    /// Both interpreters place the points with this, so they agree on the foam.
+    pub fn jitter(cell: [i32; 3], seed: u32) -> [f32; 3] {
+        let h = hash(cell[0] as u32 ^ hash(cell[1] as u32 ^ hash(cell[2] as u32 ^ hash(seed))));
+        // The top 24 bits fit in an f32 exactly.
+        let unit = |h: u32| (hash(h) >> 8) as f32 * (1.0 / 16777216.0);
+        [unit(h), unit(h ^ 1), unit(h ^ 2)]
+    }
+}
+
+/// Chris Wellons' lowbias32, which mixes every bit of `x` into every bit of the result.
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^ (x >> 16)
+}
+
+pub struct Constant {
+    pub value: f32,
+}
+
+impl InstData for Constant {
+    const OP: Op = Op::Constant;
+    fn from_inst(inst: Inst) -> Self {
+        Self {
+            value: f32::from_bits(inst.arg::<0>()),
+        }
+    }
+
+    fn to_inst(self, data: &mut [u32; 7]) {
+        data[0] = self.value.to_bits();
+    }
+}
+
+pub struct Coordinate {
+    pub matrix_idx: usize,
+    pub axis: usize,
+    /// The coordinate is clamped to the box around the filled shape, which is this
+    /// far from the origin along the axis.
+    pub half_extent: f32,
+}
+
+impl InstData for Coordinate {
+    const OP: Op = Op::Coordinate;
+    fn from_inst(inst: Inst) -> Self {
+        Self {
+            matrix_idx: inst.arg::<0>() as usize,
+            axis: inst.arg::<1>() as usize,
+            half_extent: f32::from_bits(inst.arg::<2>()),
+        }
+    }
+
+    fn to_inst(self, data: &mut [u32; 7]) {
+        data[0] = self.matrix_idx as u32;
+        data[1] = self.axis as u32;
+        data[2] = self.half_extent.to_bits();
+    }
+}
+
+pub struct GradedSheet {
+    pub matrix_idx: usize,
+    /// One of the fills from `Op::Gyroid` to `Op::Frd`.
+    pub sheet: Op,
+    pub scale: usize,
+    /// The point is clamped to the box around the filled shape, like the coordinates
+    /// of the scale.
+    pub half_extents: [f32; 3],
+}
+
+impl InstData for GradedSheet {
+    const OP: Op = Op::GradedSheet;
+    fn from_inst(inst: Inst) -> Self {
+        Self {
+            matrix_idx: inst.arg::<0>() as usize,
+            sheet: unsafe { mem::transmute(inst.arg::<1>()) },
+            scale: inst.arg::<2>() as usize,
+            half_extents: [
+                f32::from_bits(inst.arg::<3>()),
+                f32::from_bits(inst.arg::<4>()),
+                f32::from_bits(inst.arg::<5>()),
+            ],
+        }
+    }
+
+    fn to_inst(self, data: &mut [u32; 7]) {
+        data[0] = self.matrix_idx as u32;
+        data[1] = self.sheet as u32;
+        data[2] = self.scale as u32;
+        data[3] = self.half_extents[0].to_bits();
+        data[4] = self.half_extents[1].to_bits();
+        data[5] = self.half_extents[2].to_bits();
+    }
+}
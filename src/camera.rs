@@ -49,6 +49,14 @@ impl ArcballCamera {
         }
     }
 
+    /// Points the camera at the origin from the given angles, in radians.
+    pub fn set_orientation(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = 0.0;
+        self.pitch = 0.0;
+        self.add_yaw(yaw);
+        self.add_pitch(pitch);
+    }
+
     fn add_yaw(&mut self, dyaw: f32) {
         self.yaw = (self.yaw + dyaw) % TAU;
     }
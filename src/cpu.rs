@@ -0,0 +1,246 @@
+//! A software renderer that mirrors `render_sdf_final` in the shader,
+//! for machines without a usable adapter.
+//!
+//! It doesn't classify tiles or prune the tape, so every ray is marched
+//! from the eye with the whole tape.
+
+use rayon::prelude::*;
+use ultraviolet::{f32x8, Mat4, Vec3, Vec3x8, Vec4};
+use wide::{CmpGt, CmpLt};
+use winit::dpi::PhysicalSize;
+
+use crate::{camera::Camera, headless::Image, tree::Tape};
+
+/// The same constants as the shader.
+const EPSILON: f32 = 0.001;
+const MAX_STEPS: usize = 64;
+const MAX_DISTANCE: f32 = 100.0;
+
+const TILE_SIZE: u32 = 8;
+
+struct RenderParams {
+    view_mat: Mat4,
+    eye: Vec3,
+    light: Vec3,
+    resolution: PhysicalSize<u32>,
+    neg_z_depth: f32,
+}
+
+/// Renders `tape` into an image the way the GPU renderer would,
+/// with every 8x8 tile rendered on the thread pool.
+pub fn render(
+    tape: &Tape,
+    camera: &dyn Camera,
+    light: Vec3,
+    field_of_view: f32,
+    size: PhysicalSize<u32>,
+) -> Image {
+    let field_of_view = field_of_view.to_radians();
+    let params = RenderParams {
+        view_mat: camera.matrix().transposed(),
+        eye: camera.eye(),
+        light,
+        resolution: size,
+        neg_z_depth: -(size.width as f32 / (field_of_view / 2.0).tan()),
+    };
+
+    let grid_width = (size.width + TILE_SIZE - 1) / TILE_SIZE;
+    let grid_height = (size.height + TILE_SIZE - 1) / TILE_SIZE;
+
+    let tiles: Vec<_> = (0..grid_width * grid_height)
+        .into_par_iter()
+        .map(|idx| render_tile(tape, &params, idx % grid_width, idx / grid_width))
+        .collect();
+
+    let mut rgba = vec![0; size.width as usize * size.height as usize * 4];
+    for (idx, tile) in tiles.iter().enumerate() {
+        let tile_x = (idx as u32 % grid_width) * TILE_SIZE;
+        let tile_y = (idx as u32 / grid_width) * TILE_SIZE;
+        let width = TILE_SIZE.min(size.width - tile_x) as usize;
+
+        for (y, row) in tile.chunks(TILE_SIZE as usize * 4).enumerate() {
+            let y = tile_y as usize + y;
+            if y >= size.height as usize {
+                break;
+            }
+            let start = (y * size.width as usize + tile_x as usize) * 4;
+            rgba[start..start + width * 4].copy_from_slice(&row[..width * 4]);
+        }
+    }
+
+    Image { size, rgba }
+}
+
+/// Renders the rows of a tile as batches of eight rays.
+fn render_tile(
+    tape: &Tape,
+    params: &RenderParams,
+    tile_x: u32,
+    tile_y: u32,
+) -> [u8; (TILE_SIZE * TILE_SIZE * 4) as usize] {
+    let mut pixels = [0; (TILE_SIZE * TILE_SIZE * 4) as usize];
+
+    for y in 0..TILE_SIZE {
+        let mut dirs = [Vec3::zero(); 8];
+        for (x, dir) in dirs.iter_mut().enumerate() {
+            let coords = (tile_x * TILE_SIZE + x as u32, tile_y * TILE_SIZE + y);
+            *dir = compute_ray_direction(params, coords);
+        }
+
+        let (hits, depth_ratios) = sphere_march(tape, params.eye, Vec3x8::from(dirs));
+        let hits: [Vec3; 8] = hits.into();
+        let depth_ratios: [f32; 8] = depth_ratios.into();
+
+        for x in 0..8 {
+            let color = shade(tape, params, hits[x], depth_ratios[x]);
+            let idx = (y * TILE_SIZE) as usize * 4 + x * 4;
+            pixels[idx..idx + 4].copy_from_slice(&to_srgb_bytes(color));
+        }
+    }
+
+    pixels
+}
+
+fn compute_ray_direction(params: &RenderParams, texture_coords: (u32, u32)) -> Vec3 {
+    let x = texture_coords.0 as f32 - params.resolution.width as f32 / 2.0;
+    let y = texture_coords.1 as f32 - params.resolution.height as f32 / 2.0;
+    let dir = Vec3::new(x, y, params.neg_z_depth).normalized();
+    (params.view_mat * Vec4::new(dir.x, dir.y, dir.z, 0.0)).truncated()
+}
+
+/// Marches eight rays at once, and returns where they hit and their depth ratios,
+/// which are negative for rays that missed.
+///
+/// Rays that are done stop moving, so every lane matches a scalar march.
+fn sphere_march(tape: &Tape, origin: Vec3, ray_dir: Vec3x8) -> (Vec3x8, f32x8) {
+    let origin = Vec3x8::splat(origin);
+    let mut t = f32x8::splat(0.0);
+    let mut depth_ratio = f32x8::splat(-1.0);
+    let mut done = f32x8::splat(0.0);
+
+    for i in 0..MAX_STEPS {
+        done = done | t.cmp_gt(f32x8::splat(MAX_DISTANCE));
+        if done.all() {
+            break;
+        }
+
+        let r = tape.eval(origin + ray_dir * t);
+
+        let hit = r.cmp_lt(t * EPSILON) & !done;
+        let ratio = f32x8::splat(i as f32 / (MAX_STEPS - 1) as f32);
+        depth_ratio = hit.blend(ratio, depth_ratio);
+        done = done | hit;
+
+        t = done.blend(t, t + r);
+    }
+
+    (origin + ray_dir * t, depth_ratio)
+}
+
+/// The normal is the direction of the gradient, as in the shader.
+fn normal(tape: &Tape, p: Vec3) -> Vec3 {
+    tape.eval_deriv(p).gradient.normalized()
+}
+
+fn shade(tape: &Tape, params: &RenderParams, hit: Vec3, depth_ratio: f32) -> Vec3 {
+    if depth_ratio >= 0.0 {
+        let color = Vec3::new(171.0 / 255.0, 146.0 / 255.0, 103.0 / 255.0);
+        let shade = Vec3::new(99.0 / 255.0, 84.0 / 255.0, 59.0 / 255.0);
+        let ao = 1.0 - depth_ratio;
+
+        let normals = normal(tape, hit) * 0.5 + Vec3::broadcast(0.5);
+        let dif = normals.dot(params.light.normalized());
+        let color = lerp(color, shade, dif) * ao;
+
+        lerp(color, Vec3::new(1.0, 0.0, 0.0), depth_ratio)
+    } else {
+        // Background color
+        Vec3::new(140.0 / 255.0, 156.0 / 255.0, 161.0 / 255.0)
+    }
+}
+
+fn lerp(from: Vec3, to: Vec3, s: f32) -> Vec3 {
+    from + (to - from) * s
+}
+
+/// The GPU writes colors to a linear texture and the swap chain encodes them as sRGB,
+/// so this does the same to look identical.
+fn to_srgb_bytes(color: Vec3) -> [u8; 4] {
+    let encode = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        let c = if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        };
+        (c * 255.0).round() as u8
+    };
+
+    [encode(color.x), encode(color.y), encode(color.z), 255]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::CsgTree;
+
+    /// A scalar version of the shader's `sphere_march`, starting at the eye.
+    fn scalar_march(tape: &Tape, origin: Vec3, ray_dir: Vec3) -> f32 {
+        let mut t = 0.0;
+        for i in 0..MAX_STEPS {
+            if t > MAX_DISTANCE {
+                break;
+            }
+            let r: [f32; 8] = tape.eval(Vec3x8::splat(origin + ray_dir * t)).into();
+            if r[0] < EPSILON * t {
+                return i as f32 / (MAX_STEPS - 1) as f32;
+            }
+            t += r[0];
+        }
+        -1.0
+    }
+
+    #[test]
+    fn batched_march_matches_scalar() {
+        let tape = CsgTree::new_example().compile().unwrap();
+        let eye = Vec3::new(0.0, 2.0, 6.0);
+        let params = RenderParams {
+            view_mat: Mat4::look_at(eye, Vec3::zero(), Vec3::unit_y()).transposed(),
+            eye,
+            light: Vec3::new(10.0, 30.0, 30.0),
+            resolution: PhysicalSize::new(64, 48),
+            neg_z_depth: -(64.0 / (45f32.to_radians() / 2.0).tan()),
+        };
+
+        let mut hits = 0;
+        for y in 0..48 {
+            for x in (0..64).step_by(8) {
+                let mut dirs = [Vec3::zero(); 8];
+                for (i, dir) in dirs.iter_mut().enumerate() {
+                    *dir = compute_ray_direction(&params, (x + i as u32, y));
+                }
+
+                let (_, depth_ratios) = sphere_march(&tape, eye, Vec3x8::from(dirs));
+                let depth_ratios: [f32; 8] = depth_ratios.into();
+                for (dir, depth_ratio) in dirs.iter().zip(&depth_ratios) {
+                    assert_eq!(*depth_ratio, scalar_march(&tape, eye, *dir));
+                    if *depth_ratio >= 0.0 {
+                        hits += 1;
+                    }
+                }
+            }
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn normals_point_outwards() {
+        let tape = CsgTree::new_example().compile().unwrap();
+
+        // On the faces of the box, away from where the sphere blends into them.
+        let n = normal(&tape, Vec3::new(0.8, 1.0, 0.8));
+        assert!((n - Vec3::unit_y()).mag() < 1e-3);
+        let n = normal(&tape, Vec3::new(-1.0, 0.8, -0.7));
+        assert!((n + Vec3::unit_x()).mag() < 1e-3);
+    }
+}
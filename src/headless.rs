@@ -0,0 +1,167 @@
+//! Rendering into an offscreen texture instead of a window,
+//! e.g. for batch jobs and golden-image tests.
+
+use std::{error::Error, fmt, fs::File, io, num::NonZeroU32, path::Path};
+use ultraviolet::Vec3;
+use winit::dpi::PhysicalSize;
+
+use crate::{camera::Camera, sdf, tree::Tape};
+
+/// The format of the offscreen texture.
+///
+/// It's an sRGB format like the swap chain usually is,
+/// so images look the same as they do in the window.
+const OUTPUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    /// There is no adapter, or no software adapter if one was asked for.
+    NoAdapter,
+    RequestDevice(wgpu::RequestDeviceError),
+    /// The rendered image couldn't be read back.
+    ReadBack(wgpu::BufferAsyncError),
+    Encode(png::EncodingError),
+    Io(io::Error),
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadlessError::NoAdapter => write!(f, "failed to find an appropriate adapter"),
+            HeadlessError::RequestDevice(e) => write!(f, "failed to create device: {}", e),
+            HeadlessError::ReadBack(e) => write!(f, "failed to read back the image: {}", e),
+            HeadlessError::Encode(e) => write!(f, "failed to encode the image: {}", e),
+            HeadlessError::Io(e) => write!(f, "failed to write the image: {}", e),
+        }
+    }
+}
+
+impl Error for HeadlessError {}
+
+/// Returns an adapter that doesn't need a surface to render to.
+///
+/// When `software` is set, only adapters running on the CPU are considered,
+/// which works without a display or a discrete GPU.
+pub async fn request_adapter(
+    instance: &wgpu::Instance,
+    software: bool,
+) -> Result<wgpu::Adapter, HeadlessError> {
+    let adapter = if software {
+        instance
+            .enumerate_adapters(wgpu::BackendBit::all())
+            .find(|adapter| adapter.get_info().device_type == wgpu::DeviceType::Cpu)
+    } else {
+        instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+            })
+            .await
+    };
+
+    adapter.ok_or(HeadlessError::NoAdapter)
+}
+
+/// An image read back from the GPU, with four bytes per pixel.
+pub struct Image {
+    pub size: PhysicalSize<u32>,
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    pub fn save_png(&self, path: &Path) -> Result<(), HeadlessError> {
+        let file = File::create(path).map_err(HeadlessError::Io)?;
+        let mut encoder =
+            png::Encoder::new(io::BufWriter::new(file), self.size.width, self.size.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.rgba))
+            .map_err(HeadlessError::Encode)
+    }
+}
+
+/// Renders `tape` once at `size` and reads the image back.
+pub async fn render(
+    adapter: &wgpu::Adapter,
+    tape: &Tape,
+    camera: &dyn Camera,
+    light: Vec3,
+    field_of_view: f32,
+    size: PhysicalSize<u32>,
+) -> Result<Image, HeadlessError> {
+    let (device, queue) = sdf::request_device(adapter)
+        .await
+        .map_err(HeadlessError::RequestDevice)?;
+
+    let sdf_renderer = sdf::SDFRender::new(&device, size, OUTPUT_FORMAT, tape);
+
+    let extent = wgpu::Extent3d {
+        width: size.width,
+        height: size.height,
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: OUTPUT_FORMAT,
+        usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    // Rows of a texture copy have to be aligned, so the buffer can be wider than the image.
+    let unpadded_bytes_per_row = size.width as usize * 4;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize;
+    let bytes_per_row = (unpadded_bytes_per_row + align - 1) / align * align;
+
+    let output = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (bytes_per_row * size.height as usize) as u64,
+        usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder =
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+    sdf_renderer.render(camera, light, field_of_view, &view, &mut encoder);
+
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &output,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(bytes_per_row as u32),
+                rows_per_image: None,
+            },
+        },
+        extent,
+    );
+
+    queue.submit(Some(encoder.finish()));
+
+    let slice = output.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    mapping.await.map_err(HeadlessError::ReadBack)?;
+
+    let rgba = slice
+        .get_mapped_range()
+        .chunks(bytes_per_row)
+        .flat_map(|row| &row[..unpadded_bytes_per_row])
+        .copied()
+        .collect();
+    output.unmap();
+
+    Ok(Image { size, rgba })
+}
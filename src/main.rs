@@ -1,18 +1,291 @@
 use camera::{ArcballCamera, Camera};
+use mesh::Grid;
+use std::{
+    env,
+    fs::File,
+    io::BufWriter,
+    mem,
+    path::{Path, PathBuf},
+    process,
+};
 use tree::CsgTree;
 use ultraviolet::Vec3;
+use watch::SceneWatcher;
 use winit::{
-    event::{Event, WindowEvent},
+    dpi::PhysicalSize,
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::Window,
 };
 
 mod camera;
+mod cpu;
+mod headless;
+mod mesh;
 // mod op;
 mod sdf;
 mod tree;
+mod watch;
+
+const USAGE: &str = "\
+usage: sdf [options] [scene]
+
+Shows the scene in a RON scene file, or an example scene without one.
+The scene is reloaded whenever the file changes.
+
+options:
+    --headless <file>    render one frame to a PNG file instead of opening a window
+    --size <w>x<h>       the size of the headless frame, 1280x720 by default
+    --software           render the headless frame with a software adapter
+    --cpu                render on the CPU instead of with the adapter
+    --mesh <file>        write a mesh of the model instead of opening a window, as an
+                         STL, OBJ, PLY or 3MF file depending on its extension
+    --bounds <x,y,z,x,y,z>
+                         the lower and upper corners of the meshed region, -4 to 4 by default
+    --resolution <cells> the number of cells along the longest side of the bounds, from 1 to
+                         1024, 128 by default
+    --ascii              write an ASCII STL file instead of a binary one
+    --dual-contouring    mesh with an adaptive octree that keeps sharp edges and corners,
+                         with the resolution rounded up to a power of two, up to 256
+    --yaw <degrees>      the starting camera yaw
+    --pitch <degrees>    the starting camera pitch
+    --distance <units>   the starting camera distance from the origin";
+
+const FIELD_OF_VIEW: f32 = 45.0;
+/// The most cells along a side of a mesh. Marching cubes samples every point of
+/// the grid up front, and this keeps those samples within a few gigabytes.
+const MAX_RESOLUTION: usize = 1024;
+const LIGHT: [f32; 3] = [10.0, 30.0, 30.0];
+/// The color of the model in the renderers, for mesh formats with vertex colors.
+const MODEL_COLOR: [u8; 3] = [171, 146, 103];
+
+struct Options {
+    /// The scene file to show, if one was given.
+    scene: Option<PathBuf>,
+    /// Where to save a headless render, if one was asked for.
+    headless: Option<PathBuf>,
+    size: PhysicalSize<u32>,
+    software: bool,
+    /// Render with the CPU renderer, even if there is an adapter.
+    cpu: bool,
+    /// Where to save a mesh of the model, if one was asked for.
+    mesh: Option<PathBuf>,
+    bounds: (Vec3, Vec3),
+    resolution: usize,
+    ascii: bool,
+    dual_contouring: bool,
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            scene: None,
+            headless: None,
+            size: PhysicalSize::new(1280, 720),
+            software: false,
+            cpu: false,
+            mesh: None,
+            bounds: (Vec3::broadcast(-4.0), Vec3::broadcast(4.0)),
+            resolution: 128,
+            ascii: false,
+            dual_contouring: false,
+            yaw: 0.0,
+            pitch: 0.0,
+            distance: 10.0,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            let number = |value: String| {
+                value
+                    .parse::<f32>()
+                    .map_err(|_| format!("{} is not a number: {}", arg, value))
+            };
+
+            match arg.as_str() {
+                "--headless" => options.headless = Some(value()?.into()),
+                "--size" => {
+                    let value = value()?;
+                    let size = value
+                        .split_once('x')
+                        .and_then(|(w, h)| {
+                            Some(PhysicalSize::<u32>::new(w.parse().ok()?, h.parse().ok()?))
+                        })
+                        .filter(|size| size.width > 0 && size.height > 0);
+                    options.size = size.ok_or_else(|| format!("invalid size: {}", value))?;
+                }
+                "--software" => options.software = true,
+                "--cpu" => options.cpu = true,
+                "--mesh" => options.mesh = Some(value()?.into()),
+                "--bounds" => {
+                    let value = value()?;
+                    let b = value
+                        .split(',')
+                        .map(|c| c.trim().parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .ok()
+                        .filter(|b| b.len() == 6 && b[0] < b[3] && b[1] < b[4] && b[2] < b[5])
+                        .ok_or_else(|| format!("invalid bounds: {}", value))?;
+                    options.bounds = (Vec3::new(b[0], b[1], b[2]), Vec3::new(b[3], b[4], b[5]));
+                }
+                "--resolution" => {
+                    let value = value()?;
+                    options.resolution = value
+                        .parse()
+                        .ok()
+                        .filter(|&r| r > 0 && r <= MAX_RESOLUTION)
+                        .ok_or_else(|| format!("invalid resolution: {}", value))?;
+                }
+                "--ascii" => options.ascii = true,
+                "--dual-contouring" => options.dual_contouring = true,
+                "--yaw" => options.yaw = number(value()?)?.to_radians(),
+                "--pitch" => options.pitch = number(value()?)?.to_radians(),
+                "--distance" => options.distance = number(value()?)?,
+                _ if !arg.starts_with('-') && options.scene.is_none() => {
+                    options.scene = Some(arg.into())
+                }
+                _ => return Err(format!("unknown argument: {}", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn tree(&self) -> CsgTree {
+        match &self.scene {
+            Some(path) => CsgTree::load(path)
+                .unwrap_or_else(|e| exit_with_error(format!("{}: {}", path.display(), e))),
+            None => CsgTree::new_example(),
+        }
+    }
+
+    fn camera(&self) -> ArcballCamera {
+        let mut camera = ArcballCamera::new(self.distance, 0.3);
+        camera.set_orientation(self.yaw, self.pitch);
+        camera
+    }
+}
+
+async fn render_headless(options: &Options, path: PathBuf) {
+    let csg = options.tree();
+    let tape = csg.compile().unwrap_or_else(|e| exit_with_error(e));
+
+    let mut camera = options.camera();
+    camera.resize(options.size, FIELD_OF_VIEW, 0.1);
+
+    let instance = wgpu::Instance::new(wgpu::BackendBit::all());
+    let adapter = if options.cpu {
+        None
+    } else {
+        match headless::request_adapter(&instance, options.software).await {
+            Ok(adapter) => Some(adapter),
+            Err(e) => {
+                eprintln!("{}, falling back to the CPU renderer", e);
+                None
+            }
+        }
+    };
+
+    let image = if let Some(adapter) = adapter {
+        headless::render(
+            &adapter,
+            &tape,
+            &camera,
+            LIGHT.into(),
+            FIELD_OF_VIEW,
+            options.size,
+        )
+        .await
+        .unwrap_or_else(|e| exit_with_error(e))
+    } else {
+        cpu::render(&tape, &camera, LIGHT.into(), FIELD_OF_VIEW, options.size)
+    };
+
+    image.save_png(&path).unwrap_or_else(|e| exit_with_error(e));
+}
+
+enum MeshFormat {
+    Stl,
+    Obj,
+    Ply,
+    ThreeMf,
+}
+
+impl MeshFormat {
+    fn from_path(path: &Path) -> Result<Self, String> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
 
-async fn run(event_loop: EventLoop<()>, window: Window) -> ! {
+        match extension.as_deref() {
+            Some("stl") => Ok(MeshFormat::Stl),
+            Some("obj") => Ok(MeshFormat::Obj),
+            Some("ply") => Ok(MeshFormat::Ply),
+            Some("3mf") => Ok(MeshFormat::ThreeMf),
+            _ => Err(format!(
+                "unknown mesh format, expected .stl, .obj, .ply or .3mf: {}",
+                path.display()
+            )),
+        }
+    }
+}
+
+fn export_mesh(options: &Options, path: PathBuf) {
+    let format = MeshFormat::from_path(&path).unwrap_or_else(|e| exit_with_error(e));
+    let csg = options.tree();
+    let tape = csg.compile().unwrap_or_else(|e| exit_with_error(e));
+
+    let (min, max) = options.bounds;
+    let mesh = if options.dual_contouring {
+        let depth = options.resolution.next_power_of_two().trailing_zeros();
+        if depth > mesh::MAX_OCTREE_DEPTH {
+            exit_with_error(format!(
+                "the resolution for dual contouring is at most {}",
+                1 << mesh::MAX_OCTREE_DEPTH
+            ));
+        }
+        mesh::dual_contouring(&tape, min, max, depth)
+    } else {
+        let grid = Grid::with_max_resolution(min, max, options.resolution);
+        mesh::marching_cubes(&grid, &grid.sample(&tape))
+    };
+
+    let file = File::create(&path).unwrap_or_else(|e| exit_with_error(e));
+    let result = match format {
+        MeshFormat::Stl if options.ascii => {
+            mesh::write_ascii_stl(&mesh, "sdf", BufWriter::new(file))
+        }
+        MeshFormat::Stl => mesh::write_binary_stl(&mesh, BufWriter::new(file)),
+        MeshFormat::Obj => {
+            let normals = mesh.vertex_normals(&tape);
+            mesh::write_obj(&mesh, &normals, "sdf", BufWriter::new(file))
+        }
+        MeshFormat::Ply => {
+            let normals = mesh.vertex_normals(&tape);
+            let colors = vec![MODEL_COLOR; mesh.vertices.len()];
+            mesh::write_ply(&mesh, &normals, &colors, BufWriter::new(file))
+        }
+        MeshFormat::ThreeMf => mesh::write_3mf(&[("sdf", &mesh)], BufWriter::new(file)),
+    };
+    result.unwrap_or_else(|e| exit_with_error(e));
+
+    println!(
+        "wrote {} triangles to {}",
+        mesh.triangles.len(),
+        path.display()
+    );
+}
+
+fn exit_with_error(e: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", e);
+    process::exit(1)
+}
+
+async fn run(event_loop: EventLoop<()>, window: Window, options: Options) -> ! {
     let instance = wgpu::Instance::new(wgpu::BackendBit::all());
     let surface = unsafe { instance.create_surface(&window) };
     let adapter = instance
@@ -22,28 +295,27 @@ async fn run(event_loop: EventLoop<()>, window: Window) -> ! {
             compatible_surface: Some(&surface),
         })
         .await
-        .expect("Failed to find an appropriate adapter");
+        .unwrap_or_else(|| {
+            exit_with_error(
+                "failed to find an appropriate adapter, \
+                 try rendering with `--headless <file> --cpu` instead",
+            )
+        });
 
     // Create the logical device and command queue
-    let (device, queue) = adapter
-        .request_device(
-            &wgpu::DeviceDescriptor {
-                label: None,
-                features: wgpu::Features::PUSH_CONSTANTS,
-                limits: wgpu::Limits {
-                    max_push_constant_size: 128,
-                    ..Default::default()
-                },
-            },
-            None,
-        )
+    let (device, queue) = sdf::request_device(&adapter)
         .await
         .expect("Failed to create device");
 
     let swapchain_format = adapter.get_swap_chain_preferred_format(&surface).unwrap();
     let initial_size = window.inner_size();
 
-    let mut sdf_renderer = sdf::SDFRender::new(&device, initial_size, swapchain_format);
+    let mut watcher = options.scene.clone().map(SceneWatcher::new);
+    let csg = options.tree();
+    print!("{}", csg);
+    let mut tape = csg.compile().unwrap_or_else(|e| exit_with_error(e));
+
+    let mut sdf_renderer = sdf::SDFRender::new(&device, initial_size, swapchain_format, &tape);
 
     let mut sc_desc = wgpu::SwapChainDescriptor {
         usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
@@ -55,14 +327,18 @@ async fn run(event_loop: EventLoop<()>, window: Window) -> ! {
 
     let mut swap_chain = device.create_swap_chain(&surface, &sc_desc);
 
-    let mut camera = ArcballCamera::new(10.0, 0.3);
-    let light = Vec3::new(10.0, 30.0, 30.0);
-    let fov = 45.0;
+    let mut camera = options.camera();
+    let light = Vec3::from(LIGHT);
+    let fov = FIELD_OF_VIEW;
 
     camera.resize(initial_size, fov, 0.1);
 
-    let csg = CsgTree::new_example();
-    print!("{}", csg);
+    let mut size = initial_size;
+    let cpu = options.cpu;
+    // Whether to count the steps of the next frame, after toggling cone tracing.
+    let mut report_steps = false;
+    // The step counts being read back, and whether cone tracing was on for them.
+    let mut step_counts: Vec<(bool, sdf::StepCount)> = Vec::new();
 
     event_loop.run(move |event, _, control_flow| {
         // Have the closure take ownership of the resources.
@@ -86,8 +362,33 @@ async fn run(event_loop: EventLoop<()>, window: Window) -> ! {
 
                 camera.resize(new_size, fov, 0.1);
                 sdf_renderer.resize(&device, new_size);
+                size = new_size;
             }
             Event::MainEventsCleared => {
+                // Keep showing the last scene that compiled if the new one has errors.
+                match watcher.as_mut().and_then(|watcher| watcher.poll()) {
+                    Some(Ok((csg, new_tape))) => {
+                        print!("{}", csg);
+                        sdf_renderer.set_tape(&device, &queue, &new_tape);
+                        tape = new_tape;
+                    }
+                    Some(Err(e)) => eprintln!(
+                        "error: {}: {}",
+                        watcher.as_ref().unwrap().path().display(),
+                        e
+                    ),
+                    None => {}
+                }
+
+                for (cone_tracing, mut count) in mem::take(&mut step_counts) {
+                    let on_off = if cone_tracing { "on" } else { "off" };
+                    match count.poll(&device) {
+                        Some(Ok(steps)) => println!("cone tracing {}: {} steps", on_off, steps),
+                        Some(Err(e)) => eprintln!("error: failed to count steps: {}", e),
+                        None => step_counts.push((cone_tracing, count)),
+                    }
+                }
+
                 window.request_redraw();
             }
             Event::RedrawRequested(_) => {
@@ -98,9 +399,42 @@ async fn run(event_loop: EventLoop<()>, window: Window) -> ! {
                 let mut encoder =
                     device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-                sdf_renderer.render(&camera, light, fov, &frame.view, &mut encoder);
+                if cpu {
+                    let image = cpu::render(&tape, &camera, light, fov, size);
+                    sdf_renderer.blit_image(&queue, &image, &frame.view, &mut encoder);
+                } else {
+                    sdf_renderer.render(&camera, light, fov, &frame.view, &mut encoder);
+                }
 
                 queue.submit(Some(encoder.finish()));
+
+                if report_steps {
+                    report_steps = false;
+                    let count = sdf_renderer.count_steps(&device, &queue);
+                    step_counts.push((sdf_renderer.cone_tracing(), count));
+                }
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::C),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                // Toggle the cone tracing prepass, and print the step counts
+                // of the frames before and after to compare them.
+                if !cpu {
+                    let count = sdf_renderer.count_steps(&device, &queue);
+                    step_counts.push((sdf_renderer.cone_tracing(), count));
+                    report_steps = true;
+                }
+                sdf_renderer.set_cone_tracing(!sdf_renderer.cone_tracing());
             }
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
@@ -112,9 +446,23 @@ async fn run(event_loop: EventLoop<()>, window: Window) -> ! {
 }
 
 fn main() {
+    let mut options = Options::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(2)
+    });
+
+    wgpu_subscriber::initialize_default_subscriber(None);
+
+    if let Some(path) = options.mesh.take() {
+        return export_mesh(&options, path);
+    }
+
+    if let Some(path) = options.headless.take() {
+        return pollster::block_on(render_headless(&options, path));
+    }
+
     let event_loop = EventLoop::new();
     let window = Window::new(&event_loop).unwrap();
 
-    wgpu_subscriber::initialize_default_subscriber(None);
-    pollster::block_on(run(event_loop, window))
+    pollster::block_on(run(event_loop, window, options))
 }
@@ -0,0 +1,677 @@
+//! Adaptive dual contouring, which keeps the sharp edges and corners
+//! that marching cubes rounds off.
+//!
+//! The bounds are subdivided as an octree, and cells that interval arithmetic
+//! proves to be entirely outside or inside the model aren't subdivided any further.
+//! Every leaf that the surface crosses gets one vertex, placed where the planes
+//! through its edge crossings, with normals from the gradient, best meet.
+//! Leaves are merged while one vertex still fits their planes, which leaves
+//! large cells in flat regions, and a quad is then made around every edge that
+//! crosses the surface from the vertices of the leaves around it.
+//!
+//! See "Dual Contouring of Hermite Data" by Ju, Losasso, Schaefer and Warren.
+
+use rayon::prelude::*;
+use ultraviolet::{Vec3, Vec3x8};
+
+use super::{marching_cubes::cell_loops, qef::Qef, Mesh};
+use crate::tree::Tape;
+
+/// The deepest octree that is built. Cells are only split where the surface
+/// may pass through them, but the surface of a fill passes through nearly
+/// every cell, and `8^8` leaves already take a couple of gigabytes.
+pub const MAX_OCTREE_DEPTH: u32 = 8;
+
+/// How far a merged vertex may be from its planes on average,
+/// relative to the size of the smallest cells.
+const TOLERANCE: f32 = 0.1;
+
+/// The number of times each crossing is narrowed down, by a factor of nine each time.
+const CROSSING_STEPS: usize = 3;
+
+/// The edges of a cell as pairs of corners, where corner `i`
+/// is at `(i & 1, (i >> 1) & 1, (i >> 2) & 1)`.
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// The four cells around an edge, in half cell steps along the other two axes,
+/// counter-clockwise when looking down the edge.
+const QUADRANTS: [(i64, i64); 4] = [(-1, -1), (1, -1), (1, 1), (-1, 1)];
+
+enum Node {
+    /// Entirely outside the model.
+    Empty,
+    /// Entirely inside the model.
+    Full,
+    Leaf(Leaf),
+    /// Eight children, ordered like the corners.
+    Branch(Vec<Node>),
+}
+
+struct Leaf {
+    /// A bit for each corner that is inside the model.
+    inside: u8,
+    qef: Qef,
+    vertex: Vec3,
+    /// The index of `vertex` in the mesh.
+    index: u32,
+}
+
+fn offset(corner: usize, size: u32) -> [u32; 3] {
+    [
+        (corner as u32 & 1) * size,
+        ((corner as u32 >> 1) & 1) * size,
+        (corner as u32 >> 2) * size,
+    ]
+}
+
+fn add(a: [u32; 3], b: [u32; 3]) -> [u32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn is_manifold(values: &[f32; 8]) -> bool {
+    let mut loops = 0;
+    cell_loops(values, |_| loops += 1);
+    loops == 1
+}
+
+/// Cells are addressed by lattice coordinates,
+/// where the smallest cells are one unit across.
+struct Octree<'a> {
+    tape: &'a Tape,
+    min: Vec3,
+    /// The upper corner of the bounds, which may be inside the root
+    /// cell since every cell is a cube.
+    max: Vec3,
+    cell_size: f32,
+    /// The size of the root cell.
+    size: u32,
+}
+
+impl<'a> Octree<'a> {
+    fn point(&self, p: [u32; 3]) -> Vec3 {
+        self.min + Vec3::new(p[0] as f32, p[1] as f32, p[2] as f32) * self.cell_size
+    }
+
+    fn is_on_or_beyond_bounds(&self, p: Vec3) -> bool {
+        p.x <= self.min.x
+            || p.y <= self.min.y
+            || p.z <= self.min.z
+            || p.x >= self.max.x
+            || p.y >= self.max.y
+            || p.z >= self.max.z
+    }
+
+    /// Points on or beyond the bounds are treated as outside,
+    /// so the mesh is closed even where the model is cut by them.
+    fn clip(&self, p: Vec3, value: f32) -> f32 {
+        if self.is_on_or_beyond_bounds(p) {
+            value.max(f32::EPSILON)
+        } else {
+            value
+        }
+    }
+
+    fn eval(&self, points: &[Vec3; 8]) -> [f32; 8] {
+        self.tape.eval(Vec3x8::from(*points)).into()
+    }
+
+    fn build(&self, origin: [u32; 3], size: u32) -> Node {
+        let (lo, hi) = (self.point(origin), self.point(add(origin, [size; 3])));
+        if lo.x >= self.max.x || lo.y >= self.max.y || lo.z >= self.max.z {
+            return Node::Empty;
+        }
+
+        let interval = self.tape.eval_interval(lo, hi);
+        if interval.low > 0.0 {
+            return Node::Empty;
+        }
+        let within_bounds = !self.is_on_or_beyond_bounds(lo) && !self.is_on_or_beyond_bounds(hi);
+        if interval.high < 0.0 && within_bounds {
+            return Node::Full;
+        }
+
+        if size == 1 {
+            return self.leaf(origin);
+        }
+
+        let half = size / 2;
+        let children = (0..8)
+            .into_par_iter()
+            .map(|i| self.build(add(origin, offset(i, half)), half))
+            .collect();
+        self.simplify(origin, size, children)
+    }
+
+    /// Makes a leaf of one of the smallest cells.
+    fn leaf(&self, origin: [u32; 3]) -> Node {
+        let mut corners = [Vec3::zero(); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            *corner = self.point(add(origin, offset(i, 1)));
+        }
+        let raw = self.eval(&corners);
+
+        let mut inside = 0;
+        for i in 0..8 {
+            if self.clip(corners[i], raw[i]) < 0.0 {
+                inside |= 1 << i;
+            }
+        }
+        match inside {
+            0 => return Node::Empty,
+            0xff => return Node::Full,
+            _ => {}
+        }
+
+        let mut qef = Qef::default();
+        for &(a, b) in &EDGES {
+            if (inside >> a & 1) != (inside >> b & 1) {
+                let (point, normal) = self.crossing((corners[a], raw[a]), (corners[b], raw[b]));
+                qef.add(point, normal);
+            }
+        }
+
+        let vertex = self.place(&qef, origin, 1);
+        Node::Leaf(Leaf {
+            inside,
+            qef,
+            vertex,
+            index: 0,
+        })
+    }
+
+    /// Finds where the surface crosses between two points with different signs,
+    /// given their unclipped values, and returns it with the surface normal.
+    fn crossing(&self, mut a: (Vec3, f32), mut b: (Vec3, f32)) -> (Vec3, Vec3) {
+        let a_inside = self.clip(a.0, a.1) < 0.0;
+
+        for _ in 0..CROSSING_STEPS {
+            let mut points = [Vec3::zero(); 8];
+            for (k, p) in points.iter_mut().enumerate() {
+                *p = a.0 + (b.0 - a.0) * ((k + 1) as f32 / 9.0);
+            }
+            let values = self.eval(&points);
+
+            for k in 0..8 {
+                let sample = (points[k], values[k]);
+                if (self.clip(sample.0, sample.1) < 0.0) == a_inside {
+                    a = sample;
+                } else {
+                    b = sample;
+                    break;
+                }
+            }
+        }
+
+        let (va, vb) = (self.clip(a.0, a.1), self.clip(b.0, b.1));
+        let t = (va / (va - vb)).clamp(0.0, 1.0);
+        let point = a.0 + (b.0 - a.0) * t;
+
+        // An outside point that is only outside because of the bounds
+        // means that the surface there is the bounds.
+        let outside = if a_inside { b } else { a };
+        if outside.1 < 0.0 {
+            return (point, self.bounds_normal(point));
+        }
+
+        let gradient = self.tape.eval_deriv(point).gradient;
+        let normal = if gradient.mag_sq() > 0.0 {
+            gradient.normalized()
+        } else if a_inside {
+            (b.0 - a.0).normalized()
+        } else {
+            (a.0 - b.0).normalized()
+        };
+        (point, normal)
+    }
+
+    /// Returns the outward normal of the side of the bounds closest to `p`.
+    fn bounds_normal(&self, p: Vec3) -> Vec3 {
+        let sides = [
+            (p.x - self.min.x, -Vec3::unit_x()),
+            (self.max.x - p.x, Vec3::unit_x()),
+            (p.y - self.min.y, -Vec3::unit_y()),
+            (self.max.y - p.y, Vec3::unit_y()),
+            (p.z - self.min.z, -Vec3::unit_z()),
+            (self.max.z - p.z, Vec3::unit_z()),
+        ];
+
+        sides
+            .iter()
+            .fold((f32::INFINITY, Vec3::zero()), |closest, &side| {
+                if side.0 < closest.0 {
+                    side
+                } else {
+                    closest
+                }
+            })
+            .1
+    }
+
+    /// Solves for a vertex, kept within its cell and the bounds.
+    fn place(&self, qef: &Qef, origin: [u32; 3], size: u32) -> Vec3 {
+        let lo = self.point(origin).max_by_component(self.min);
+        let hi = self
+            .point(add(origin, [size; 3]))
+            .min_by_component(self.max);
+        qef.solve().max_by_component(lo).min_by_component(hi)
+    }
+
+    /// Merges eight children into one leaf if the result has the same topology
+    /// and its vertex is still close to all of their planes.
+    fn simplify(&self, origin: [u32; 3], size: u32, children: Vec<Node>) -> Node {
+        if children.iter().all(|c| matches!(c, Node::Empty)) {
+            return Node::Empty;
+        }
+        if children.iter().all(|c| matches!(c, Node::Full)) {
+            return Node::Full;
+        }
+        if children.iter().any(|c| matches!(c, Node::Branch(_))) {
+            return Node::Branch(children);
+        }
+
+        // The values at the corners of the children.
+        let half = size / 2;
+        let mut lattice = [0.0; 27];
+        let index = |x: usize, y: usize, z: usize| (z * 3 + y) * 3 + x;
+        for chunk in 0..4 {
+            let mut points = [self.point(origin); 8];
+            for (k, p) in points.iter_mut().enumerate() {
+                let i = (chunk * 8 + k).min(26);
+                *p = self.point(add(
+                    origin,
+                    [
+                        (i % 3) as u32 * half,
+                        (i / 3 % 3) as u32 * half,
+                        (i / 9) as u32 * half,
+                    ],
+                ));
+            }
+            let values = self.eval(&points);
+            for k in 0..8 {
+                let i = chunk * 8 + k;
+                if i < 27 {
+                    lattice[i] = self.clip(points[k], values[k]);
+                }
+            }
+        }
+
+        let corner_values = |x: usize, y: usize, z: usize, step: usize| {
+            let mut values = [0.0; 8];
+            for (i, v) in values.iter_mut().enumerate() {
+                *v = lattice[index(
+                    x + (i & 1) * step,
+                    y + ((i >> 1) & 1) * step,
+                    z + (i >> 2) * step,
+                )];
+            }
+            values
+        };
+
+        let values = corner_values(0, 0, 0, 2);
+        let mut inside = 0;
+        for (i, &v) in values.iter().enumerate() {
+            if v < 0.0 {
+                inside |= 1 << i;
+            }
+        }
+        // The surface would be lost entirely if it doesn't cross the merged cell,
+        // and one vertex can't join several pieces of it.
+        if inside == 0 || inside == 0xff || !is_manifold(&values) {
+            return Node::Branch(children);
+        }
+        for (i, child) in children.iter().enumerate() {
+            if let Node::Leaf(_) = child {
+                let values = corner_values(i & 1, (i >> 1) & 1, i >> 2, 1);
+                if !is_manifold(&values) {
+                    return Node::Branch(children);
+                }
+            }
+        }
+
+        // The middle of every edge, face and the cell must have the same sign as
+        // one of the corners of the edge, face or cell, or the merged cell would
+        // miss where the surface crosses in and back out between its corners.
+        for z in 0..3 {
+            for y in 0..3 {
+                for x in 0..3 {
+                    let middle = lattice[index(x, y, z)] < 0.0;
+                    let matches = (0..8).any(|i| {
+                        let pick = |c: usize, bit: usize| if c == 1 { 2 * bit } else { c };
+                        let corner = index(pick(x, i & 1), pick(y, (i >> 1) & 1), pick(z, i >> 2));
+                        (lattice[corner] < 0.0) == middle
+                    });
+                    if !matches {
+                        return Node::Branch(children);
+                    }
+                }
+            }
+        }
+
+        let mut qef = Qef::default();
+        for child in &children {
+            if let Node::Leaf(leaf) = child {
+                qef += leaf.qef;
+            }
+        }
+        let vertex = self.place(&qef, origin, size);
+        let tolerance = TOLERANCE * self.cell_size;
+        if qef.error(vertex) > tolerance * tolerance * qef.count() as f32 {
+            return Node::Branch(children);
+        }
+
+        Node::Leaf(Leaf {
+            inside,
+            qef,
+            vertex,
+            index: 0,
+        })
+    }
+
+    /// Returns the node containing a point in half lattice units, and its size.
+    fn find<'n>(&self, root: &'n Node, q: [i64; 3]) -> Option<(&'n Node, u32)> {
+        if q.iter().any(|&c| c <= 0 || c >= 2 * self.size as i64) {
+            return None;
+        }
+
+        let (mut node, mut origin, mut size) = (root, [0; 3], self.size);
+        while let Node::Branch(children) = node {
+            size /= 2;
+            let mut i = 0;
+            for axis in 0..3 {
+                if q[axis] >= 2 * (origin[axis] + size) as i64 {
+                    i |= 1 << axis;
+                    origin[axis] += size;
+                }
+            }
+            node = &children[i];
+        }
+        Some((node, size))
+    }
+}
+
+fn number_vertices(node: &mut Node, mesh: &mut Mesh) {
+    match node {
+        Node::Leaf(leaf) => {
+            leaf.index = mesh.vertices.len() as u32;
+            mesh.vertices.push(leaf.vertex);
+        }
+        Node::Branch(children) => {
+            for child in children {
+                number_vertices(child, mesh);
+            }
+        }
+        Node::Empty | Node::Full => {}
+    }
+}
+
+/// Makes the polygons around the edges of every leaf, which are shared by
+/// up to four leaves. Each polygon is only made from the smallest leaves
+/// around its edge, and then only from the first of them.
+fn contour(
+    octree: &Octree,
+    root: &Node,
+    node: &Node,
+    origin: [u32; 3],
+    size: u32,
+    mesh: &mut Mesh,
+) {
+    let leaf = match node {
+        Node::Leaf(leaf) => leaf,
+        Node::Branch(children) => {
+            let half = size / 2;
+            for (i, child) in children.iter().enumerate() {
+                contour(
+                    octree,
+                    root,
+                    child,
+                    add(origin, offset(i, half)),
+                    half,
+                    mesh,
+                );
+            }
+            return;
+        }
+        Node::Empty | Node::Full => return,
+    };
+
+    'edges: for &(a, b) in &EDGES {
+        let a_inside = leaf.inside >> a & 1 != 0;
+        if a_inside == (leaf.inside >> b & 1 != 0) {
+            continue;
+        }
+
+        let axis = (a ^ b).trailing_zeros() as usize;
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let start = add(origin, offset(a, size));
+        let own = quadrant((
+            if a >> u & 1 == 1 { -1 } else { 1 },
+            if a >> v & 1 == 1 { -1 } else { 1 },
+        ));
+
+        let mut vertices = [0; 4];
+        for (k, &(du, dv)) in QUADRANTS.iter().enumerate() {
+            let mut q = [0; 3];
+            q[axis] = 2 * start[axis] as i64 + size as i64;
+            q[u] = 2 * start[u] as i64 + du;
+            q[v] = 2 * start[v] as i64 + dv;
+
+            match octree.find(root, q) {
+                Some((Node::Leaf(other), other_size)) if other_size >= size => {
+                    if other_size == size && k < own {
+                        continue 'edges;
+                    }
+                    vertices[k] = other.index;
+                }
+                _ => continue 'edges,
+            }
+        }
+
+        // The surface goes from inside to outside along the axis,
+        // and the quadrants are counter-clockwise looking down it.
+        if !a_inside {
+            vertices.reverse();
+        }
+        polygon(mesh, &vertices);
+    }
+}
+
+fn quadrant(q: (i64, i64)) -> usize {
+    QUADRANTS.iter().position(|&other| other == q).unwrap()
+}
+
+/// Adds a quad, or a triangle where a larger leaf fills two quadrants.
+fn polygon(mesh: &mut Mesh, vertices: &[u32; 4]) {
+    let mut unique = [0; 4];
+    let mut len = 0;
+    for (k, &v) in vertices.iter().enumerate() {
+        if v != vertices[(k + 3) % 4] {
+            unique[len] = v;
+            len += 1;
+        }
+    }
+
+    match len {
+        3 => mesh.triangles.push([unique[0], unique[1], unique[2]]),
+        4 => {
+            // Split along the shorter diagonal, which follows sharp edges.
+            let p = |i: usize| mesh.vertices[unique[i] as usize];
+            let [a, b, c, d] = unique;
+            if (p(0) - p(2)).mag_sq() <= (p(1) - p(3)).mag_sq() {
+                mesh.triangles.push([a, b, c]);
+                mesh.triangles.push([a, c, d]);
+            } else {
+                mesh.triangles.push([b, c, d]);
+                mesh.triangles.push([b, d, a]);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Extracts the surface of `tape` within the bounds with an octree that is
+/// `2^depth` of the smallest cells across along the longest side of the bounds.
+///
+/// Points on the boundary of the bounds are treated as outside,
+/// so the mesh is closed even where the model is cut by them.
+pub fn dual_contouring(tape: &Tape, min: Vec3, max: Vec3, depth: u32) -> Mesh {
+    assert!(depth <= MAX_OCTREE_DEPTH);
+    let size = 1 << depth;
+    let octree = Octree {
+        tape,
+        min,
+        max,
+        cell_size: (max - min).component_max() / size as f32,
+        size,
+    };
+    // The far side of the root cell along the longest axis must be exactly on
+    // the bounds, so round the bounds to what the lattice computes.
+    let octree = Octree {
+        max: max.min_by_component(octree.point([size; 3])),
+        ..octree
+    };
+
+    let mut root = octree.build([0; 3], size);
+
+    let mut mesh = Mesh::default();
+    number_vertices(&mut root, &mut mesh);
+    contour(&octree, &root, &root, [0; 3], size, &mut mesh);
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mesh::{assert_watertight, marching_cubes, Grid},
+        tree::{ConstantOrExpr::Constant, CsgNode, CsgTree, Shape},
+    };
+    use std::rc::Rc;
+
+    fn box_tape(center: Vec3, sides: Vec3) -> Tape {
+        CsgTree::new(CsgNode::Translate {
+            x: Constant(center.x),
+            y: Constant(center.y),
+            z: Constant(center.z),
+            node: Rc::new(CsgNode::Shape(
+                Shape::Box {
+                    side_x: Constant(sides.x),
+                    side_y: Constant(sides.y),
+                    side_z: Constant(sides.z),
+                },
+                None,
+            )),
+        })
+        .compile()
+        .unwrap()
+    }
+
+    #[test]
+    fn box_keeps_sharp_corners() {
+        let (center, sides) = (Vec3::new(0.13, 0.07, -0.11), Vec3::new(0.9, 0.7, 0.8));
+        let tape = box_tape(center, sides);
+        let (min, max) = (Vec3::broadcast(-2.0), Vec3::broadcast(2.0));
+        let mesh = dual_contouring(&tape, min, max, 5);
+
+        assert!(!mesh.triangles.is_empty());
+        assert_watertight(&mesh);
+
+        for i in 0..8 {
+            let corner = center
+                + Vec3::new(
+                    if i & 1 == 0 { -sides.x } else { sides.x },
+                    if i & 2 == 0 { -sides.y } else { sides.y },
+                    if i & 4 == 0 { -sides.z } else { sides.z },
+                );
+            let closest = mesh
+                .vertices
+                .iter()
+                .map(|&v| (v - corner).mag())
+                .fold(f32::INFINITY, f32::min);
+            assert!(closest < 1e-3, "corner {:?} is {} away", corner, closest);
+        }
+
+        // Flat faces need far fewer triangles than marching cubes.
+        let grid = Grid::new(min, max, [32, 32, 32]);
+        let cubes = marching_cubes(&grid, &grid.sample(&tape));
+        assert!(mesh.triangles.len() * 4 < cubes.triangles.len());
+    }
+
+    #[test]
+    fn sphere() {
+        let tape = CsgTree::new(CsgNode::Shape(
+            Shape::Sphere {
+                radius: Constant(1.0),
+            },
+            None,
+        ))
+        .compile()
+        .unwrap();
+        let mesh = dual_contouring(&tape, Vec3::broadcast(-1.5), Vec3::broadcast(1.5), 5);
+
+        assert!(!mesh.triangles.is_empty());
+        assert_watertight(&mesh);
+
+        for &v in &mesh.vertices {
+            assert!((v.mag() - 1.0).abs() < 0.02);
+        }
+        for &t in &mesh.triangles {
+            let [a, b, c] = mesh.triangle(t);
+            let centroid = (a + b + c) / 3.0;
+            assert!(mesh.normal(t).dot(centroid) > 0.0, "triangle faces inwards");
+        }
+    }
+
+    #[test]
+    fn cut_by_bounds() {
+        // The bounds cut through a sphere blended into a box, so the mesh is
+        // capped at them, and the caps meet the model along sharp edges.
+        let tape = CsgTree::new(CsgNode::SmoothUnion {
+            lhs: Rc::new(CsgNode::Translate {
+                x: Constant(1.5),
+                y: Constant(0.0),
+                z: Constant(0.0),
+                node: Rc::new(CsgNode::Shape(
+                    Shape::Sphere {
+                        radius: Constant(0.7),
+                    },
+                    None,
+                )),
+            }),
+            rhs: Rc::new(CsgNode::Shape(
+                Shape::Box {
+                    side_x: Constant(1.0),
+                    side_y: Constant(1.0),
+                    side_z: Constant(1.0),
+                },
+                None,
+            )),
+            k: Constant(0.4),
+        })
+        .compile()
+        .unwrap();
+        let (min, max) = (Vec3::new(-1.5, -0.5, -1.5), Vec3::new(2.5, 0.5, 1.5));
+        let mesh = dual_contouring(&tape, min, max, 5);
+
+        assert!(!mesh.triangles.is_empty());
+        assert_watertight(&mesh);
+        for &v in &mesh.vertices {
+            assert!(v.y >= min.y && v.y <= max.y);
+        }
+        // The cap meets a side of the box at the corner of the bounds.
+        let corner = Vec3::new(-1.0, 0.5, 1.0);
+        assert!(mesh.vertices.iter().any(|&v| (v - corner).mag() < 1e-3));
+    }
+}
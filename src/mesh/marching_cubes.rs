@@ -0,0 +1,317 @@
+//! Marching cubes without the usual lookup table.
+//!
+//! The polygons of each cell are traced around its faces instead. Faces with
+//! two diagonal corners inside are resolved by the value at their center,
+//! which only depends on the face, so neighbouring cells always agree on how
+//! the surface crosses it and the mesh is watertight.
+
+use std::collections::HashMap;
+use ultraviolet::Vec3;
+
+use super::{Grid, Mesh};
+
+/// The corners of each face in counter-clockwise order when seen from outside the cell.
+/// Corner `i` is at `(i & 1, (i >> 1) & 1, (i >> 2) & 1)`.
+const FACES: [[usize; 4]; 6] = [
+    [0, 4, 6, 2], // -x
+    [1, 3, 7, 5], // +x
+    [0, 1, 5, 4], // -y
+    [2, 6, 7, 3], // +y
+    [0, 2, 3, 1], // -z
+    [4, 5, 7, 6], // +z
+];
+
+/// No edge, in the arrays indexed by edge.
+const NONE: u8 = u8::MAX;
+
+/// Edges of a cell are identified by their lower corner and axis,
+/// as `corner * 3 + axis`.
+fn edge(a: usize, b: usize) -> u8 {
+    let axis = (a ^ b).trailing_zeros() as usize;
+    (a.min(b) * 3 + axis) as u8
+}
+
+/// Returns a mask of the faces that contain an edge.
+fn edge_faces(e: u8) -> u8 {
+    let (corner, axis) = (e as usize / 3, e as usize % 3);
+    let other = corner | (1 << axis);
+
+    let mut mask = 0;
+    for (i, face) in FACES.iter().enumerate() {
+        if face.contains(&corner) && face.contains(&other) {
+            mask |= 1 << i;
+        }
+    }
+    mask
+}
+
+/// Returns where a fan can start in `polygon` without any of its diagonals
+/// lying on a face, where the neighbouring cell could have the same edge.
+fn fan_apex(polygon: &[u8]) -> Option<usize> {
+    (0..polygon.len()).find(|&apex| {
+        (2..polygon.len() - 1).all(|i| {
+            let other = polygon[(apex + i) % polygon.len()];
+            edge_faces(polygon[apex]) & edge_faces(other) == 0
+        })
+    })
+}
+
+/// Traces the closed loops of edges where the surface crosses a cell,
+/// given the values at its corners. Loops are ordered so that fans of them
+/// are counter-clockwise when seen from outside.
+pub(super) fn cell_loops(values: &[f32; 8], mut emit: impl FnMut(&[u8])) {
+    // `next[e]` is the edge that the segment starting at edge `e` ends at.
+    let mut next = [NONE; 24];
+
+    for face in &FACES {
+        // The crossed edges in order around the face, and whether
+        // the surface is entered when going over them.
+        let mut crossings = [(NONE, false); 4];
+        let mut count = 0;
+
+        for k in 0..4 {
+            let (a, b) = (face[k], face[(k + 1) % 4]);
+            let (a_inside, b_inside) = (values[a] < 0.0, values[b] < 0.0);
+            if a_inside != b_inside {
+                crossings[count] = (edge(a, b), b_inside);
+                count += 1;
+            }
+        }
+
+        match count {
+            0 => {}
+            2 => {
+                let (start, end) = if crossings[0].1 {
+                    (crossings[0].0, crossings[1].0)
+                } else {
+                    (crossings[1].0, crossings[0].0)
+                };
+                next[start as usize] = end;
+            }
+            4 => {
+                // Two diagonal corners are inside. Segments always go from where
+                // the surface is entered to where it's exited, and either keep the
+                // inside corners apart or join them through the center.
+                let center: f32 = face.iter().map(|&c| values[c]).sum::<f32>() / 4.0;
+                let offset = if center < 0.0 { 3 } else { 1 };
+
+                for k in 0..4 {
+                    let (start, entering) = crossings[k];
+                    if entering {
+                        next[start as usize] = crossings[(k + offset) % 4].0;
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    let mut visited = [false; 24];
+    let mut polygon = [NONE; 12];
+
+    for start in 0..24 {
+        if next[start] == NONE || visited[start] {
+            continue;
+        }
+
+        let mut len = 0;
+        let mut e = start;
+        while !visited[e] {
+            visited[e] = true;
+            polygon[len] = e as u8;
+            len += 1;
+            e = next[e] as usize;
+        }
+
+        emit(&polygon[..len]);
+    }
+}
+
+/// Builds the mesh one cell at a time.
+struct Extractor<'a> {
+    grid: &'a Grid,
+    mesh: Mesh,
+    /// Maps `point index * 3 + axis` of the edges of the grid to their vertex.
+    vertices: HashMap<usize, u32>,
+}
+
+impl<'a> Extractor<'a> {
+    fn push_vertex(&mut self, p: Vec3) -> u32 {
+        self.mesh.vertices.push(p);
+        (self.mesh.vertices.len() - 1) as u32
+    }
+
+    /// Returns the vertex where the surface crosses edge `e` of the cell at `cell`,
+    /// which is shared with the other cells around the edge.
+    fn edge_vertex(&mut self, cell: [usize; 3], values: &[f32; 8], e: u8) -> u32 {
+        let (corner, axis) = (e as usize / 3, e as usize % 3);
+        let other = corner | (1 << axis);
+        let point = |corner: usize| {
+            [
+                cell[0] + (corner & 1),
+                cell[1] + ((corner >> 1) & 1),
+                cell[2] + (corner >> 2),
+            ]
+        };
+
+        let [x, y, z] = point(corner);
+        let key = self.grid.index(x, y, z) * 3 + axis;
+        if let Some(&v) = self.vertices.get(&key) {
+            return v;
+        }
+
+        let (v0, v1) = (values[corner], values[other]);
+        let t = v0 / (v0 - v1);
+        let p0 = self.grid.point(x, y, z);
+        let [x, y, z] = point(other);
+        let p1 = self.grid.point(x, y, z);
+
+        let v = self.push_vertex(p0 + (p1 - p0) * t);
+        self.vertices.insert(key, v);
+        v
+    }
+
+    fn polygon(&mut self, cell: [usize; 3], values: &[f32; 8], polygon: &[u8]) {
+        let mut vertices = [0; 12];
+        for (v, &e) in vertices.iter_mut().zip(polygon) {
+            *v = self.edge_vertex(cell, values, e);
+        }
+        let vertices = &vertices[..polygon.len()];
+
+        if let Some(apex) = fan_apex(polygon) {
+            let n = vertices.len();
+            for i in 1..n - 1 {
+                self.mesh.triangles.push([
+                    vertices[apex],
+                    vertices[(apex + i) % n],
+                    vertices[(apex + i + 1) % n],
+                ]);
+            }
+        } else {
+            // Every fan would put a diagonal on a face, so fan from a new vertex instead.
+            let sum = vertices
+                .iter()
+                .fold(Vec3::zero(), |sum, &v| sum + self.mesh.vertices[v as usize]);
+            let center = self.push_vertex(sum / vertices.len() as f32);
+
+            for i in 0..vertices.len() {
+                let next = vertices[(i + 1) % vertices.len()];
+                self.mesh.triangles.push([center, vertices[i], next]);
+            }
+        }
+    }
+}
+
+/// Extracts the surface where `samples` crosses zero, where `samples` holds
+/// the distance at every point of `grid` as returned by [`Grid::sample`].
+///
+/// Points on the boundary of the grid are treated as outside,
+/// so the mesh is closed even where the model is cut by the bounds.
+pub fn marching_cubes(grid: &Grid, samples: &[f32]) -> Mesh {
+    let [nx, ny, nz] = grid.points();
+    assert_eq!(samples.len(), nx * ny * nz);
+
+    let value = |x: usize, y: usize, z: usize| {
+        let v = samples[grid.index(x, y, z)];
+        let on_boundary = x == 0 || y == 0 || z == 0 || x == nx - 1 || y == ny - 1 || z == nz - 1;
+        if on_boundary {
+            v.max(f32::EPSILON)
+        } else {
+            v
+        }
+    };
+
+    let mut extractor = Extractor {
+        grid,
+        mesh: Mesh::default(),
+        vertices: HashMap::new(),
+    };
+
+    for z in 0..nz - 1 {
+        for y in 0..ny - 1 {
+            for x in 0..nx - 1 {
+                let mut values = [0.0; 8];
+                for (i, v) in values.iter_mut().enumerate() {
+                    *v = value(x + (i & 1), y + ((i >> 1) & 1), z + (i >> 2));
+                }
+
+                let inside = values.iter().filter(|&&v| v < 0.0).count();
+                if inside == 0 || inside == 8 {
+                    continue;
+                }
+
+                cell_loops(&values, |polygon| {
+                    extractor.polygon([x, y, z], &values, polygon)
+                });
+            }
+        }
+    }
+
+    extractor.mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::assert_watertight;
+
+    fn sample(grid: &Grid, f: impl Fn(Vec3) -> f32) -> Vec<f32> {
+        let [nx, ny, nz] = grid.points();
+        let mut samples = Vec::with_capacity(nx * ny * nz);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    samples.push(f(grid.point(x, y, z)));
+                }
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn sphere() {
+        let grid = Grid::new(Vec3::broadcast(-1.5), Vec3::broadcast(1.5), [20, 24, 28]);
+        let mesh = marching_cubes(&grid, &sample(&grid, |p| p.mag() - 1.0));
+
+        assert!(!mesh.triangles.is_empty());
+        assert_watertight(&mesh);
+
+        for &v in &mesh.vertices {
+            assert!((v.mag() - 1.0).abs() < 0.02);
+        }
+        for &t in &mesh.triangles {
+            let [a, b, c] = mesh.triangle(t);
+            let centroid = (a + b + c) / 3.0;
+            assert!(mesh.normal(t).dot(centroid) > 0.0, "triangle faces inwards");
+        }
+    }
+
+    #[test]
+    fn cut_by_bounds() {
+        // The bounds cut through the sphere, so the mesh is capped at them.
+        let grid = Grid::new(Vec3::broadcast(-0.8), Vec3::broadcast(0.8), [10, 10, 10]);
+        let mesh = marching_cubes(&grid, &sample(&grid, |p| p.mag() - 1.0));
+
+        assert!(!mesh.triangles.is_empty());
+        assert_watertight(&mesh);
+    }
+
+    #[test]
+    fn ambiguous_cells() {
+        // Random values hit every configuration, including ambiguous faces.
+        let mut state = 0x2545_f491_u32;
+        let mut random = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 - 0.5
+        };
+
+        let grid = Grid::new(Vec3::zero(), Vec3::one(), [12, 12, 12]);
+        let samples: Vec<f32> = (0..13 * 13 * 13).map(|_| random()).collect();
+        let mesh = marching_cubes(&grid, &samples);
+
+        assert!(!mesh.triangles.is_empty());
+        assert_watertight(&mesh);
+    }
+}
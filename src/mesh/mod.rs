@@ -0,0 +1,159 @@
+//! Extracting triangle meshes from the distance field, e.g. for 3D printing.
+
+mod dual_contouring;
+mod marching_cubes;
+mod obj;
+mod ply;
+mod qef;
+mod stl;
+mod three_mf;
+
+use rayon::prelude::*;
+use ultraviolet::Vec3;
+
+use crate::tree::Tape;
+
+pub use self::{
+    dual_contouring::{dual_contouring, MAX_OCTREE_DEPTH},
+    marching_cubes::marching_cubes,
+    obj::write_obj,
+    ply::write_ply,
+    stl::{write_ascii_stl, write_binary_stl},
+    three_mf::write_3mf,
+};
+
+/// An indexed triangle mesh.
+///
+/// Triangles are counter-clockwise when seen from outside the model.
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vec3>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl Mesh {
+    pub fn triangle(&self, triangle: [u32; 3]) -> [Vec3; 3] {
+        [
+            self.vertices[triangle[0] as usize],
+            self.vertices[triangle[1] as usize],
+            self.vertices[triangle[2] as usize],
+        ]
+    }
+
+    /// Returns the outward unit normal of a triangle,
+    /// or zero if the triangle is degenerate.
+    pub fn normal(&self, triangle: [u32; 3]) -> Vec3 {
+        let [a, b, c] = self.triangle(triangle);
+        let n = (b - a).cross(c - a);
+        let mag = n.mag();
+        if mag > 0.0 {
+            n / mag
+        } else {
+            Vec3::zero()
+        }
+    }
+
+    /// Returns the unit normal at every vertex from the gradient of the distance,
+    /// rather than by averaging the triangles around it.
+    pub fn vertex_normals(&self, tape: &Tape) -> Vec<Vec3> {
+        self.vertices
+            .par_iter()
+            .map(|&v| {
+                let gradient = tape.eval_deriv(v).gradient;
+                if gradient.mag_sq() > 0.0 {
+                    gradient.normalized()
+                } else {
+                    Vec3::zero()
+                }
+            })
+            .collect()
+    }
+}
+
+/// A regular grid of points that the distance field is sampled at.
+#[derive(Debug, Clone, Copy)]
+pub struct Grid {
+    pub min: Vec3,
+    pub max: Vec3,
+    /// The number of cells along each axis,
+    /// so there is one more point than this along each axis.
+    pub resolution: [usize; 3],
+}
+
+impl Grid {
+    pub fn new(min: Vec3, max: Vec3, resolution: [usize; 3]) -> Self {
+        assert!(resolution.iter().all(|&r| r > 0));
+        Self {
+            min,
+            max,
+            resolution,
+        }
+    }
+
+    /// Creates a grid of cubic cells, with `resolution` cells along its longest axis.
+    pub fn with_max_resolution(min: Vec3, max: Vec3, resolution: usize) -> Self {
+        let size = max - min;
+        let cell_size = size.component_max() / resolution as f32;
+        let cells = |extent: f32| ((extent / cell_size).round() as usize).max(1);
+
+        Self::new(min, max, [cells(size.x), cells(size.y), cells(size.z)])
+    }
+
+    /// Returns the number of points along each axis.
+    pub fn points(&self) -> [usize; 3] {
+        let [x, y, z] = self.resolution;
+        [x + 1, y + 1, z + 1]
+    }
+
+    /// Returns the index of a point, with x varying fastest.
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        let [nx, ny, _] = self.points();
+        (z * ny + y) * nx + x
+    }
+
+    pub fn point(&self, x: usize, y: usize, z: usize) -> Vec3 {
+        let [rx, ry, rz] = self.resolution;
+        let size = self.max - self.min;
+        self.min
+            + Vec3::new(
+                size.x * x as f32 / rx as f32,
+                size.y * y as f32 / ry as f32,
+                size.z * z as f32 / rz as f32,
+            )
+    }
+
+    /// Evaluates `tape` at every point, one z slice per task on the thread pool.
+    pub fn sample(&self, tape: &Tape) -> Vec<f32> {
+        let [nx, ny, nz] = self.points();
+        let mut samples = vec![0.0; nx * ny * nz];
+
+        samples
+            .par_chunks_mut(nx * ny)
+            .enumerate()
+            .for_each(|(z, slice)| {
+                let points: Vec<Vec3> = (0..nx * ny)
+                    .map(|i| self.point(i % nx, i / nx, z))
+                    .collect();
+                tape.eval_points(&points, slice);
+            });
+
+        samples
+    }
+}
+
+/// Checks that every edge is shared by exactly two triangles
+/// that go along it in opposite directions.
+#[cfg(test)]
+fn assert_watertight(mesh: &Mesh) {
+    let mut edges = std::collections::HashMap::new();
+    for t in &mesh.triangles {
+        for k in 0..3 {
+            *edges.entry((t[k], t[(k + 1) % 3])).or_insert(0) += 1;
+        }
+    }
+
+    for (&(a, b), &count) in &edges {
+        assert_eq!(count, 1, "edge {} -> {} is used {} times", a, b, count);
+        assert_eq!(edges.get(&(b, a)), Some(&1), "edge {} -> {} is open", a, b);
+    }
+}
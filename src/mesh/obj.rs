@@ -0,0 +1,58 @@
+//! A writer for Wavefront OBJ files.
+//! https://en.wikipedia.org/wiki/Wavefront_.obj_file
+
+use std::io::{self, Write};
+use ultraviolet::Vec3;
+
+use super::Mesh;
+
+/// Writes `mesh` as an OBJ file named `name`, with a normal for every vertex.
+pub fn write_obj(mesh: &Mesh, normals: &[Vec3], name: &str, mut w: impl Write) -> io::Result<()> {
+    assert_eq!(normals.len(), mesh.vertices.len());
+
+    writeln!(w, "o {}", name)?;
+    for v in &mesh.vertices {
+        writeln!(w, "v {:e} {:e} {:e}", v.x, v.y, v.z)?;
+    }
+    for n in normals {
+        writeln!(w, "vn {:e} {:e} {:e}", n.x, n.y, n.z)?;
+    }
+
+    // Indices start at one, and each vertex uses the normal with the same index.
+    for t in &mesh.triangles {
+        let [a, b, c] = [t[0] + 1, t[1] + 1, t[2] + 1];
+        writeln!(w, "f {}//{} {}//{} {}//{}", a, a, b, b, c, c)?;
+    }
+
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obj() {
+        let mesh = Mesh {
+            vertices: vec![Vec3::zero(), Vec3::unit_x(), Vec3::unit_y()],
+            triangles: vec![[0, 1, 2]],
+        };
+        let normals = vec![Vec3::unit_z(); 3];
+
+        let mut bytes = Vec::new();
+        write_obj(&mesh, &normals, "triangle", &mut bytes).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+
+        assert_eq!(
+            text,
+            "o triangle\n\
+             v 0e0 0e0 0e0\n\
+             v 1e0 0e0 0e0\n\
+             v 0e0 1e0 0e0\n\
+             vn 0e0 0e0 1e0\n\
+             vn 0e0 0e0 1e0\n\
+             vn 0e0 0e0 1e0\n\
+             f 1//1 2//2 3//3\n"
+        );
+    }
+}
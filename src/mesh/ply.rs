@@ -0,0 +1,94 @@
+//! A writer for binary PLY files.
+//! http://paulbourke.net/dataformats/ply/
+
+use std::io::{self, Write};
+use ultraviolet::Vec3;
+
+use super::Mesh;
+
+/// Writes `mesh` as a little endian binary PLY file,
+/// with a normal and an RGB color for every vertex.
+pub fn write_ply(
+    mesh: &Mesh,
+    normals: &[Vec3],
+    colors: &[[u8; 3]],
+    mut w: impl Write,
+) -> io::Result<()> {
+    assert_eq!(normals.len(), mesh.vertices.len());
+    assert_eq!(colors.len(), mesh.vertices.len());
+
+    write!(
+        w,
+        "ply\n\
+         format binary_little_endian 1.0\n\
+         element vertex {}\n\
+         property float x\n\
+         property float y\n\
+         property float z\n\
+         property float nx\n\
+         property float ny\n\
+         property float nz\n\
+         property uchar red\n\
+         property uchar green\n\
+         property uchar blue\n\
+         element face {}\n\
+         property list uchar uint vertex_indices\n\
+         end_header\n",
+        mesh.vertices.len(),
+        mesh.triangles.len()
+    )?;
+
+    for ((v, n), color) in mesh.vertices.iter().zip(normals).zip(colors) {
+        for &c in &[v.x, v.y, v.z, n.x, n.y, n.z] {
+            w.write_all(&c.to_le_bytes())?;
+        }
+        w.write_all(color)?;
+    }
+
+    for t in &mesh.triangles {
+        w.write_all(&[3])?;
+        for &i in t {
+            w.write_all(&i.to_le_bytes())?;
+        }
+    }
+
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ply() {
+        let mesh = Mesh {
+            vertices: vec![Vec3::zero(), Vec3::unit_x(), Vec3::unit_y()],
+            triangles: vec![[0, 1, 2]],
+        };
+        let normals = vec![Vec3::unit_z(); 3];
+        let colors = vec![[255, 128, 0]; 3];
+
+        let mut bytes = Vec::new();
+        write_ply(&mesh, &normals, &colors, &mut bytes).unwrap();
+
+        let header_end = b"end_header\n";
+        let body = bytes
+            .windows(header_end.len())
+            .position(|w| w == header_end)
+            .unwrap()
+            + header_end.len();
+        let header = std::str::from_utf8(&bytes[..body]).unwrap();
+        assert!(header.contains("element vertex 3\n"));
+        assert!(header.contains("element face 1\n"));
+
+        // Each vertex is six floats and three bytes, and the face is a count and three indices.
+        assert_eq!(bytes.len() - body, 3 * 27 + 1 + 3 * 4);
+        // The second vertex is (1, 0, 0).
+        assert_eq!(bytes[body + 27..body + 31], 1f32.to_le_bytes());
+        assert_eq!(bytes[body + 24..body + 27], [255, 128, 0]);
+        assert_eq!(
+            bytes[bytes.len() - 13..],
+            [3, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! Quadratic error functions, which measure the squared distance from a point
+//! to a set of planes, for placing dual contouring vertices on sharp features.
+
+use std::ops::AddAssign;
+use ultraviolet::Vec3;
+
+/// Eigenvalues smaller than this fraction of the largest are dropped when solving,
+/// so planes that are nearly parallel don't throw the vertex far away.
+const EIGENVALUE_CUTOFF: f64 = 0.02;
+
+const JACOBI_SWEEPS: usize = 8;
+
+/// The planes are stored as the normal equations of the least squares problem
+/// `A x = b`, where each plane adds its normal as a row of `A`. This is
+/// accumulated in `f64`, since the error is a difference of large sums.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Qef {
+    ata: [[f64; 3]; 3],
+    atb: [f64; 3],
+    btb: f64,
+    /// The sum of the points, for the mass point that the solution is pulled towards.
+    point_sum: [f64; 3],
+    count: u32,
+}
+
+impl Qef {
+    /// Adds the plane through `point` with unit `normal`.
+    pub fn add(&mut self, point: Vec3, normal: Vec3) {
+        let n = to_f64(normal);
+        let p = to_f64(point);
+        let d = dot(n, p);
+
+        for i in 0..3 {
+            for j in 0..3 {
+                self.ata[i][j] += n[i] * n[j];
+            }
+            self.atb[i] += n[i] * d;
+            self.point_sum[i] += p[i];
+        }
+        self.btb += d * d;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mass_point(&self) -> Vec3 {
+        let n = self.count.max(1) as f64;
+        let [x, y, z] = self.point_sum;
+        Vec3::new((x / n) as f32, (y / n) as f32, (z / n) as f32)
+    }
+
+    /// Returns the sum of squared distances from `x` to the planes.
+    pub fn error(&self, x: Vec3) -> f32 {
+        let x = to_f64(x);
+        let ax = mul(&self.ata, x);
+        (dot(x, ax) - 2.0 * dot(x, self.atb) + self.btb).max(0.0) as f32
+    }
+
+    /// Returns the point closest to all of the planes.
+    ///
+    /// Directions that the planes don't constrain, like along a sharp edge
+    /// or everywhere on a flat face, are taken from the mass point.
+    pub fn solve(&self) -> Vec3 {
+        let mass_point = to_f64(self.mass_point());
+        let ax = mul(&self.ata, mass_point);
+        let rhs = [
+            self.atb[0] - ax[0],
+            self.atb[1] - ax[1],
+            self.atb[2] - ax[2],
+        ];
+
+        let (values, vectors) = symmetric_eigen(self.ata);
+        let largest = values.iter().cloned().fold(0.0, f64::max);
+
+        let mut x = mass_point;
+        for (k, &value) in values.iter().enumerate() {
+            if value <= largest * EIGENVALUE_CUTOFF || value <= 0.0 {
+                continue;
+            }
+            let v = [vectors[0][k], vectors[1][k], vectors[2][k]];
+            let s = dot(v, rhs) / value;
+            for i in 0..3 {
+                x[i] += v[i] * s;
+            }
+        }
+
+        Vec3::new(x[0] as f32, x[1] as f32, x[2] as f32)
+    }
+}
+
+impl AddAssign for Qef {
+    fn add_assign(&mut self, rhs: Self) {
+        for i in 0..3 {
+            for j in 0..3 {
+                self.ata[i][j] += rhs.ata[i][j];
+            }
+            self.atb[i] += rhs.atb[i];
+            self.point_sum[i] += rhs.point_sum[i];
+        }
+        self.btb += rhs.btb;
+        self.count += rhs.count;
+    }
+}
+
+fn to_f64(v: Vec3) -> [f64; 3] {
+    [v.x as f64, v.y as f64, v.z as f64]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn mul(m: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    [dot(m[0], v), dot(m[1], v), dot(m[2], v)]
+}
+
+/// Diagonalizes a symmetric matrix with Jacobi rotations, and returns
+/// its eigenvalues and a matrix with the eigenvectors as columns.
+fn symmetric_eigen(mut a: [[f64; 3]; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    for _ in 0..JACOBI_SWEEPS {
+        for &(p, q) in &[(0, 1), (0, 2), (1, 2)] {
+            if a[p][q].abs() < 1e-12 {
+                continue;
+            }
+
+            let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let c = 1.0 / (t * t + 1.0).sqrt();
+            let s = t * c;
+
+            for row in &mut a {
+                let (kp, kq) = (row[p], row[q]);
+                row[p] = c * kp - s * kq;
+                row[q] = s * kp + c * kq;
+            }
+            // `p` is always before `q`.
+            let (rows_p, rows_q) = a.split_at_mut(q);
+            for (pk, qk) in rows_p[p].iter_mut().zip(&mut rows_q[0]) {
+                let (x, y) = (*pk, *qk);
+                *pk = c * x - s * y;
+                *qk = s * x + c * y;
+            }
+            for row in &mut v {
+                let (kp, kq) = (row[p], row[q]);
+                row[p] = c * kp - s * kq;
+                row[q] = s * kp + c * kq;
+            }
+        }
+    }
+
+    ([a[0][0], a[1][1], a[2][2]], v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corner() {
+        // Three faces of a box meeting at (1, 2, 3), with a few points on each.
+        let mut qef = Qef::default();
+        for &offset in &[0.1, 0.3] {
+            qef.add(Vec3::new(1.0, 2.0 - offset, 3.0 - 0.2), Vec3::unit_x());
+            qef.add(Vec3::new(1.0 - offset, 2.0, 3.0 - 0.4), Vec3::unit_y());
+            qef.add(Vec3::new(1.0 - 0.2, 2.0 - offset, 3.0), Vec3::unit_z());
+        }
+
+        let x = qef.solve();
+        assert!((x - Vec3::new(1.0, 2.0, 3.0)).mag() < 1e-5);
+        assert!(qef.error(x) < 1e-6);
+    }
+
+    #[test]
+    fn edge() {
+        // Two faces meeting along the z axis leave z to the mass point.
+        let n = Vec3::new(1.0, 1.0, 0.0).normalized();
+        let mut qef = Qef::default();
+        qef.add(Vec3::new(0.0, -0.5, 0.2), Vec3::unit_x());
+        qef.add(Vec3::new(-0.5, 0.5, 0.4), n);
+        qef.add(Vec3::new(0.5, -0.5, 0.6), n);
+
+        let x = qef.solve();
+        assert!((x - Vec3::new(0.0, 0.0, 0.4)).mag() < 1e-5);
+    }
+
+    #[test]
+    fn flat() {
+        let mut qef = Qef::default();
+        qef.add(Vec3::new(0.0, 1.0, 0.0), Vec3::unit_y());
+        qef.add(Vec3::new(1.0, 1.0, 2.0), Vec3::unit_y());
+
+        let x = qef.solve();
+        assert!((x - Vec3::new(0.5, 1.0, 1.0)).mag() < 1e-5);
+        assert_eq!(qef.error(Vec3::new(3.0, 2.0, 0.0)), 2.0);
+    }
+}
@@ -0,0 +1,96 @@
+//! Writers for binary and ASCII STL files.
+//! https://en.wikipedia.org/wiki/STL_(file_format)
+
+use std::io::{self, Write};
+use ultraviolet::Vec3;
+
+use super::Mesh;
+
+/// Writes `mesh` as a binary STL file, which is what most slicers expect.
+pub fn write_binary_stl(mesh: &Mesh, mut w: impl Write) -> io::Result<()> {
+    let mut header = [0; 80];
+    let name = b"sdf binary stl";
+    header[..name.len()].copy_from_slice(name);
+    w.write_all(&header)?;
+    w.write_all(&(mesh.triangles.len() as u32).to_le_bytes())?;
+
+    for &triangle in &mesh.triangles {
+        write_vec3(&mut w, mesh.normal(triangle))?;
+        for &v in &mesh.triangle(triangle) {
+            write_vec3(&mut w, v)?;
+        }
+        // The attribute byte count, which is unused.
+        w.write_all(&[0, 0])?;
+    }
+
+    w.flush()
+}
+
+fn write_vec3(w: &mut impl Write, v: Vec3) -> io::Result<()> {
+    w.write_all(&v.x.to_le_bytes())?;
+    w.write_all(&v.y.to_le_bytes())?;
+    w.write_all(&v.z.to_le_bytes())
+}
+
+/// Writes `mesh` as an ASCII STL file named `name`.
+pub fn write_ascii_stl(mesh: &Mesh, name: &str, mut w: impl Write) -> io::Result<()> {
+    writeln!(w, "solid {}", name)?;
+
+    for &triangle in &mesh.triangles {
+        let n = mesh.normal(triangle);
+        writeln!(w, "  facet normal {:e} {:e} {:e}", n.x, n.y, n.z)?;
+        writeln!(w, "    outer loop")?;
+        for v in &mesh.triangle(triangle) {
+            writeln!(w, "      vertex {:e} {:e} {:e}", v.x, v.y, v.z)?;
+        }
+        writeln!(w, "    endloop")?;
+        writeln!(w, "  endfacet")?;
+    }
+
+    writeln!(w, "endsolid {}", name)?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tetrahedron() -> Mesh {
+        Mesh {
+            vertices: vec![Vec3::zero(), Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()],
+            triangles: vec![[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]],
+        }
+    }
+
+    #[test]
+    fn binary_stl() {
+        let mut bytes = Vec::new();
+        write_binary_stl(&tetrahedron(), &mut bytes).unwrap();
+
+        assert_eq!(bytes.len(), 84 + 4 * 50);
+        assert_eq!(bytes[80..84], 4u32.to_le_bytes());
+
+        let f32_at = |offset: usize| {
+            let mut b = [0; 4];
+            b.copy_from_slice(&bytes[offset..offset + 4]);
+            f32::from_le_bytes(b)
+        };
+        // The first triangle is on the z = 0 plane and faces down.
+        assert_eq!([f32_at(84), f32_at(88), f32_at(92)], [0.0, 0.0, -1.0]);
+        // Its last vertex is (1, 0, 0).
+        assert_eq!([f32_at(120), f32_at(124), f32_at(128)], [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn ascii_stl() {
+        let mut bytes = Vec::new();
+        write_ascii_stl(&tetrahedron(), "tetrahedron", &mut bytes).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+
+        assert!(text.starts_with("solid tetrahedron\n"));
+        assert!(text.ends_with("endsolid tetrahedron\n"));
+        assert_eq!(text.matches("facet normal").count(), 4);
+        assert_eq!(text.matches("vertex").count(), 12);
+        assert!(text.contains("facet normal 0e0 0e0 -1e0\n"));
+    }
+}
@@ -0,0 +1,131 @@
+//! A writer for 3MF files, which are zip archives of XML parts.
+//! https://3mf.io/specification/
+
+use std::io::{self, Seek, Write};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use super::Mesh;
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+  <Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+  <Default Extension="model" ContentType="application/vnd.ms-package.3dmanufacturing-3dmodel+xml"/>
+</Types>
+"#;
+
+const RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+  <Relationship Target="/3D/3dmodel.model" Id="rel0" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/>
+</Relationships>
+"#;
+
+/// Writes named meshes as separate bodies of one 3MF file.
+pub fn write_3mf(bodies: &[(&str, &Mesh)], w: impl Write + Seek) -> io::Result<()> {
+    let mut zip = ZipWriter::new(w);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("[Content_Types].xml", options)?;
+    zip.write_all(CONTENT_TYPES.as_bytes())?;
+    zip.start_file("_rels/.rels", options)?;
+    zip.write_all(RELATIONSHIPS.as_bytes())?;
+
+    zip.start_file("3D/3dmodel.model", options)?;
+    write_model(bodies, &mut zip)?;
+
+    zip.finish()?.flush()
+}
+
+fn write_model(bodies: &[(&str, &Mesh)], w: &mut impl Write) -> io::Result<()> {
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        w,
+        r#"<model unit="millimeter" xml:lang="en-US" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02">"#
+    )?;
+    writeln!(w, "  <resources>")?;
+
+    // Object ids start at one.
+    for (id, (name, mesh)) in bodies.iter().enumerate() {
+        writeln!(
+            w,
+            r#"    <object id="{}" name="{}" type="model">"#,
+            id + 1,
+            escape(name)
+        )?;
+        writeln!(w, "      <mesh>")?;
+
+        writeln!(w, "        <vertices>")?;
+        for v in &mesh.vertices {
+            writeln!(
+                w,
+                r#"          <vertex x="{}" y="{}" z="{}"/>"#,
+                v.x, v.y, v.z
+            )?;
+        }
+        writeln!(w, "        </vertices>")?;
+
+        writeln!(w, "        <triangles>")?;
+        for t in &mesh.triangles {
+            writeln!(
+                w,
+                r#"          <triangle v1="{}" v2="{}" v3="{}"/>"#,
+                t[0], t[1], t[2]
+            )?;
+        }
+        writeln!(w, "        </triangles>")?;
+
+        writeln!(w, "      </mesh>")?;
+        writeln!(w, "    </object>")?;
+    }
+
+    writeln!(w, "  </resources>")?;
+    writeln!(w, "  <build>")?;
+    for id in 1..=bodies.len() {
+        writeln!(w, r#"    <item objectid="{}"/>"#, id)?;
+    }
+    writeln!(w, "  </build>")?;
+    writeln!(w, "</model>")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+    use ultraviolet::Vec3;
+
+    #[test]
+    fn two_bodies() {
+        let tetrahedron = Mesh {
+            vertices: vec![Vec3::zero(), Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()],
+            triangles: vec![[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]],
+        };
+        let bodies = [("first", &tetrahedron), ("a \"second\"", &tetrahedron)];
+
+        let mut bytes = Cursor::new(Vec::new());
+        write_3mf(&bodies, &mut bytes).unwrap();
+
+        let mut archive = zip::ZipArchive::new(bytes).unwrap();
+        assert!(archive.by_name("[Content_Types].xml").is_ok());
+        assert!(archive.by_name("_rels/.rels").is_ok());
+
+        let mut model = String::new();
+        archive
+            .by_name("3D/3dmodel.model")
+            .unwrap()
+            .read_to_string(&mut model)
+            .unwrap();
+
+        assert_eq!(model.matches("<object ").count(), 2);
+        assert_eq!(model.matches("<vertex ").count(), 8);
+        assert_eq!(model.matches("<triangle ").count(), 8);
+        assert!(model.contains(r#"name="a &quot;second&quot;""#));
+        assert!(model.contains(r#"<vertex x="1" y="0" z="0"/>"#));
+        assert!(model.contains(r#"<item objectid="2"/>"#));
+    }
+}
@@ -1,13 +1,25 @@
 use core::slice;
-use std::mem;
+use std::{
+    future::Future,
+    mem,
+    num::NonZeroU32,
+    pin::Pin,
+    ptr,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
 use ultraviolet::{Mat4, UVec2, Vec3};
 use wgpu::util::{BufferInitDescriptor, DeviceExt as _};
 use winit::dpi::PhysicalSize;
 
-use crate::camera::Camera;
-use shared::inst::{Inst, RectangularPrism, Ret, SmoothUnion, Sphere};
+use crate::{camera::Camera, headless::Image, tree::Tape};
+use shared::inst::Inst;
 
 const STORAGE_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+/// Images rendered on the CPU are already sRGB encoded.
+const IMAGE_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Rays are marched up to this distance from the eye.
+const MAX_DISTANCE: f32 = 100.0;
 
 macro_rules! include_spirv_shader {
     ($($token:tt)*) => {
@@ -41,35 +53,112 @@ struct RenderParams {
     resolution: UVec2,
     grid_size: UVec2,
     neg_z_depth: f32,
-    _pad2: Pad<12>,
+    initial_tape_len: u32,
+    tile8x8_tape_start: u32,
+    max_distance: f32,
 }
 
 static_assertions::assert_eq_size!(RenderParams, [u8; 128]);
 
-// #[derive(Default)]
-// #[repr(C)]
-// struct ConeTracingParams {
-//     view_mat: Mat4,
-//     eye: Vec3,
-//     _pad0: Pad<4>,
-//     resolution: UVec2,
-//     grid_size: UVec2,
-//     neg_z_depth: f32,
-//     cone_multiplier: f32,
-//     _pad1: Pad<8>,
-// }
+#[derive(Default)]
+#[repr(C)]
+struct ConeTracingParams {
+    view_mat: Mat4,
+    eye: Vec3,
+    _pad0: Pad<4>,
+    resolution: UVec2,
+    grid_size: UVec2,
+    neg_z_depth: f32,
+    max_distance: f32,
+    enabled: u32,
+    _pad1: Pad<4>,
+}
+
+static_assertions::assert_eq_size!(ConeTracingParams, [u8; 112]);
+
+/// The buffers written by the tile passes,
+/// which depend on the resolution and the length of the tape.
+///
+/// Every tile has space for a whole tape, so long tapes at high resolutions
+/// can run into the device's storage buffer size limit.
+struct TileBuffers {
+    /// The initial tape, followed by space for the pruned tape
+    /// of every 64x64 tile and then of every 8x8 tile.
+    tapes: wgpu::Buffer,
+    /// The status and first depth slice of every 64x64 tile and then of every 8x8 tile.
+    status: wgpu::Buffer,
+    /// Space for the choices made while evaluating the tape of every tile.
+    choices: wgpu::Buffer,
+}
+
+impl TileBuffers {
+    fn new(device: &wgpu::Device, resolution: PhysicalSize<u32>, tape_len: usize) -> Self {
+        let (tiles64x64, tiles8x8) = tile_counts(resolution);
+
+        Self {
+            tapes: create_storage_buffer(
+                device,
+                tape_len * (1 + tiles64x64 + tiles8x8) * mem::size_of::<Inst>(),
+            ),
+            status: create_storage_buffer(
+                device,
+                2 * (tiles64x64 + tiles8x8) * mem::size_of::<u32>(),
+            ),
+            choices: create_storage_buffer(device, tape_len * tiles8x8 * mem::size_of::<u32>()),
+        }
+    }
+}
+
+/// Requests a device with the features and limits the renderer needs from `adapter`.
+pub async fn request_device(
+    adapter: &wgpu::Adapter,
+) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::PUSH_CONSTANTS,
+                limits: wgpu::Limits {
+                    max_push_constant_size: 128,
+                    ..Default::default()
+                },
+            },
+            None,
+        )
+        .await
+}
 
 pub struct SDFRender {
     linear_sampler: wgpu::Sampler,
     texture: wgpu::TextureView,
     resolution: PhysicalSize<u32>,
-    // starting_depth_buffer: wgpu::Buffer,
-    testing_tape: wgpu::Buffer,
+    /// The depth every ray of a 64x64 tile starts marching from.
+    starting_depth_buffer: wgpu::Buffer,
+    /// Whether the cone tracing prepass pushes the starting depths
+    /// away from the eye.
+    cone_tracing: bool,
+    /// The number of sphere marching steps taken for every pixel in the last frame.
+    steps_buffer: wgpu::Buffer,
+    /// The initial tape, which is copied to the start of the
+    /// global tape buffer before rendering.
+    tape: wgpu::Buffer,
+    /// The number of instructions that fit in `tape`.
+    tape_capacity: usize,
+    tape_len: usize,
     matrices: wgpu::Buffer,
+    /// The number of matrices that fit in `matrices`.
+    matrices_capacity: usize,
+    tiles: TileBuffers,
+
+    cone_trace_bgl: wgpu::BindGroupLayout,
+    cone_trace_bg: wgpu::BindGroup,
+    cone_trace_pipeline: wgpu::ComputePipeline,
+
+    tiles_bgl: wgpu::BindGroupLayout,
+    tiles_bg: wgpu::BindGroup,
+    tiles64x64_pipeline: wgpu::ComputePipeline,
+    tiles8x8_pipeline: wgpu::ComputePipeline,
 
-    // cone_trace_bgl: wgpu::BindGroupLayout,
-    // cone_trace_bg: wgpu::BindGroup,
-    // cone_trace_pipeline: wgpu::ComputePipeline,
     sdf_final_bgl: wgpu::BindGroupLayout,
     sdf_final_bg: wgpu::BindGroup,
     sdf_final_pipeline: wgpu::ComputePipeline,
@@ -77,6 +166,10 @@ pub struct SDFRender {
     blit_bgl: wgpu::BindGroupLayout,
     blit_bg: wgpu::BindGroup,
     blit_pipeline: wgpu::RenderPipeline,
+
+    /// Holds images rendered on the CPU, to blit them like the storage texture.
+    image_texture: wgpu::Texture,
+    image_blit_bg: wgpu::BindGroup,
 }
 
 impl SDFRender {
@@ -84,78 +177,87 @@ impl SDFRender {
         device: &wgpu::Device,
         initial_size: PhysicalSize<u32>,
         swapchain_format: wgpu::TextureFormat,
+        tape: &Tape,
     ) -> Self {
         let linear_sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
         let texture = create_texture(device, initial_size);
 
-        let matrices = &[
-            Mat4::identity(),
-            Mat4::from_translation(Vec3::new(1.5, 0.0, 0.0)),
-        ];
-
         let matrices = device.create_buffer_init(&BufferInitDescriptor {
             label: None,
-            usage: wgpu::BufferUsage::STORAGE,
-            contents: unsafe {
-                slice::from_raw_parts(
-                    matrices.as_ptr() as *const u8,
-                    matrices.len() * mem::size_of::<Mat4>(),
-                )
-            },
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            contents: slice_as_bytes(&tape.matrices),
         });
+        let matrices_capacity = tape.matrices.len();
 
-        let tape = &[
-            Inst::make(
-                0,
-                RectangularPrism {
-                    matrix_idx: 0,
-                    x: 1.0,
-                    y: 1.0,
-                    z: 1.0,
-                },
-            ),
-            // Inst::make(0, Sphere {
-            //     matrix_idx: 1,
-            //     radius: 0.7,
-            // }),
-            // Inst::make(0, SmoothUnion { k: 0.5 }),
-            Inst::make(0, Ret),
-        ];
-
-        let testing_tape = device.create_buffer_init(&BufferInitDescriptor {
+        let tape_len = tape.insts.len();
+        let tape_capacity = tape_len;
+        let tape = device.create_buffer_init(&BufferInitDescriptor {
             label: None,
-            usage: wgpu::BufferUsage::STORAGE,
-            contents: unsafe {
-                slice::from_raw_parts(
-                    tape.as_ptr() as *const u8,
-                    tape.len() * mem::size_of::<Inst>(),
-                )
-            },
+            usage: wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST,
+            contents: slice_as_bytes(&tape.insts),
         });
+        let tiles = TileBuffers::new(device, initial_size, tape_len);
+
+        let starting_depth_buffer = create_starting_depth_buffer(device, initial_size);
+        let steps_buffer = create_steps_buffer(device, initial_size);
 
-        // let starting_depth_buffer = create_starting_depth_buffer(device, initial_size);
+        let (cone_trace_bgl, cone_trace_pipeline) = create_cone_trace_components(device);
+        let cone_trace_bg = create_cone_trace_bind_group(
+            device,
+            &cone_trace_bgl,
+            &tiles,
+            &matrices,
+            &starting_depth_buffer,
+        );
 
-        // let (cone_trace_bgl, cone_trace_pipeline) = create_cone_trace_components(device);
-        // let cone_trace_bg = create_cone_trace_bind_group(device, &cone_trace_bgl, &starting_depth_buffer);
+        let (tiles_bgl, tiles64x64_pipeline, tiles8x8_pipeline) = create_tiles_components(device);
+        let tiles_bg = create_tiles_bind_group(device, &tiles_bgl, &tiles, &matrices);
 
         let (sdf_final_bgl, sdf_final_pipeline) = create_sdf_final_components(device);
-        let sdf_final_bg =
-            create_sdf_final_bind_group(device, &sdf_final_bgl, &texture, &testing_tape, &matrices);
+        let sdf_final_bg = create_sdf_final_bind_group(
+            device,
+            &sdf_final_bgl,
+            &texture,
+            &tiles,
+            &matrices,
+            &starting_depth_buffer,
+            &steps_buffer,
+        );
 
         let (blit_bgl, blit_pipeline) = create_blit_components(device, swapchain_format);
         let blit_bg = create_blit_bind_group(device, &texture, &blit_bgl, &linear_sampler);
 
+        let image_texture = create_image_texture(device, initial_size);
+        let image_blit_bg = create_blit_bind_group(
+            device,
+            &image_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            &blit_bgl,
+            &linear_sampler,
+        );
+
         Self {
             linear_sampler,
             texture,
             resolution: initial_size,
-            // starting_depth_buffer,
-            testing_tape,
+            starting_depth_buffer,
+            cone_tracing: true,
+            steps_buffer,
+            tape,
+            tape_capacity,
+            tape_len,
             matrices,
+            matrices_capacity,
+            tiles,
+
+            cone_trace_bgl,
+            cone_trace_bg,
+            cone_trace_pipeline,
+
+            tiles_bgl,
+            tiles_bg,
+            tiles64x64_pipeline,
+            tiles8x8_pipeline,
 
-            // cone_trace_bgl,
-            // cone_trace_bg,
-            // cone_trace_pipeline,
             sdf_final_bgl,
             sdf_final_bg,
             sdf_final_pipeline,
@@ -163,27 +265,142 @@ impl SDFRender {
             blit_bgl,
             blit_bg,
             blit_pipeline,
+
+            image_texture,
+            image_blit_bg,
         }
     }
 
     pub fn resize(&mut self, device: &wgpu::Device, new_size: PhysicalSize<u32>) {
         self.texture = create_texture(device, new_size);
         self.resolution = new_size;
+        self.tiles = TileBuffers::new(device, new_size, self.tape_len);
+        self.starting_depth_buffer = create_starting_depth_buffer(device, new_size);
+        self.steps_buffer = create_steps_buffer(device, new_size);
+
+        self.rebind(device);
+
+        self.blit_bg =
+            create_blit_bind_group(device, &self.texture, &self.blit_bgl, &self.linear_sampler);
+
+        self.image_texture = create_image_texture(device, new_size);
+        self.image_blit_bg = create_blit_bind_group(
+            device,
+            &self
+                .image_texture
+                .create_view(&wgpu::TextureViewDescriptor::default()),
+            &self.blit_bgl,
+            &self.linear_sampler,
+        );
+    }
+
+    /// Replaces the model being rendered.
+    ///
+    /// The buffers are reallocated if the new tape or its matrices don't fit
+    /// in the current ones, and the tile buffers if the tape's length changed.
+    pub fn set_tape(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, tape: &Tape) {
+        if tape.insts.len() > self.tape_capacity {
+            self.tape_capacity = tape.insts.len().next_power_of_two();
+            self.tape = device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: (self.tape_capacity * mem::size_of::<Inst>()) as u64,
+                usage: wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST,
+                mapped_at_creation: false,
+            });
+        }
+        queue.write_buffer(&self.tape, 0, slice_as_bytes(&tape.insts));
+
+        let mut rebind = self.write_matrices(device, queue, &tape.matrices);
+
+        if tape.insts.len() != self.tape_len {
+            self.tape_len = tape.insts.len();
+            self.tiles = TileBuffers::new(device, self.resolution, self.tape_len);
+            rebind = true;
+        }
+
+        if rebind {
+            self.rebind(device);
+        }
+    }
+
+    /// Replaces the shape transforms without changing the tape,
+    /// e.g. to move part of the model.
+    pub fn set_matrices(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, matrices: &[Mat4]) {
+        if self.write_matrices(device, queue, matrices) {
+            self.rebind(device);
+        }
+    }
 
-        // self.starting_depth_buffer = create_starting_depth_buffer(device, self.resolution);
+    /// Returns whether the matrix buffer was reallocated.
+    fn write_matrices(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        matrices: &[Mat4],
+    ) -> bool {
+        let reallocated = matrices.len() > self.matrices_capacity;
+
+        if reallocated {
+            self.matrices_capacity = matrices.len().next_power_of_two();
+            self.matrices =
+                create_storage_buffer(device, self.matrices_capacity * mem::size_of::<Mat4>());
+        }
+        queue.write_buffer(&self.matrices, 0, slice_as_bytes(matrices));
 
-        // self.cone_trace_bg = create_cone_trace_bind_group(device, &self.cone_trace_bgl, &self.starting_depth_buffer);
+        reallocated
+    }
 
+    /// Enables or disables the cone tracing prepass.
+    ///
+    /// When it's disabled, every ray starts marching at its tile's first
+    /// non-empty depth slice, which makes it easy to compare step counts.
+    pub fn set_cone_tracing(&mut self, enabled: bool) {
+        self.cone_tracing = enabled;
+    }
+
+    pub fn cone_tracing(&self) -> bool {
+        self.cone_tracing
+    }
+
+    /// Starts reading back the number of sphere marching steps taken in the
+    /// last frame rendered on the GPU.
+    pub fn count_steps(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> StepCount {
+        let size = self.resolution.width as u64 * self.resolution.height as u64 * 4;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(&self.steps_buffer, 0, &buffer, 0, size);
+        queue.submit(Some(encoder.finish()));
+
+        let mapping = Box::pin(buffer.slice(..).map_async(wgpu::MapMode::Read));
+        StepCount { buffer, mapping }
+    }
+
+    fn rebind(&mut self, device: &wgpu::Device) {
+        self.cone_trace_bg = create_cone_trace_bind_group(
+            device,
+            &self.cone_trace_bgl,
+            &self.tiles,
+            &self.matrices,
+            &self.starting_depth_buffer,
+        );
+        self.tiles_bg =
+            create_tiles_bind_group(device, &self.tiles_bgl, &self.tiles, &self.matrices);
         self.sdf_final_bg = create_sdf_final_bind_group(
             device,
             &self.sdf_final_bgl,
             &self.texture,
-            &self.testing_tape,
+            &self.tiles,
             &self.matrices,
+            &self.starting_depth_buffer,
+            &self.steps_buffer,
         );
-
-        self.blit_bg =
-            create_blit_bind_group(device, &self.texture, &self.blit_bgl, &self.linear_sampler);
     }
 
     pub fn render(
@@ -200,62 +417,82 @@ impl SDFRender {
         let resolution = UVec2::new(self.resolution.width, self.resolution.height);
         let neg_z_depth = -(self.resolution.width as f32 / (field_of_view / 2.0).tan());
         let grid_size = resolution.map(|t| t + 64 - 1) / 64;
+        let grid_size8x8 = resolution.map(|t| t + 8 - 1) / 8;
+        let (tiles64x64, _) = tile_counts(self.resolution);
+
+        let render_params = RenderParams {
+            view_mat,
+            eye,
+            resolution,
+            light,
+            neg_z_depth,
+            grid_size,
+            initial_tape_len: self.tape_len as u32,
+            tile8x8_tape_start: (self.tape_len * (1 + tiles64x64)) as u32,
+            max_distance: MAX_DISTANCE,
+            ..Default::default()
+        };
+        let render_params_bytes = unsafe {
+            slice::from_raw_parts(
+                &render_params as *const _ as *const u8,
+                mem::size_of::<RenderParams>(),
+            )
+        };
+
+        let cone_trace_params = ConeTracingParams {
+            view_mat,
+            eye,
+            resolution,
+            grid_size,
+            neg_z_depth,
+            max_distance: MAX_DISTANCE,
+            enabled: self.cone_tracing as u32,
+            ..Default::default()
+        };
+        let cone_trace_params_bytes = unsafe {
+            slice::from_raw_parts(
+                &cone_trace_params as *const _ as *const u8,
+                mem::size_of::<ConeTracingParams>(),
+            )
+        };
+
+        encoder.copy_buffer_to_buffer(
+            &self.tape,
+            0,
+            &self.tiles.tapes,
+            0,
+            (self.tape_len * mem::size_of::<Inst>()) as u64,
+        );
 
         {
             let mut cpass =
                 encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
 
-            // // Prerender Cone Tracing
-            // let cone_trace_params = ConeTracingParams {
-            //     view_mat,
-            //     eye,
-            //     resolution,
-            //     grid_size,
-            //     neg_z_depth,
-            //     cone_multiplier: {
-            //         let vertical_fov = field_of_view * (self.resolution.height as f32 / self.resolution.width as f32);
-
-            //         let s = Vec2::new(
-            //             (field_of_view / 2.0).tan() * 1.0 / self.resolution.width as f32,
-            //             (vertical_fov / 2.0).tan() * 1.0 / self.resolution.height as f32,
-            //         ).mag();
-            //         1.0 / (1.0 + s)
-            //     },
-            //     ..Default::default()
-            // };
-
-            // cpass.set_pipeline(&self.cone_trace_pipeline);
-            // cpass.set_push_constants(0, unsafe {
-            //     slice::from_raw_parts(
-            //         &cone_trace_params as *const _ as *const u8,
-            //         mem::size_of::<ConeTracingParams>(),
-            //     )
-            // });
-            // cpass.set_bind_group(0, &self.cone_trace_bg, &[]);
-            // cpass.dispatch(
-            //     (self.resolution.width + 64 * 8 - 1) / (64 * 8),
-            //     (self.resolution.height + 64 * 8 - 1) / (64 * 8),
-            //     1,
-            // );
+            // Push the starting depth of each 64x64 tile away from the eye.
+            cpass.set_pipeline(&self.cone_trace_pipeline);
+            cpass.set_push_constants(0, cone_trace_params_bytes);
+            cpass.set_bind_group(0, &self.cone_trace_bg, &[]);
+            cpass.dispatch((grid_size.x + 8 - 1) / 8, (grid_size.y + 8 - 1) / 8, 1);
+
+            // Prune the tape for each 64x64 tile, then for each 8x8 tile.
+            // Each pass runs a thread per tile, in workgroups of 8x8 tiles.
+            cpass.set_pipeline(&self.tiles64x64_pipeline);
+            cpass.set_push_constants(0, render_params_bytes);
+            cpass.set_bind_group(0, &self.tiles_bg, &[]);
+            cpass.dispatch((grid_size.x + 8 - 1) / 8, (grid_size.y + 8 - 1) / 8, 1);
+
+            cpass.set_pipeline(&self.tiles8x8_pipeline);
+            cpass.set_push_constants(0, render_params_bytes);
+            cpass.set_bind_group(0, &self.tiles_bg, &[]);
+            cpass.dispatch(
+                (grid_size8x8.x + 8 - 1) / 8,
+                (grid_size8x8.y + 8 - 1) / 8,
+                1,
+            );
 
             // SDF Rendering
-            let render_params = RenderParams {
-                view_mat,
-                eye,
-                resolution,
-                light,
-                neg_z_depth,
-                grid_size,
-                ..Default::default()
-            };
-
             cpass.set_pipeline(&self.sdf_final_pipeline);
-            cpass.set_push_constants(0, unsafe {
-                slice::from_raw_parts(
-                    &render_params as *const _ as *const u8,
-                    mem::size_of::<RenderParams>(),
-                )
-            });
+            cpass.set_push_constants(0, render_params_bytes);
             cpass.set_bind_group(0, &self.sdf_final_bg, &[]);
             cpass.dispatch(
                 (self.resolution.width + 8 - 1) / 8,
@@ -263,32 +500,130 @@ impl SDFRender {
                 1,
             );
         }
-        {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
-                color_attachments: &[wgpu::RenderPassColorAttachment {
-                    view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: None,
-            });
 
-            rpass.set_pipeline(&self.blit_pipeline);
-            rpass.set_push_constants(
-                wgpu::ShaderStage::FRAGMENT,
-                0,
-                UVec2::new(self.resolution.width, self.resolution.height).as_byte_slice(),
-            );
-            rpass.set_bind_group(0, &self.blit_bg, &[]);
-            rpass.draw(0..3, 0..1);
+        self.blit(&self.blit_bg, view, encoder);
+    }
+
+    /// Draws an image rendered on the CPU, which must be the size of the renderer.
+    pub fn blit_image(
+        &self,
+        queue: &wgpu::Queue,
+        image: &Image,
+        view: &wgpu::TextureView,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        assert_eq!(image.size, self.resolution);
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.image_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &image.rgba,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(image.size.width * 4),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: image.size.width,
+                height: image.size.height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        self.blit(&self.image_blit_bg, view, encoder);
+    }
+
+    /// Draws the texture in `bind_group` over the whole of `view`.
+    fn blit(
+        &self,
+        bind_group: &wgpu::BindGroup,
+        view: &wgpu::TextureView,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+
+        rpass.set_pipeline(&self.blit_pipeline);
+        rpass.set_push_constants(
+            wgpu::ShaderStage::FRAGMENT,
+            0,
+            UVec2::new(self.resolution.width, self.resolution.height).as_byte_slice(),
+        );
+        rpass.set_bind_group(0, bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+/// A readback of the step counts of a frame, which finishes without blocking
+/// the event loop.
+pub struct StepCount {
+    buffer: wgpu::Buffer,
+    mapping: Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>>>>,
+}
+
+impl StepCount {
+    /// Returns the total number of steps once the device has copied them,
+    /// or `None` if it hasn't yet.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<Result<u64, wgpu::BufferAsyncError>> {
+        device.poll(wgpu::Maintain::Poll);
+
+        // The event loop polls again every frame, so nothing needs to be woken.
+        let waker = noop_waker();
+        match self.mapping.as_mut().poll(&mut Context::from_waker(&waker)) {
+            Poll::Pending => None,
+            Poll::Ready(Err(e)) => Some(Err(e)),
+            Poll::Ready(Ok(())) => {
+                let steps = self
+                    .buffer
+                    .slice(..)
+                    .get_mapped_range()
+                    .chunks_exact(4)
+                    .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]) as u64)
+                    .sum();
+                Some(Ok(steps))
+            }
         }
     }
 }
 
+fn noop_waker() -> Waker {
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(ptr::null(), &VTABLE)
+    }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+    unsafe { Waker::from_raw(clone(ptr::null())) }
+}
+
+fn slice_as_bytes<T>(slice: &[T]) -> &[u8] {
+    unsafe { slice::from_raw_parts(slice.as_ptr() as *const u8, mem::size_of_val(slice)) }
+}
+
+/// Returns the number of 64x64 tiles and 8x8 tiles covering the resolution.
+fn tile_counts(resolution: PhysicalSize<u32>) -> (usize, usize) {
+    let count = |size: u32| {
+        let width = (resolution.width + size - 1) / size;
+        let height = (resolution.height + size - 1) / size;
+        width as usize * height as usize
+    };
+
+    (count(64), count(8))
+}
+
 fn create_blit_bind_group(
     device: &wgpu::Device,
     texture: &wgpu::TextureView,
@@ -375,6 +710,31 @@ fn create_blit_components(
     (bind_group_layout, render_pipeline)
 }
 
+fn create_storage_buffer(device: &wgpu::Device, size: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: size as u64,
+        usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_image_texture(device: &wgpu::Device, size: PhysicalSize<u32>) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: IMAGE_TEXTURE_FORMAT,
+        usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+    })
+}
+
 fn create_texture(device: &wgpu::Device, size: PhysicalSize<u32>) -> wgpu::TextureView {
     device
         .create_texture(&wgpu::TextureDescriptor {
@@ -393,77 +753,174 @@ fn create_texture(device: &wgpu::Device, size: PhysicalSize<u32>) -> wgpu::Textu
         .create_view(&wgpu::TextureViewDescriptor::default())
 }
 
-// fn create_starting_depth_buffer(device: &wgpu::Device, resolution: PhysicalSize<u32>) -> wgpu::Buffer {
-//     let length = (resolution.width as usize * resolution.height as usize + 64 * 64 - 1) / (64 * 64);
-
-//     device.create_buffer(&wgpu::BufferDescriptor {
-//         label: None,
-//         size: (length * mem::size_of::<f32>()) as u64,
-//         usage: wgpu::BufferUsage::STORAGE,
-//         mapped_at_creation: false,
-//     })
-// }
-
-// fn create_cone_trace_components(
-//     device: &wgpu::Device,
-// ) -> (wgpu::BindGroupLayout, wgpu::ComputePipeline) {
-//     let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-//         label: None,
-//         entries: &[
-//             wgpu::BindGroupLayoutEntry {
-//                 binding: 0,
-//                 visibility: wgpu::ShaderStage::COMPUTE,
-//                 ty: wgpu::BindingType::Buffer {
-//                     ty: wgpu::BufferBindingType::Storage { read_only: false },
-//                     has_dynamic_offset: false,
-//                     min_binding_size: None,
-//                 },
-//                 count: None,
-//             },
-//         ],
-//     });
-
-//     let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-//         label: None,
-//         bind_group_layouts: &[&bind_group_layout],
-//         push_constant_ranges: &[wgpu::PushConstantRange {
-//             stages: wgpu::ShaderStage::COMPUTE,
-//             range: 0..mem::size_of::<ConeTracingParams>() as u32,
-//         }],
-//     });
-
-//     let shader = device.create_shader_module(&include_spirv!(env!("spirv://compute_renderer::prerender_cone_trace")));
-
-//     let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-//         label: None,
-//         layout: Some(&pipeline_layout),
-//         module: &shader,
-//         entry_point: "compute_renderer::prerender_cone_trace",
-//     });
-
-//     (bind_group_layout, pipeline)
-// }
-
-// fn create_cone_trace_bind_group(
-//     device: &wgpu::Device,
-//     layout: &wgpu::BindGroupLayout,
-//     starting_depth_buffer: &wgpu::Buffer,
-// ) -> wgpu::BindGroup {
-//     device.create_bind_group(&wgpu::BindGroupDescriptor {
-//         label: None,
-//         layout,
-//         entries: &[
-//             wgpu::BindGroupEntry {
-//                 binding: 0,
-//                 resource: wgpu::BindingResource::Buffer {
-//                     buffer: &starting_depth_buffer,
-//                     offset: 0,
-//                     size: None,
-//                 },
-//             },
-//         ],
-//     })
-// }
+fn create_starting_depth_buffer(
+    device: &wgpu::Device,
+    resolution: PhysicalSize<u32>,
+) -> wgpu::Buffer {
+    let (tiles64x64, _) = tile_counts(resolution);
+
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (tiles64x64 * mem::size_of::<f32>()) as u64,
+        usage: wgpu::BufferUsage::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_steps_buffer(device: &wgpu::Device, resolution: PhysicalSize<u32>) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (resolution.width as usize * resolution.height as usize * mem::size_of::<u32>())
+            as u64,
+        usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_cone_trace_components(
+    device: &wgpu::Device,
+) -> (wgpu::BindGroupLayout, wgpu::ComputePipeline) {
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            storage_buffer_layout_entry(0, true),
+            storage_buffer_layout_entry(1, true),
+            storage_buffer_layout_entry(2, false),
+        ],
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[wgpu::PushConstantRange {
+            stages: wgpu::ShaderStage::COMPUTE,
+            range: 0..mem::size_of::<ConeTracingParams>() as u32,
+        }],
+    });
+
+    let shader = device.create_shader_module(&include_spirv_shader!(env!(
+        "spirv://compute_renderer::cone_push_64x64"
+    )));
+
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: None,
+        layout: Some(&pipeline_layout),
+        module: &shader,
+        entry_point: "compute_renderer::cone_push_64x64",
+    });
+
+    (bind_group_layout, pipeline)
+}
+
+/// The cone tracing pass reads the initial tape from the start of the global tape buffer.
+fn create_cone_trace_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    tiles: &TileBuffers,
+    matrices: &wgpu::Buffer,
+    starting_depth_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[
+            buffer_entry(0, &tiles.tapes),
+            buffer_entry(1, matrices),
+            buffer_entry(2, starting_depth_buffer),
+        ],
+    })
+}
+
+fn storage_buffer_layout_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStage::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn buffer_entry(binding: u32, buffer: &wgpu::Buffer) -> wgpu::BindGroupEntry {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: wgpu::BindingResource::Buffer {
+            buffer,
+            offset: 0,
+            size: None,
+        },
+    }
+}
+
+fn create_tiles_components(
+    device: &wgpu::Device,
+) -> (
+    wgpu::BindGroupLayout,
+    wgpu::ComputePipeline,
+    wgpu::ComputePipeline,
+) {
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            storage_buffer_layout_entry(0, false),
+            storage_buffer_layout_entry(1, true),
+            storage_buffer_layout_entry(2, false),
+            storage_buffer_layout_entry(3, false),
+        ],
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[wgpu::PushConstantRange {
+            stages: wgpu::ShaderStage::COMPUTE,
+            range: 0..mem::size_of::<RenderParams>() as u32,
+        }],
+    });
+
+    let shader64x64 = device.create_shader_module(&include_spirv_shader!(env!(
+        "spirv://compute_renderer::evaluate_ray_volume_64x64_tiles"
+    )));
+    let shader8x8 = device.create_shader_module(&include_spirv_shader!(env!(
+        "spirv://compute_renderer::evaluate_ray_volume_8x8_tiles"
+    )));
+
+    let pipeline64x64 = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: None,
+        layout: Some(&pipeline_layout),
+        module: &shader64x64,
+        entry_point: "compute_renderer::evaluate_ray_volume_64x64_tiles",
+    });
+    let pipeline8x8 = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: None,
+        layout: Some(&pipeline_layout),
+        module: &shader8x8,
+        entry_point: "compute_renderer::evaluate_ray_volume_8x8_tiles",
+    });
+
+    (bind_group_layout, pipeline64x64, pipeline8x8)
+}
+
+fn create_tiles_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    tiles: &TileBuffers,
+    matrices: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[
+            buffer_entry(0, &tiles.tapes),
+            buffer_entry(1, matrices),
+            buffer_entry(2, &tiles.status),
+            buffer_entry(3, &tiles.choices),
+        ],
+    })
+}
 
 fn create_sdf_final_components(
     device: &wgpu::Device,
@@ -481,26 +938,11 @@ fn create_sdf_final_components(
                 },
                 count: None,
             },
-            wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStage::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            wgpu::BindGroupLayoutEntry {
-                binding: 2,
-                visibility: wgpu::ShaderStage::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
+            storage_buffer_layout_entry(1, true),
+            storage_buffer_layout_entry(2, true),
+            storage_buffer_layout_entry(3, true),
+            storage_buffer_layout_entry(4, true),
+            storage_buffer_layout_entry(5, false),
         ],
     });
 
@@ -531,8 +973,10 @@ fn create_sdf_final_bind_group(
     device: &wgpu::Device,
     layout: &wgpu::BindGroupLayout,
     texture: &wgpu::TextureView,
-    tape: &wgpu::Buffer,
+    tiles: &TileBuffers,
     matrices: &wgpu::Buffer,
+    starting_depth_buffer: &wgpu::Buffer,
+    steps_buffer: &wgpu::Buffer,
 ) -> wgpu::BindGroup {
     device.create_bind_group(&wgpu::BindGroupDescriptor {
         label: None,
@@ -542,22 +986,11 @@ fn create_sdf_final_bind_group(
                 binding: 0,
                 resource: wgpu::BindingResource::TextureView(texture),
             },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::Buffer {
-                    buffer: tape,
-                    offset: 0,
-                    size: None,
-                },
-            },
-            wgpu::BindGroupEntry {
-                binding: 2,
-                resource: wgpu::BindingResource::Buffer {
-                    buffer: matrices,
-                    offset: 0,
-                    size: None,
-                },
-            },
+            buffer_entry(1, &tiles.tapes),
+            buffer_entry(2, matrices),
+            buffer_entry(3, &tiles.status),
+            buffer_entry(4, starting_depth_buffer),
+            buffer_entry(5, steps_buffer),
         ],
     })
 }
@@ -1,38 +1,33 @@
-use super::eval::{Eval, Real1 as _};
+use ultraviolet::f32x8;
 
-pub fn union<E: Eval>(lhs: E::R1, rhs: E::R1) -> E::R1 {
+pub fn union(lhs: f32x8, rhs: f32x8) -> f32x8 {
     lhs.min(rhs)
 }
 
-pub fn smooth_union<E: Eval>(lhs: E::R1, rhs: E::R1, k: E::R1) -> E::R1 {
-    let h = (((lhs.clone() - rhs.clone()) / k.clone()) * 0.5 + 0.5)
-        .clamp(E::R1::new(0.0), E::R1::new(1.0));
+pub fn smooth_union(lhs: f32x8, rhs: f32x8, k: f32x8) -> f32x8 {
+    let h = ((rhs - lhs) * 0.5 / k + 0.5)
+        .max(f32x8::splat(0.0))
+        .min(f32x8::splat(1.0));
 
-    h.mix(rhs, lhs) - k * h.clone() * (-h + 1.0)
+    rhs + (lhs - rhs) * h - k * h * (1.0 - h)
 }
 
-pub fn intersection<E: Eval>(lhs: E::R1, rhs: E::R1) -> E::R1 {
+pub fn intersection(lhs: f32x8, rhs: f32x8) -> f32x8 {
     lhs.max(rhs)
 }
 
-pub fn smooth_intersection<E: Eval>(lhs: E::R1, rhs: E::R1, k: E::R1) -> E::R1 {
-    let h = (((lhs.clone() - rhs.clone()) / k.clone()) * -0.5 + 0.5)
-        .clamp(E::R1::new(0.0), E::R1::new(1.0));
+pub fn smooth_intersection(lhs: f32x8, rhs: f32x8, k: f32x8) -> f32x8 {
+    let h = ((lhs - rhs) * 0.5 / k + 0.5)
+        .max(f32x8::splat(0.0))
+        .min(f32x8::splat(1.0));
 
-    h.mix(rhs, lhs) + k * h.clone() * (-h + 1.0)
+    rhs + (lhs - rhs) * h + k * h * (1.0 - h)
 }
 
-pub fn subtraction<E: Eval>(lhs: E::R1, rhs: E::R1) -> E::R1 {
+pub fn subtraction(lhs: f32x8, rhs: f32x8) -> f32x8 {
     (-lhs).max(rhs)
 }
 
-pub fn smooth_subtraction<E: Eval>(lhs: E::R1, rhs: E::R1, k: E::R1) -> E::R1 {
-    let h = (((lhs.clone() - rhs.clone()) / k.clone()) * -0.5 + 0.5)
-        .clamp(E::R1::new(0.0), E::R1::new(1.0));
-
-    h.mix(rhs, -lhs) + k * h.clone() * (-h + 1.0)
+pub fn smooth_subtraction(lhs: f32x8, rhs: f32x8, k: f32x8) -> f32x8 {
+    smooth_intersection(-lhs, rhs, k)
 }
-
-// pub fn translate<E: Eval>(d: E::R1, by: E::R13) -> E::R1 {
-
-// }
@@ -0,0 +1,705 @@
+//! Forward mode automatic differentiation, for the gradient of the distance.
+
+use shared::inst::{
+    Abs, Bcc, Capsule, Cone, Constant, Coordinate, Cos, Cylinder, Diamond, Ellipsoid, Fcc, Frd,
+    GradedSheet, Gyroid, Iwp, Kelvin, Lidinoid, Negation, Neovius, Octet, Op, Plane,
+    RectangularPrism, SchwarzP, Sin, SmoothIntersection, SmoothSubtraction, SmoothUnion, Sphere,
+    Sqrt, Torus, Voronoi, REGISTER_COUNT,
+};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use ultraviolet::{Mat4, Vec3};
+
+use super::{
+    struts::{self, Strut},
+    voronoi,
+};
+use crate::tree::Tape;
+
+/// A value and its derivatives with respect to x, y and z.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deriv {
+    pub value: f32,
+    pub gradient: Vec3,
+}
+
+impl Deriv {
+    pub fn constant(value: f32) -> Self {
+        Self {
+            value,
+            gradient: Vec3::zero(),
+        }
+    }
+
+    pub fn min(self, rhs: Self) -> Self {
+        if self.value <= rhs.value {
+            self
+        } else {
+            rhs
+        }
+    }
+
+    pub fn max(self, rhs: Self) -> Self {
+        if self.value >= rhs.value {
+            self
+        } else {
+            rhs
+        }
+    }
+
+    pub fn abs(self) -> Self {
+        if self.value < 0.0 {
+            -self
+        } else {
+            self
+        }
+    }
+
+    pub fn sqrt(self) -> Self {
+        let value = self.value.sqrt();
+        let gradient = if value > 0.0 {
+            self.gradient * (0.5 / value)
+        } else {
+            Vec3::zero()
+        };
+        Self { value, gradient }
+    }
+
+    pub fn clamp(self, low: f32, high: f32) -> Self {
+        self.max(Self::constant(low)).min(Self::constant(high))
+    }
+
+    pub fn sin(self) -> Self {
+        Self {
+            value: self.value.sin(),
+            gradient: self.gradient * self.value.cos(),
+        }
+    }
+
+    pub fn cos(self) -> Self {
+        Self {
+            value: self.value.cos(),
+            gradient: self.gradient * -self.value.sin(),
+        }
+    }
+}
+
+impl Neg for Deriv {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self {
+            value: -self.value,
+            gradient: -self.gradient,
+        }
+    }
+}
+
+impl Add for Deriv {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            value: self.value + rhs.value,
+            gradient: self.gradient + rhs.gradient,
+        }
+    }
+}
+
+impl Add<f32> for Deriv {
+    type Output = Self;
+
+    fn add(self, rhs: f32) -> Self {
+        Self {
+            value: self.value + rhs,
+            ..self
+        }
+    }
+}
+
+impl Sub for Deriv {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self + -rhs
+    }
+}
+
+impl Sub<f32> for Deriv {
+    type Output = Self;
+
+    fn sub(self, rhs: f32) -> Self {
+        self + -rhs
+    }
+}
+
+impl Mul for Deriv {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self {
+            value: self.value * rhs.value,
+            gradient: self.gradient * rhs.value + rhs.gradient * self.value,
+        }
+    }
+}
+
+impl Mul<f32> for Deriv {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self {
+        Self {
+            value: self.value * rhs,
+            gradient: self.gradient * rhs,
+        }
+    }
+}
+
+impl Div for Deriv {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        Self {
+            value: self.value / rhs.value,
+            gradient: (self.gradient * rhs.value - rhs.gradient * self.value)
+                / (rhs.value * rhs.value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Deriv3 {
+    x: Deriv,
+    y: Deriv,
+    z: Deriv,
+}
+
+impl Deriv3 {
+    fn map(self, f: impl Fn(Deriv) -> Deriv) -> Self {
+        Self {
+            x: f(self.x),
+            y: f(self.y),
+            z: f(self.z),
+        }
+    }
+
+    fn mag(self) -> Deriv {
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+}
+
+fn transform_deriv3(mat: &Mat4, p: Deriv3) -> Deriv3 {
+    let [x_axis, y_axis, z_axis, w_axis] = mat.cols;
+    Deriv3 {
+        x: p.x * x_axis.x + p.y * y_axis.x + p.z * z_axis.x + w_axis.x,
+        y: p.x * x_axis.y + p.y * y_axis.y + p.z * z_axis.y + w_axis.y,
+        z: p.x * x_axis.z + p.y * y_axis.z + p.z * z_axis.z + w_axis.z,
+    }
+}
+
+fn rectangular_prism(p: Deriv3, sides: Vec3) -> Deriv {
+    let q = Deriv3 {
+        x: p.x.abs() - sides.x,
+        y: p.y.abs() - sides.y,
+        z: p.z.abs() - sides.z,
+    };
+    let zero = Deriv::constant(0.0);
+
+    q.map(|c| c.max(zero)).mag() + q.x.max(q.y.max(q.z)).min(zero)
+}
+
+fn length2(x: Deriv, y: Deriv) -> Deriv {
+    (x * x + y * y).sqrt()
+}
+
+fn cylinder(p: Deriv3, radius: f32, half_height: f32) -> Deriv {
+    let zero = Deriv::constant(0.0);
+    let dx = length2(p.x, p.z) - radius;
+    let dy = p.y.abs() - half_height;
+    dx.max(dy).min(zero) + length2(dx.max(zero), dy.max(zero))
+}
+
+fn torus(p: Deriv3, major_radius: f32, minor_radius: f32) -> Deriv {
+    length2(length2(p.x, p.z) - major_radius, p.y) - minor_radius
+}
+
+fn capsule(p: Deriv3, radius: f32, half_height: f32) -> Deriv {
+    let y = p.y - p.y.clamp(-half_height, half_height);
+    Deriv3 { y, ..p }.mag() - radius
+}
+
+fn cone(p: Deriv3, bottom_radius: f32, top_radius: f32, half_height: f32) -> Deriv {
+    let (qx, qy) = (length2(p.x, p.z), p.y);
+    let (k2x, k2y) = (top_radius - bottom_radius, 2.0 * half_height);
+
+    let radius = if qy.value < 0.0 {
+        bottom_radius
+    } else {
+        top_radius
+    };
+    let cap_x = qx - qx.min(Deriv::constant(radius));
+    let cap_y = qy.abs() - half_height;
+    let t =
+        ((-qx + top_radius) * k2x + (-qy + half_height) * k2y) * (1.0 / (k2x * k2x + k2y * k2y));
+    let t = t.clamp(0.0, 1.0);
+    let side_x = qx - top_radius + t * k2x;
+    let side_y = qy - half_height + t * k2y;
+
+    let d = (cap_x * cap_x + cap_y * cap_y)
+        .min(side_x * side_x + side_y * side_y)
+        .sqrt();
+    if side_x.value < 0.0 && cap_y.value < 0.0 {
+        -d
+    } else {
+        d
+    }
+}
+
+fn plane(p: Deriv3, normal: Vec3, offset: f32) -> Deriv {
+    p.x * normal.x + p.y * normal.y + p.z * normal.z - offset
+}
+
+fn ellipsoid(p: Deriv3, radii: Vec3) -> Deriv {
+    let k0 = Deriv3 {
+        x: p.x * (1.0 / radii.x),
+        y: p.y * (1.0 / radii.y),
+        z: p.z * (1.0 / radii.z),
+    }
+    .mag();
+    let k1 = Deriv3 {
+        x: p.x * (1.0 / (radii.x * radii.x)),
+        y: p.y * (1.0 / (radii.y * radii.y)),
+        z: p.z * (1.0 / (radii.z * radii.z)),
+    }
+    .mag();
+    if k1.value == 0.0 {
+        return Deriv::constant(-radii.component_min());
+    }
+    k0 * (k0 - 1.0) / k1
+}
+
+fn sheet(g: Deriv, scale: Deriv, thickness: f32, lipschitz: f32) -> Deriv {
+    (g.abs() * scale - thickness) * (1.0 / lipschitz)
+}
+
+fn sin_cos(p: Deriv3, frequency: Deriv) -> (Deriv3, Deriv3) {
+    let p = p.map(|c| c * frequency);
+    (p.map(Deriv::sin), p.map(Deriv::cos))
+}
+
+fn gyroid(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let (s, c) = sin_cos(p, Deriv::constant(1.0) / scale);
+    let g = s.x * c.z + s.y * c.x + s.z * c.y;
+    sheet(g, scale, thickness, Gyroid::LIPSCHITZ)
+}
+
+fn schwarz_p(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let (_, c) = sin_cos(p, Deriv::constant(1.0) / scale);
+    sheet(c.x + c.y + c.z, scale, thickness, SchwarzP::LIPSCHITZ)
+}
+
+fn diamond(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let (s, c) = sin_cos(p, Deriv::constant(1.0) / scale);
+    let g = s.x * s.y * s.z + s.x * c.y * c.z + c.x * s.y * c.z + c.x * c.y * s.z;
+    sheet(g, scale, thickness, Diamond::LIPSCHITZ)
+}
+
+fn neovius(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let (_, c) = sin_cos(p, Deriv::constant(1.0) / scale);
+    let g = (c.x + c.y + c.z) * 3.0 + c.x * c.y * c.z * 4.0;
+    sheet(g, scale, thickness, Neovius::LIPSCHITZ)
+}
+
+fn iwp(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let frequency = Deriv::constant(1.0) / scale;
+    let ((_, c), (_, c2)) = (sin_cos(p, frequency), sin_cos(p, frequency * 2.0));
+    let g = (c.x * c.y + c.y * c.z + c.z * c.x) * 2.0 - (c2.x + c2.y + c2.z);
+    sheet(g, scale, thickness, Iwp::LIPSCHITZ)
+}
+
+fn lidinoid(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let frequency = Deriv::constant(1.0) / scale;
+    let ((s, c), (s2, c2)) = (sin_cos(p, frequency), sin_cos(p, frequency * 2.0));
+    let g = (s2.x * c.y * s.z + s2.y * c.z * s.x + s2.z * c.x * s.y) * 0.5
+        - (c2.x * c2.y + c2.y * c2.z + c2.z * c2.x) * 0.5
+        + 0.15;
+    sheet(g, scale, thickness, Lidinoid::LIPSCHITZ)
+}
+
+fn frd(p: Deriv3, scale: Deriv, thickness: f32) -> Deriv {
+    let frequency = Deriv::constant(1.0) / scale;
+    let ((_, c), (_, c2)) = (sin_cos(p, frequency), sin_cos(p, frequency * 2.0));
+    let g = c.x * c.y * c.z * 4.0 - (c2.x * c2.y + c2.y * c2.z + c2.z * c2.x);
+    sheet(g, scale, thickness, Frd::LIPSCHITZ)
+}
+
+fn graded_sheet(sheet: Op, p: Deriv3, scale: Deriv, half_extents: [f32; 3]) -> Deriv {
+    let [hx, hy, hz] = half_extents;
+    let p = Deriv3 {
+        x: p.x.clamp(-hx, hx),
+        y: p.y.clamp(-hy, hy),
+        z: p.z.clamp(-hz, hz),
+    };
+    match sheet {
+        Op::Gyroid => gyroid(p, scale, 0.0),
+        Op::SchwarzP => schwarz_p(p, scale, 0.0),
+        Op::Diamond => diamond(p, scale, 0.0),
+        Op::Neovius => neovius(p, scale, 0.0),
+        Op::Iwp => iwp(p, scale, 0.0),
+        Op::Lidinoid => lidinoid(p, scale, 0.0),
+        Op::Frd => frd(p, scale, 0.0),
+        _ => unreachable!("{:?} isn't a sheet", sheet),
+    }
+}
+
+/// Rounding is flat between its steps, so folding keeps the gradient,
+/// flipped where `abs` flips it.
+fn lattice(p: Deriv3, cell_size: f32, strut_radius: f32, cell_struts: &[Strut]) -> Deriv {
+    let q = p.map(|c| (c - cell_size * (c.value / cell_size).round()).abs());
+    let half = cell_size * 0.5;
+
+    let segment = |&(a, b): &Strut| {
+        let (a, b) = (Vec3::from(a) * half, Vec3::from(b) * half);
+        let ba = b - a;
+        let pa = Deriv3 {
+            x: q.x - a.x,
+            y: q.y - a.y,
+            z: q.z - a.z,
+        };
+        let t = ((pa.x * ba.x + pa.y * ba.y + pa.z * ba.z) * (1.0 / ba.mag_sq())).clamp(0.0, 1.0);
+        Deriv3 {
+            x: pa.x - t * ba.x,
+            y: pa.y - t * ba.y,
+            z: pa.z - t * ba.z,
+        }
+        .mag()
+    };
+    cell_struts
+        .iter()
+        .map(segment)
+        .fold(Deriv::constant(f32::INFINITY), Deriv::min)
+        - strut_radius
+}
+
+/// The closest wall is flat, so the distance grows along its normal.
+fn foam(p: Deriv3, cell_size: f32, thickness: f32, seed: u32) -> Deriv {
+    let point = Vec3::new(p.x.value, p.y.value, p.z.value);
+    let (d, direction) = voronoi::walls(point, cell_size, seed);
+    let along = p.x * direction.x + p.y * direction.y + p.z * direction.z;
+    along + (d - thickness - along.value)
+}
+
+fn smooth_union(lhs: Deriv, rhs: Deriv, k: f32) -> Deriv {
+    let h = ((rhs - lhs) * (0.5 / k) + 0.5).clamp(0.0, 1.0);
+    rhs + (lhs - rhs) * h - h * (-h + 1.0) * k
+}
+
+fn smooth_intersection(lhs: Deriv, rhs: Deriv, k: f32) -> Deriv {
+    let h = ((lhs - rhs) * (0.5 / k) + 0.5).clamp(0.0, 1.0);
+    rhs + (lhs - rhs) * h + h * (-h + 1.0) * k
+}
+
+impl Tape {
+    /// Evaluates the distance and its gradient at `p`.
+    pub fn eval_deriv(&self, p: Vec3) -> Deriv {
+        let mut regs = [Deriv::constant(0.0); REGISTER_COUNT];
+        let p = Deriv3 {
+            x: Deriv {
+                value: p.x,
+                gradient: Vec3::unit_x(),
+            },
+            y: Deriv {
+                value: p.y,
+                gradient: Vec3::unit_y(),
+            },
+            z: Deriv {
+                value: p.z,
+                gradient: Vec3::unit_z(),
+            },
+        };
+
+        for &inst in &self.insts {
+            let d = match inst.op() {
+                Op::Ret => return regs[inst.reg()],
+
+                // Combinations
+                Op::Union => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs].min(regs[rhs])
+                }
+                Op::Intersection => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs].max(regs[rhs])
+                }
+                Op::Subtraction => {
+                    let (lhs, rhs) = inst.operands();
+                    (-regs[lhs]).max(regs[rhs])
+                }
+                Op::SmoothUnion => {
+                    let su = inst.extract::<SmoothUnion>();
+                    smooth_union(regs[su.lhs], regs[su.rhs], su.k)
+                }
+                Op::SmoothIntersection => {
+                    let si = inst.extract::<SmoothIntersection>();
+                    smooth_intersection(regs[si.lhs], regs[si.rhs], si.k)
+                }
+                Op::SmoothSubtraction => {
+                    let ss = inst.extract::<SmoothSubtraction>();
+                    smooth_intersection(-regs[ss.lhs], regs[ss.rhs], ss.k)
+                }
+
+                // Shapes
+                Op::Sphere => {
+                    let s = inst.extract::<Sphere>();
+                    transform_deriv3(&self.matrices[s.matrix_idx], p).mag() - s.radius
+                }
+                Op::RectangularPrism => {
+                    let prism = inst.extract::<RectangularPrism>();
+                    let p = transform_deriv3(&self.matrices[prism.matrix_idx], p);
+                    rectangular_prism(p, Vec3::new(prism.x, prism.y, prism.z))
+                }
+                Op::Cylinder => {
+                    let c = inst.extract::<Cylinder>();
+                    let p = transform_deriv3(&self.matrices[c.matrix_idx], p);
+                    cylinder(p, c.radius, c.half_height)
+                }
+                Op::Torus => {
+                    let t = inst.extract::<Torus>();
+                    let p = transform_deriv3(&self.matrices[t.matrix_idx], p);
+                    torus(p, t.major_radius, t.minor_radius)
+                }
+                Op::Capsule => {
+                    let c = inst.extract::<Capsule>();
+                    let p = transform_deriv3(&self.matrices[c.matrix_idx], p);
+                    capsule(p, c.radius, c.half_height)
+                }
+                Op::Cone => {
+                    let c = inst.extract::<Cone>();
+                    let p = transform_deriv3(&self.matrices[c.matrix_idx], p);
+                    cone(p, c.bottom_radius, c.top_radius, c.half_height)
+                }
+                Op::Plane => {
+                    let plane_inst = inst.extract::<Plane>();
+                    let p = transform_deriv3(&self.matrices[plane_inst.matrix_idx], p);
+                    let normal = Vec3::new(
+                        plane_inst.normal_x,
+                        plane_inst.normal_y,
+                        plane_inst.normal_z,
+                    );
+                    plane(p, normal, plane_inst.offset)
+                }
+                Op::Ellipsoid => {
+                    let e = inst.extract::<Ellipsoid>();
+                    let p = transform_deriv3(&self.matrices[e.matrix_idx], p);
+                    ellipsoid(p, Vec3::new(e.x, e.y, e.z))
+                }
+
+                // Fills
+                Op::Gyroid => {
+                    let g = inst.extract::<Gyroid>();
+                    let p = transform_deriv3(&self.matrices[g.matrix_idx], p);
+                    gyroid(p, Deriv::constant(g.scale), g.thickness)
+                }
+                Op::SchwarzP => {
+                    let sp = inst.extract::<SchwarzP>();
+                    let p = transform_deriv3(&self.matrices[sp.matrix_idx], p);
+                    schwarz_p(p, Deriv::constant(sp.scale), sp.thickness)
+                }
+                Op::Diamond => {
+                    let d = inst.extract::<Diamond>();
+                    let p = transform_deriv3(&self.matrices[d.matrix_idx], p);
+                    diamond(p, Deriv::constant(d.scale), d.thickness)
+                }
+                Op::Neovius => {
+                    let n = inst.extract::<Neovius>();
+                    let p = transform_deriv3(&self.matrices[n.matrix_idx], p);
+                    neovius(p, Deriv::constant(n.scale), n.thickness)
+                }
+                Op::Iwp => {
+                    let iw = inst.extract::<Iwp>();
+                    let p = transform_deriv3(&self.matrices[iw.matrix_idx], p);
+                    iwp(p, Deriv::constant(iw.scale), iw.thickness)
+                }
+                Op::Lidinoid => {
+                    let l = inst.extract::<Lidinoid>();
+                    let p = transform_deriv3(&self.matrices[l.matrix_idx], p);
+                    lidinoid(p, Deriv::constant(l.scale), l.thickness)
+                }
+                Op::Frd => {
+                    let fr = inst.extract::<Frd>();
+                    let p = transform_deriv3(&self.matrices[fr.matrix_idx], p);
+                    frd(p, Deriv::constant(fr.scale), fr.thickness)
+                }
+
+                // Strut fills
+                Op::Bcc => {
+                    let b = inst.extract::<Bcc>();
+                    let p = transform_deriv3(&self.matrices[b.matrix_idx], p);
+                    lattice(p, b.cell_size, b.strut_radius, struts::BCC)
+                }
+                Op::Fcc => {
+                    let f = inst.extract::<Fcc>();
+                    let p = transform_deriv3(&self.matrices[f.matrix_idx], p);
+                    lattice(p, f.cell_size, f.strut_radius, struts::FCC)
+                }
+                Op::Octet => {
+                    let o = inst.extract::<Octet>();
+                    let p = transform_deriv3(&self.matrices[o.matrix_idx], p);
+                    lattice(p, o.cell_size, o.strut_radius, struts::OCTET)
+                }
+                Op::Kelvin => {
+                    let k = inst.extract::<Kelvin>();
+                    let p = transform_deriv3(&self.matrices[k.matrix_idx], p);
+                    lattice(p, k.cell_size, k.strut_radius, struts::KELVIN)
+                }
+                Op::Voronoi => {
+                    let v = inst.extract::<Voronoi>();
+                    let p = transform_deriv3(&self.matrices[v.matrix_idx], p);
+                    foam(p, v.cell_size, v.thickness, v.seed)
+                }
+
+                // Values that vary over space
+                Op::Constant => Deriv::constant(inst.extract::<Constant>().value),
+                Op::Coordinate => {
+                    let c = inst.extract::<Coordinate>();
+                    let p = transform_deriv3(&self.matrices[c.matrix_idx], p);
+                    [p.x, p.y, p.z][c.axis].clamp(-c.half_extent, c.half_extent)
+                }
+                Op::Sum => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs] + regs[rhs]
+                }
+                Op::Difference => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs] - regs[rhs]
+                }
+                Op::Product => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs] * regs[rhs]
+                }
+                Op::Quotient => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs] / regs[rhs]
+                }
+                Op::Negation => -regs[inst.extract::<Negation>().operand],
+                Op::Abs => regs[inst.extract::<Abs>().operand].abs(),
+                Op::Sqrt => regs[inst.extract::<Sqrt>().operand].sqrt(),
+                Op::Sin => regs[inst.extract::<Sin>().operand].sin(),
+                Op::Cos => regs[inst.extract::<Cos>().operand].cos(),
+                Op::GradedSheet => {
+                    let g = inst.extract::<GradedSheet>();
+                    let p = transform_deriv3(&self.matrices[g.matrix_idx], p);
+                    graded_sheet(g.sheet, p, regs[g.scale], g.half_extents)
+                }
+            };
+
+            regs[inst.reg()] = d;
+        }
+
+        panic!("the tape doesn't end with a return")
+    }
+}
+
+/// The distance at `p`, and its gradient estimated with central differences,
+/// evaluated in one batch.
+#[cfg(test)]
+pub(super) fn central_differences(tape: &Tape, p: Vec3) -> (f32, Vec3) {
+    let h = 1e-3;
+    let offsets = [
+        Vec3::new(h, 0.0, 0.0),
+        Vec3::new(-h, 0.0, 0.0),
+        Vec3::new(0.0, h, 0.0),
+        Vec3::new(0.0, -h, 0.0),
+        Vec3::new(0.0, 0.0, h),
+        Vec3::new(0.0, 0.0, -h),
+        Vec3::zero(),
+        Vec3::zero(),
+    ];
+    let v: [f32; 8] = tape
+        .eval(ultraviolet::Vec3x8::splat(p) + ultraviolet::Vec3x8::from(offsets))
+        .into();
+    let differences = Vec3::new(v[0] - v[1], v[2] - v[3], v[4] - v[5]) / (2.0 * h);
+    (v[6], differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{ConstantOrExpr, CsgNode, CsgTree, Shape};
+
+    fn shapes() -> Vec<Shape> {
+        let c = ConstantOrExpr::Constant;
+        vec![
+            Shape::Cylinder {
+                radius: c(0.8),
+                half_height: c(1.2),
+            },
+            Shape::Torus {
+                major_radius: c(1.0),
+                minor_radius: c(0.3),
+            },
+            Shape::Capsule {
+                radius: c(0.5),
+                half_height: c(0.9),
+            },
+            Shape::Cone {
+                bottom_radius: c(1.0),
+                top_radius: c(0.2),
+                half_height: c(1.1),
+            },
+            Shape::Plane {
+                normal_x: c(1.0),
+                normal_y: c(2.0),
+                normal_z: c(-0.5),
+                offset: c(0.3),
+            },
+            Shape::Ellipsoid {
+                radius_x: c(1.2),
+                radius_y: c(0.6),
+                radius_z: c(0.9),
+            },
+        ]
+    }
+
+    #[test]
+    fn gradient_matches_differences() {
+        let tape = CsgTree::new_example().compile().unwrap();
+
+        for &p in &[
+            Vec3::new(0.3, 1.2, -0.4),
+            Vec3::new(1.1, 0.9, 0.2),
+            Vec3::new(2.0, -0.3, 0.5),
+            Vec3::new(-1.4, -1.6, 1.3),
+        ] {
+            let d = tape.eval_deriv(p);
+            let (value, differences) = central_differences(&tape, p);
+            assert!((d.value - value).abs() < 1e-5);
+            assert!((d.gradient - differences).mag() < 1e-2, "at {:?}", p);
+        }
+    }
+
+    #[test]
+    fn primitives() {
+        for shape in shapes() {
+            let tree = CsgTree::new(CsgNode::Shape(shape, None));
+            let name = tree.to_string();
+            let tape = tree.compile().unwrap();
+
+            for i in 0..64 {
+                let c = |i: usize| (i % 4) as f32 * 0.9 - 1.4;
+                let p = Vec3::new(c(i), c(i / 4), c(i / 16));
+                let d = tape.eval_deriv(p);
+                let (value, differences) = central_differences(&tape, p);
+
+                assert!((d.value - value).abs() < 1e-5, "{} at {:?}", name, p);
+                assert!(
+                    (d.gradient - differences).mag() < 1e-2,
+                    "{} at {:?}: {:?} != {:?}",
+                    name,
+                    p,
+                    d.gradient,
+                    differences
+                );
+            }
+        }
+    }
+}
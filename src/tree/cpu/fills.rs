@@ -1,28 +1,216 @@
-use ultraviolet::{f32x8, Vec3x8};
+use shared::inst::{Diamond, Frd, Gyroid, Iwp, Lidinoid, Neovius, Op, SchwarzP};
+use ultraviolet::{f32x8, Vec3, Vec3x8};
 
-// float sdf_gyroid(vec3 p, float scale, float thickness) {
-//     p *= scale;
-//     return (abs(dot(sin(p), cos(p.zxy))) / scale - thickness) * 0.6;
-// }
+/// The sheet where `|g| * scale < thickness`, for the implicit function `g` of a
+/// lattice at `p / scale`, whose cells are `2π * scale` wide. `g` isn't a distance,
+/// so it's divided by its largest gradient to keep it from overestimating one.
+fn sheet(g: f32x8, scale: f32x8, thickness: f32x8, lipschitz: f32) -> f32x8 {
+    (g.abs() * scale - thickness) / f32x8::splat(lipschitz)
+}
+
+fn sin_cos(p: Vec3x8) -> (Vec3x8, Vec3x8) {
+    (p.map(|c| c.sin()), p.map(|c| c.cos()))
+}
 
 pub fn gyroid(p: Vec3x8, scale: f32x8, thickness: f32x8) -> f32x8 {
+    let (s, c) = sin_cos(p / scale);
+    let g = s.dot(Vec3x8::new(c.z, c.x, c.y));
+    sheet(g, scale, thickness, Gyroid::LIPSCHITZ)
+}
+
+pub fn schwarz_p(p: Vec3x8, scale: f32x8, thickness: f32x8) -> f32x8 {
+    let (_, c) = sin_cos(p / scale);
+    sheet(c.x + c.y + c.z, scale, thickness, SchwarzP::LIPSCHITZ)
+}
+
+pub fn diamond(p: Vec3x8, scale: f32x8, thickness: f32x8) -> f32x8 {
+    let (s, c) = sin_cos(p / scale);
+    let g = s.x * s.y * s.z + s.x * c.y * c.z + c.x * s.y * c.z + c.x * c.y * s.z;
+    sheet(g, scale, thickness, Diamond::LIPSCHITZ)
+}
+
+pub fn neovius(p: Vec3x8, scale: f32x8, thickness: f32x8) -> f32x8 {
+    let (_, c) = sin_cos(p / scale);
+    let g = (c.x + c.y + c.z) * 3.0 + c.x * c.y * c.z * 4.0;
+    sheet(g, scale, thickness, Neovius::LIPSCHITZ)
+}
+
+pub fn iwp(p: Vec3x8, scale: f32x8, thickness: f32x8) -> f32x8 {
     let p = p / scale;
-    (p.map(|c| c.sin())
-        .dot(Vec3x8::new(p.z, p.x, p.y).map(|c| c.cos()))
-        .abs()
-        * scale
-        - thickness)
-        * 0.6
+    let ((_, c), (_, c2)) = (sin_cos(p), sin_cos(p * f32x8::splat(2.0)));
+    let g = (c.x * c.y + c.y * c.z + c.z * c.x) * 2.0 - (c2.x + c2.y + c2.z);
+    sheet(g, scale, thickness, Iwp::LIPSCHITZ)
 }
 
-// float sdf_schwarzP(vec3 p, float scale, float thickness) {
-//     p *= scale;
-//     float implicit = abs(dot(cos(p), vec3(1.0)));
-//     // float grad = length(sin(p));
-//     return ((implicit) / scale - thickness) * 0.6;
-// }
+pub fn lidinoid(p: Vec3x8, scale: f32x8, thickness: f32x8) -> f32x8 {
+    let p = p / scale;
+    let ((s, c), (s2, c2)) = (sin_cos(p), sin_cos(p * f32x8::splat(2.0)));
+    let g = (s2.x * c.y * s.z + s2.y * c.z * s.x + s2.z * c.x * s.y) * 0.5
+        - (c2.x * c2.y + c2.y * c2.z + c2.z * c2.x) * 0.5
+        + f32x8::splat(0.15);
+    sheet(g, scale, thickness, Lidinoid::LIPSCHITZ)
+}
 
-pub fn schwarz_p(p: Vec3x8, scale: f32x8, thickness: f32x8) -> f32x8 {
+pub fn frd(p: Vec3x8, scale: f32x8, thickness: f32x8) -> f32x8 {
     let p = p / scale;
-    (p.map(|c| c.cos()).dot(Vec3x8::one()).abs() * scale - thickness) * 0.6
+    let ((_, c), (_, c2)) = (sin_cos(p), sin_cos(p * f32x8::splat(2.0)));
+    let g = c.x * c.y * c.z * 4.0 - (c2.x * c2.y + c2.y * c2.z + c2.z * c2.x);
+    sheet(g, scale, thickness, Frd::LIPSCHITZ)
+}
+
+/// The sheet of the fill `sheet` without its thickness, for a scale that varies over space.
+/// `p` is clamped to the box with the half sides `half_extents` around the filled shape,
+/// where the scale is known to be bounded.
+pub fn graded_sheet(sheet: Op, p: Vec3x8, scale: f32x8, half_extents: Vec3) -> f32x8 {
+    let half_extents = Vec3x8::splat(half_extents);
+    let p = p.clamped(-half_extents, half_extents);
+    let zero = f32x8::splat(0.0);
+    match sheet {
+        Op::Gyroid => gyroid(p, scale, zero),
+        Op::SchwarzP => schwarz_p(p, scale, zero),
+        Op::Diamond => diamond(p, scale, zero),
+        Op::Neovius => neovius(p, scale, zero),
+        Op::Iwp => iwp(p, scale, zero),
+        Op::Lidinoid => lidinoid(p, scale, zero),
+        Op::Frd => frd(p, scale, zero),
+        _ => unreachable!("{:?} isn't a sheet", sheet),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tree::cpu::deriv::central_differences;
+    use crate::tree::{ConstantOrExpr, CsgNode, CsgTree, Fill, Shape};
+    use std::rc::Rc;
+    use ultraviolet::Vec3;
+
+    fn filled_box(fill: Fill) -> CsgNode {
+        let c = ConstantOrExpr::Constant;
+        CsgNode::Shape(
+            Shape::Box {
+                side_x: c(1.5),
+                side_y: c(1.5),
+                side_z: c(1.5),
+            },
+            Some(fill),
+        )
+    }
+
+    /// Struts are turned, so that the points on the diagonals of the grids in
+    /// the tests aren't on their axes, where the gradient is undefined.
+    fn turned(node: CsgNode) -> CsgNode {
+        let c = ConstantOrExpr::Constant;
+        CsgNode::Rotate {
+            roll: c(0.3),
+            pitch: c(0.2),
+            yaw: c(0.1),
+            node: Rc::new(node),
+        }
+    }
+
+    /// Boxes filled with each lattice.
+    fn filled() -> Vec<CsgNode> {
+        let c = ConstantOrExpr::Constant;
+        let sheets: [fn(ConstantOrExpr, ConstantOrExpr) -> Fill; 7] = [
+            |scale, thickness| Fill::Gyroid { scale, thickness },
+            |scale, thickness| Fill::SchwarzP { scale, thickness },
+            |scale, thickness| Fill::Diamond { scale, thickness },
+            |scale, thickness| Fill::Neovius { scale, thickness },
+            |scale, thickness| Fill::Iwp { scale, thickness },
+            |scale, thickness| Fill::Lidinoid { scale, thickness },
+            |scale, thickness| Fill::Frd { scale, thickness },
+        ];
+        let struts: [fn(ConstantOrExpr, ConstantOrExpr) -> Fill; 4] = [
+            |cell_size, strut_radius| Fill::Bcc {
+                cell_size,
+                strut_radius,
+            },
+            |cell_size, strut_radius| Fill::Fcc {
+                cell_size,
+                strut_radius,
+            },
+            |cell_size, strut_radius| Fill::Octet {
+                cell_size,
+                strut_radius,
+            },
+            |cell_size, strut_radius| Fill::Kelvin {
+                cell_size,
+                strut_radius,
+            },
+        ];
+        let sheets = sheets.iter().map(|fill| filled_box(fill(c(0.3), c(0.05))));
+        let struts = struts
+            .iter()
+            .map(|fill| turned(filled_box(fill(c(0.8), c(0.1)))));
+        let foam = filled_box(Fill::Voronoi {
+            cell_size: c(0.6),
+            thickness: c(0.05),
+            seed: 2,
+        });
+        sheets.chain(struts).chain(Some(foam)).collect()
+    }
+
+    /// Boxes filled with lattices whose sizes vary over space.
+    fn graded() -> Vec<CsgNode> {
+        let parse = |source| ConstantOrExpr::parse(source).unwrap();
+        vec![
+            filled_box(Fill::Gyroid {
+                scale: parse("0.3 + x/20"),
+                thickness: parse("0.05 + 0.02*sin(y)"),
+            }),
+            filled_box(Fill::Neovius {
+                scale: parse("0.3"),
+                thickness: parse("(z + 2)^2 / 100"),
+            }),
+            turned(filled_box(Fill::Kelvin {
+                cell_size: parse("0.8"),
+                strut_radius: parse("0.1 + max(x, -y)/20"),
+            })),
+            filled_box(Fill::Voronoi {
+                cell_size: parse("0.6"),
+                thickness: parse("0.05 + z/40"),
+                seed: 4,
+            }),
+        ]
+    }
+
+    #[test]
+    fn gradient_matches_differences() {
+        for node in filled().into_iter().chain(graded()) {
+            let tree = CsgTree::new(node);
+            let tape = tree.compile().unwrap();
+
+            for i in 0..64 {
+                let c = |i: usize| (i % 4) as f32 * 0.9 - 1.4;
+                let p = Vec3::new(c(i), c(i / 4), c(i / 16));
+                let d = tape.eval_deriv(p);
+                let (value, differences) = central_differences(&tape, p);
+
+                assert!((d.value - value).abs() < 1e-5, "{} at {:?}", tree, p);
+                assert!(
+                    (d.gradient - differences).mag() < 1e-2,
+                    "{} at {:?}: {:?} != {:?}",
+                    tree,
+                    p,
+                    d.gradient,
+                    differences
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fills_never_overestimate() {
+        for node in filled().into_iter().chain(graded()) {
+            let tree = CsgTree::new(node);
+            let tape = tree.compile().unwrap();
+
+            for i in 0..1000 {
+                let c = |i: usize| (i % 10) as f32 * 0.29 - 1.3;
+                let p = Vec3::new(c(i), c(i / 10), c(i / 100));
+                let gradient = tape.eval_deriv(p).gradient;
+                assert!(gradient.mag() <= 1.0 + 1e-5, "{} at {:?}", tree, p);
+            }
+        }
+    }
 }
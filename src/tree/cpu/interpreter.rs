@@ -0,0 +1,341 @@
+use shared::inst::{
+    Abs, Bcc, Capsule, Cone, Constant, Coordinate, Cos, Cylinder, Diamond, Ellipsoid, Fcc, Frd,
+    GradedSheet, Gyroid, Iwp, Kelvin, Lidinoid, Negation, Neovius, Octet, Op, Plane,
+    RectangularPrism, SchwarzP, Sin, SmoothIntersection, SmoothSubtraction, SmoothUnion, Sphere,
+    Sqrt, Torus, Voronoi, REGISTER_COUNT,
+};
+use ultraviolet::{f32x8, Mat4, Vec3, Vec3x8};
+
+use super::{combinations as c, fills, shapes, struts, voronoi};
+use crate::tree::Tape;
+
+/// Transforms eight points by the same matrix.
+fn transform_point3(mat: &Mat4, p: Vec3x8) -> Vec3x8 {
+    let [x_axis, y_axis, z_axis, w_axis] = mat.cols;
+    let row = |x: f32, y: f32, z: f32, w: f32| {
+        p.x * f32x8::splat(x) + p.y * f32x8::splat(y) + p.z * f32x8::splat(z) + f32x8::splat(w)
+    };
+
+    Vec3x8::new(
+        row(x_axis.x, y_axis.x, z_axis.x, w_axis.x),
+        row(x_axis.y, y_axis.y, z_axis.y, w_axis.y),
+        row(x_axis.z, y_axis.z, z_axis.z, w_axis.z),
+    )
+}
+
+impl Tape {
+    /// Evaluates the tape at eight points at once.
+    ///
+    /// This has the same semantics as `interpreter::sdf` in the shader,
+    /// so it can be used as a reference for it.
+    pub fn eval(&self, p: Vec3x8) -> f32x8 {
+        let mut regs = [f32x8::splat(0.0); REGISTER_COUNT];
+
+        for &inst in &self.insts {
+            let d = match inst.op() {
+                Op::Ret => return regs[inst.reg()],
+
+                // Combinations
+                Op::Union => {
+                    let (lhs, rhs) = inst.operands();
+                    c::union(regs[lhs], regs[rhs])
+                }
+                Op::Intersection => {
+                    let (lhs, rhs) = inst.operands();
+                    c::intersection(regs[lhs], regs[rhs])
+                }
+                Op::Subtraction => {
+                    let (lhs, rhs) = inst.operands();
+                    c::subtraction(regs[lhs], regs[rhs])
+                }
+                Op::SmoothUnion => {
+                    let su = inst.extract::<SmoothUnion>();
+                    c::smooth_union(regs[su.lhs], regs[su.rhs], f32x8::splat(su.k))
+                }
+                Op::SmoothIntersection => {
+                    let si = inst.extract::<SmoothIntersection>();
+                    c::smooth_intersection(regs[si.lhs], regs[si.rhs], f32x8::splat(si.k))
+                }
+                Op::SmoothSubtraction => {
+                    let ss = inst.extract::<SmoothSubtraction>();
+                    c::smooth_subtraction(regs[ss.lhs], regs[ss.rhs], f32x8::splat(ss.k))
+                }
+
+                // Shapes
+                Op::Sphere => {
+                    let sphere = inst.extract::<Sphere>();
+                    let p = transform_point3(&self.matrices[sphere.matrix_idx], p);
+                    shapes::sphere(p, f32x8::splat(sphere.radius))
+                }
+                Op::RectangularPrism => {
+                    let prism = inst.extract::<RectangularPrism>();
+                    let p = transform_point3(&self.matrices[prism.matrix_idx], p);
+                    shapes::rectangular_prism(
+                        p,
+                        Vec3x8::splat(Vec3::new(prism.x, prism.y, prism.z)),
+                    )
+                }
+                Op::Cylinder => {
+                    let c = inst.extract::<Cylinder>();
+                    let p = transform_point3(&self.matrices[c.matrix_idx], p);
+                    shapes::cylinder(p, f32x8::splat(c.radius), f32x8::splat(c.half_height))
+                }
+                Op::Torus => {
+                    let t = inst.extract::<Torus>();
+                    let p = transform_point3(&self.matrices[t.matrix_idx], p);
+                    shapes::torus(
+                        p,
+                        f32x8::splat(t.major_radius),
+                        f32x8::splat(t.minor_radius),
+                    )
+                }
+                Op::Capsule => {
+                    let c = inst.extract::<Capsule>();
+                    let p = transform_point3(&self.matrices[c.matrix_idx], p);
+                    shapes::capsule(p, f32x8::splat(c.radius), f32x8::splat(c.half_height))
+                }
+                Op::Cone => {
+                    let c = inst.extract::<Cone>();
+                    let p = transform_point3(&self.matrices[c.matrix_idx], p);
+                    shapes::cone(
+                        p,
+                        f32x8::splat(c.bottom_radius),
+                        f32x8::splat(c.top_radius),
+                        f32x8::splat(c.half_height),
+                    )
+                }
+                Op::Plane => {
+                    let plane = inst.extract::<Plane>();
+                    let p = transform_point3(&self.matrices[plane.matrix_idx], p);
+                    let normal = Vec3::new(plane.normal_x, plane.normal_y, plane.normal_z);
+                    shapes::plane(p, Vec3x8::splat(normal), f32x8::splat(plane.offset))
+                }
+                Op::Ellipsoid => {
+                    let e = inst.extract::<Ellipsoid>();
+                    let p = transform_point3(&self.matrices[e.matrix_idx], p);
+                    shapes::ellipsoid(p, Vec3x8::splat(Vec3::new(e.x, e.y, e.z)))
+                }
+
+                // Fills
+                Op::Gyroid => {
+                    let g = inst.extract::<Gyroid>();
+                    let p = transform_point3(&self.matrices[g.matrix_idx], p);
+                    fills::gyroid(p, f32x8::splat(g.scale), f32x8::splat(g.thickness))
+                }
+                Op::SchwarzP => {
+                    let sp = inst.extract::<SchwarzP>();
+                    let p = transform_point3(&self.matrices[sp.matrix_idx], p);
+                    fills::schwarz_p(p, f32x8::splat(sp.scale), f32x8::splat(sp.thickness))
+                }
+                Op::Diamond => {
+                    let d = inst.extract::<Diamond>();
+                    let p = transform_point3(&self.matrices[d.matrix_idx], p);
+                    fills::diamond(p, f32x8::splat(d.scale), f32x8::splat(d.thickness))
+                }
+                Op::Neovius => {
+                    let n = inst.extract::<Neovius>();
+                    let p = transform_point3(&self.matrices[n.matrix_idx], p);
+                    fills::neovius(p, f32x8::splat(n.scale), f32x8::splat(n.thickness))
+                }
+                Op::Iwp => {
+                    let iw = inst.extract::<Iwp>();
+                    let p = transform_point3(&self.matrices[iw.matrix_idx], p);
+                    fills::iwp(p, f32x8::splat(iw.scale), f32x8::splat(iw.thickness))
+                }
+                Op::Lidinoid => {
+                    let l = inst.extract::<Lidinoid>();
+                    let p = transform_point3(&self.matrices[l.matrix_idx], p);
+                    fills::lidinoid(p, f32x8::splat(l.scale), f32x8::splat(l.thickness))
+                }
+                Op::Frd => {
+                    let fr = inst.extract::<Frd>();
+                    let p = transform_point3(&self.matrices[fr.matrix_idx], p);
+                    fills::frd(p, f32x8::splat(fr.scale), f32x8::splat(fr.thickness))
+                }
+
+                // Strut fills
+                Op::Bcc => {
+                    let b = inst.extract::<Bcc>();
+                    let p = transform_point3(&self.matrices[b.matrix_idx], p);
+                    let (cell, radius) = (f32x8::splat(b.cell_size), f32x8::splat(b.strut_radius));
+                    struts::lattice(p, cell, radius, struts::BCC)
+                }
+                Op::Fcc => {
+                    let f = inst.extract::<Fcc>();
+                    let p = transform_point3(&self.matrices[f.matrix_idx], p);
+                    let (cell, radius) = (f32x8::splat(f.cell_size), f32x8::splat(f.strut_radius));
+                    struts::lattice(p, cell, radius, struts::FCC)
+                }
+                Op::Octet => {
+                    let o = inst.extract::<Octet>();
+                    let p = transform_point3(&self.matrices[o.matrix_idx], p);
+                    let (cell, radius) = (f32x8::splat(o.cell_size), f32x8::splat(o.strut_radius));
+                    struts::lattice(p, cell, radius, struts::OCTET)
+                }
+                Op::Kelvin => {
+                    let k = inst.extract::<Kelvin>();
+                    let p = transform_point3(&self.matrices[k.matrix_idx], p);
+                    let (cell, radius) = (f32x8::splat(k.cell_size), f32x8::splat(k.strut_radius));
+                    struts::lattice(p, cell, radius, struts::KELVIN)
+                }
+                Op::Voronoi => {
+                    let v = inst.extract::<Voronoi>();
+                    let p = transform_point3(&self.matrices[v.matrix_idx], p);
+                    voronoi::foam(p, v.cell_size, v.thickness, v.seed)
+                }
+
+                // Values that vary over space
+                Op::Constant => f32x8::splat(inst.extract::<Constant>().value),
+                Op::Coordinate => {
+                    let c = inst.extract::<Coordinate>();
+                    let p = transform_point3(&self.matrices[c.matrix_idx], p);
+                    let half_extent = f32x8::splat(c.half_extent);
+                    [p.x, p.y, p.z][c.axis].max(-half_extent).min(half_extent)
+                }
+                Op::Sum => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs] + regs[rhs]
+                }
+                Op::Difference => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs] - regs[rhs]
+                }
+                Op::Product => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs] * regs[rhs]
+                }
+                Op::Quotient => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs] / regs[rhs]
+                }
+                Op::Negation => -regs[inst.extract::<Negation>().operand],
+                Op::Abs => regs[inst.extract::<Abs>().operand].abs(),
+                Op::Sqrt => regs[inst.extract::<Sqrt>().operand].sqrt(),
+                Op::Sin => regs[inst.extract::<Sin>().operand].sin(),
+                Op::Cos => regs[inst.extract::<Cos>().operand].cos(),
+                Op::GradedSheet => {
+                    let g = inst.extract::<GradedSheet>();
+                    let p = transform_point3(&self.matrices[g.matrix_idx], p);
+                    fills::graded_sheet(g.sheet, p, regs[g.scale], g.half_extents.into())
+                }
+            };
+
+            regs[inst.reg()] = d;
+        }
+
+        panic!("the tape doesn't end with a return")
+    }
+
+    /// Evaluates the tape at every point in `points`, eight at a time,
+    /// and writes the distances to `out`.
+    pub fn eval_points(&self, points: &[Vec3], out: &mut [f32]) {
+        assert_eq!(points.len(), out.len());
+
+        for (points, out) in points.chunks(8).zip(out.chunks_mut(8)) {
+            // The last batch is padded by repeating its first point.
+            let mut batch = [points[0]; 8];
+            batch[..points.len()].copy_from_slice(points);
+
+            let d: [f32; 8] = self.eval(Vec3x8::from(batch)).into();
+            out.copy_from_slice(&d[..out.len()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::inst::{Inst, Ret, Subtraction, Union};
+
+    fn smooth_union(lhs: f32, rhs: f32, k: f32) -> f32 {
+        let h = ((rhs - lhs) * 0.5 / k + 0.5).clamp(0.0, 1.0);
+        rhs + (lhs - rhs) * h - k * h * (1.0 - h)
+    }
+
+    fn rectangular_prism(p: Vec3, sides: Vec3) -> f32 {
+        let q = p.abs() - sides;
+        q.max_by_component(Vec3::zero()).mag() + q.x.max(q.y.max(q.z)).min(0.0)
+    }
+
+    #[test]
+    fn eval_matches_scalar() {
+        let tape = Tape {
+            insts: vec![
+                Inst::make(
+                    0,
+                    Sphere {
+                        matrix_idx: 0,
+                        radius: 0.7,
+                    },
+                ),
+                Inst::make(
+                    1,
+                    RectangularPrism {
+                        matrix_idx: 1,
+                        x: 1.0,
+                        y: 0.5,
+                        z: 0.8,
+                    },
+                ),
+                Inst::make(
+                    0,
+                    SmoothUnion {
+                        lhs: 0,
+                        rhs: 1,
+                        k: 0.4,
+                    },
+                ),
+                Inst::make(
+                    1,
+                    Sphere {
+                        matrix_idx: 1,
+                        radius: 0.3,
+                    },
+                ),
+                Inst::make(0, Subtraction { lhs: 1, rhs: 0 }),
+                Inst::make(
+                    1,
+                    Sphere {
+                        matrix_idx: 2,
+                        radius: 0.5,
+                    },
+                ),
+                Inst::make(0, Union { lhs: 0, rhs: 1 }),
+                Inst::make(0, Ret),
+            ],
+            matrices: vec![
+                Mat4::from_translation(Vec3::new(-1.5, 0.0, 0.0)),
+                Mat4::from_rotation_y(0.5),
+                Mat4::from_translation(Vec3::new(0.0, -2.0, 0.0)),
+            ],
+        };
+
+        let scalar = |p: Vec3| {
+            let sphere = (p - Vec3::new(1.5, 0.0, 0.0)).mag() - 0.7;
+            let rotated = tape.matrices[1].transform_point3(p);
+            let prism = rectangular_prism(rotated, Vec3::new(1.0, 0.5, 0.8));
+            let hollowed = (-(rotated.mag() - 0.3)).max(smooth_union(sphere, prism, 0.4));
+            hollowed.min((p - Vec3::new(0.0, 2.0, 0.0)).mag() - 0.5)
+        };
+
+        // An odd number of points, so the last batch is partial.
+        let points: Vec<Vec3> = (0..13 * 13 * 13)
+            .map(|i| {
+                let c = |i: usize| (i % 13) as f32 * 0.4 - 2.4;
+                Vec3::new(c(i), c(i / 13), c(i / 169))
+            })
+            .collect();
+        let mut distances = vec![0.0; points.len()];
+        tape.eval_points(&points, &mut distances);
+
+        for (&p, &d) in points.iter().zip(&distances) {
+            assert!(
+                (d - scalar(p)).abs() < 1e-5,
+                "{:?}: {} != {}",
+                p,
+                d,
+                scalar(p)
+            );
+        }
+    }
+}
@@ -0,0 +1,757 @@
+//! Interval arithmetic, which bounds the distance over a box of space.
+
+use shared::inst::{
+    Abs, Bcc, Capsule, Cone, Constant, Coordinate, Cos, Cylinder, Diamond, Ellipsoid, Fcc, Frd,
+    GradedSheet, Gyroid, Iwp, Kelvin, Lidinoid, Negation, Neovius, Octet, Op, Plane,
+    RectangularPrism, SchwarzP, Sin, SmoothIntersection, SmoothSubtraction, SmoothUnion, Sphere,
+    Sqrt, Torus, Voronoi, REGISTER_COUNT,
+};
+use std::{
+    f32::consts::{FRAC_PI_2, TAU},
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+use ultraviolet::{f32x8, Mat4, Vec3, Vec3x8};
+
+use super::{shapes, struts, voronoi};
+use crate::tree::Tape;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub low: f32,
+    pub high: f32,
+}
+
+impl Interval {
+    pub fn new(low: f32, high: f32) -> Self {
+        Self { low, high }
+    }
+
+    pub fn splat(x: f32) -> Self {
+        Self::new(x, x)
+    }
+
+    pub fn min(self, rhs: Self) -> Self {
+        Self::new(self.low.min(rhs.low), self.high.min(rhs.high))
+    }
+
+    pub fn max(self, rhs: Self) -> Self {
+        Self::new(self.low.max(rhs.low), self.high.max(rhs.high))
+    }
+
+    pub fn clamp(self, low: f32, high: f32) -> Self {
+        self.max(Self::splat(low)).min(Self::splat(high))
+    }
+
+    pub fn abs(self) -> Self {
+        if self.low >= 0.0 {
+            self
+        } else if self.high <= 0.0 {
+            -self
+        } else {
+            Self::new(0.0, (-self.low).max(self.high))
+        }
+    }
+
+    pub fn square(self) -> Self {
+        let abs = self.abs();
+        Self::new(abs.low * abs.low, abs.high * abs.high)
+    }
+
+    pub fn sqrt(self) -> Self {
+        Self::new(self.low.max(0.0).sqrt(), self.high.max(0.0).sqrt())
+    }
+
+    /// `1 / x`, which is unbounded if `x` contains zero.
+    pub fn recip(self) -> Self {
+        if self.low > 0.0 || self.high < 0.0 {
+            Self::new(1.0 / self.high, 1.0 / self.low)
+        } else {
+            Self::new(f32::NEG_INFINITY, f32::INFINITY)
+        }
+    }
+
+    /// The extremes are at the ends, unless a peak or a trough is in between.
+    pub fn sin(self) -> Self {
+        if self.high - self.low >= TAU {
+            return Self::new(-1.0, 1.0);
+        }
+
+        // Whether x + 2πk is in the interval for some k.
+        let contains = |x: f32| ((self.low - x) / TAU).ceil() <= ((self.high - x) / TAU).floor();
+        let (a, b) = (self.low.sin(), self.high.sin());
+        Self::new(
+            if contains(-FRAC_PI_2) { -1.0 } else { a.min(b) },
+            if contains(FRAC_PI_2) { 1.0 } else { a.max(b) },
+        )
+    }
+
+    pub fn cos(self) -> Self {
+        (self + FRAC_PI_2).sin()
+    }
+}
+
+impl Neg for Interval {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(-self.high, -self.low)
+    }
+}
+
+impl Add for Interval {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.low + rhs.low, self.high + rhs.high)
+    }
+}
+
+impl Add<f32> for Interval {
+    type Output = Self;
+
+    fn add(self, rhs: f32) -> Self {
+        Self::new(self.low + rhs, self.high + rhs)
+    }
+}
+
+impl Sub for Interval {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.low - rhs.high, self.high - rhs.low)
+    }
+}
+
+impl Sub<f32> for Interval {
+    type Output = Self;
+
+    fn sub(self, rhs: f32) -> Self {
+        Self::new(self.low - rhs, self.high - rhs)
+    }
+}
+
+impl Mul for Interval {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let products = [
+            self.low * rhs.low,
+            self.low * rhs.high,
+            self.high * rhs.low,
+            self.high * rhs.high,
+        ];
+        Self::new(
+            products.iter().copied().fold(f32::INFINITY, f32::min),
+            products.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        )
+    }
+}
+
+impl Mul<f32> for Interval {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self {
+        if rhs >= 0.0 {
+            Self::new(self.low * rhs, self.high * rhs)
+        } else {
+            Self::new(self.high * rhs, self.low * rhs)
+        }
+    }
+}
+
+impl Div for Interval {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        Mul::mul(self, rhs.recip())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Interval3 {
+    x: Interval,
+    y: Interval,
+    z: Interval,
+}
+
+impl Interval3 {
+    fn map(self, f: impl Fn(Interval) -> Interval) -> Self {
+        Self {
+            x: f(self.x),
+            y: f(self.y),
+            z: f(self.z),
+        }
+    }
+
+    fn abs(self) -> Self {
+        Self {
+            x: self.x.abs(),
+            y: self.y.abs(),
+            z: self.z.abs(),
+        }
+    }
+
+    fn mag(self) -> Interval {
+        (self.x.square() + self.y.square() + self.z.square()).sqrt()
+    }
+}
+
+fn transform_interval3(mat: &Mat4, p: Interval3) -> Interval3 {
+    let [x_axis, y_axis, z_axis, w_axis] = mat.cols;
+    Interval3 {
+        x: p.x * x_axis.x + p.y * y_axis.x + p.z * z_axis.x + w_axis.x,
+        y: p.x * x_axis.y + p.y * y_axis.y + p.z * z_axis.y + w_axis.y,
+        z: p.x * x_axis.z + p.y * y_axis.z + p.z * z_axis.z + w_axis.z,
+    }
+}
+
+fn sphere(p: Interval3, radius: f32) -> Interval {
+    p.mag() - radius
+}
+
+fn rectangular_prism(p: Interval3, sides: Vec3) -> Interval {
+    let q = p.abs();
+    let q = Interval3 {
+        x: q.x - sides.x,
+        y: q.y - sides.y,
+        z: q.z - sides.z,
+    };
+    let zero = Interval::splat(0.0);
+    let outside = Interval3 {
+        x: q.x.max(zero),
+        y: q.y.max(zero),
+        z: q.z.max(zero),
+    };
+
+    outside.mag() + q.x.max(q.y.max(q.z)).min(zero)
+}
+
+fn length2(x: Interval, y: Interval) -> Interval {
+    (x.square() + y.square()).sqrt()
+}
+
+fn cylinder(p: Interval3, radius: f32, half_height: f32) -> Interval {
+    let zero = Interval::splat(0.0);
+    let dx = length2(p.x, p.z) - radius;
+    let dy = p.y.abs() - half_height;
+    dx.max(dy).min(zero) + length2(dx.max(zero), dy.max(zero))
+}
+
+fn torus(p: Interval3, major_radius: f32, minor_radius: f32) -> Interval {
+    length2(length2(p.x, p.z) - major_radius, p.y) - minor_radius
+}
+
+/// `y - clamp(y, -h, h)` never decreases, so it's bounded by its values at the ends.
+fn capsule(p: Interval3, radius: f32, half_height: f32) -> Interval {
+    let outside = |y: f32| y - y.max(-half_height).min(half_height);
+    let p = Interval3 {
+        y: Interval::new(outside(p.y.low), outside(p.y.high)),
+        ..p
+    };
+    p.mag() - radius
+}
+
+/// Bounds an exact distance that can't be followed with intervals, such as
+/// one that branches a lot or folds space into repeating cells. An exact
+/// distance can't change by more than the radius of the box from the distance
+/// at its centre.
+fn around_center(p: Interval3, distance: impl Fn(Vec3x8) -> f32x8) -> Interval {
+    let low = Vec3::new(p.x.low, p.y.low, p.z.low);
+    let high = Vec3::new(p.x.high, p.y.high, p.z.high);
+    let d: [f32; 8] = distance(Vec3x8::splat((low + high) * 0.5)).into();
+    let radius = (high - low).mag() * 0.5;
+    Interval::new(d[0] - radius, d[0] + radius)
+}
+
+/// The cone is bounded [`around_center`].
+fn cone(p: Interval3, bottom_radius: f32, top_radius: f32, half_height: f32) -> Interval {
+    around_center(p, |p| {
+        shapes::cone(
+            p,
+            f32x8::splat(bottom_radius),
+            f32x8::splat(top_radius),
+            f32x8::splat(half_height),
+        )
+    })
+}
+
+fn plane(p: Interval3, normal: Vec3, offset: f32) -> Interval {
+    p.x * normal.x + p.y * normal.y + p.z * normal.z - offset
+}
+
+/// The ellipsoid's distance is between the distances to the unit sphere,
+/// after scaling the point to it, scaled by the smallest and largest radii.
+fn ellipsoid(p: Interval3, radii: Vec3) -> Interval {
+    let scaled = Interval3 {
+        x: p.x * (1.0 / radii.x),
+        y: p.y * (1.0 / radii.y),
+        z: p.z * (1.0 / radii.z),
+    };
+    let t = scaled.mag() - 1.0;
+    let (small, large) = (radii.component_min(), radii.component_max());
+    Interval::new(
+        (t.low * small).min(t.low * large),
+        (t.high * small).max(t.high * large),
+    )
+}
+
+fn sheet(g: Interval, scale: Interval, thickness: f32, lipschitz: f32) -> Interval {
+    (g.abs() * scale - thickness) * (1.0 / lipschitz)
+}
+
+fn sin_cos(p: Interval3, frequency: Interval) -> (Interval3, Interval3) {
+    let p = p.map(|c| c * frequency);
+    (p.map(Interval::sin), p.map(Interval::cos))
+}
+
+fn gyroid(p: Interval3, scale: Interval, thickness: f32) -> Interval {
+    let (s, c) = sin_cos(p, scale.recip());
+    let g = s.x * c.z + s.y * c.x + s.z * c.y;
+    sheet(g, scale, thickness, Gyroid::LIPSCHITZ)
+}
+
+fn schwarz_p(p: Interval3, scale: Interval, thickness: f32) -> Interval {
+    let (_, c) = sin_cos(p, scale.recip());
+    sheet(c.x + c.y + c.z, scale, thickness, SchwarzP::LIPSCHITZ)
+}
+
+fn diamond(p: Interval3, scale: Interval, thickness: f32) -> Interval {
+    let (s, c) = sin_cos(p, scale.recip());
+    let g = s.x * s.y * s.z + s.x * c.y * c.z + c.x * s.y * c.z + c.x * c.y * s.z;
+    sheet(g, scale, thickness, Diamond::LIPSCHITZ)
+}
+
+fn neovius(p: Interval3, scale: Interval, thickness: f32) -> Interval {
+    let (_, c) = sin_cos(p, scale.recip());
+    let g = (c.x + c.y + c.z) * 3.0 + c.x * c.y * c.z * 4.0;
+    sheet(g, scale, thickness, Neovius::LIPSCHITZ)
+}
+
+fn iwp(p: Interval3, scale: Interval, thickness: f32) -> Interval {
+    let frequency = scale.recip();
+    let ((_, c), (_, c2)) = (sin_cos(p, frequency), sin_cos(p, frequency * 2.0));
+    let g = (c.x * c.y + c.y * c.z + c.z * c.x) * 2.0 - (c2.x + c2.y + c2.z);
+    sheet(g, scale, thickness, Iwp::LIPSCHITZ)
+}
+
+fn lidinoid(p: Interval3, scale: Interval, thickness: f32) -> Interval {
+    let frequency = scale.recip();
+    let ((s, c), (s2, c2)) = (sin_cos(p, frequency), sin_cos(p, frequency * 2.0));
+    let g = (s2.x * c.y * s.z + s2.y * c.z * s.x + s2.z * c.x * s.y) * 0.5
+        - (c2.x * c2.y + c2.y * c2.z + c2.z * c2.x) * 0.5
+        + 0.15;
+    sheet(g, scale, thickness, Lidinoid::LIPSCHITZ)
+}
+
+fn frd(p: Interval3, scale: Interval, thickness: f32) -> Interval {
+    let frequency = scale.recip();
+    let ((_, c), (_, c2)) = (sin_cos(p, frequency), sin_cos(p, frequency * 2.0));
+    let g = c.x * c.y * c.z * 4.0 - (c2.x * c2.y + c2.y * c2.z + c2.z * c2.x);
+    sheet(g, scale, thickness, Frd::LIPSCHITZ)
+}
+
+fn graded_sheet(sheet: Op, p: Interval3, scale: Interval, half_extents: [f32; 3]) -> Interval {
+    let [hx, hy, hz] = half_extents;
+    let p = Interval3 {
+        x: p.x.clamp(-hx, hx),
+        y: p.y.clamp(-hy, hy),
+        z: p.z.clamp(-hz, hz),
+    };
+    match sheet {
+        Op::Gyroid => gyroid(p, scale, 0.0),
+        Op::SchwarzP => schwarz_p(p, scale, 0.0),
+        Op::Diamond => diamond(p, scale, 0.0),
+        Op::Neovius => neovius(p, scale, 0.0),
+        Op::Iwp => iwp(p, scale, 0.0),
+        Op::Lidinoid => lidinoid(p, scale, 0.0),
+        Op::Frd => frd(p, scale, 0.0),
+        _ => unreachable!("{:?} isn't a sheet", sheet),
+    }
+}
+
+/// Lattices fold space into cells, so they're bounded [`around_center`].
+fn lattice(
+    p: Interval3,
+    cell_size: f32,
+    strut_radius: f32,
+    cell_struts: &[struts::Strut],
+) -> Interval {
+    let (cell, radius) = (f32x8::splat(cell_size), f32x8::splat(strut_radius));
+    around_center(p, |p| struts::lattice(p, cell, radius, cell_struts))
+}
+
+/// Foams fold space into cells too, so they're bounded [`around_center`].
+fn foam(p: Interval3, cell_size: f32, thickness: f32, seed: u32) -> Interval {
+    around_center(p, |p| voronoi::foam(p, cell_size, thickness, seed))
+}
+
+/// A smooth union is never more than `k / 4` below the union.
+fn smooth_union(lhs: Interval, rhs: Interval, k: f32) -> Interval {
+    let union = lhs.min(rhs);
+    Interval::new(union.low - k / 4.0, union.high)
+}
+
+/// A smooth intersection is never more than `k / 4` above the intersection.
+fn smooth_intersection(lhs: Interval, rhs: Interval, k: f32) -> Interval {
+    let intersection = lhs.max(rhs);
+    Interval::new(intersection.low, intersection.high + k / 4.0)
+}
+
+impl Tape {
+    /// Bounds the distance over the box between `min` and `max`.
+    ///
+    /// The bounds are conservative, so if they don't contain zero,
+    /// the surface doesn't pass through the box.
+    pub fn eval_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        let mut regs = [Interval::splat(0.0); REGISTER_COUNT];
+        let p = Interval3 {
+            x: Interval::new(min.x, max.x),
+            y: Interval::new(min.y, max.y),
+            z: Interval::new(min.z, max.z),
+        };
+
+        for &inst in &self.insts {
+            let d = match inst.op() {
+                Op::Ret => return regs[inst.reg()],
+
+                // Combinations
+                Op::Union => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs].min(regs[rhs])
+                }
+                Op::Intersection => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs].max(regs[rhs])
+                }
+                Op::Subtraction => {
+                    let (lhs, rhs) = inst.operands();
+                    (-regs[lhs]).max(regs[rhs])
+                }
+                Op::SmoothUnion => {
+                    let su = inst.extract::<SmoothUnion>();
+                    smooth_union(regs[su.lhs], regs[su.rhs], su.k)
+                }
+                Op::SmoothIntersection => {
+                    let si = inst.extract::<SmoothIntersection>();
+                    smooth_intersection(regs[si.lhs], regs[si.rhs], si.k)
+                }
+                Op::SmoothSubtraction => {
+                    let ss = inst.extract::<SmoothSubtraction>();
+                    smooth_intersection(-regs[ss.lhs], regs[ss.rhs], ss.k)
+                }
+
+                // Shapes
+                Op::Sphere => {
+                    let s = inst.extract::<Sphere>();
+                    sphere(
+                        transform_interval3(&self.matrices[s.matrix_idx], p),
+                        s.radius,
+                    )
+                }
+                Op::RectangularPrism => {
+                    let prism = inst.extract::<RectangularPrism>();
+                    let p = transform_interval3(&self.matrices[prism.matrix_idx], p);
+                    rectangular_prism(p, Vec3::new(prism.x, prism.y, prism.z))
+                }
+                Op::Cylinder => {
+                    let c = inst.extract::<Cylinder>();
+                    let p = transform_interval3(&self.matrices[c.matrix_idx], p);
+                    cylinder(p, c.radius, c.half_height)
+                }
+                Op::Torus => {
+                    let t = inst.extract::<Torus>();
+                    let p = transform_interval3(&self.matrices[t.matrix_idx], p);
+                    torus(p, t.major_radius, t.minor_radius)
+                }
+                Op::Capsule => {
+                    let c = inst.extract::<Capsule>();
+                    let p = transform_interval3(&self.matrices[c.matrix_idx], p);
+                    capsule(p, c.radius, c.half_height)
+                }
+                Op::Cone => {
+                    let c = inst.extract::<Cone>();
+                    let p = transform_interval3(&self.matrices[c.matrix_idx], p);
+                    cone(p, c.bottom_radius, c.top_radius, c.half_height)
+                }
+                Op::Plane => {
+                    let plane_inst = inst.extract::<Plane>();
+                    let p = transform_interval3(&self.matrices[plane_inst.matrix_idx], p);
+                    let normal = Vec3::new(
+                        plane_inst.normal_x,
+                        plane_inst.normal_y,
+                        plane_inst.normal_z,
+                    );
+                    plane(p, normal, plane_inst.offset)
+                }
+                Op::Ellipsoid => {
+                    let e = inst.extract::<Ellipsoid>();
+                    let p = transform_interval3(&self.matrices[e.matrix_idx], p);
+                    ellipsoid(p, Vec3::new(e.x, e.y, e.z))
+                }
+
+                // Fills
+                Op::Gyroid => {
+                    let g = inst.extract::<Gyroid>();
+                    let p = transform_interval3(&self.matrices[g.matrix_idx], p);
+                    gyroid(p, Interval::splat(g.scale), g.thickness)
+                }
+                Op::SchwarzP => {
+                    let sp = inst.extract::<SchwarzP>();
+                    let p = transform_interval3(&self.matrices[sp.matrix_idx], p);
+                    schwarz_p(p, Interval::splat(sp.scale), sp.thickness)
+                }
+                Op::Diamond => {
+                    let d = inst.extract::<Diamond>();
+                    let p = transform_interval3(&self.matrices[d.matrix_idx], p);
+                    diamond(p, Interval::splat(d.scale), d.thickness)
+                }
+                Op::Neovius => {
+                    let n = inst.extract::<Neovius>();
+                    let p = transform_interval3(&self.matrices[n.matrix_idx], p);
+                    neovius(p, Interval::splat(n.scale), n.thickness)
+                }
+                Op::Iwp => {
+                    let iw = inst.extract::<Iwp>();
+                    let p = transform_interval3(&self.matrices[iw.matrix_idx], p);
+                    iwp(p, Interval::splat(iw.scale), iw.thickness)
+                }
+                Op::Lidinoid => {
+                    let l = inst.extract::<Lidinoid>();
+                    let p = transform_interval3(&self.matrices[l.matrix_idx], p);
+                    lidinoid(p, Interval::splat(l.scale), l.thickness)
+                }
+                Op::Frd => {
+                    let fr = inst.extract::<Frd>();
+                    let p = transform_interval3(&self.matrices[fr.matrix_idx], p);
+                    frd(p, Interval::splat(fr.scale), fr.thickness)
+                }
+
+                // Strut fills
+                Op::Bcc => {
+                    let b = inst.extract::<Bcc>();
+                    let p = transform_interval3(&self.matrices[b.matrix_idx], p);
+                    lattice(p, b.cell_size, b.strut_radius, struts::BCC)
+                }
+                Op::Fcc => {
+                    let f = inst.extract::<Fcc>();
+                    let p = transform_interval3(&self.matrices[f.matrix_idx], p);
+                    lattice(p, f.cell_size, f.strut_radius, struts::FCC)
+                }
+                Op::Octet => {
+                    let o = inst.extract::<Octet>();
+                    let p = transform_interval3(&self.matrices[o.matrix_idx], p);
+                    lattice(p, o.cell_size, o.strut_radius, struts::OCTET)
+                }
+                Op::Kelvin => {
+                    let k = inst.extract::<Kelvin>();
+                    let p = transform_interval3(&self.matrices[k.matrix_idx], p);
+                    lattice(p, k.cell_size, k.strut_radius, struts::KELVIN)
+                }
+                Op::Voronoi => {
+                    let v = inst.extract::<Voronoi>();
+                    let p = transform_interval3(&self.matrices[v.matrix_idx], p);
+                    foam(p, v.cell_size, v.thickness, v.seed)
+                }
+
+                // Values that vary over space
+                Op::Constant => Interval::splat(inst.extract::<Constant>().value),
+                Op::Coordinate => {
+                    let c = inst.extract::<Coordinate>();
+                    let p = transform_interval3(&self.matrices[c.matrix_idx], p);
+                    [p.x, p.y, p.z][c.axis].clamp(-c.half_extent, c.half_extent)
+                }
+                Op::Sum => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs] + regs[rhs]
+                }
+                Op::Difference => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs] - regs[rhs]
+                }
+                Op::Product => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs] * regs[rhs]
+                }
+                Op::Quotient => {
+                    let (lhs, rhs) = inst.operands();
+                    regs[lhs] / regs[rhs]
+                }
+                Op::Negation => -regs[inst.extract::<Negation>().operand],
+                Op::Abs => regs[inst.extract::<Abs>().operand].abs(),
+                Op::Sqrt => regs[inst.extract::<Sqrt>().operand].sqrt(),
+                Op::Sin => regs[inst.extract::<Sin>().operand].sin(),
+                Op::Cos => regs[inst.extract::<Cos>().operand].cos(),
+                Op::GradedSheet => {
+                    let g = inst.extract::<GradedSheet>();
+                    let p = transform_interval3(&self.matrices[g.matrix_idx], p);
+                    graded_sheet(g.sheet, p, regs[g.scale], g.half_extents)
+                }
+            };
+
+            regs[inst.reg()] = d;
+        }
+
+        panic!("the tape doesn't end with a return")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tree::{ConstantOrExpr, CsgNode, CsgTree, Fill, Shape};
+    use std::rc::Rc;
+    use ultraviolet::{Vec3, Vec3x8};
+
+    /// A xorshift generator, so the boxes are the same on every run.
+    struct Random(u32);
+
+    impl Random {
+        /// A number from `low` to `high`.
+        fn next(&mut self, low: f32, high: f32) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            low + (high - low) * (self.0 as f32 / u32::MAX as f32)
+        }
+
+        fn vec3(&mut self, low: f32, high: f32) -> Vec3 {
+            Vec3::new(
+                self.next(low, high),
+                self.next(low, high),
+                self.next(low, high),
+            )
+        }
+    }
+
+    /// Every kind of shape, and a box filled with every kind of lattice.
+    fn trees() -> Vec<CsgNode> {
+        let c = ConstantOrExpr::Constant;
+        let parse = |source| ConstantOrExpr::parse(source).unwrap();
+        let shapes = vec![
+            Shape::Sphere { radius: c(1.1) },
+            Shape::Box {
+                side_x: c(0.9),
+                side_y: c(1.2),
+                side_z: c(0.6),
+            },
+            Shape::Cylinder {
+                radius: c(0.8),
+                half_height: c(1.2),
+            },
+            Shape::Torus {
+                major_radius: c(1.0),
+                minor_radius: c(0.3),
+            },
+            Shape::Capsule {
+                radius: c(0.5),
+                half_height: c(0.9),
+            },
+            Shape::Cone {
+                bottom_radius: c(1.0),
+                top_radius: c(0.2),
+                half_height: c(1.1),
+            },
+            Shape::Plane {
+                normal_x: c(1.0),
+                normal_y: c(2.0),
+                normal_z: c(-0.5),
+                offset: c(0.3),
+            },
+            Shape::Ellipsoid {
+                radius_x: c(1.2),
+                radius_y: c(0.6),
+                radius_z: c(0.9),
+            },
+        ];
+        let fills = vec![
+            Fill::SchwarzP {
+                scale: c(0.3),
+                thickness: c(0.05),
+            },
+            Fill::Lidinoid {
+                scale: c(0.3),
+                thickness: c(0.05),
+            },
+            Fill::Octet {
+                cell_size: c(0.8),
+                strut_radius: c(0.1),
+            },
+            Fill::Voronoi {
+                cell_size: c(0.6),
+                thickness: c(0.05),
+                seed: 2,
+            },
+            Fill::Gyroid {
+                scale: parse("0.3 + x/20"),
+                thickness: parse("0.05 + 0.02*sin(y)"),
+            },
+            Fill::Kelvin {
+                cell_size: parse("0.8"),
+                strut_radius: parse("0.1 + max(x, -y)/20"),
+            },
+        ];
+        let filled = fills.into_iter().map(|fill| CsgNode::Rotate {
+            roll: c(0.3),
+            pitch: c(0.2),
+            yaw: c(0.1),
+            node: Rc::new(CsgNode::Shape(
+                Shape::Box {
+                    side_x: c(1.5),
+                    side_y: c(1.5),
+                    side_z: c(1.5),
+                },
+                Some(fill),
+            )),
+        });
+        shapes
+            .into_iter()
+            .map(|shape| CsgNode::Shape(shape, None))
+            .chain(filled)
+            .collect()
+    }
+
+    #[test]
+    fn bounds_contain_samples() {
+        let mut random = Random(0x2545_f491);
+
+        for node in trees() {
+            let tree = CsgTree::new(node);
+            let tape = tree.compile().unwrap();
+
+            for _ in 0..200 {
+                let (centre, half_size) = (random.vec3(-2.0, 2.0), random.vec3(0.01, 1.0));
+                let (min, max) = (centre - half_size, centre + half_size);
+                let bounds = tape.eval_interval(min, max);
+
+                // The corners, and points anywhere in the box.
+                let mut corners = [min; 8];
+                for (i, corner) in corners.iter_mut().enumerate() {
+                    let pick = |bit, low: f32, high| if i & bit == 0 { low } else { high };
+                    *corner = Vec3::new(
+                        pick(1, min.x, max.x),
+                        pick(2, min.y, max.y),
+                        pick(4, min.z, max.z),
+                    );
+                }
+                let mut inside = [centre; 8];
+                for p in &mut inside {
+                    *p += half_size * random.vec3(-1.0, 1.0);
+                }
+                for &points in &[corners, inside] {
+                    let d: [f32; 8] = tape.eval(Vec3x8::from(points)).into();
+                    for (&p, &d) in points.iter().zip(&d) {
+                        assert!(
+                            bounds.low - 1e-4 <= d && d <= bounds.high + 1e-4,
+                            "{} at {:?} in {:?}..{:?}: {} isn't in {:?}",
+                            tree,
+                            p,
+                            min,
+                            max,
+                            d,
+                            bounds
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
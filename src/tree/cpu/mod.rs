@@ -1,2 +1,10 @@
+mod combinations;
+mod deriv;
 mod fills;
+mod interpreter;
+mod interval;
 mod shapes;
+mod struts;
+mod voronoi;
+
+pub(super) use self::interval::Interval;
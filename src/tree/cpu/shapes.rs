@@ -1,4 +1,5 @@
 use ultraviolet::{f32x8, Vec2x8, Vec3x8};
+use wide::{CmpEq, CmpLt};
 
 // pub fn circle(point: impl Point, radius: Op) -> Op {
 //     Op2::XY.mag() - radius
@@ -14,9 +15,9 @@ pub fn sphere(p: Vec3x8, radius: f32x8) -> f32x8 {
 // }
 
 /// A box.
-pub fn rectangular_prism(p: Vec3x8, sides: f32x8) -> f32x8 {
-    let q = p.abs().map(|c| c - sides);
-    return q.max_by_component(Vec3x8::zero()).mag() + q.x.max(q.y.max(q.z).min(f32x8::splat(0.0)));
+pub fn rectangular_prism(p: Vec3x8, sides: Vec3x8) -> f32x8 {
+    let q = p.abs() - sides;
+    q.max_by_component(Vec3x8::zero()).mag() + q.x.max(q.y.max(q.z)).min(f32x8::splat(0.0))
 }
 
 // float sdf_cylinder(vec3 p, float h, float r) {
@@ -24,7 +25,74 @@ pub fn rectangular_prism(p: Vec3x8, sides: f32x8) -> f32x8 {
 //   return min(max(d.x,d.y),0.0) + length(max(d,0.0));
 // }
 
-pub fn cylinder(p: Vec3x8, height: f32x8, radius: f32x8) -> f32x8 {
-    let d = Vec2x8::new(Vec2x8::new(p.x, p.z).mag(), p.y).abs() - Vec2x8::new(height, radius);
+pub fn cylinder(p: Vec3x8, radius: f32x8, half_height: f32x8) -> f32x8 {
+    let d = Vec2x8::new(Vec2x8::new(p.x, p.z).mag(), p.y).abs() - Vec2x8::new(radius, half_height);
     d.x.max(d.y).min(f32x8::splat(0.0)) + d.max_by_component(Vec2x8::zero()).mag()
 }
+
+pub fn torus(p: Vec3x8, major_radius: f32x8, minor_radius: f32x8) -> f32x8 {
+    Vec2x8::new(Vec2x8::new(p.x, p.z).mag() - major_radius, p.y).mag() - minor_radius
+}
+
+pub fn capsule(p: Vec3x8, radius: f32x8, half_height: f32x8) -> f32x8 {
+    let y = p.y - p.y.max(-half_height).min(half_height);
+    Vec3x8::new(p.x, y, p.z).mag() - radius
+}
+
+/// A capped cone, with the distance to whichever is closer of the caps and the side.
+pub fn cone(p: Vec3x8, bottom_radius: f32x8, top_radius: f32x8, half_height: f32x8) -> f32x8 {
+    let zero = f32x8::splat(0.0);
+    let q = Vec2x8::new(Vec2x8::new(p.x, p.z).mag(), p.y);
+    let k1 = Vec2x8::new(top_radius, half_height);
+    let k2 = Vec2x8::new(top_radius - bottom_radius, half_height * f32x8::splat(2.0));
+
+    let radius = q.y.cmp_lt(zero).blend(bottom_radius, top_radius);
+    let cap = Vec2x8::new(q.x - q.x.min(radius), q.y.abs() - half_height);
+    let t = ((k1 - q).dot(k2) / k2.mag_sq())
+        .max(zero)
+        .min(f32x8::splat(1.0));
+    let side = q - k1 + k2 * t;
+
+    let d = cap.mag_sq().min(side.mag_sq()).sqrt();
+    (side.x.cmp_lt(zero) & cap.y.cmp_lt(zero)).blend(-d, d)
+}
+
+/// The half-space behind a plane through `normal * offset`.
+pub fn plane(p: Vec3x8, normal: Vec3x8, offset: f32x8) -> f32x8 {
+    p.dot(normal) - offset
+}
+
+/// Not exact, but close to the distance near the surface. The centre is as deep as
+/// the smallest radius, where the estimate would be `0 / 0`.
+pub fn ellipsoid(p: Vec3x8, radii: Vec3x8) -> f32x8 {
+    let k0 = (p / radii).mag();
+    let k1 = (p / (radii * radii)).mag();
+    let centre = -radii.x.min(radii.y).min(radii.z);
+    let d = k0 * (k0 - f32x8::splat(1.0)) / k1;
+    k1.cmp_eq(f32x8::splat(0.0)).blend(centre, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tree::{ConstantOrExpr, CsgNode, CsgTree, Shape};
+    use ultraviolet::{Vec3, Vec3x8};
+
+    #[test]
+    fn ellipsoid_centre() {
+        let c = ConstantOrExpr::Constant;
+        let tree = CsgTree::new(CsgNode::Shape(
+            Shape::Ellipsoid {
+                radius_x: c(1.2),
+                radius_y: c(0.6),
+                radius_z: c(0.9),
+            },
+            None,
+        ));
+        let tape = tree.compile().unwrap();
+
+        let d: [f32; 8] = tape.eval(Vec3x8::splat(Vec3::zero())).into();
+        assert_eq!(d[0], -0.6);
+        let deriv = tape.eval_deriv(Vec3::zero());
+        assert_eq!((deriv.value, deriv.gradient), (-0.6, Vec3::zero()));
+    }
+}
@@ -0,0 +1,182 @@
+//! Strut lattices, which are capsules between the nodes of a cubic cell,
+//! repeated through all of space.
+
+use ultraviolet::{f32x8, Vec3, Vec3x8};
+
+/// A strut from one point to another.
+pub type Strut = ([f32; 3], [f32; 3]);
+
+// The struts in the corner of a cell between its centre, at the origin, and
+// `(1, 1, 1)`, in units of half the side of the cell. The lattices are symmetric
+// across the middle and the faces of a cell, so these are mirrored into the
+// rest of it, and into the cells around it.
+
+/// From the centre of the cell to its corners.
+pub const BCC: &[Strut] = &[([0.0, 0.0, 0.0], [1.0, 1.0, 1.0])];
+/// Along the diagonals of the faces.
+pub const FCC: &[Strut] = &[
+    ([1.0, 0.0, 0.0], [1.0, 1.0, 1.0]),
+    ([0.0, 1.0, 0.0], [1.0, 1.0, 1.0]),
+    ([0.0, 0.0, 1.0], [1.0, 1.0, 1.0]),
+];
+/// The face diagonals, and the octahedron between the centres of the faces.
+pub const OCTET: &[Strut] = &[
+    ([1.0, 0.0, 0.0], [1.0, 1.0, 1.0]),
+    ([0.0, 1.0, 0.0], [1.0, 1.0, 1.0]),
+    ([0.0, 0.0, 1.0], [1.0, 1.0, 1.0]),
+    ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0]),
+];
+/// The edges of a truncated octahedron, with its vertices at the permutations
+/// of `(0, ±1/2, ±1)`.
+pub const KELVIN: &[Strut] = &[
+    ([0.0, 0.5, 1.0], [0.5, 0.0, 1.0]),
+    ([1.0, 0.0, 0.5], [1.0, 0.5, 0.0]),
+    ([0.0, 1.0, 0.5], [0.5, 1.0, 0.0]),
+    ([0.0, 0.5, 1.0], [0.0, 1.0, 0.5]),
+    ([0.5, 0.0, 1.0], [1.0, 0.0, 0.5]),
+    ([0.5, 1.0, 0.0], [1.0, 0.5, 0.0]),
+];
+
+/// Moves `p` into the corner of its cell that the struts are given in.
+fn fold(p: Vec3x8, cell_size: f32x8) -> Vec3x8 {
+    (p - (p / cell_size).map(|c| c.round()) * cell_size).abs()
+}
+
+/// The distance to the segment from `a` to `b`.
+fn segment(p: Vec3x8, a: Vec3x8, b: Vec3x8) -> f32x8 {
+    let (pa, ba) = (p - a, b - a);
+    let t = (pa.dot(ba) / ba.mag_sq())
+        .max(f32x8::splat(0.0))
+        .min(f32x8::splat(1.0));
+    (pa - ba * t).mag()
+}
+
+/// The distance to the lattice of `struts`, in cells with sides of `cell_size`.
+pub fn lattice(p: Vec3x8, cell_size: f32x8, strut_radius: f32x8, struts: &[Strut]) -> f32x8 {
+    let (q, half) = (fold(p, cell_size), cell_size * f32x8::splat(0.5));
+    let node = |n: [f32; 3]| Vec3x8::splat(Vec3::from(n)) * half;
+
+    struts
+        .iter()
+        .map(|&(a, b)| segment(q, node(a), node(b)))
+        .fold(f32x8::splat(f32::INFINITY), f32x8::min)
+        - strut_radius
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CELL_SIZE: f32 = 0.8;
+    const LATTICES: [&[Strut]; 4] = [BCC, FCC, OCTET, KELVIN];
+
+    fn distance(p: Vec3, struts: &[Strut]) -> f32 {
+        let d: [f32; 8] = lattice(
+            Vec3x8::splat(p),
+            f32x8::splat(CELL_SIZE),
+            f32x8::splat(0.1),
+            struts,
+        )
+        .into();
+        d[0]
+    }
+
+    /// Points in the cells around the origin.
+    fn points() -> impl Iterator<Item = Vec3> {
+        (0..1000).map(|i| {
+            let c = |i: usize| (i % 10) as f32 * 0.23 - 1.1;
+            Vec3::new(c(i), c(i / 10), c(i / 100))
+        })
+    }
+
+    #[test]
+    fn on_struts() {
+        for struts in LATTICES.iter() {
+            for &(a, b) in struts.iter() {
+                let (a, b) = (Vec3::from(a), Vec3::from(b));
+                for &t in &[0.0, 0.3, 0.5, 1.0] {
+                    let on = (a + (b - a) * t) * CELL_SIZE * 0.5;
+                    // In every corner of the cell, and in the next cells.
+                    for &mirror in &[Vec3::one(), Vec3::new(-1.0, 1.0, -1.0)] {
+                        for &cell in &[Vec3::zero(), Vec3::new(1.0, 0.0, -2.0)] {
+                            let p = on * mirror + cell * CELL_SIZE;
+                            assert!((distance(p, struts) + 0.1).abs() < 1e-5, "at {:?}", p);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn symmetric() {
+        let h = CELL_SIZE * 0.5;
+        for struts in LATTICES.iter() {
+            for p in points() {
+                let d = distance(p, struts);
+                for &mirrored in &[
+                    // Across the middle of the cell, and across its faces.
+                    Vec3::new(-p.x, p.y, p.z),
+                    Vec3::new(p.x, -p.y, -p.z),
+                    Vec3::new(2.0 * h - p.x, p.y, p.z),
+                    Vec3::new(p.x, 2.0 * h - p.y, p.z),
+                    Vec3::new(p.x, p.y, -2.0 * h - p.z),
+                    // Into the next cells, and with the axes swapped.
+                    p + Vec3::new(CELL_SIZE, -CELL_SIZE, 0.0),
+                    Vec3::new(p.y, p.z, p.x),
+                    Vec3::new(p.z, p.y, p.x),
+                ] {
+                    let other = distance(mirrored, struts);
+                    assert!((d - other).abs() < 1e-5, "{:?} and {:?}", p, mirrored);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exact() {
+        // Every strut in the cells around the origin, mirrored into each corner.
+        let h = CELL_SIZE * 0.5;
+        let corners: Vec<_> = (0..8)
+            .map(|i| {
+                let sign = |bit| if i & bit == 0 { 1.0 } else { -1.0 };
+                Vec3::new(sign(1), sign(2), sign(4))
+            })
+            .collect();
+        let cells: Vec<_> = (0..27)
+            .map(|i| {
+                let c = |i: i32| (i % 3 - 1) as f32 * CELL_SIZE;
+                Vec3::new(c(i), c(i / 3), c(i / 9))
+            })
+            .collect();
+
+        for struts in LATTICES.iter() {
+            let mut segments = Vec::new();
+            for &(a, b) in struts.iter() {
+                for &corner in &corners {
+                    for &cell in &cells {
+                        let a = Vec3::from(a) * corner * h + cell;
+                        let b = Vec3::from(b) * corner * h + cell;
+                        segments.push((a, b));
+                    }
+                }
+            }
+
+            for p in points().map(|p| p * 0.3) {
+                let expected = segments
+                    .iter()
+                    .map(|&(a, b)| {
+                        let (pa, ba) = (p - a, b - a);
+                        let t = (pa.dot(ba) / ba.mag_sq()).clamp(0.0, 1.0);
+                        (pa - ba * t).mag()
+                    })
+                    .fold(f32::INFINITY, f32::min)
+                    - 0.1;
+                let d = distance(p, struts);
+                assert!((d - expected).abs() < 1e-5, "at {:?}", p);
+            }
+        }
+    }
+}
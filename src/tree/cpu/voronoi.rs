@@ -0,0 +1,131 @@
+//! Voronoi foams, which are walls between the cells around seed points, one at
+//! a random place in each cube of a grid through all of space.
+
+use shared::inst::Voronoi;
+use ultraviolet::{f32x8, Vec3, Vec3x8};
+
+/// The seed point of the cube whose lowest corner is `cube`, in units of cubes.
+fn seed_point(cube: Vec3, seed: u32) -> Vec3 {
+    let jitter = Voronoi::jitter([cube.x as i32, cube.y as i32, cube.z as i32], seed);
+    cube + Vec3::from(jitter)
+}
+
+/// The offsets of the cubes up to `reach` cubes away along each axis.
+fn around(reach: i32) -> impl Iterator<Item = Vec3> {
+    (-reach..=reach).flat_map(move |x| {
+        (-reach..=reach).flat_map(move |y| {
+            (-reach..=reach).map(move |z| Vec3::new(x as f32, y as f32, z as f32))
+        })
+    })
+}
+
+/// The distance from `p` to the closest wall, in cubes with sides of `cell_size`,
+/// and the direction it grows in.
+///
+/// A seed point three cubes away along an axis is at least two cubes from `p`, farther
+/// than the one in `p`'s own cube can be, so the closest seed point is within two cubes.
+/// Its cell is convex, so the closest wall is the closest of the planes halfway to the
+/// points around it, which are searched for within three cubes.
+pub fn walls(p: Vec3, cell_size: f32, seed: u32) -> (f32, Vec3) {
+    let q = p / cell_size;
+    let cube = q.map(f32::floor);
+    let distance_sq = |a: &Vec3| (*a - q).mag_sq();
+    let nearest = around(2)
+        .map(|offset| seed_point(cube + offset, seed))
+        .min_by(|a, b| distance_sq(a).partial_cmp(&distance_sq(b)).unwrap())
+        .unwrap();
+
+    let (mut distance, mut direction) = (f32::INFINITY, Vec3::zero());
+    for offset in around(3) {
+        let other = seed_point(cube + offset, seed);
+        let between = other - nearest;
+        // The nearest point is placed the same way again, and has no wall with itself.
+        if between == Vec3::zero() {
+            continue;
+        }
+        let normal = between.normalized();
+        let d = ((nearest + other) * 0.5 - q).dot(normal);
+        if d < distance {
+            distance = d;
+            direction = -normal;
+        }
+    }
+    (distance * cell_size, direction)
+}
+
+/// The distance to the walls of a foam, which reach `thickness` to either side.
+pub fn foam(p: Vec3x8, cell_size: f32, thickness: f32, seed: u32) -> f32x8 {
+    let points: [Vec3; 8] = p.into();
+    let mut d = [0.0; 8];
+    for (d, &p) in d.iter_mut().zip(&points) {
+        *d = walls(p, cell_size, seed).0 - thickness;
+    }
+    f32x8::from(d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The distance to the closest wall, from every seed point in the cubes up to
+    /// `reach` cubes away.
+    fn brute_force(p: Vec3, cell_size: f32, seed: u32, reach: i32) -> f32 {
+        let q = p / cell_size;
+        let cube = q.map(f32::floor);
+        let points: Vec<_> = around(reach)
+            .map(|offset| seed_point(cube + offset, seed))
+            .collect();
+        let distance_sq = |a: &Vec3| (*a - q).mag_sq();
+        let nearest = points
+            .iter()
+            .min_by(|a, b| distance_sq(a).partial_cmp(&distance_sq(b)).unwrap())
+            .unwrap();
+        points
+            .iter()
+            .filter(|&other| other != nearest)
+            .map(|&other| ((*nearest + other) * 0.5 - q).dot((other - *nearest).normalized()))
+            .fold(f32::INFINITY, f32::min)
+            * cell_size
+    }
+
+    #[test]
+    fn walls() {
+        for seed in 0..4 {
+            for i in 0..1000 {
+                let c = |i: usize| (i % 10) as f32 * 0.37 - 1.9;
+                let p = Vec3::new(c(i), c(i / 10), c(i / 100));
+                let (d, direction) = super::walls(p, 0.7, seed);
+                assert!(
+                    (d - brute_force(p, 0.7, seed, 4)).abs() < 1e-5,
+                    "at {:?}",
+                    p
+                );
+                assert!((direction.mag() - 1.0).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_two_cubes_away() {
+        // The closest seed point isn't in any of the cubes next to this point's.
+        let p = Vec3::new(17.009, 19.178, -3.357);
+        let d = super::walls(p, 1.0, 2).0;
+        assert!((d - brute_force(p, 1.0, 2, 4)).abs() < 1e-5);
+        assert!(d > 0.0);
+    }
+
+    #[test]
+    fn seeds() {
+        let p = Vec3::new(0.3, -1.2, 2.5);
+        assert_eq!(super::walls(p, 1.0, 7), super::walls(p, 1.0, 7));
+        assert_ne!(super::walls(p, 1.0, 7).0, super::walls(p, 1.0, 8).0);
+
+        // Every cube has one seed point in it.
+        for x in -3..3 {
+            for z in -3..3 {
+                let jitter = Voronoi::jitter([x, 5, z], 7);
+                assert!(jitter.iter().all(|&c| (0.0..1.0).contains(&c)));
+            }
+        }
+    }
+}
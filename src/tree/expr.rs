@@ -0,0 +1,634 @@
+//! Math expressions for the values in a CSG tree, like `2*r + sin(t)`.
+//!
+//! Expressions are parsed with the shunting-yard algorithm,
+//! https://en.wikipedia.org/wiki/Shunting-yard_algorithm, and are evaluated
+//! with named parameters when the tree is compiled. The values of fills can also
+//! use the coordinates of the point, and are then evaluated at every point instead.
+
+use std::{collections::HashMap, error::Error, fmt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Func {
+    Abs,
+    Sqrt,
+    Pow,
+    Sin,
+    Cos,
+    Min,
+    Max,
+}
+
+impl Func {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "abs" => Func::Abs,
+            "sqrt" => Func::Sqrt,
+            "pow" => Func::Pow,
+            "sin" => Func::Sin,
+            "cos" => Func::Cos,
+            "min" => Func::Min,
+            "max" => Func::Max,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Func::Abs => "abs",
+            Func::Sqrt => "sqrt",
+            Func::Pow => "pow",
+            Func::Sin => "sin",
+            Func::Cos => "cos",
+            Func::Min => "min",
+            Func::Max => "max",
+        }
+    }
+
+    /// The number of arguments the function takes.
+    pub fn arity(self) -> usize {
+        match self {
+            Func::Abs | Func::Sqrt | Func::Sin | Func::Cos => 1,
+            Func::Pow | Func::Min | Func::Max => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    /// `^`, which is right associative.
+    Pow,
+}
+
+impl BinaryOp {
+    fn from_char(c: char) -> Option<Self> {
+        Some(match c {
+            '+' => BinaryOp::Add,
+            '-' => BinaryOp::Sub,
+            '*' => BinaryOp::Mul,
+            '/' => BinaryOp::Div,
+            '^' => BinaryOp::Pow,
+            _ => return None,
+        })
+    }
+
+    fn symbol(self) -> char {
+        match self {
+            BinaryOp::Add => '+',
+            BinaryOp::Sub => '-',
+            BinaryOp::Mul => '*',
+            BinaryOp::Div => '/',
+            BinaryOp::Pow => '^',
+        }
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Add | BinaryOp::Sub => 1,
+            BinaryOp::Mul | BinaryOp::Div => 2,
+            BinaryOp::Pow => 4,
+        }
+    }
+}
+
+/// The names of the coordinates of the point, in the frame of the filled shape.
+pub const COORDINATES: [&str; 3] = ["x", "y", "z"];
+
+/// Negation binds tighter than `*` but looser than `^`, so `-x^2` is `-(x^2)`.
+const NEG_PRECEDENCE: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f32),
+    Param(String),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedChar(char),
+    InvalidNumber(String),
+    UnknownFunction(String),
+    /// A token where an operand, like a number or `(`, was expected.
+    ExpectedOperand,
+    /// A token where an operator, like `+` or `)`, was expected.
+    ExpectedOperator,
+    UnexpectedEnd,
+    UnmatchedOpen,
+    UnmatchedClose,
+    /// A `,` outside the arguments of a function.
+    UnexpectedComma,
+    WrongArgumentCount {
+        func: Func,
+        found: usize,
+    },
+}
+
+/// An error in an expression, at a column counted in characters from one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub column: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: ", self.column)?;
+        match &self.kind {
+            ParseErrorKind::UnexpectedChar(c) => write!(f, "unexpected character `{}`", c),
+            ParseErrorKind::InvalidNumber(s) => write!(f, "invalid number `{}`", s),
+            ParseErrorKind::UnknownFunction(name) => write!(f, "unknown function `{}`", name),
+            ParseErrorKind::ExpectedOperand => write!(f, "expected a number, name or `(`"),
+            ParseErrorKind::ExpectedOperator => write!(f, "expected an operator or `)`"),
+            ParseErrorKind::UnexpectedEnd => write!(f, "unexpected end of expression"),
+            ParseErrorKind::UnmatchedOpen => write!(f, "`(` is never closed"),
+            ParseErrorKind::UnmatchedClose => write!(f, "`)` has no matching `(`"),
+            ParseErrorKind::UnexpectedComma => write!(f, "`,` outside of function arguments"),
+            ParseErrorKind::WrongArgumentCount { func, found } => write!(
+                f,
+                "`{}` takes {} argument{}, but {} were given",
+                func.name(),
+                func.arity(),
+                if func.arity() == 1 { "" } else { "s" },
+                found
+            ),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    UnknownParameter(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::UnknownParameter(name) => write!(f, "unknown parameter `{}`", name),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Op(char),
+    Open,
+    Close,
+    Comma,
+}
+
+/// Splits an expression into tokens and their columns.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let token = if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                i += 1;
+                if i < chars.len() && (chars[i] == '+' || chars[i] == '-') {
+                    i += 1;
+                }
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+
+            let text: String = chars[start..i].iter().collect();
+            let number = text.parse().map_err(|_| ParseError {
+                column,
+                kind: ParseErrorKind::InvalidNumber(text.clone()),
+            })?;
+            tokens.push((Token::Number(number), column));
+            continue;
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), column));
+            continue;
+        } else if BinaryOp::from_char(c).is_some() {
+            Token::Op(c)
+        } else {
+            match c {
+                '(' => Token::Open,
+                ')' => Token::Close,
+                ',' => Token::Comma,
+                _ => {
+                    return Err(ParseError {
+                        column,
+                        kind: ParseErrorKind::UnexpectedChar(c),
+                    })
+                }
+            }
+        };
+
+        tokens.push((token, column));
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+/// The operator stack of the shunting-yard algorithm.
+enum Pending {
+    Binary(BinaryOp),
+    Neg,
+    /// A `(`, which starts the arguments of `func` if it has one.
+    Open {
+        column: usize,
+        func: Option<Func>,
+        args: usize,
+    },
+}
+
+impl Pending {
+    /// Pops the operands of an operator and pushes the result instead.
+    fn apply(self, operands: &mut Vec<Expr>) {
+        let expr = match self {
+            Pending::Binary(op) => {
+                let rhs = operands.pop().unwrap();
+                let lhs = operands.pop().unwrap();
+                Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+            }
+            Pending::Neg => Expr::Neg(Box::new(operands.pop().unwrap())),
+            Pending::Open { .. } => unreachable!(),
+        };
+        operands.push(expr);
+    }
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(source)?;
+        let end = source.chars().count() + 1;
+        let error = |column, kind| Err(ParseError { column, kind });
+
+        let mut operands = Vec::new();
+        let mut stack = Vec::new();
+        // Whether the next token should start an operand, rather than be an operator.
+        let mut expect_operand = true;
+
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some((token, column)) = tokens.next() {
+            match token {
+                Token::Number(_) | Token::Ident(_) | Token::Open if !expect_operand => {
+                    return error(column, ParseErrorKind::ExpectedOperator);
+                }
+                Token::Op(_) | Token::Close | Token::Comma if expect_operand => {
+                    match token {
+                        Token::Op('-') => stack.push(Pending::Neg),
+                        // A unary plus does nothing.
+                        Token::Op('+') => {}
+                        _ => return error(column, ParseErrorKind::ExpectedOperand),
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            match token {
+                Token::Number(x) => {
+                    operands.push(Expr::Number(x));
+                    expect_operand = false;
+                }
+                Token::Ident(name) => {
+                    if let Some((Token::Open, _)) = tokens.peek() {
+                        tokens.next();
+                        let func = Func::from_name(&name).ok_or(ParseError {
+                            column,
+                            kind: ParseErrorKind::UnknownFunction(name),
+                        })?;
+                        stack.push(Pending::Open {
+                            column,
+                            func: Some(func),
+                            args: 0,
+                        });
+                    } else {
+                        operands.push(Expr::Param(name));
+                        expect_operand = false;
+                    }
+                }
+                Token::Open => stack.push(Pending::Open {
+                    column,
+                    func: None,
+                    args: 0,
+                }),
+                Token::Op(c) => {
+                    let op = BinaryOp::from_char(c).unwrap();
+                    // Apply the operators that bind tighter than this one, or as
+                    // tight if this one is left associative.
+                    while let Some(top) = stack.last() {
+                        let precedence = match top {
+                            Pending::Binary(top) => top.precedence(),
+                            Pending::Neg => NEG_PRECEDENCE,
+                            Pending::Open { .. } => break,
+                        };
+                        if precedence > op.precedence()
+                            || (precedence == op.precedence() && op != BinaryOp::Pow)
+                        {
+                            stack.pop().unwrap().apply(&mut operands);
+                        } else {
+                            break;
+                        }
+                    }
+                    stack.push(Pending::Binary(op));
+                    expect_operand = true;
+                }
+                Token::Comma | Token::Close => {
+                    let open = loop {
+                        match stack.pop() {
+                            Some(Pending::Open { column, func, args }) => {
+                                break (column, func, args + 1)
+                            }
+                            Some(pending) => pending.apply(&mut operands),
+                            None if token == Token::Comma => {
+                                return error(column, ParseErrorKind::UnexpectedComma)
+                            }
+                            None => return error(column, ParseErrorKind::UnmatchedClose),
+                        }
+                    };
+
+                    match (token, open) {
+                        (Token::Comma, (_, None, _)) => {
+                            return error(column, ParseErrorKind::UnexpectedComma)
+                        }
+                        (Token::Comma, (open_column, func, args)) => {
+                            stack.push(Pending::Open {
+                                column: open_column,
+                                func,
+                                args,
+                            });
+                            expect_operand = true;
+                        }
+                        (_, (open_column, Some(func), args)) => {
+                            if args != func.arity() {
+                                let kind = ParseErrorKind::WrongArgumentCount { func, found: args };
+                                return error(open_column, kind);
+                            }
+                            let args = operands.split_off(operands.len() - args);
+                            operands.push(Expr::Call(func, args));
+                        }
+                        (_, (_, None, _)) => {}
+                    }
+                }
+            }
+        }
+
+        if expect_operand {
+            return error(end, ParseErrorKind::UnexpectedEnd);
+        }
+        while let Some(pending) = stack.pop() {
+            if let Pending::Open { column, .. } = pending {
+                return error(column, ParseErrorKind::UnmatchedOpen);
+            }
+            pending.apply(&mut operands);
+        }
+
+        debug_assert_eq!(operands.len(), 1);
+        Ok(operands.pop().unwrap())
+    }
+
+    /// Evaluates the expression with the values of its parameters.
+    pub fn eval(&self, parameters: &HashMap<String, f32>) -> Result<f32, EvalError> {
+        Ok(match self {
+            Expr::Number(x) => *x,
+            Expr::Param(name) => *parameters
+                .get(name)
+                .ok_or_else(|| EvalError::UnknownParameter(name.clone()))?,
+            Expr::Neg(x) => -x.eval(parameters)?,
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(parameters)?, rhs.eval(parameters)?);
+                match op {
+                    BinaryOp::Add => lhs + rhs,
+                    BinaryOp::Sub => lhs - rhs,
+                    BinaryOp::Mul => lhs * rhs,
+                    BinaryOp::Div => lhs / rhs,
+                    BinaryOp::Pow => lhs.powf(rhs),
+                }
+            }
+            Expr::Call(func, args) => {
+                let x = args[0].eval(parameters)?;
+                let y = || args[1].eval(parameters);
+                match func {
+                    Func::Abs => x.abs(),
+                    Func::Sqrt => x.sqrt(),
+                    Func::Pow => x.powf(y()?),
+                    Func::Sin => x.sin(),
+                    Func::Cos => x.cos(),
+                    Func::Min => x.min(y()?),
+                    Func::Max => x.max(y()?),
+                }
+            }
+        })
+    }
+
+    /// Whether the expression depends on the point, through one of [`COORDINATES`].
+    pub fn uses_point(&self) -> bool {
+        match self {
+            Expr::Number(_) => false,
+            Expr::Param(name) => COORDINATES.contains(&name.as_str()),
+            Expr::Neg(x) => x.uses_point(),
+            Expr::Binary(_, lhs, rhs) => lhs.uses_point() || rhs.uses_point(),
+            Expr::Call(_, args) => args.iter().any(Expr::uses_point),
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(op, ..) => op.precedence(),
+            Expr::Neg(_) => NEG_PRECEDENCE,
+            _ => u8::MAX,
+        }
+    }
+}
+
+/// Writes the expression back out, with only the parentheses it needs.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operand = |f: &mut fmt::Formatter<'_>, expr: &Expr, parens: bool| {
+            if parens {
+                write!(f, "({})", expr)
+            } else {
+                write!(f, "{}", expr)
+            }
+        };
+
+        match self {
+            Expr::Number(x) => write!(f, "{}", x),
+            Expr::Param(name) => write!(f, "{}", name),
+            Expr::Neg(x) => {
+                write!(f, "-")?;
+                operand(f, x, x.precedence() < NEG_PRECEDENCE)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let precedence = op.precedence();
+                let (lhs_parens, rhs_parens) = if *op == BinaryOp::Pow {
+                    (
+                        lhs.precedence() <= precedence,
+                        rhs.precedence() < precedence,
+                    )
+                } else {
+                    (
+                        lhs.precedence() < precedence,
+                        rhs.precedence() <= precedence,
+                    )
+                };
+
+                operand(f, lhs, lhs_parens)?;
+                if precedence == 1 {
+                    write!(f, " {} ", op.symbol())?;
+                } else {
+                    write!(f, "{}", op.symbol())?;
+                }
+                operand(f, rhs, rhs_parens)
+            }
+            Expr::Call(func, args) => {
+                write!(f, "{}(", func.name())?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str) -> f32 {
+        let parameters = [("r".to_string(), 1.5), ("t".to_string(), 0.5)]
+            .iter()
+            .cloned()
+            .collect();
+        Expr::parse(source).unwrap().eval(&parameters).unwrap()
+    }
+
+    fn error(source: &str) -> (usize, ParseErrorKind) {
+        let e = Expr::parse(source).unwrap_err();
+        (e.column, e.kind)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("2*r + sin(t)"), 3.0 + 0.5f32.sin());
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("8 / 4 / 2"), 1.0);
+        assert_eq!(eval("1 - 2 - 3"), -4.0);
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(eval("-2^2"), -4.0);
+        assert_eq!(eval("2^-1"), 0.5);
+        assert_eq!(eval("-r * -2"), 3.0);
+        assert_eq!(eval("+1.5e1 - .5"), 14.5);
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(eval("abs(-r)"), 1.5);
+        assert_eq!(eval("sqrt(16)"), 4.0);
+        assert_eq!(eval("pow(2, 1 + 2)"), 8.0);
+        assert_eq!(eval("cos(0)"), 1.0);
+        assert_eq!(eval("min(r, t) + max(r, t)"), 2.0);
+        assert_eq!(eval("max(min(1, 2), abs(-(3)))"), 3.0);
+    }
+
+    #[test]
+    fn uses_point() {
+        let uses_point = |source| Expr::parse(source).unwrap().uses_point();
+        assert!(uses_point("0.2 + x/100"));
+        assert!(uses_point("max(r, sin(z))"));
+        assert!(uses_point("-y^2"));
+        assert!(!uses_point("2*r + sin(t)"));
+        assert!(!uses_point("xy + pow(x0, 2)"));
+    }
+
+    #[test]
+    fn display() {
+        for &source in &[
+            "2*r + sin(t)",
+            "(1 + 2)*3",
+            "1 - (2 - 3)",
+            "(2^3)^2",
+            "2^3^2",
+            "-(r + 1)",
+            "-r^2",
+            "pow(r, 2)/max(t, 1e-3)",
+        ] {
+            let expr = Expr::parse(source).unwrap();
+            assert_eq!(expr.to_string(), source.replace("1e-3", "0.001"));
+            assert_eq!(Expr::parse(&expr.to_string()).unwrap(), expr);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        use ParseErrorKind::*;
+
+        assert_eq!(error("2 * $"), (5, UnexpectedChar('$')));
+        assert_eq!(error("1.2.3"), (1, InvalidNumber("1.2.3".into())));
+        assert_eq!(error("tan(1)"), (1, UnknownFunction("tan".into())));
+        assert_eq!(error("1 + * 2"), (5, ExpectedOperand));
+        assert_eq!(error("2 r"), (3, ExpectedOperator));
+        assert_eq!(error("1 +"), (4, UnexpectedEnd));
+        assert_eq!(error(""), (1, UnexpectedEnd));
+        assert_eq!(error("(1 + 2"), (1, UnmatchedOpen));
+        assert_eq!(error("1 + 2)"), (6, UnmatchedClose));
+        assert_eq!(error("(1, 2)"), (3, UnexpectedComma));
+        assert_eq!(error("1, 2"), (2, UnexpectedComma));
+        assert_eq!(
+            error("1 + min(1, 2, 3)"),
+            (
+                5,
+                WrongArgumentCount {
+                    func: Func::Min,
+                    found: 3
+                }
+            )
+        );
+        assert_eq!(
+            error("sin()").1,
+            ExpectedOperand,
+            "functions always take arguments"
+        );
+
+        assert_eq!(
+            Expr::parse("2 * r").unwrap().eval(&HashMap::new()),
+            Err(EvalError::UnknownParameter("r".into()))
+        );
+        assert_eq!(
+            Expr::parse("1 +\t@").unwrap_err().to_string(),
+            "column 5: unexpected character `@`"
+        );
+    }
+}
@@ -0,0 +1,1756 @@
+//! Compiles a [`CsgTree`] into the instruction tape and matrix buffer that
+//! the shader interpreter in `sdf-shader` evaluates.
+
+use std::{collections::HashMap, error::Error, fmt};
+
+use shared::inst::{
+    Abs, Bcc, Capsule, Cone, Constant, Coordinate, Cos, Cylinder, Diamond, Difference, Ellipsoid,
+    Fcc, Frd, GradedSheet, Gyroid, Inst, Intersection, Iwp, Kelvin, Lidinoid, Negation, Neovius,
+    Octet, Op, Plane, Product, Quotient, RectangularPrism, Ret, SchwarzP, Sin, SmoothIntersection,
+    SmoothSubtraction, SmoothUnion, Sphere, Sqrt, Subtraction, Sum, Torus, Union, Voronoi,
+    REGISTER_COUNT,
+};
+use ultraviolet::{Mat4, Vec3};
+
+use crate::tree::{
+    cpu::Interval,
+    expr::{BinaryOp, Func, COORDINATES},
+    ConstantOrExpr, CsgNode, CsgTree, Dimension, Drain, EvalError, Expr, Fill, Shape, Unit,
+};
+
+/// The largest exponent of a power of a value that varies over space, which
+/// is expanded into a product.
+const MAX_GRADED_EXPONENT: f32 = 16.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    /// The tree has no root node.
+    EmptyTree,
+    /// An expression couldn't be evaluated to a constant.
+    Expr(EvalError),
+    /// A value has a unit of the wrong dimension, like an angle in degrees
+    /// where a length is expected.
+    WrongUnit { unit: Unit, expected: Dimension },
+    /// A plane's normal has no direction.
+    ZeroNormal,
+    /// The cell size of a strut or foam fill uses the point, which only sheets support.
+    GradedCellSize,
+    /// A power of a value that uses the point doesn't have a constant, whole exponent.
+    GradedPower,
+    /// How fast the size or thickness of a fill changes can't be bounded over
+    /// the shape it fills, so its distance can't be kept from overestimating.
+    GradedBound,
+    /// The tree needs more registers than the interpreter has.
+    OutOfRegisters { needed: usize },
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::EmptyTree => write!(f, "cannot compile an empty CSG tree"),
+            CompileError::Expr(e) => write!(f, "{}", e),
+            CompileError::WrongUnit { unit, expected } => {
+                write!(
+                    f,
+                    "expected a value with a unit of {}, not `{}`",
+                    expected, unit
+                )
+            }
+            CompileError::ZeroNormal => write!(f, "a plane's normal can't be zero"),
+            CompileError::GradedCellSize => {
+                write!(
+                    f,
+                    "the cell size of a strut or foam fill can't vary over space"
+                )
+            }
+            CompileError::GradedPower => write!(
+                f,
+                "powers of values that vary over space need a whole exponent from -{0} to {0}",
+                MAX_GRADED_EXPONENT
+            ),
+            CompileError::GradedBound => write!(
+                f,
+                "the size of a fill must stay positive, and it and the thickness can't \
+                 change without bound, over the shape it fills"
+            ),
+            CompileError::OutOfRegisters { needed } => write!(
+                f,
+                "the tree needs {} registers to evaluate, but only {} are available",
+                needed, REGISTER_COUNT
+            ),
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+/// An instruction tape and the inverse transforms that its shapes refer to.
+#[derive(Clone, Default)]
+pub struct Tape {
+    pub insts: Vec<Inst>,
+    pub matrices: Vec<Mat4>,
+}
+
+impl CsgTree {
+    pub fn compile(&self) -> Result<Tape, CompileError> {
+        let root = self.root.as_ref().ok_or(CompileError::EmptyTree)?;
+
+        let needed = registers_needed(root);
+        if needed > REGISTER_COUNT {
+            return Err(CompileError::OutOfRegisters { needed });
+        }
+
+        let mut compiler = Compiler {
+            tape: Tape::default(),
+            parameters: &self.parameters,
+            free: [true; REGISTER_COUNT],
+        };
+        let mut transform = Transform::new(Mat4::identity());
+
+        let out = compiler.node(root, &mut transform)?;
+        compiler.tape.insts.push(Inst::make(out, Ret));
+
+        Ok(compiler.tape)
+    }
+}
+
+/// The number of registers needed to evaluate `node` when the operand that
+/// needs more registers is always evaluated first (Sethi-Ullman numbering).
+fn registers_needed(node: &CsgNode) -> usize {
+    match node {
+        CsgNode::Shape(_, None) => 1,
+        // The lattice of a fill is evaluated after the shape, and intersected with it.
+        CsgNode::Shape(_, Some(fill)) => 1 + lattice_registers(fill),
+        // The skin is evaluated after the shape, from the shape again and the wall
+        // thickness, and then the drains and the lattice, which are combined with it.
+        // A drain needs no more registers than the wall thickness.
+        CsgNode::Infill {
+            fill,
+            wall_thickness,
+            ..
+        } => 2 + value_registers(wall_thickness).max(lattice_registers(fill)),
+        CsgNode::Union { lhs, rhs }
+        | CsgNode::SmoothUnion { lhs, rhs, .. }
+        | CsgNode::Intersection { lhs, rhs }
+        | CsgNode::SmoothIntersection { lhs, rhs, .. }
+        | CsgNode::Subtraction { lhs, rhs }
+        | CsgNode::SmoothSubtraction { lhs, rhs, .. } => {
+            combined(registers_needed(lhs), registers_needed(rhs))
+        }
+        CsgNode::Translate { node, .. } | CsgNode::Rotate { node, .. } => registers_needed(node),
+    }
+}
+
+/// The registers needed to evaluate the lattice of a fill.
+fn lattice_registers(fill: &Fill) -> usize {
+    let (size, thickness) = fill.parameters();
+    if !size.uses_point() && !thickness.uses_point() {
+        return 1;
+    }
+    // A graded scale is evaluated before the lattice, and the thickness after
+    // it, and then divided by the Lipschitz constant. The difference is divided
+    // by a bound on its gradient, which needs one more register.
+    let scale = if size.uses_point() {
+        value_registers(size)
+    } else {
+        0
+    };
+    scale.max(1 + combined(value_registers(thickness), 1))
+}
+
+/// The registers needed by a combination of operands that need `lhs` and `rhs`.
+fn combined(lhs: usize, rhs: usize) -> usize {
+    if lhs == rhs {
+        lhs + 1
+    } else {
+        lhs.max(rhs)
+    }
+}
+
+/// The registers needed to evaluate a value of a fill at every point, which is at
+/// least as many as its lowered expression needs.
+fn value_registers(c: &ConstantOrExpr) -> usize {
+    match c {
+        ConstantOrExpr::Constant(_) => 1,
+        ConstantOrExpr::Expr(expr) => expr_registers(expr),
+        // The value is multiplied by the unit's factor.
+        ConstantOrExpr::WithUnit(value, _) if value.uses_point() => {
+            combined(value_registers(value), 1)
+        }
+        ConstantOrExpr::WithUnit(..) => 1,
+    }
+}
+
+/// Like [`registers_needed`], for an expression.
+fn expr_registers(expr: &Expr) -> usize {
+    if !expr.uses_point() {
+        return 1;
+    }
+    match expr {
+        Expr::Number(_) | Expr::Param(_) => 1,
+        Expr::Neg(x) => expr_registers(x),
+        // Powers become products of the base, which need one more register than it
+        // for the product so far.
+        Expr::Binary(BinaryOp::Pow, base, _) => expr_registers(base) + 1,
+        Expr::Call(Func::Pow, args) => expr_registers(&args[0]) + 1,
+        Expr::Binary(_, lhs, rhs) => combined(expr_registers(lhs), expr_registers(rhs)),
+        Expr::Call(_, args) => match args.as_slice() {
+            [x] => expr_registers(x),
+            [lhs, rhs] => combined(expr_registers(lhs), expr_registers(rhs)),
+            _ => unreachable!("functions take one or two arguments"),
+        },
+    }
+}
+
+/// The opcode, Lipschitz constant and amplitude of a fill that is a sheet.
+fn sheet(fill: &Fill) -> Option<(Op, f32, f32)> {
+    Some(match fill {
+        Fill::Gyroid { .. } => (Op::Gyroid, Gyroid::LIPSCHITZ, Gyroid::AMPLITUDE),
+        Fill::SchwarzP { .. } => (Op::SchwarzP, SchwarzP::LIPSCHITZ, SchwarzP::AMPLITUDE),
+        Fill::Diamond { .. } => (Op::Diamond, Diamond::LIPSCHITZ, Diamond::AMPLITUDE),
+        Fill::Neovius { .. } => (Op::Neovius, Neovius::LIPSCHITZ, Neovius::AMPLITUDE),
+        Fill::Iwp { .. } => (Op::Iwp, Iwp::LIPSCHITZ, Iwp::AMPLITUDE),
+        Fill::Lidinoid { .. } => (Op::Lidinoid, Lidinoid::LIPSCHITZ, Lidinoid::AMPLITUDE),
+        Fill::Frd { .. } => (Op::Frd, Frd::LIPSCHITZ, Frd::AMPLITUDE),
+        Fill::Bcc { .. }
+        | Fill::Fcc { .. }
+        | Fill::Octet { .. }
+        | Fill::Kelvin { .. }
+        | Fill::Voronoi { .. } => return None,
+    })
+}
+
+/// The instruction for a fill with a constant size and thickness.
+fn fill_inst(reg: usize, fill: &Fill, matrix_idx: usize, size: f32, thickness: f32) -> Inst {
+    match fill {
+        Fill::Gyroid { .. } => Inst::make(
+            reg,
+            Gyroid {
+                matrix_idx,
+                scale: size,
+                thickness,
+            },
+        ),
+        Fill::SchwarzP { .. } => Inst::make(
+            reg,
+            SchwarzP {
+                matrix_idx,
+                scale: size,
+                thickness,
+            },
+        ),
+        Fill::Diamond { .. } => Inst::make(
+            reg,
+            Diamond {
+                matrix_idx,
+                scale: size,
+                thickness,
+            },
+        ),
+        Fill::Neovius { .. } => Inst::make(
+            reg,
+            Neovius {
+                matrix_idx,
+                scale: size,
+                thickness,
+            },
+        ),
+        Fill::Iwp { .. } => Inst::make(
+            reg,
+            Iwp {
+                matrix_idx,
+                scale: size,
+                thickness,
+            },
+        ),
+        Fill::Lidinoid { .. } => Inst::make(
+            reg,
+            Lidinoid {
+                matrix_idx,
+                scale: size,
+                thickness,
+            },
+        ),
+        Fill::Frd { .. } => Inst::make(
+            reg,
+            Frd {
+                matrix_idx,
+                scale: size,
+                thickness,
+            },
+        ),
+        Fill::Bcc { .. } => Inst::make(
+            reg,
+            Bcc {
+                matrix_idx,
+                cell_size: size,
+                strut_radius: thickness,
+            },
+        ),
+        Fill::Fcc { .. } => Inst::make(
+            reg,
+            Fcc {
+                matrix_idx,
+                cell_size: size,
+                strut_radius: thickness,
+            },
+        ),
+        Fill::Octet { .. } => Inst::make(
+            reg,
+            Octet {
+                matrix_idx,
+                cell_size: size,
+                strut_radius: thickness,
+            },
+        ),
+        Fill::Kelvin { .. } => Inst::make(
+            reg,
+            Kelvin {
+                matrix_idx,
+                cell_size: size,
+                strut_radius: thickness,
+            },
+        ),
+        Fill::Voronoi { seed, .. } => Inst::make(
+            reg,
+            Voronoi {
+                matrix_idx,
+                cell_size: size,
+                thickness,
+                seed: *seed,
+            },
+        ),
+    }
+}
+
+/// Bounds on the value and the gradient of a lowered expression over the box
+/// around a filled shape, where its coordinates are clamped.
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    value: Interval,
+    gradient: [Interval; 3],
+}
+
+impl Bounds {
+    /// Forward-mode differentiation, with the chain rule applied to intervals.
+    ///
+    /// Clamping a coordinate to the box only ever flattens it, so the derivatives
+    /// of the coordinates are taken to be 1.
+    fn of(expr: &Expr, half_extents: Vec3) -> Self {
+        let of = |x: &Expr| Self::of(x, half_extents);
+        match expr {
+            Expr::Number(value) => Self {
+                value: Interval::splat(*value),
+                gradient: [Interval::splat(0.0); 3],
+            },
+            Expr::Param(name) => {
+                let axis = COORDINATES.iter().position(|c| c == name).unwrap();
+                let mut gradient = [Interval::splat(0.0); 3];
+                gradient[axis] = Interval::splat(1.0);
+                Self {
+                    value: Interval::new(-half_extents[axis], half_extents[axis]),
+                    gradient,
+                }
+            }
+            Expr::Neg(x) => {
+                let x = of(x);
+                x.chain(-x.value, Interval::splat(-1.0))
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (u, v) = (of(lhs), of(rhs));
+                match op {
+                    BinaryOp::Add => u.zip(v, u.value + v.value, |du, dv| du + dv),
+                    BinaryOp::Sub => u.zip(v, u.value - v.value, |du, dv| du - dv),
+                    BinaryOp::Mul => {
+                        u.zip(v, u.value * v.value, |du, dv| du * v.value + u.value * dv)
+                    }
+                    BinaryOp::Div => {
+                        let recip = v.value.recip();
+                        let value = u.value * recip;
+                        u.zip(v, value, |du, dv| (du - value * dv) * recip)
+                    }
+                    BinaryOp::Pow => unreachable!("powers are expanded when lowered"),
+                }
+            }
+            Expr::Call(func, args) => match (func, args.as_slice()) {
+                (Func::Abs, [x]) => {
+                    let x = of(x);
+                    let sign = if x.value.low >= 0.0 {
+                        Interval::splat(1.0)
+                    } else if x.value.high <= 0.0 {
+                        Interval::splat(-1.0)
+                    } else {
+                        Interval::new(-1.0, 1.0)
+                    };
+                    x.chain(x.value.abs(), sign)
+                }
+                (Func::Sqrt, [x]) => {
+                    let x = of(x);
+                    let value = x.value.sqrt();
+                    x.chain(value, (value * 2.0).recip())
+                }
+                (Func::Sin, [x]) => {
+                    let x = of(x);
+                    x.chain(x.value.sin(), x.value.cos())
+                }
+                (Func::Cos, [x]) => {
+                    let x = of(x);
+                    x.chain(x.value.cos(), -x.value.sin())
+                }
+                // Where one side is always the smaller or the larger one, the
+                // gradient is its gradient, and otherwise it's either.
+                (Func::Min, [lhs, rhs]) => {
+                    let (u, v) = (of(lhs), of(rhs));
+                    let value = u.value.min(v.value);
+                    if u.value.high <= v.value.low {
+                        Self { value, ..u }
+                    } else if v.value.high <= u.value.low {
+                        Self { value, ..v }
+                    } else {
+                        u.zip(v, value, hull)
+                    }
+                }
+                (Func::Max, [lhs, rhs]) => {
+                    let (u, v) = (of(lhs), of(rhs));
+                    let value = u.value.max(v.value);
+                    if u.value.low >= v.value.high {
+                        Self { value, ..u }
+                    } else if v.value.low >= u.value.high {
+                        Self { value, ..v }
+                    } else {
+                        u.zip(v, value, hull)
+                    }
+                }
+                _ => unreachable!("powers are expanded when lowered"),
+            },
+        }
+    }
+
+    /// The bounds of `f(self)`, where `f` has the value `value` and its derivative
+    /// is in `derivative`.
+    fn chain(self, value: Interval, derivative: Interval) -> Self {
+        let mut gradient = self.gradient;
+        for g in &mut gradient {
+            *g = *g * derivative;
+        }
+        Self { value, gradient }
+    }
+
+    /// The bounds of a function of `self` and `rhs` with the value `value`, whose
+    /// partial derivatives are `f` of theirs.
+    fn zip(self, rhs: Self, value: Interval, f: impl Fn(Interval, Interval) -> Interval) -> Self {
+        let mut gradient = self.gradient;
+        for (g, &rhs) in gradient.iter_mut().zip(&rhs.gradient) {
+            *g = f(*g, rhs);
+        }
+        Self { value, gradient }
+    }
+
+    /// The longest the gradient can be.
+    fn steepest(&self) -> f32 {
+        self.gradient
+            .iter()
+            .map(|g| g.low.abs().max(g.high.abs()).powi(2))
+            .sum::<f32>()
+            .sqrt()
+    }
+}
+
+/// The smallest interval containing both `lhs` and `rhs`.
+fn hull(lhs: Interval, rhs: Interval) -> Interval {
+    Interval::new(lhs.low.min(rhs.low), lhs.high.max(rhs.high))
+}
+
+/// The local-to-world transform of the subtree currently being compiled.
+struct Transform {
+    mat: Mat4,
+    /// The index of `mat.inversed()` in the matrix buffer, once a shape has used it.
+    idx: Option<usize>,
+}
+
+impl Transform {
+    fn new(mat: Mat4) -> Self {
+        Self { mat, idx: None }
+    }
+}
+
+struct Compiler<'a> {
+    tape: Tape,
+    parameters: &'a HashMap<String, f32>,
+    /// `free[r]` is set when register `r` doesn't hold a value that is still needed.
+    free: [bool; REGISTER_COUNT],
+}
+
+impl<'a> Compiler<'a> {
+    /// Emits instructions that compute the distance to `node` and returns
+    /// the register that holds it.
+    fn node(&mut self, node: &CsgNode, transform: &mut Transform) -> Result<usize, CompileError> {
+        let out = match node {
+            CsgNode::Shape(shape, fill) => {
+                let matrix_idx = self.matrix_idx(transform);
+                let out = self.alloc()?;
+                let inst = self.shape(shape, out, matrix_idx)?;
+                self.tape.insts.push(inst);
+
+                if let Some(fill) = fill {
+                    // The lattice shares the shape's transform, so it moves with the shape.
+                    let half_extents = self.half_extents(shape)?;
+                    let lattice = self.lattice(fill, matrix_idx, half_extents)?;
+                    self.tape.insts.push(Inst::make(
+                        out,
+                        Intersection {
+                            lhs: out,
+                            rhs: lattice,
+                        },
+                    ));
+                    self.free[lattice] = true;
+                }
+                out
+            }
+            CsgNode::Infill {
+                shape,
+                fill,
+                wall_thickness,
+                drains,
+            } => self.infill(shape, fill, wall_thickness, drains, transform)?,
+            CsgNode::Union { lhs, rhs } => {
+                let (lhs, rhs, out) = self.operands(lhs, rhs, transform)?;
+                self.tape.insts.push(Inst::make(out, Union { lhs, rhs }));
+                out
+            }
+            CsgNode::SmoothUnion { lhs, rhs, k } => {
+                let k = self.length(k)?;
+                let (lhs, rhs, out) = self.operands(lhs, rhs, transform)?;
+                self.tape
+                    .insts
+                    .push(Inst::make(out, SmoothUnion { lhs, rhs, k }));
+                out
+            }
+            CsgNode::Intersection { lhs, rhs } => {
+                let (lhs, rhs, out) = self.operands(lhs, rhs, transform)?;
+                self.tape
+                    .insts
+                    .push(Inst::make(out, Intersection { lhs, rhs }));
+                out
+            }
+            CsgNode::SmoothIntersection { lhs, rhs, k } => {
+                let k = self.length(k)?;
+                let (lhs, rhs, out) = self.operands(lhs, rhs, transform)?;
+                self.tape
+                    .insts
+                    .push(Inst::make(out, SmoothIntersection { lhs, rhs, k }));
+                out
+            }
+            CsgNode::Subtraction { lhs, rhs } => {
+                let (lhs, rhs, out) = self.operands(lhs, rhs, transform)?;
+                self.tape
+                    .insts
+                    .push(Inst::make(out, Subtraction { lhs, rhs }));
+                out
+            }
+            CsgNode::SmoothSubtraction { lhs, rhs, k } => {
+                let k = self.length(k)?;
+                let (lhs, rhs, out) = self.operands(lhs, rhs, transform)?;
+                self.tape
+                    .insts
+                    .push(Inst::make(out, SmoothSubtraction { lhs, rhs, k }));
+                out
+            }
+            CsgNode::Translate { x, y, z, node } => {
+                let by = Vec3::new(self.length(x)?, self.length(y)?, self.length(z)?);
+                let mut transform = Transform::new(transform.mat * Mat4::from_translation(by));
+                self.node(node, &mut transform)?
+            }
+            CsgNode::Rotate {
+                roll,
+                pitch,
+                yaw,
+                node,
+            } => {
+                let rotation = Mat4::from_euler_angles(
+                    self.angle(roll)?,
+                    self.angle(pitch)?,
+                    self.angle(yaw)?,
+                );
+                let mut transform = Transform::new(transform.mat * rotation);
+                self.node(node, &mut transform)?
+            }
+        };
+
+        Ok(out)
+    }
+
+    /// The instruction that computes the distance to `shape` into `out`.
+    fn shape(&self, shape: &Shape, out: usize, matrix_idx: usize) -> Result<Inst, CompileError> {
+        Ok(match shape {
+            Shape::Sphere { radius } => Inst::make(
+                out,
+                Sphere {
+                    matrix_idx,
+                    radius: self.length(radius)?,
+                },
+            ),
+            Shape::Box {
+                side_x,
+                side_y,
+                side_z,
+            } => Inst::make(
+                out,
+                RectangularPrism {
+                    matrix_idx,
+                    x: self.length(side_x)?,
+                    y: self.length(side_y)?,
+                    z: self.length(side_z)?,
+                },
+            ),
+            Shape::Cylinder {
+                radius,
+                half_height,
+            } => Inst::make(
+                out,
+                Cylinder {
+                    matrix_idx,
+                    radius: self.length(radius)?,
+                    half_height: self.length(half_height)?,
+                },
+            ),
+            Shape::Torus {
+                major_radius,
+                minor_radius,
+            } => Inst::make(
+                out,
+                Torus {
+                    matrix_idx,
+                    major_radius: self.length(major_radius)?,
+                    minor_radius: self.length(minor_radius)?,
+                },
+            ),
+            Shape::Capsule {
+                radius,
+                half_height,
+            } => Inst::make(
+                out,
+                Capsule {
+                    matrix_idx,
+                    radius: self.length(radius)?,
+                    half_height: self.length(half_height)?,
+                },
+            ),
+            Shape::Cone {
+                bottom_radius,
+                top_radius,
+                half_height,
+            } => Inst::make(
+                out,
+                Cone {
+                    matrix_idx,
+                    bottom_radius: self.length(bottom_radius)?,
+                    top_radius: self.length(top_radius)?,
+                    half_height: self.length(half_height)?,
+                },
+            ),
+            Shape::Plane {
+                normal_x,
+                normal_y,
+                normal_z,
+                offset,
+            } => {
+                let normal = Vec3::new(
+                    self.length(normal_x)?,
+                    self.length(normal_y)?,
+                    self.length(normal_z)?,
+                );
+                if normal == Vec3::zero() {
+                    return Err(CompileError::ZeroNormal);
+                }
+                let normal = normal.normalized();
+                Inst::make(
+                    out,
+                    Plane {
+                        matrix_idx,
+                        normal_x: normal.x,
+                        normal_y: normal.y,
+                        normal_z: normal.z,
+                        offset: self.length(offset)?,
+                    },
+                )
+            }
+            Shape::Ellipsoid {
+                radius_x,
+                radius_y,
+                radius_z,
+            } => Inst::make(
+                out,
+                Ellipsoid {
+                    matrix_idx,
+                    x: self.length(radius_x)?,
+                    y: self.length(radius_y)?,
+                    z: self.length(radius_z)?,
+                },
+            ),
+        })
+    }
+
+    /// The half sides of the box around `shape`, centred on the origin of its frame,
+    /// which are infinite for shapes without one.
+    fn half_extents(&self, shape: &Shape) -> Result<Vec3, CompileError> {
+        Ok(match shape {
+            Shape::Sphere { radius } => Vec3::broadcast(self.length(radius)?),
+            Shape::Box {
+                side_x,
+                side_y,
+                side_z,
+            } => Vec3::new(
+                self.length(side_x)?,
+                self.length(side_y)?,
+                self.length(side_z)?,
+            ),
+            Shape::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let radius = self.length(radius)?;
+                Vec3::new(radius, self.length(half_height)?, radius)
+            }
+            Shape::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let minor_radius = self.length(minor_radius)?;
+                let radius = self.length(major_radius)? + minor_radius;
+                Vec3::new(radius, minor_radius, radius)
+            }
+            Shape::Capsule {
+                radius,
+                half_height,
+            } => {
+                let radius = self.length(radius)?;
+                Vec3::new(radius, self.length(half_height)? + radius, radius)
+            }
+            Shape::Cone {
+                bottom_radius,
+                top_radius,
+                half_height,
+            } => {
+                let radius = self.length(bottom_radius)?.max(self.length(top_radius)?);
+                Vec3::new(radius, self.length(half_height)?, radius)
+            }
+            Shape::Plane { .. } => Vec3::broadcast(f32::INFINITY),
+            Shape::Ellipsoid {
+                radius_x,
+                radius_y,
+                radius_z,
+            } => Vec3::new(
+                self.length(radius_x)?,
+                self.length(radius_y)?,
+                self.length(radius_z)?,
+            ),
+        })
+    }
+
+    /// Emits a shape with a skin on the inside of its surface and a lattice inside
+    /// that, and returns the register that holds it.
+    ///
+    /// That's the shape intersected with the union of the skin and the lattice, where
+    /// the skin is everywhere that isn't deeper in the shape than the wall thickness,
+    /// with the drains subtracted from it.
+    fn infill(
+        &mut self,
+        shape: &Shape,
+        fill: &Fill,
+        wall_thickness: &ConstantOrExpr,
+        drains: &[Drain],
+        transform: &mut Transform,
+    ) -> Result<usize, CompileError> {
+        let matrix_idx = self.matrix_idx(transform);
+        let out = self.alloc()?;
+        let inst = self.shape(shape, out, matrix_idx)?;
+        self.tape.insts.push(inst);
+        let half_extents = self.half_extents(shape)?;
+
+        // Every register is only read once, so the shape is evaluated again for the skin,
+        // which is `-(d + wall_thickness)`.
+        let skin = self.alloc()?;
+        let inst = self.shape(shape, skin, matrix_idx)?;
+        self.tape.insts.push(inst);
+        self.tape
+            .insts
+            .push(Inst::make(skin, Negation { operand: skin }));
+        let wall_thickness = self.graded(wall_thickness)?;
+        // Like a graded fill, a graded skin is divided by a bound on its gradient.
+        let bound = 1.0 + Bounds::of(&wall_thickness, half_extents).steepest();
+        if !bound.is_finite() {
+            return Err(CompileError::GradedBound);
+        }
+        let wall_thickness = self.expr(&wall_thickness, matrix_idx, half_extents)?;
+        self.tape.insts.push(Inst::make(
+            skin,
+            Difference {
+                lhs: skin,
+                rhs: wall_thickness,
+            },
+        ));
+        self.free[wall_thickness] = true;
+        if bound > 1.0 {
+            let rhs = self.alloc()?;
+            self.tape
+                .insts
+                .push(Inst::make(rhs, Constant { value: bound }));
+            self.tape
+                .insts
+                .push(Inst::make(skin, Quotient { lhs: skin, rhs }));
+            self.free[rhs] = true;
+        }
+
+        for drain in drains {
+            let at = Vec3::new(
+                self.length(&drain.x)?,
+                self.length(&drain.y)?,
+                self.length(&drain.z)?,
+            );
+            let mut transform = Transform::new(transform.mat * Mat4::from_translation(at));
+            let matrix_idx = self.matrix_idx(&mut transform);
+            let hole = self.alloc()?;
+            let radius = self.length(&drain.radius)?;
+            self.tape
+                .insts
+                .push(Inst::make(hole, Sphere { matrix_idx, radius }));
+            self.tape.insts.push(Inst::make(
+                skin,
+                Subtraction {
+                    lhs: hole,
+                    rhs: skin,
+                },
+            ));
+            self.free[hole] = true;
+        }
+
+        let lattice = self.lattice(fill, matrix_idx, half_extents)?;
+        self.tape.insts.push(Inst::make(
+            skin,
+            Union {
+                lhs: skin,
+                rhs: lattice,
+            },
+        ));
+        self.free[lattice] = true;
+        self.tape.insts.push(Inst::make(
+            out,
+            Intersection {
+                lhs: out,
+                rhs: skin,
+            },
+        ));
+        self.free[skin] = true;
+        Ok(out)
+    }
+
+    /// Emits the lattice of a fill through all of space, and returns the register
+    /// that holds it. A graded fill is only a bound on the distance within the box
+    /// with the half sides `half_extents` around the shape it fills.
+    fn lattice(
+        &mut self,
+        fill: &Fill,
+        matrix_idx: usize,
+        half_extents: Vec3,
+    ) -> Result<usize, CompileError> {
+        let (size, thickness) = fill.parameters();
+        if size.uses_point() || thickness.uses_point() {
+            return self.graded_fill(fill, matrix_idx, half_extents);
+        }
+        let lattice = self.alloc()?;
+        let (size, thickness) = (self.length(size)?, self.length(thickness)?);
+        self.tape
+            .insts
+            .push(fill_inst(lattice, fill, matrix_idx, size, thickness));
+        Ok(lattice)
+    }
+
+    /// Evaluates both operands of a combination and returns their registers,
+    /// along with a register for the result.
+    ///
+    /// The operands are released before the result is allocated, so the
+    /// result usually reuses one of their registers.
+    fn operands(
+        &mut self,
+        lhs: &CsgNode,
+        rhs: &CsgNode,
+        transform: &mut Transform,
+    ) -> Result<(usize, usize, usize), CompileError> {
+        let (lhs, rhs) = if registers_needed(rhs) > registers_needed(lhs) {
+            let rhs = self.node(rhs, transform)?;
+            (self.node(lhs, transform)?, rhs)
+        } else {
+            let lhs = self.node(lhs, transform)?;
+            (lhs, self.node(rhs, transform)?)
+        };
+
+        self.free[lhs] = true;
+        self.free[rhs] = true;
+
+        Ok((lhs, rhs, self.alloc()?))
+    }
+
+    /// Emits a fill whose size or thickness varies over space, and returns the
+    /// register that holds it.
+    ///
+    /// The lattice is evaluated without a thickness, which is subtracted from it
+    /// afterwards, in the same units as the constant fills subtract it in.
+    ///
+    /// Where the size and the thickness change, the difference changes faster than
+    /// the distance, so it's divided by a bound on its gradient over the box around
+    /// the filled shape. The coordinates are clamped to the box, so the bound holds
+    /// outside it too. For a sheet `|g(p / s)| * s / L - t / L`, that's at most
+    /// `1 + |∇s| * (|p| / s + max |g| / L) + |∇t| / L`, and for struts and foams
+    /// `1 + |∇t|`.
+    fn graded_fill(
+        &mut self,
+        fill: &Fill,
+        matrix_idx: usize,
+        half_extents: Vec3,
+    ) -> Result<usize, CompileError> {
+        let (size, thickness) = fill.parameters();
+        let sheet = sheet(fill);
+        let mut bound = 1.0;
+
+        let lattice = if size.uses_point() {
+            let (sheet, lipschitz, amplitude) = sheet.ok_or(CompileError::GradedCellSize)?;
+            let scale = self.graded(size)?;
+            let bounds = Bounds::of(&scale, half_extents);
+            if bounds.value.low <= 0.0 {
+                return Err(CompileError::GradedBound);
+            }
+            bound +=
+                bounds.steepest() * (half_extents.mag() / bounds.value.low + amplitude / lipschitz);
+            let scale = self.expr(&scale, matrix_idx, half_extents)?;
+            self.free[scale] = true;
+            let lattice = self.alloc()?;
+            self.tape.insts.push(Inst::make(
+                lattice,
+                GradedSheet {
+                    matrix_idx,
+                    sheet,
+                    scale,
+                    half_extents: *half_extents.as_array(),
+                },
+            ));
+            lattice
+        } else {
+            let lattice = self.alloc()?;
+            let inst = fill_inst(lattice, fill, matrix_idx, self.length(size)?, 0.0);
+            self.tape.insts.push(inst);
+            lattice
+        };
+
+        let thickness = match (self.graded(thickness)?, sheet) {
+            (Expr::Number(t), Some((_, lipschitz, _))) => Expr::Number(t / lipschitz),
+            (t, Some((_, lipschitz, _))) => Expr::Binary(
+                BinaryOp::Div,
+                Box::new(t),
+                Box::new(Expr::Number(lipschitz)),
+            ),
+            (t, None) => t,
+        };
+        bound += Bounds::of(&thickness, half_extents).steepest();
+        if !bound.is_finite() {
+            return Err(CompileError::GradedBound);
+        }
+        let thickness = self.expr(&thickness, matrix_idx, half_extents)?;
+
+        self.free[lattice] = true;
+        self.free[thickness] = true;
+        let out = self.alloc()?;
+        self.tape.insts.push(Inst::make(
+            out,
+            Difference {
+                lhs: lattice,
+                rhs: thickness,
+            },
+        ));
+        if bound > 1.0 {
+            let rhs = self.alloc()?;
+            self.tape
+                .insts
+                .push(Inst::make(rhs, Constant { value: bound }));
+            self.tape
+                .insts
+                .push(Inst::make(out, Quotient { lhs: out, rhs }));
+            self.free[rhs] = true;
+        }
+        Ok(out)
+    }
+
+    /// Lowers a value of a fill to an expression in millimetres, where everything
+    /// that doesn't use the point is evaluated to a number.
+    fn graded(&self, c: &ConstantOrExpr) -> Result<Expr, CompileError> {
+        if !c.uses_point() {
+            return self.length(c).map(Expr::Number);
+        }
+        match c {
+            ConstantOrExpr::Constant(_) => unreachable!(),
+            ConstantOrExpr::Expr(expr) => self.lower(expr),
+            ConstantOrExpr::WithUnit(value, unit) => {
+                if unit.dimension() != Dimension::Length {
+                    return Err(CompileError::WrongUnit {
+                        unit: *unit,
+                        expected: Dimension::Length,
+                    });
+                }
+                Ok(Expr::Binary(
+                    BinaryOp::Mul,
+                    Box::new(self.graded(value)?),
+                    Box::new(Expr::Number(unit.normalize(1.0))),
+                ))
+            }
+        }
+    }
+
+    fn lower(&self, expr: &Expr) -> Result<Expr, CompileError> {
+        if !expr.uses_point() {
+            return expr
+                .eval(self.parameters)
+                .map(Expr::Number)
+                .map_err(CompileError::Expr);
+        }
+        Ok(match expr {
+            Expr::Number(_) => unreachable!(),
+            Expr::Param(_) => expr.clone(),
+            Expr::Neg(x) => Expr::Neg(Box::new(self.lower(x)?)),
+            Expr::Binary(BinaryOp::Pow, base, exponent) => self.power(base, exponent)?,
+            Expr::Call(Func::Pow, args) => self.power(&args[0], &args[1])?,
+            Expr::Binary(op, lhs, rhs) => {
+                Expr::Binary(*op, Box::new(self.lower(lhs)?), Box::new(self.lower(rhs)?))
+            }
+            Expr::Call(func, args) => Expr::Call(
+                *func,
+                args.iter()
+                    .map(|arg| self.lower(arg))
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+
+    /// There's no instruction for powers, so they're expanded into products of the
+    /// base, which needs a whole exponent.
+    fn power(&self, base: &Expr, exponent: &Expr) -> Result<Expr, CompileError> {
+        if exponent.uses_point() {
+            return Err(CompileError::GradedPower);
+        }
+        let n = exponent.eval(self.parameters).map_err(CompileError::Expr)?;
+        if n.fract() != 0.0 || n.abs() > MAX_GRADED_EXPONENT {
+            return Err(CompileError::GradedPower);
+        }
+        if n == 0.0 {
+            return Ok(Expr::Number(1.0));
+        }
+
+        let base = self.lower(base)?;
+        let product = (1..n.abs() as usize).fold(base.clone(), |product, _| {
+            Expr::Binary(BinaryOp::Mul, Box::new(product), Box::new(base.clone()))
+        });
+        Ok(if n < 0.0 {
+            Expr::Binary(
+                BinaryOp::Div,
+                Box::new(Expr::Number(1.0)),
+                Box::new(product),
+            )
+        } else {
+            product
+        })
+    }
+
+    /// Emits instructions that compute a lowered expression at every point, with the
+    /// coordinates in the frame of `matrix_idx` clamped to the box with the half sides
+    /// `half_extents`, and returns the register that holds it.
+    fn expr(
+        &mut self,
+        expr: &Expr,
+        matrix_idx: usize,
+        half_extents: Vec3,
+    ) -> Result<usize, CompileError> {
+        match expr {
+            Expr::Number(value) => {
+                let out = self.alloc()?;
+                self.tape
+                    .insts
+                    .push(Inst::make(out, Constant { value: *value }));
+                Ok(out)
+            }
+            Expr::Param(name) => {
+                let axis = COORDINATES.iter().position(|c| c == name).unwrap();
+                let out = self.alloc()?;
+                self.tape.insts.push(Inst::make(
+                    out,
+                    Coordinate {
+                        matrix_idx,
+                        axis,
+                        half_extent: half_extents[axis],
+                    },
+                ));
+                Ok(out)
+            }
+            Expr::Neg(x) => self.unary(x, matrix_idx, half_extents, |out, operand| {
+                Inst::make(out, Negation { operand })
+            }),
+            Expr::Binary(op, lhs, rhs) => self.binary(
+                lhs,
+                rhs,
+                matrix_idx,
+                half_extents,
+                |out, lhs, rhs| match op {
+                    BinaryOp::Add => Inst::make(out, Sum { lhs, rhs }),
+                    BinaryOp::Sub => Inst::make(out, Difference { lhs, rhs }),
+                    BinaryOp::Mul => Inst::make(out, Product { lhs, rhs }),
+                    BinaryOp::Div => Inst::make(out, Quotient { lhs, rhs }),
+                    BinaryOp::Pow => unreachable!("powers are expanded when lowered"),
+                },
+            ),
+            Expr::Call(func, args) => match (func, args.as_slice()) {
+                (Func::Abs, [x]) => self.unary(x, matrix_idx, half_extents, |out, operand| {
+                    Inst::make(out, Abs { operand })
+                }),
+                (Func::Sqrt, [x]) => self.unary(x, matrix_idx, half_extents, |out, operand| {
+                    Inst::make(out, Sqrt { operand })
+                }),
+                (Func::Sin, [x]) => self.unary(x, matrix_idx, half_extents, |out, operand| {
+                    Inst::make(out, Sin { operand })
+                }),
+                (Func::Cos, [x]) => self.unary(x, matrix_idx, half_extents, |out, operand| {
+                    Inst::make(out, Cos { operand })
+                }),
+                // These are the same as a union and an intersection of distances.
+                (Func::Min, [lhs, rhs]) => {
+                    self.binary(lhs, rhs, matrix_idx, half_extents, |out, lhs, rhs| {
+                        Inst::make(out, Union { lhs, rhs })
+                    })
+                }
+                (Func::Max, [lhs, rhs]) => {
+                    self.binary(lhs, rhs, matrix_idx, half_extents, |out, lhs, rhs| {
+                        Inst::make(out, Intersection { lhs, rhs })
+                    })
+                }
+                _ => unreachable!("powers are expanded when lowered"),
+            },
+        }
+    }
+
+    fn unary(
+        &mut self,
+        x: &Expr,
+        matrix_idx: usize,
+        half_extents: Vec3,
+        inst: impl FnOnce(usize, usize) -> Inst,
+    ) -> Result<usize, CompileError> {
+        let operand = self.expr(x, matrix_idx, half_extents)?;
+        self.free[operand] = true;
+        let out = self.alloc()?;
+        self.tape.insts.push(inst(out, operand));
+        Ok(out)
+    }
+
+    /// Like [`Compiler::operands`], for expressions.
+    fn binary(
+        &mut self,
+        lhs: &Expr,
+        rhs: &Expr,
+        matrix_idx: usize,
+        half_extents: Vec3,
+        inst: impl FnOnce(usize, usize, usize) -> Inst,
+    ) -> Result<usize, CompileError> {
+        let (lhs, rhs) = if expr_registers(rhs) > expr_registers(lhs) {
+            let rhs = self.expr(rhs, matrix_idx, half_extents)?;
+            (self.expr(lhs, matrix_idx, half_extents)?, rhs)
+        } else {
+            let lhs = self.expr(lhs, matrix_idx, half_extents)?;
+            (lhs, self.expr(rhs, matrix_idx, half_extents)?)
+        };
+
+        self.free[lhs] = true;
+        self.free[rhs] = true;
+        let out = self.alloc()?;
+        self.tape.insts.push(inst(out, lhs, rhs));
+        Ok(out)
+    }
+
+    fn alloc(&mut self) -> Result<usize, CompileError> {
+        let reg = self
+            .free
+            .iter()
+            .position(|&free| free)
+            .ok_or(CompileError::OutOfRegisters {
+                needed: REGISTER_COUNT + 1,
+            })?;
+        self.free[reg] = false;
+        Ok(reg)
+    }
+
+    /// Expressions are evaluated here, so they become constants on the tape,
+    /// and units are normalized to millimetres.
+    fn length(&self, c: &ConstantOrExpr) -> Result<f32, CompileError> {
+        self.constant(c, Dimension::Length)
+    }
+
+    /// Like [`Compiler::length`], for angles in radians.
+    fn angle(&self, c: &ConstantOrExpr) -> Result<f32, CompileError> {
+        self.constant(c, Dimension::Angle)
+    }
+
+    fn constant(&self, c: &ConstantOrExpr, expected: Dimension) -> Result<f32, CompileError> {
+        if let Some(unit) = c.unit() {
+            if unit.dimension() != expected {
+                return Err(CompileError::WrongUnit { unit, expected });
+            }
+        }
+        c.get(self.parameters).map_err(CompileError::Expr)
+    }
+
+    fn matrix_idx(&mut self, transform: &mut Transform) -> usize {
+        let matrices = &mut self.tape.matrices;
+        let mat = transform.mat;
+        *transform.idx.get_or_insert_with(|| {
+            matrices.push(mat.inversed());
+            matrices.len() - 1
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::inst::Op;
+    use std::{f32::consts::PI, rc::Rc};
+
+    fn sphere(radius: f32) -> Rc<CsgNode> {
+        Rc::new(CsgNode::Shape(
+            Shape::Sphere {
+                radius: ConstantOrExpr::Constant(radius),
+            },
+            None,
+        ))
+    }
+
+    fn ops(tape: &Tape) -> Vec<Op> {
+        tape.insts.iter().map(|inst| inst.op()).collect()
+    }
+
+    #[test]
+    fn compile_union() {
+        let tree = CsgTree::new(CsgNode::Union {
+            lhs: sphere(1.0),
+            rhs: Rc::new(CsgNode::Translate {
+                x: ConstantOrExpr::Constant(1.5),
+                y: ConstantOrExpr::Constant(0.0),
+                z: ConstantOrExpr::Constant(0.0),
+                node: sphere(0.5),
+            }),
+        });
+        let tape = tree.compile().unwrap();
+
+        assert_eq!(ops(&tape), [Op::Sphere, Op::Sphere, Op::Union, Op::Ret]);
+        assert_eq!(tape.matrices.len(), 2);
+        assert_eq!(
+            tape.matrices[1].transform_point3(Vec3::new(1.5, 0.0, 0.0)),
+            Vec3::zero()
+        );
+    }
+
+    #[test]
+    fn compile_fill() {
+        let tree = CsgTree::new(CsgNode::Union {
+            lhs: sphere(1.0),
+            rhs: Rc::new(CsgNode::Translate {
+                x: ConstantOrExpr::Constant(2.0),
+                y: ConstantOrExpr::Constant(0.0),
+                z: ConstantOrExpr::Constant(0.0),
+                node: Rc::new(CsgNode::Shape(
+                    Shape::Box {
+                        side_x: ConstantOrExpr::Constant(1.0),
+                        side_y: ConstantOrExpr::Constant(1.0),
+                        side_z: ConstantOrExpr::Constant(1.0),
+                    },
+                    Some(Fill::Gyroid {
+                        scale: ConstantOrExpr::parse("1 cm").unwrap(),
+                        thickness: ConstantOrExpr::Constant(0.1),
+                    }),
+                )),
+            }),
+        });
+        let tape = tree.compile().unwrap();
+
+        // The filled box needs two registers, so it is evaluated first.
+        assert_eq!(
+            ops(&tape),
+            [
+                Op::RectangularPrism,
+                Op::Gyroid,
+                Op::Intersection,
+                Op::Sphere,
+                Op::Union,
+                Op::Ret
+            ]
+        );
+        let gyroid = tape.insts[1].extract::<Gyroid>();
+        assert_eq!(
+            gyroid.matrix_idx,
+            tape.insts[0].extract::<RectangularPrism>().matrix_idx
+        );
+        assert_eq!(gyroid.scale, 10.0);
+        let intersection = tape.insts[2].extract::<Intersection>();
+        assert_eq!(
+            (intersection.lhs, intersection.rhs),
+            (tape.insts[0].reg(), tape.insts[1].reg())
+        );
+        assert_eq!(tape.insts[2].reg(), tape.insts[0].reg());
+    }
+
+    fn filled_sphere(fill: Fill) -> CsgTree {
+        CsgTree::new(CsgNode::Shape(
+            Shape::Sphere {
+                radius: ConstantOrExpr::Constant(3.0),
+            },
+            Some(fill),
+        ))
+    }
+
+    #[test]
+    fn compile_graded_fill() {
+        let parse = |source: &str| ConstantOrExpr::parse(source).unwrap();
+        let gyroid = |scale: &str, thickness: &str| {
+            let mut tree = filled_sphere(Fill::Gyroid {
+                scale: parse(scale),
+                thickness: parse(thickness),
+            });
+            tree.set_parameter("s", 0.5);
+            tree.set_parameter("t", 0.1);
+            tree
+        };
+        let tape = gyroid("(s + x/10) cm", "t").compile().unwrap();
+
+        // The division needs two registers, so it comes before the sum, and the
+        // constant thickness is divided by the Lipschitz constant when compiled.
+        // The sheet is then divided by a bound on its gradient.
+        assert_eq!(
+            ops(&tape),
+            [
+                Op::Sphere,
+                Op::Coordinate,
+                Op::Constant,
+                Op::Quotient,
+                Op::Constant,
+                Op::Sum,
+                Op::Constant,
+                Op::Product,
+                Op::GradedSheet,
+                Op::Constant,
+                Op::Difference,
+                Op::Constant,
+                Op::Quotient,
+                Op::Intersection,
+                Op::Ret
+            ]
+        );
+        assert_eq!(tape.insts[8].extract::<GradedSheet>().sheet, Op::Gyroid);
+        assert_eq!(
+            tape.insts[9].extract::<Constant>().value,
+            0.1 / Gyroid::LIPSCHITZ
+        );
+
+        assert_eq!(
+            tape.insts[8].extract::<GradedSheet>().half_extents,
+            [3.0; 3]
+        );
+        assert_eq!(tape.insts[1].extract::<Coordinate>().half_extent, 3.0);
+
+        // At each point, the fill is the same as one with the sizes at that point,
+        // up to the bound, which is from a scale of 2 to 8 mm that changes by 1 mm
+        // per mm over the sphere's bounding box.
+        let bound = tape.insts[11].extract::<Constant>().value;
+        let expected = 1.0 + (27.0f32.sqrt() / 2.0 + Gyroid::AMPLITUDE / Gyroid::LIPSCHITZ);
+        assert!((bound - expected).abs() < 1e-4, "{}", bound);
+        for &x in &[-2.0, -0.5, 1.0, 2.5] {
+            let p = Vec3::new(x, 0.8, -1.2);
+            let mut graded = [0.0];
+            tape.eval_points(&[p], &mut graded);
+
+            let scale = format!("{} mm", (0.5 + x / 10.0) * 10.0);
+            let constant = gyroid(&scale, "t").compile().unwrap();
+            let mut expected = [0.0];
+            constant.eval_points(&[p], &mut expected);
+            assert!((graded[0] * bound - expected[0]).abs() < 1e-4, "at {:?}", p);
+        }
+
+        // Only the thickness varies here, and powers become products.
+        let tape = gyroid("s", "t * (1 + y^2)").compile().unwrap();
+        assert_eq!(tape.insts[1].op(), Op::Gyroid);
+        assert_eq!(tape.insts[1].extract::<Gyroid>().thickness, 0.0);
+        assert!(ops(&tape).contains(&Op::Product));
+        assert!(!ops(&tape).contains(&Op::GradedSheet));
+    }
+
+    #[test]
+    fn compile_foam() {
+        let parse = |source: &str| ConstantOrExpr::parse(source).unwrap();
+        let foam = |cell_size: &str, thickness: &str| {
+            filled_sphere(Fill::Voronoi {
+                cell_size: parse(cell_size),
+                thickness: parse(thickness),
+                seed: 42,
+            })
+            .compile()
+        };
+
+        let tape = foam("5 mm", "0.2").unwrap();
+        assert_eq!(
+            ops(&tape),
+            [Op::Sphere, Op::Voronoi, Op::Intersection, Op::Ret]
+        );
+        let voronoi = tape.insts[1].extract::<Voronoi>();
+        assert_eq!(
+            (voronoi.cell_size, voronoi.thickness, voronoi.seed),
+            (5.0, 0.2, 42)
+        );
+
+        // A graded thickness is subtracted from walls without any, and divided by
+        // how fast it changes.
+        let tape = foam("5", "0.2 + x/100").unwrap();
+        assert_eq!(tape.insts[1].extract::<Voronoi>().thickness, 0.0);
+        let len = tape.insts.len();
+        assert_eq!(
+            ops(&tape)[len - 5..],
+            [
+                Op::Difference,
+                Op::Constant,
+                Op::Quotient,
+                Op::Intersection,
+                Op::Ret
+            ]
+        );
+        assert!((tape.insts[len - 4].extract::<Constant>().value - 1.01).abs() < 1e-6);
+        assert_eq!(
+            foam("5 + x", "0.2").err(),
+            Some(CompileError::GradedCellSize)
+        );
+    }
+
+    #[test]
+    fn compile_infill() {
+        let parse = |source: &str| ConstantOrExpr::parse(source).unwrap();
+        let infill = |wall_thickness: &str, drains: Vec<Drain>| {
+            CsgTree::new(CsgNode::Infill {
+                shape: Shape::Sphere {
+                    radius: ConstantOrExpr::Constant(3.0),
+                },
+                fill: Fill::Gyroid {
+                    scale: ConstantOrExpr::Constant(1.0),
+                    thickness: ConstantOrExpr::Constant(0.1),
+                },
+                wall_thickness: parse(wall_thickness),
+                drains,
+            })
+            .compile()
+            .unwrap()
+        };
+        let drain = || Drain {
+            x: ConstantOrExpr::Constant(3.0),
+            y: ConstantOrExpr::Constant(0.0),
+            z: ConstantOrExpr::Constant(0.0),
+            radius: ConstantOrExpr::Constant(0.8),
+        };
+
+        let tape = infill("0.4", vec![drain()]);
+        assert_eq!(
+            ops(&tape),
+            [
+                Op::Sphere,
+                Op::Sphere,
+                Op::Negation,
+                Op::Constant,
+                Op::Difference,
+                Op::Sphere,
+                Op::Subtraction,
+                Op::Gyroid,
+                Op::Union,
+                Op::Intersection,
+                Op::Ret
+            ]
+        );
+        assert_eq!(tape.matrices.len(), 2);
+
+        let eval = |tape: &Tape, p: Vec3| {
+            let mut d = [0.0];
+            tape.eval_points(&[p], &mut d);
+            d[0]
+        };
+        let lattice = filled_sphere(Fill::Gyroid {
+            scale: ConstantOrExpr::Constant(1.0),
+            thickness: ConstantOrExpr::Constant(0.1),
+        })
+        .compile()
+        .unwrap();
+
+        // The skin is solid all round, except where the drain goes through it.
+        for &p in &[
+            Vec3::new(0.0, 2.8, 0.0),
+            Vec3::new(0.0, 0.0, -2.7),
+            Vec3::new(-2.8, 0.3, 0.0),
+        ] {
+            assert!(eval(&tape, p) < 0.0, "at {:?}", p);
+        }
+        assert!(eval(&tape, Vec3::new(0.0, 3.2, 0.0)) > 0.0);
+        let p = Vec3::new(2.8, 0.0, 0.0);
+        assert!((eval(&tape, p) - eval(&lattice, p)).abs() < 1e-5);
+
+        // Inside the skin, solid and empty space are those of the lattice.
+        for i in 0..64 {
+            let c = |i: usize| (i % 4) as f32 * 0.9 - 1.35;
+            let p = Vec3::new(c(i), c(i / 4), c(i / 16));
+            let expected = eval(&lattice, p);
+            if expected.abs() > 1e-3 {
+                assert_eq!(eval(&tape, p) < 0.0, expected < 0.0, "at {:?}", p);
+            }
+        }
+
+        // A graded wall is computed on the tape, and is only thick at the top. The
+        // skin is divided by how fast it changes.
+        let tape = infill("0.3 + z/10", vec![]);
+        assert_eq!(
+            ops(&tape)[..9],
+            [
+                Op::Sphere,
+                Op::Sphere,
+                Op::Negation,
+                Op::Coordinate,
+                Op::Constant,
+                Op::Quotient,
+                Op::Constant,
+                Op::Sum,
+                Op::Difference
+            ]
+        );
+        assert_eq!(tape.insts[9].extract::<Constant>().value, 1.1);
+        assert_eq!(tape.insts[10].op(), Op::Quotient);
+        assert!(eval(&tape, Vec3::new(0.0, 0.0, 2.5)) < 0.0);
+        let p = Vec3::new(0.0, 0.0, -2.5);
+        assert!((eval(&tape, p) - eval(&lattice, p)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn compile_parameters() {
+        let mut tree = CsgTree::new(CsgNode::Translate {
+            x: ConstantOrExpr::parse("max(r, 1) * 2").unwrap(),
+            y: ConstantOrExpr::Constant(0.0),
+            z: ConstantOrExpr::Constant(0.0),
+            node: Rc::new(CsgNode::Shape(
+                Shape::Sphere {
+                    radius: ConstantOrExpr::parse("r + 0.5").unwrap(),
+                },
+                None,
+            )),
+        });
+        tree.set_parameter("r", 1.5);
+        let tape = tree.compile().unwrap();
+
+        assert_eq!(tape.insts[0].extract::<Sphere>().radius, 2.0);
+        assert_eq!(
+            tape.matrices[0].transform_point3(Vec3::new(3.0, 0.0, 0.0)),
+            Vec3::zero()
+        );
+    }
+
+    #[test]
+    fn zero_normal() {
+        let tree = CsgTree::new(CsgNode::Shape(
+            Shape::Plane {
+                normal_x: ConstantOrExpr::Constant(0.0),
+                normal_y: ConstantOrExpr::Constant(0.0),
+                normal_z: ConstantOrExpr::Constant(0.0),
+                offset: ConstantOrExpr::Constant(1.0),
+            },
+            None,
+        ));
+        assert!(matches!(tree.compile(), Err(CompileError::ZeroNormal)));
+    }
+
+    #[test]
+    fn compile_units() {
+        let parse = |source| ConstantOrExpr::parse(source).unwrap();
+        let tree = CsgTree::new(CsgNode::Rotate {
+            roll: parse("90 deg"),
+            pitch: parse("0rad"),
+            yaw: parse("0"),
+            node: Rc::new(CsgNode::Translate {
+                x: parse("1 in"),
+                y: parse("(r + 1) cm"),
+                z: parse("0 m"),
+                node: Rc::new(CsgNode::Shape(
+                    Shape::Box {
+                        side_x: parse("10 mm"),
+                        side_y: parse("1 cm"),
+                        side_z: parse("0.5 in"),
+                    },
+                    None,
+                )),
+            }),
+        });
+        assert!(tree
+            .to_string()
+            .contains("box, sides = ⟨10 mm, 1 cm, 0.5 in⟩"));
+        assert!(tree.to_string().contains("(r + 1) cm"));
+
+        let mut tree = tree;
+        tree.set_parameter("r", 1.0);
+        let tape = tree.compile().unwrap();
+        assert_eq!(tape.insts[0].extract::<RectangularPrism>().y, 10.0);
+        let p = tape.matrices[0].inversed().transform_point3(Vec3::zero());
+        let expected = Mat4::from_euler_angles(PI / 2.0, 0.0, 0.0)
+            .transform_point3(Vec3::new(25.4, 20.0, 0.0));
+        assert!((p - expected).mag() < 1e-4);
+
+        let wrong = CsgTree::new(CsgNode::Shape(
+            Shape::Sphere {
+                radius: parse("90°"),
+            },
+            None,
+        ));
+        assert_eq!(
+            wrong.compile().err(),
+            Some(CompileError::WrongUnit {
+                unit: Unit::Degree,
+                expected: Dimension::Length
+            })
+        );
+    }
+
+    /// Evaluates a tape of spheres and unions at `p`.
+    fn eval_spheres(tape: &Tape, p: Vec3) -> f32 {
+        let mut regs = [0.0f32; REGISTER_COUNT];
+        for &inst in &tape.insts {
+            match inst.op() {
+                Op::Sphere => {
+                    let sphere = inst.extract::<Sphere>();
+                    let p = tape.matrices[sphere.matrix_idx].transform_point3(p);
+                    regs[inst.reg()] = p.mag() - sphere.radius;
+                }
+                Op::Union => {
+                    let u = inst.extract::<Union>();
+                    regs[inst.reg()] = regs[u.lhs].min(regs[u.rhs]);
+                }
+                Op::Ret => return regs[inst.reg()],
+                _ => unreachable!(),
+            }
+        }
+        unreachable!()
+    }
+
+    /// A balanced tree of `2^depth` unit spheres spaced along the x axis.
+    fn balanced(depth: u32, offset: f32) -> CsgNode {
+        if depth == 0 {
+            return CsgNode::Translate {
+                x: ConstantOrExpr::Constant(offset),
+                y: ConstantOrExpr::Constant(0.0),
+                z: ConstantOrExpr::Constant(0.0),
+                node: sphere(1.0),
+            };
+        }
+        CsgNode::Union {
+            lhs: Rc::new(balanced(depth - 1, offset)),
+            rhs: Rc::new(balanced(
+                depth - 1,
+                offset + 3.0 * (1 << (depth - 1)) as f32,
+            )),
+        }
+    }
+
+    #[test]
+    fn compile_deep_tree() {
+        let tree = CsgTree::new(balanced(6, 0.0));
+        let tape = tree.compile().unwrap();
+
+        assert_eq!(tape.insts.len(), 64 + 63 + 1);
+        assert_eq!(tape.insts.iter().map(|inst| inst.reg()).max(), Some(6));
+
+        for i in 0..64 {
+            let center = Vec3::new(3.0 * i as f32, 0.0, 0.0);
+            assert!((eval_spheres(&tape, center) + 1.0).abs() < 1e-4);
+            assert!((eval_spheres(&tape, center + Vec3::new(0.0, 2.0, 0.0)) - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn compile_errors() {
+        assert_eq!(
+            CsgTree::empty().compile().err(),
+            Some(CompileError::EmptyTree)
+        );
+
+        let expr = CsgTree::new(CsgNode::Shape(
+            Shape::Sphere {
+                radius: ConstantOrExpr::parse("2 * r").unwrap(),
+            },
+            None,
+        ));
+        assert_eq!(
+            expr.compile().err(),
+            Some(CompileError::Expr(EvalError::UnknownParameter("r".into())))
+        );
+
+        let parse = |source| ConstantOrExpr::parse(source).unwrap();
+        let graded = |scale, thickness| {
+            filled_sphere(Fill::Gyroid {
+                scale: parse(scale),
+                thickness: parse(thickness),
+            })
+            .compile()
+            .err()
+        };
+        assert_eq!(graded("x^0.5", "0.1"), Some(CompileError::GradedPower));
+        assert_eq!(graded("1", "pow(2, x)"), Some(CompileError::GradedPower));
+        assert_eq!(
+            graded("1", "x deg"),
+            Some(CompileError::WrongUnit {
+                unit: Unit::Degree,
+                expected: Dimension::Length
+            })
+        );
+        // The scale has to stay positive, and neither can change without bound.
+        assert_eq!(graded("x", "0.1"), Some(CompileError::GradedBound));
+        assert_eq!(graded("1", "sqrt(x)"), Some(CompileError::GradedBound));
+        let plane = CsgTree::new(CsgNode::Shape(
+            Shape::Plane {
+                normal_x: parse("0"),
+                normal_y: parse("1"),
+                normal_z: parse("0"),
+                offset: parse("0"),
+            },
+            Some(Fill::Gyroid {
+                scale: parse("2 + sin(x)"),
+                thickness: parse("0.1"),
+            }),
+        ));
+        assert_eq!(plane.compile().err(), Some(CompileError::GradedBound));
+        let struts = filled_sphere(Fill::Bcc {
+            cell_size: parse("1 + x/10"),
+            strut_radius: parse("0.1"),
+        });
+        assert_eq!(struts.compile().err(), Some(CompileError::GradedCellSize));
+        // Only fills can vary over space.
+        let point = CsgTree::new(CsgNode::Shape(
+            Shape::Sphere {
+                radius: parse("1 + x"),
+            },
+            None,
+        ));
+        assert_eq!(
+            point.compile().err(),
+            Some(CompileError::Expr(EvalError::UnknownParameter("x".into())))
+        );
+
+        let deep = CsgTree::new(balanced(REGISTER_COUNT as u32, 0.0));
+        assert_eq!(
+            deep.compile().err(),
+            Some(CompileError::OutOfRegisters {
+                needed: REGISTER_COUNT + 1
+            })
+        );
+    }
+}
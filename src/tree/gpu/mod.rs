@@ -1 +1,4 @@
+mod compile;
 mod inst;
+
+pub use self::compile::{CompileError, Tape};
@@ -1,7 +1,17 @@
-use std::{fmt, rc::Rc};
+use std::{collections::HashMap, fmt, rc::Rc};
 
 mod cpu;
+mod expr;
 mod gpu;
+mod scene;
+mod units;
+
+pub use self::{
+    expr::{EvalError, Expr, ParseError},
+    gpu::{CompileError, Tape},
+    scene::SceneError,
+    units::{Dimension, Unit},
+};
 
 #[derive(Debug)]
 pub enum Shape {
@@ -13,7 +23,38 @@ pub enum Shape {
         side_y: ConstantOrExpr,
         side_z: ConstantOrExpr,
     },
-    // ...
+    /// Around the y axis, like the rest of the round shapes.
+    Cylinder {
+        radius: ConstantOrExpr,
+        half_height: ConstantOrExpr,
+    },
+    Torus {
+        major_radius: ConstantOrExpr,
+        minor_radius: ConstantOrExpr,
+    },
+    Capsule {
+        radius: ConstantOrExpr,
+        half_height: ConstantOrExpr,
+    },
+    /// A top radius of zero makes a pointed cone.
+    Cone {
+        bottom_radius: ConstantOrExpr,
+        top_radius: ConstantOrExpr,
+        half_height: ConstantOrExpr,
+    },
+    /// The half-space behind a plane, which is `offset` along the normal from the origin.
+    /// The normal doesn't need to be normalized.
+    Plane {
+        normal_x: ConstantOrExpr,
+        normal_y: ConstantOrExpr,
+        normal_z: ConstantOrExpr,
+        offset: ConstantOrExpr,
+    },
+    Ellipsoid {
+        radius_x: ConstantOrExpr,
+        radius_y: ConstantOrExpr,
+        radius_z: ConstantOrExpr,
+    },
 }
 
 impl fmt::Display for Shape {
@@ -25,10 +66,58 @@ impl fmt::Display for Shape {
                 side_y,
                 side_z,
             } => write!(f, "box, sides = ⟨{}, {}, {}⟩", side_x, side_y, side_z),
+            Shape::Cylinder {
+                radius,
+                half_height,
+            } => write!(f, "cylinder, r = {}, half height = {}", radius, half_height),
+            Shape::Torus {
+                major_radius,
+                minor_radius,
+            } => write!(f, "torus, R = {}, r = {}", major_radius, minor_radius),
+            Shape::Capsule {
+                radius,
+                half_height,
+            } => write!(f, "capsule, r = {}, half height = {}", radius, half_height),
+            Shape::Cone {
+                bottom_radius,
+                top_radius,
+                half_height,
+            } => write!(
+                f,
+                "cone, r = ⟨{}, {}⟩, half height = {}",
+                bottom_radius, top_radius, half_height
+            ),
+            Shape::Plane {
+                normal_x,
+                normal_y,
+                normal_z,
+                offset,
+            } => write!(
+                f,
+                "plane, normal = ⟨{}, {}, {}⟩, offset = {}",
+                normal_x, normal_y, normal_z, offset
+            ),
+            Shape::Ellipsoid {
+                radius_x,
+                radius_y,
+                radius_z,
+            } => write!(
+                f,
+                "ellipsoid, radii = ⟨{}, {}, {}⟩",
+                radius_x, radius_y, radius_z
+            ),
         }
     }
 }
 
+/// A lattice that a shape is filled with, either as a sheet along a triply
+/// periodic minimal surface, whose cells are `2π * scale` wide, as struts
+/// between the nodes of cubic cells that are `cell_size` wide, or as a foam.
+///
+/// The scale and thickness of a sheet, and the radius of struts, can vary over space
+/// with expressions of the point's coordinates `x`, `y` and `z`, in the shape's frame.
+/// Scales that change by more than a small part of a cell per cell can make the
+/// distance overestimate, since the lattice is stretched along with them.
 #[derive(Debug)]
 pub enum Fill {
     Gyroid {
@@ -39,36 +128,192 @@ pub enum Fill {
         scale: ConstantOrExpr,
         thickness: ConstantOrExpr,
     },
-    // ...
+    /// Schwarz's diamond surface.
+    Diamond {
+        scale: ConstantOrExpr,
+        thickness: ConstantOrExpr,
+    },
+    Neovius {
+        scale: ConstantOrExpr,
+        thickness: ConstantOrExpr,
+    },
+    /// Schoen's I-WP surface.
+    Iwp {
+        scale: ConstantOrExpr,
+        thickness: ConstantOrExpr,
+    },
+    Lidinoid {
+        scale: ConstantOrExpr,
+        thickness: ConstantOrExpr,
+    },
+    /// Schoen's F-RD surface.
+    Frd {
+        scale: ConstantOrExpr,
+        thickness: ConstantOrExpr,
+    },
+    /// Struts from the centre of each cell to its corners.
+    Bcc {
+        cell_size: ConstantOrExpr,
+        strut_radius: ConstantOrExpr,
+    },
+    /// Struts along the diagonals of the faces of each cell.
+    Fcc {
+        cell_size: ConstantOrExpr,
+        strut_radius: ConstantOrExpr,
+    },
+    /// The face diagonals, and the octahedron between the centres of the faces.
+    Octet {
+        cell_size: ConstantOrExpr,
+        strut_radius: ConstantOrExpr,
+    },
+    /// The edges of a truncated octahedron in each cell.
+    Kelvin {
+        cell_size: ConstantOrExpr,
+        strut_radius: ConstantOrExpr,
+    },
+    /// Walls between the cells around random points, one in each cube of a grid
+    /// with sides of `cell_size`, like a closed-cell foam. The walls reach
+    /// `thickness` to either side, and `seed` picks the points, so the same seed
+    /// always gives the same foam.
+    Voronoi {
+        cell_size: ConstantOrExpr,
+        thickness: ConstantOrExpr,
+        seed: u32,
+    },
+}
+
+impl Fill {
+    /// Returns the size of the cells, and the thickness of the sheet or the radius
+    /// of the struts, which every fill has.
+    pub fn parameters(&self) -> (&ConstantOrExpr, &ConstantOrExpr) {
+        match self {
+            Fill::Gyroid { scale, thickness }
+            | Fill::SchwarzP { scale, thickness }
+            | Fill::Diamond { scale, thickness }
+            | Fill::Neovius { scale, thickness }
+            | Fill::Iwp { scale, thickness }
+            | Fill::Lidinoid { scale, thickness }
+            | Fill::Frd { scale, thickness } => (scale, thickness),
+            Fill::Bcc {
+                cell_size,
+                strut_radius,
+            }
+            | Fill::Fcc {
+                cell_size,
+                strut_radius,
+            }
+            | Fill::Octet {
+                cell_size,
+                strut_radius,
+            }
+            | Fill::Kelvin {
+                cell_size,
+                strut_radius,
+            } => (cell_size, strut_radius),
+            Fill::Voronoi {
+                cell_size,
+                thickness,
+                ..
+            } => (cell_size, thickness),
+        }
+    }
 }
 
 impl fmt::Display for Fill {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Fill::Gyroid { .. } => "gyroid",
+            Fill::SchwarzP { .. } => "schwarz primitive",
+            Fill::Diamond { .. } => "diamond",
+            Fill::Neovius { .. } => "neovius",
+            Fill::Iwp { .. } => "schoen i-wp",
+            Fill::Lidinoid { .. } => "lidinoid",
+            Fill::Frd { .. } => "schoen f-rd",
+            Fill::Bcc { .. } => "bcc",
+            Fill::Fcc { .. } => "fcc",
+            Fill::Octet { .. } => "octet",
+            Fill::Kelvin { .. } => "kelvin",
+            Fill::Voronoi { .. } => "voronoi",
+        };
+        let (size, thickness) = self.parameters();
         match self {
-            Fill::Gyroid { scale, thickness } => {
-                write!(f, "gyroid(s = {}, t = {})", scale, thickness)
-            }
-            Fill::SchwarzP { scale, thickness } => {
-                write!(f, "schwarz primitive(s = {}, t = {})", scale, thickness)
+            Fill::Bcc { .. } | Fill::Fcc { .. } | Fill::Octet { .. } | Fill::Kelvin { .. } => {
+                write!(f, "{}(cell = {}, r = {})", name, size, thickness)
             }
+            Fill::Voronoi { seed, .. } => write!(
+                f,
+                "{}(cell = {}, t = {}, seed = {})",
+                name, size, thickness, seed
+            ),
+            _ => write!(f, "{}(s = {}, t = {})", name, size, thickness),
         }
     }
 }
 
-/// TODO: Needs Units
+/// Values without a unit are in millimetres or radians.
 #[derive(Debug)]
 pub enum ConstantOrExpr {
     Constant(f32),
-    /// TODO: Write a math expression parser that can convert to shader code.
-    ///     - https://en.wikipedia.org/wiki/Shunting-yard_algorithm
-    Expr(),
+    /// An expression of the tree's parameters, which is evaluated when the tree is compiled,
+    /// or at every point for the values of fills that use its coordinates.
+    Expr(Expr),
+    WithUnit(Box<ConstantOrExpr>, Unit),
 }
 
 impl ConstantOrExpr {
-    pub fn get(&self) -> f32 {
-        match *self {
-            ConstantOrExpr::Constant(x) => x,
-            ConstantOrExpr::Expr() => panic!(),
+    /// Parses a number as a constant, and anything else as an expression.
+    /// Either can be followed by a unit, like `10 mm`, `90deg` or `(2*r) in`.
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let source = source.trim_end();
+        let value = source.trim_end_matches(|c: char| c.is_alphabetic() || c == '°');
+        let unit = source[value.len()..].parse::<Unit>();
+        let after_value = value.chars().last().map_or(false, |c| {
+            c.is_ascii_digit() || c == '.' || c == ')' || c.is_whitespace()
+        });
+
+        // A parameter that is spelled like a unit can follow an operator, as in `r + m`.
+        match unit {
+            Ok(unit) if after_value => match Self::parse_without_unit(value) {
+                Ok(value) => Ok(value.with_unit(unit)),
+                Err(_) => Self::parse_without_unit(source),
+            },
+            _ => Self::parse_without_unit(source),
+        }
+    }
+
+    fn parse_without_unit(source: &str) -> Result<Self, ParseError> {
+        match source.trim().parse() {
+            Ok(x) => Ok(ConstantOrExpr::Constant(x)),
+            Err(_) => Expr::parse(source).map(ConstantOrExpr::Expr),
+        }
+    }
+
+    pub fn with_unit(self, unit: Unit) -> Self {
+        ConstantOrExpr::WithUnit(Box::new(self), unit)
+    }
+
+    pub fn unit(&self) -> Option<Unit> {
+        match self {
+            ConstantOrExpr::WithUnit(_, unit) => Some(*unit),
+            _ => None,
+        }
+    }
+
+    /// Whether the value varies over space, which only the values of fills can.
+    pub fn uses_point(&self) -> bool {
+        match self {
+            ConstantOrExpr::Constant(_) => false,
+            ConstantOrExpr::Expr(expr) => expr.uses_point(),
+            ConstantOrExpr::WithUnit(value, _) => value.uses_point(),
+        }
+    }
+
+    /// Evaluates the value, in millimetres or radians.
+    pub fn get(&self, parameters: &HashMap<String, f32>) -> Result<f32, EvalError> {
+        match self {
+            ConstantOrExpr::Constant(x) => Ok(*x),
+            ConstantOrExpr::Expr(expr) => expr.eval(parameters),
+            ConstantOrExpr::WithUnit(value, unit) => Ok(unit.normalize(value.get(parameters)?)),
         }
     }
 }
@@ -77,14 +322,53 @@ impl fmt::Display for ConstantOrExpr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ConstantOrExpr::Constant(x) => write!(f, "{}", x),
-            ConstantOrExpr::Expr() => write!(f, "no expr specified"),
+            ConstantOrExpr::Expr(expr) => write!(f, "{}", expr),
+            ConstantOrExpr::WithUnit(value, unit) => match **value {
+                ConstantOrExpr::Constant(_)
+                | ConstantOrExpr::Expr(Expr::Number(_))
+                | ConstantOrExpr::Expr(Expr::Param(_)) => write!(f, "{} {}", value, unit),
+                _ => write!(f, "({}) {}", value, unit),
+            },
         }
     }
 }
 
+/// A hole through the skin of a [`CsgNode::Infill`], for resin or powder to drain
+/// out of the lattice inside.
+#[derive(Debug)]
+pub struct Drain {
+    /// The centre of the hole in the shape's frame, usually on its surface.
+    pub x: ConstantOrExpr,
+    pub y: ConstantOrExpr,
+    pub z: ConstantOrExpr,
+    /// The hole is a ball of this radius, so it goes through skins that are thinner.
+    pub radius: ConstantOrExpr,
+}
+
+impl fmt::Display for Drain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "drain at ⟨{}, {}, {}⟩, r = {}",
+            self.x, self.y, self.z, self.radius
+        )
+    }
+}
+
 #[derive(Debug)]
 pub enum CsgNode {
+    /// A shape, or with a fill, the lattice of the fill wherever it's inside the
+    /// shape, with nothing over it. [`CsgNode::Infill`] covers it with a skin.
     Shape(Shape, Option<Fill>),
+    /// A part for printing: a solid skin `wall_thickness` thick on the inside of the
+    /// shape's surface, with the lattice of `fill` everywhere inside the skin, and
+    /// `drains` through it.
+    Infill {
+        shape: Shape,
+        fill: Fill,
+        wall_thickness: ConstantOrExpr,
+        drains: Vec<Drain>,
+    },
     Union {
         lhs: Rc<CsgNode>,
         rhs: Rc<CsgNode>,
@@ -129,9 +413,29 @@ pub enum CsgNode {
 /// A Constructive Solid Geometry Tree.
 pub struct CsgTree {
     root: Option<CsgNode>,
+    /// The values of the names in expressions.
+    parameters: HashMap<String, f32>,
 }
 
 impl CsgTree {
+    pub fn new(root: CsgNode) -> Self {
+        Self {
+            root: Some(root),
+            parameters: HashMap::new(),
+        }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            root: None,
+            parameters: HashMap::new(),
+        }
+    }
+
+    pub fn set_parameter(&mut self, name: impl Into<String>, value: f32) {
+        self.parameters.insert(name.into(), value);
+    }
+
     pub fn new_example() -> Self {
         Self {
             root: Some(CsgNode::Union {
@@ -164,6 +468,7 @@ impl CsgTree {
                     None,
                 )),
             }),
+            parameters: HashMap::new(),
         }
     }
 }
@@ -202,6 +507,18 @@ impl fmt::Display for CsgTree {
                         writeln!(f, "{}", shape)?
                     }
                 }
+                CsgNode::Infill {
+                    shape,
+                    fill,
+                    wall_thickness,
+                    drains,
+                } => {
+                    write!(f, "{}, wall = {}, infill = {}", shape, wall_thickness, fill)?;
+                    for drain in drains {
+                        write!(f, ", {}", drain)?;
+                    }
+                    writeln!(f)?
+                }
                 CsgNode::Union { lhs, rhs } => {
                     writeln!(f, "union")?;
                     recurse(f, &lhs, indent.clone(), false, false)?;
@@ -0,0 +1,1089 @@
+//! A text format for [`CsgTree`]s, written in [RON](https://github.com/ron-rs/ron).
+//!
+//! Values are numbers, or strings that are parsed like [`ConstantOrExpr::parse`],
+//! so they can have units and use the scene's parameters. Nodes in `nodes` can be
+//! used any number of times with `Ref`, and are shared rather than copied.
+//!
+//! ```text
+//! Scene(
+//!     parameters: {"r": 7},
+//!     nodes: {
+//!         "ball": Sphere(radius: "r mm"),
+//!     },
+//!     root: Some(Union(
+//!         lhs: Ref("ball"),
+//!         rhs: Translate(x: "2 cm", y: 0, z: 0, node: Ref("ball")),
+//!     )),
+//! )
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt, fs, io,
+    path::Path,
+    rc::Rc,
+};
+
+use ron::error::Position;
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::tree::{ConstantOrExpr, CsgNode, CsgTree, Drain, Fill, ParseError, Shape};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    /// The file isn't valid RON, or doesn't have the shape of a scene.
+    Syntax(ron::Error),
+    /// A value couldn't be parsed. `path` is where it is in the scene, like `root.lhs.radius`.
+    Expr {
+        path: String,
+        source: String,
+        error: ParseError,
+    },
+    /// A `Ref` names a node that isn't in `nodes`.
+    UnknownNode {
+        path: String,
+        name: String,
+    },
+    /// A node in `nodes` contains itself.
+    Cycle {
+        name: String,
+    },
+    /// Only shapes can be filled.
+    FillNeedsShape {
+        path: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "{}", e),
+            SceneError::Syntax(e) => write!(f, "{}", e),
+            SceneError::Expr {
+                path,
+                source,
+                error,
+            } => write!(f, "{}: {} in `{}`", path, error, source),
+            SceneError::UnknownNode { path, name } => {
+                write!(f, "{}: there is no node named `{}`", path, name)
+            }
+            SceneError::Cycle { name } => write!(f, "the node `{}` contains itself", name),
+            SceneError::FillNeedsShape { path } => {
+                write!(f, "{}: only a shape can be filled", path)
+            }
+        }
+    }
+}
+
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneError::Io(e) => Some(e),
+            SceneError::Syntax(e) => Some(e),
+            SceneError::Expr { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl CsgTree {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let source = fs::read_to_string(path).map_err(SceneError::Io)?;
+        Self::from_ron(&source)
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self, SceneError> {
+        let mut deserializer =
+            ron::de::Deserializer::from_str(source).map_err(SceneError::Syntax)?;
+        let scene = Scene::deserialize(&mut deserializer).and_then(|scene| {
+            deserializer.end()?;
+            Ok(scene)
+        });
+        let scene = scene.map_err(|mut e| {
+            // Errors from serde, like a value of the wrong type, don't say where they are.
+            if e.position.line == 0 {
+                let read = &source[..source.len() - deserializer.remainder().len()];
+                e.position = Position {
+                    line: read.matches('\n').count() + 1,
+                    col: read.len() - read.rfind('\n').map_or(0, |i| i + 1) + 1,
+                };
+            }
+            SceneError::Syntax(e)
+        })?;
+
+        let mut loader = Loader {
+            nodes: &scene.nodes,
+            loaded: HashMap::new(),
+        };
+        // Load every node, so that unused ones are checked too.
+        for name in scene.nodes.keys() {
+            loader.reference(name, "nodes")?;
+        }
+        let root = match &scene.root {
+            Some(root) => Some(loader.node(root, "root")?),
+            None => None,
+        };
+        drop(loader);
+
+        Ok(Self {
+            // Nothing else can hold the root, because that would be a cycle.
+            root: root.map(|root| Rc::try_unwrap(root).ok().unwrap()),
+            parameters: scene.parameters.into_iter().collect(),
+        })
+    }
+
+    /// Writes the tree as a scene. Subtrees that are used more than once are
+    /// written to `nodes`, so they are still shared when the scene is loaded.
+    pub fn to_ron(&self) -> String {
+        let mut saver = Saver {
+            uses: HashMap::new(),
+            names: HashMap::new(),
+            nodes: BTreeMap::new(),
+        };
+        if let Some(root) = &self.root {
+            saver.count(root);
+        }
+        let root = self.root.as_ref().map(|root| saver.node(root));
+
+        let scene = Scene {
+            parameters: self
+                .parameters
+                .iter()
+                .map(|(name, &value)| (name.clone(), value))
+                .collect(),
+            nodes: saver.nodes,
+            root,
+        };
+        ron::ser::to_string_pretty(&scene, ron::ser::PrettyConfig::new())
+            .expect("scenes can always be written")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Scene {
+    #[serde(default)]
+    parameters: BTreeMap<String, f32>,
+    #[serde(default)]
+    nodes: BTreeMap<String, SceneNode>,
+    root: Option<SceneNode>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum SceneNode {
+    Sphere {
+        radius: Value,
+    },
+    Box {
+        side_x: Value,
+        side_y: Value,
+        side_z: Value,
+    },
+    Cylinder {
+        radius: Value,
+        half_height: Value,
+    },
+    Torus {
+        major_radius: Value,
+        minor_radius: Value,
+    },
+    Capsule {
+        radius: Value,
+        half_height: Value,
+    },
+    Cone {
+        bottom_radius: Value,
+        top_radius: Value,
+        half_height: Value,
+    },
+    Plane {
+        normal_x: Value,
+        normal_y: Value,
+        normal_z: Value,
+        offset: Value,
+    },
+    Ellipsoid {
+        radius_x: Value,
+        radius_y: Value,
+        radius_z: Value,
+    },
+    Filled {
+        fill: SceneFill,
+        shape: Box<SceneNode>,
+    },
+    /// A shape with a solid skin around its fill.
+    Infill {
+        fill: SceneFill,
+        shape: Box<SceneNode>,
+        wall_thickness: Value,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        drains: Vec<SceneDrain>,
+    },
+    Union {
+        lhs: Box<SceneNode>,
+        rhs: Box<SceneNode>,
+    },
+    SmoothUnion {
+        lhs: Box<SceneNode>,
+        rhs: Box<SceneNode>,
+        k: Value,
+    },
+    Intersection {
+        lhs: Box<SceneNode>,
+        rhs: Box<SceneNode>,
+    },
+    SmoothIntersection {
+        lhs: Box<SceneNode>,
+        rhs: Box<SceneNode>,
+        k: Value,
+    },
+    Subtraction {
+        lhs: Box<SceneNode>,
+        rhs: Box<SceneNode>,
+    },
+    SmoothSubtraction {
+        lhs: Box<SceneNode>,
+        rhs: Box<SceneNode>,
+        k: Value,
+    },
+    Translate {
+        x: Value,
+        y: Value,
+        z: Value,
+        node: Box<SceneNode>,
+    },
+    Rotate {
+        roll: Value,
+        pitch: Value,
+        yaw: Value,
+        node: Box<SceneNode>,
+    },
+    /// A node from the scene's `nodes`.
+    Ref(String),
+}
+
+/// A hole through the skin of an `Infill` node.
+#[derive(Debug, Serialize, Deserialize)]
+struct SceneDrain {
+    x: Value,
+    y: Value,
+    z: Value,
+    radius: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum SceneFill {
+    Gyroid {
+        scale: Value,
+        thickness: Value,
+    },
+    SchwarzP {
+        scale: Value,
+        thickness: Value,
+    },
+    Diamond {
+        scale: Value,
+        thickness: Value,
+    },
+    Neovius {
+        scale: Value,
+        thickness: Value,
+    },
+    Iwp {
+        scale: Value,
+        thickness: Value,
+    },
+    Lidinoid {
+        scale: Value,
+        thickness: Value,
+    },
+    Frd {
+        scale: Value,
+        thickness: Value,
+    },
+    Bcc {
+        cell_size: Value,
+        strut_radius: Value,
+    },
+    Fcc {
+        cell_size: Value,
+        strut_radius: Value,
+    },
+    Octet {
+        cell_size: Value,
+        strut_radius: Value,
+    },
+    Kelvin {
+        cell_size: Value,
+        strut_radius: Value,
+    },
+    Voronoi {
+        cell_size: Value,
+        thickness: Value,
+        #[serde(default)]
+        seed: u32,
+    },
+}
+
+/// A number, or the source of a [`ConstantOrExpr`]. Strings are parsed after the
+/// whole file has been read, so that errors can say where in the scene they are.
+#[derive(Debug)]
+enum Value {
+    Number(f32),
+    Text(String),
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Number(x) => serializer.serialize_f32(*x),
+            Value::Text(source) => serializer.serialize_str(source),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ValueVisitor;
+
+        impl<'de> Visitor<'de> for ValueVisitor {
+            type Value = Value;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a number, or a string like \"10 mm\" or \"2 * r\"")
+            }
+
+            fn visit_f64<E: de::Error>(self, x: f64) -> Result<Value, E> {
+                Ok(Value::Number(x as f32))
+            }
+
+            fn visit_i64<E: de::Error>(self, x: i64) -> Result<Value, E> {
+                Ok(Value::Number(x as f32))
+            }
+
+            fn visit_u64<E: de::Error>(self, x: u64) -> Result<Value, E> {
+                Ok(Value::Number(x as f32))
+            }
+
+            fn visit_str<E: de::Error>(self, source: &str) -> Result<Value, E> {
+                Ok(Value::Text(source.to_string()))
+            }
+        }
+
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+impl From<&ConstantOrExpr> for Value {
+    fn from(c: &ConstantOrExpr) -> Self {
+        match c {
+            ConstantOrExpr::Constant(x) => Value::Number(*x),
+            c => Value::Text(c.to_string()),
+        }
+    }
+}
+
+struct Loader<'a> {
+    nodes: &'a BTreeMap<String, SceneNode>,
+    /// `None` while a node is being loaded, to find cycles.
+    loaded: HashMap<&'a str, Option<Rc<CsgNode>>>,
+}
+
+impl<'a> Loader<'a> {
+    fn reference(&mut self, name: &'a str, path: &str) -> Result<Rc<CsgNode>, SceneError> {
+        match self.loaded.get(name) {
+            Some(Some(node)) => return Ok(Rc::clone(node)),
+            Some(None) => return Err(SceneError::Cycle { name: name.into() }),
+            None => {}
+        }
+
+        let (name, scene_node) =
+            self.nodes
+                .get_key_value(name)
+                .ok_or_else(|| SceneError::UnknownNode {
+                    path: path.into(),
+                    name: name.into(),
+                })?;
+        self.loaded.insert(name, None);
+        let node = self.node(scene_node, &format!("nodes[{:?}]", name))?;
+        self.loaded.insert(name, Some(Rc::clone(&node)));
+        Ok(node)
+    }
+
+    fn node(&mut self, node: &'a SceneNode, path: &str) -> Result<Rc<CsgNode>, SceneError> {
+        let child = |name: &str| format!("{}.{}", path, name);
+
+        Ok(Rc::new(match node {
+            SceneNode::Sphere { .. }
+            | SceneNode::Box { .. }
+            | SceneNode::Cylinder { .. }
+            | SceneNode::Torus { .. }
+            | SceneNode::Capsule { .. }
+            | SceneNode::Cone { .. }
+            | SceneNode::Plane { .. }
+            | SceneNode::Ellipsoid { .. } => CsgNode::Shape(shape(node, path)?, None),
+            SceneNode::Filled {
+                fill: scene_fill,
+                shape: node,
+            } => CsgNode::Shape(shape(node, &child("shape"))?, Some(fill(scene_fill, path)?)),
+            SceneNode::Infill {
+                fill: scene_fill,
+                shape: node,
+                wall_thickness,
+                drains,
+            } => CsgNode::Infill {
+                shape: shape(node, &child("shape"))?,
+                fill: fill(scene_fill, path)?,
+                wall_thickness: value(wall_thickness, &child("wall_thickness"))?,
+                drains: drains
+                    .iter()
+                    .enumerate()
+                    .map(|(i, drain)| {
+                        let child = |name: &str| format!("{}.drains[{}].{}", path, i, name);
+                        Ok(Drain {
+                            x: value(&drain.x, &child("x"))?,
+                            y: value(&drain.y, &child("y"))?,
+                            z: value(&drain.z, &child("z"))?,
+                            radius: value(&drain.radius, &child("radius"))?,
+                        })
+                    })
+                    .collect::<Result<_, SceneError>>()?,
+            },
+            SceneNode::Union { lhs, rhs } => CsgNode::Union {
+                lhs: self.node(lhs, &child("lhs"))?,
+                rhs: self.node(rhs, &child("rhs"))?,
+            },
+            SceneNode::SmoothUnion { lhs, rhs, k } => CsgNode::SmoothUnion {
+                lhs: self.node(lhs, &child("lhs"))?,
+                rhs: self.node(rhs, &child("rhs"))?,
+                k: value(k, &child("k"))?,
+            },
+            SceneNode::Intersection { lhs, rhs } => CsgNode::Intersection {
+                lhs: self.node(lhs, &child("lhs"))?,
+                rhs: self.node(rhs, &child("rhs"))?,
+            },
+            SceneNode::SmoothIntersection { lhs, rhs, k } => CsgNode::SmoothIntersection {
+                lhs: self.node(lhs, &child("lhs"))?,
+                rhs: self.node(rhs, &child("rhs"))?,
+                k: value(k, &child("k"))?,
+            },
+            SceneNode::Subtraction { lhs, rhs } => CsgNode::Subtraction {
+                lhs: self.node(lhs, &child("lhs"))?,
+                rhs: self.node(rhs, &child("rhs"))?,
+            },
+            SceneNode::SmoothSubtraction { lhs, rhs, k } => CsgNode::SmoothSubtraction {
+                lhs: self.node(lhs, &child("lhs"))?,
+                rhs: self.node(rhs, &child("rhs"))?,
+                k: value(k, &child("k"))?,
+            },
+            SceneNode::Translate { x, y, z, node } => CsgNode::Translate {
+                x: value(x, &child("x"))?,
+                y: value(y, &child("y"))?,
+                z: value(z, &child("z"))?,
+                node: self.node(node, &child("node"))?,
+            },
+            SceneNode::Rotate {
+                roll,
+                pitch,
+                yaw,
+                node,
+            } => CsgNode::Rotate {
+                roll: value(roll, &child("roll"))?,
+                pitch: value(pitch, &child("pitch"))?,
+                yaw: value(yaw, &child("yaw"))?,
+                node: self.node(node, &child("node"))?,
+            },
+            SceneNode::Ref(name) => return self.reference(name, path),
+        }))
+    }
+}
+
+fn shape(node: &SceneNode, path: &str) -> Result<Shape, SceneError> {
+    let child = |name: &str| format!("{}.{}", path, name);
+
+    match node {
+        SceneNode::Sphere { radius } => Ok(Shape::Sphere {
+            radius: value(radius, &child("radius"))?,
+        }),
+        SceneNode::Box {
+            side_x,
+            side_y,
+            side_z,
+        } => Ok(Shape::Box {
+            side_x: value(side_x, &child("side_x"))?,
+            side_y: value(side_y, &child("side_y"))?,
+            side_z: value(side_z, &child("side_z"))?,
+        }),
+        SceneNode::Cylinder {
+            radius,
+            half_height,
+        } => Ok(Shape::Cylinder {
+            radius: value(radius, &child("radius"))?,
+            half_height: value(half_height, &child("half_height"))?,
+        }),
+        SceneNode::Torus {
+            major_radius,
+            minor_radius,
+        } => Ok(Shape::Torus {
+            major_radius: value(major_radius, &child("major_radius"))?,
+            minor_radius: value(minor_radius, &child("minor_radius"))?,
+        }),
+        SceneNode::Capsule {
+            radius,
+            half_height,
+        } => Ok(Shape::Capsule {
+            radius: value(radius, &child("radius"))?,
+            half_height: value(half_height, &child("half_height"))?,
+        }),
+        SceneNode::Cone {
+            bottom_radius,
+            top_radius,
+            half_height,
+        } => Ok(Shape::Cone {
+            bottom_radius: value(bottom_radius, &child("bottom_radius"))?,
+            top_radius: value(top_radius, &child("top_radius"))?,
+            half_height: value(half_height, &child("half_height"))?,
+        }),
+        SceneNode::Plane {
+            normal_x,
+            normal_y,
+            normal_z,
+            offset,
+        } => Ok(Shape::Plane {
+            normal_x: value(normal_x, &child("normal_x"))?,
+            normal_y: value(normal_y, &child("normal_y"))?,
+            normal_z: value(normal_z, &child("normal_z"))?,
+            offset: value(offset, &child("offset"))?,
+        }),
+        SceneNode::Ellipsoid {
+            radius_x,
+            radius_y,
+            radius_z,
+        } => Ok(Shape::Ellipsoid {
+            radius_x: value(radius_x, &child("radius_x"))?,
+            radius_y: value(radius_y, &child("radius_y"))?,
+            radius_z: value(radius_z, &child("radius_z"))?,
+        }),
+        _ => Err(SceneError::FillNeedsShape { path: path.into() }),
+    }
+}
+
+/// The fill of a `Filled` or `Infill` node at `path`.
+fn fill(scene_fill: &SceneFill, path: &str) -> Result<Fill, SceneError> {
+    let child = |name: &str| format!("{}.{}", path, name);
+
+    let (scale, thickness, [scale_name, thickness_name]) = match scene_fill {
+        SceneFill::Gyroid { scale, thickness }
+        | SceneFill::SchwarzP { scale, thickness }
+        | SceneFill::Diamond { scale, thickness }
+        | SceneFill::Neovius { scale, thickness }
+        | SceneFill::Iwp { scale, thickness }
+        | SceneFill::Lidinoid { scale, thickness }
+        | SceneFill::Frd { scale, thickness } => {
+            (scale, thickness, ["fill.scale", "fill.thickness"])
+        }
+        SceneFill::Bcc {
+            cell_size,
+            strut_radius,
+        }
+        | SceneFill::Fcc {
+            cell_size,
+            strut_radius,
+        }
+        | SceneFill::Octet {
+            cell_size,
+            strut_radius,
+        }
+        | SceneFill::Kelvin {
+            cell_size,
+            strut_radius,
+        } => (
+            cell_size,
+            strut_radius,
+            ["fill.cell_size", "fill.strut_radius"],
+        ),
+        SceneFill::Voronoi {
+            cell_size,
+            thickness,
+            ..
+        } => (cell_size, thickness, ["fill.cell_size", "fill.thickness"]),
+    };
+    let scale = value(scale, &child(scale_name))?;
+    let thickness = value(thickness, &child(thickness_name))?;
+    let fill = match scene_fill {
+        SceneFill::Gyroid { .. } => Fill::Gyroid { scale, thickness },
+        SceneFill::SchwarzP { .. } => Fill::SchwarzP { scale, thickness },
+        SceneFill::Diamond { .. } => Fill::Diamond { scale, thickness },
+        SceneFill::Neovius { .. } => Fill::Neovius { scale, thickness },
+        SceneFill::Iwp { .. } => Fill::Iwp { scale, thickness },
+        SceneFill::Lidinoid { .. } => Fill::Lidinoid { scale, thickness },
+        SceneFill::Frd { .. } => Fill::Frd { scale, thickness },
+        SceneFill::Bcc { .. } => Fill::Bcc {
+            cell_size: scale,
+            strut_radius: thickness,
+        },
+        SceneFill::Fcc { .. } => Fill::Fcc {
+            cell_size: scale,
+            strut_radius: thickness,
+        },
+        SceneFill::Octet { .. } => Fill::Octet {
+            cell_size: scale,
+            strut_radius: thickness,
+        },
+        SceneFill::Kelvin { .. } => Fill::Kelvin {
+            cell_size: scale,
+            strut_radius: thickness,
+        },
+        SceneFill::Voronoi { seed, .. } => Fill::Voronoi {
+            cell_size: scale,
+            thickness,
+            seed: *seed,
+        },
+    };
+    Ok(fill)
+}
+
+fn value(value: &Value, path: &str) -> Result<ConstantOrExpr, SceneError> {
+    match value {
+        Value::Number(x) => Ok(ConstantOrExpr::Constant(*x)),
+        Value::Text(source) => ConstantOrExpr::parse(source).map_err(|error| SceneError::Expr {
+            path: path.into(),
+            source: source.clone(),
+            error,
+        }),
+    }
+}
+
+struct Saver {
+    /// How many times each shared node is used in the tree.
+    uses: HashMap<*const CsgNode, usize>,
+    names: HashMap<*const CsgNode, String>,
+    nodes: BTreeMap<String, SceneNode>,
+}
+
+impl Saver {
+    fn count(&mut self, node: &CsgNode) {
+        for child in children(node) {
+            let uses = self.uses.entry(Rc::as_ptr(child)).or_insert(0);
+            *uses += 1;
+            if *uses == 1 {
+                self.count(child);
+            }
+        }
+    }
+
+    fn child(&mut self, node: &Rc<CsgNode>) -> Box<SceneNode> {
+        let ptr = Rc::as_ptr(node);
+        if self.uses[&ptr] == 1 {
+            return Box::new(self.node(node));
+        }
+
+        if !self.names.contains_key(&ptr) {
+            let name = format!("node{}", self.names.len());
+            self.names.insert(ptr, name.clone());
+            let scene_node = self.node(node);
+            self.nodes.insert(name, scene_node);
+        }
+        Box::new(SceneNode::Ref(self.names[&ptr].clone()))
+    }
+
+    fn node(&mut self, node: &CsgNode) -> SceneNode {
+        match node {
+            CsgNode::Shape(shape, fill) => match fill {
+                Some(fill) => SceneNode::Filled {
+                    fill: scene_fill(fill),
+                    shape: Box::new(scene_shape(shape)),
+                },
+                None => scene_shape(shape),
+            },
+            CsgNode::Infill {
+                shape,
+                fill,
+                wall_thickness,
+                drains,
+            } => SceneNode::Infill {
+                fill: scene_fill(fill),
+                shape: Box::new(scene_shape(shape)),
+                wall_thickness: wall_thickness.into(),
+                drains: drains
+                    .iter()
+                    .map(|drain| SceneDrain {
+                        x: (&drain.x).into(),
+                        y: (&drain.y).into(),
+                        z: (&drain.z).into(),
+                        radius: (&drain.radius).into(),
+                    })
+                    .collect(),
+            },
+            CsgNode::Union { lhs, rhs } => SceneNode::Union {
+                lhs: self.child(lhs),
+                rhs: self.child(rhs),
+            },
+            CsgNode::SmoothUnion { lhs, rhs, k } => SceneNode::SmoothUnion {
+                lhs: self.child(lhs),
+                rhs: self.child(rhs),
+                k: k.into(),
+            },
+            CsgNode::Intersection { lhs, rhs } => SceneNode::Intersection {
+                lhs: self.child(lhs),
+                rhs: self.child(rhs),
+            },
+            CsgNode::SmoothIntersection { lhs, rhs, k } => SceneNode::SmoothIntersection {
+                lhs: self.child(lhs),
+                rhs: self.child(rhs),
+                k: k.into(),
+            },
+            CsgNode::Subtraction { lhs, rhs } => SceneNode::Subtraction {
+                lhs: self.child(lhs),
+                rhs: self.child(rhs),
+            },
+            CsgNode::SmoothSubtraction { lhs, rhs, k } => SceneNode::SmoothSubtraction {
+                lhs: self.child(lhs),
+                rhs: self.child(rhs),
+                k: k.into(),
+            },
+            CsgNode::Translate { x, y, z, node } => SceneNode::Translate {
+                x: x.into(),
+                y: y.into(),
+                z: z.into(),
+                node: self.child(node),
+            },
+            CsgNode::Rotate {
+                roll,
+                pitch,
+                yaw,
+                node,
+            } => SceneNode::Rotate {
+                roll: roll.into(),
+                pitch: pitch.into(),
+                yaw: yaw.into(),
+                node: self.child(node),
+            },
+        }
+    }
+}
+
+fn scene_shape(shape: &Shape) -> SceneNode {
+    match shape {
+        Shape::Sphere { radius } => SceneNode::Sphere {
+            radius: radius.into(),
+        },
+        Shape::Box {
+            side_x,
+            side_y,
+            side_z,
+        } => SceneNode::Box {
+            side_x: side_x.into(),
+            side_y: side_y.into(),
+            side_z: side_z.into(),
+        },
+        Shape::Cylinder {
+            radius,
+            half_height,
+        } => SceneNode::Cylinder {
+            radius: radius.into(),
+            half_height: half_height.into(),
+        },
+        Shape::Torus {
+            major_radius,
+            minor_radius,
+        } => SceneNode::Torus {
+            major_radius: major_radius.into(),
+            minor_radius: minor_radius.into(),
+        },
+        Shape::Capsule {
+            radius,
+            half_height,
+        } => SceneNode::Capsule {
+            radius: radius.into(),
+            half_height: half_height.into(),
+        },
+        Shape::Cone {
+            bottom_radius,
+            top_radius,
+            half_height,
+        } => SceneNode::Cone {
+            bottom_radius: bottom_radius.into(),
+            top_radius: top_radius.into(),
+            half_height: half_height.into(),
+        },
+        Shape::Plane {
+            normal_x,
+            normal_y,
+            normal_z,
+            offset,
+        } => SceneNode::Plane {
+            normal_x: normal_x.into(),
+            normal_y: normal_y.into(),
+            normal_z: normal_z.into(),
+            offset: offset.into(),
+        },
+        Shape::Ellipsoid {
+            radius_x,
+            radius_y,
+            radius_z,
+        } => SceneNode::Ellipsoid {
+            radius_x: radius_x.into(),
+            radius_y: radius_y.into(),
+            radius_z: radius_z.into(),
+        },
+    }
+}
+
+fn scene_fill(fill: &Fill) -> SceneFill {
+    let (scale, thickness) = fill.parameters();
+    let (scale, thickness) = (scale.into(), thickness.into());
+    match fill {
+        Fill::Gyroid { .. } => SceneFill::Gyroid { scale, thickness },
+        Fill::SchwarzP { .. } => SceneFill::SchwarzP { scale, thickness },
+        Fill::Diamond { .. } => SceneFill::Diamond { scale, thickness },
+        Fill::Neovius { .. } => SceneFill::Neovius { scale, thickness },
+        Fill::Iwp { .. } => SceneFill::Iwp { scale, thickness },
+        Fill::Lidinoid { .. } => SceneFill::Lidinoid { scale, thickness },
+        Fill::Frd { .. } => SceneFill::Frd { scale, thickness },
+        Fill::Bcc { .. } => SceneFill::Bcc {
+            cell_size: scale,
+            strut_radius: thickness,
+        },
+        Fill::Fcc { .. } => SceneFill::Fcc {
+            cell_size: scale,
+            strut_radius: thickness,
+        },
+        Fill::Octet { .. } => SceneFill::Octet {
+            cell_size: scale,
+            strut_radius: thickness,
+        },
+        Fill::Kelvin { .. } => SceneFill::Kelvin {
+            cell_size: scale,
+            strut_radius: thickness,
+        },
+        Fill::Voronoi { seed, .. } => SceneFill::Voronoi {
+            cell_size: scale,
+            thickness,
+            seed: *seed,
+        },
+    }
+}
+
+fn children(node: &CsgNode) -> Vec<&Rc<CsgNode>> {
+    match node {
+        CsgNode::Shape(..) | CsgNode::Infill { .. } => vec![],
+        CsgNode::Union { lhs, rhs }
+        | CsgNode::SmoothUnion { lhs, rhs, .. }
+        | CsgNode::Intersection { lhs, rhs }
+        | CsgNode::SmoothIntersection { lhs, rhs, .. }
+        | CsgNode::Subtraction { lhs, rhs }
+        | CsgNode::SmoothSubtraction { lhs, rhs, .. } => vec![lhs, rhs],
+        CsgNode::Translate { node, .. } | CsgNode::Rotate { node, .. } => vec![node],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_scene() {
+        let scene = CsgTree::from_ron(include_str!("../../scenes/example.ron")).unwrap();
+        assert_eq!(scene.to_string(), CsgTree::new_example().to_string());
+    }
+
+    #[test]
+    fn shared_nodes() {
+        let source = r#"
+            Scene(
+                parameters: {"r": 7},
+                nodes: {
+                    "ball": Sphere(radius: "r mm"),
+                    "balls": Union(
+                        lhs: Ref("ball"),
+                        rhs: Translate(x: "2 cm", y: 0, z: 0, node: Ref("ball")),
+                    ),
+                },
+                root: Some(Filled(
+                    fill: Gyroid(scale: 2, thickness: "0.5 mm"),
+                    shape: Box(side_x: 1, side_y: "2*r", side_z: 3.5),
+                )),
+            )
+        "#;
+        let tree = CsgTree::from_ron(source).unwrap();
+        assert_eq!(tree.parameters["r"], 7.0);
+        assert_eq!(
+            tree.to_string(),
+            "box, sides = ⟨1, 2*r, 3.5⟩, fill = gyroid(s = 2, t = 0.5 mm)\n"
+        );
+
+        let source = r#"
+            Scene(
+                nodes: {"ball": Sphere(radius: 1)},
+                root: Some(SmoothUnion(
+                    lhs: Ref("ball"),
+                    rhs: Rotate(roll: "90 deg", pitch: 0, yaw: 0, node: Ref("ball")),
+                    k: 0.25,
+                )),
+            )
+        "#;
+        let tree = CsgTree::from_ron(source).unwrap();
+        let shared = |tree: &CsgTree| match &tree.root {
+            Some(CsgNode::SmoothUnion { lhs, rhs, .. }) => match &**rhs {
+                CsgNode::Rotate { node, .. } => Rc::ptr_eq(lhs, node),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        assert!(shared(&tree));
+
+        // Saving keeps the sharing, and loading what was saved gives the same tree.
+        let saved = tree.to_ron();
+        assert!(saved.contains("Ref(\"node0\")"));
+        let reloaded = CsgTree::from_ron(&saved).unwrap();
+        assert!(shared(&reloaded));
+        assert_eq!(reloaded.to_string(), tree.to_string());
+        assert_eq!(reloaded.to_ron(), saved);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| CsgTree::from_ron(source).err().unwrap().to_string();
+
+        assert_eq!(
+            error("Scene(root: Some(Sphere(radius: true)))"),
+            "1:37: invalid type: boolean `true`, \
+             expected a number, or a string like \"10 mm\" or \"2 * r\""
+        );
+        assert_eq!(
+            error(r#"Scene(root: Some(Translate(x: 0, y: "2 *", z: 0, node: Sphere(radius: 1))))"#),
+            "root.y: column 4: unexpected end of expression in `2 *`"
+        );
+        assert_eq!(
+            error(r#"Scene(root: Some(Union(lhs: Sphere(radius: 1), rhs: Ref("cube"))))"#),
+            "root.rhs: there is no node named `cube`"
+        );
+        assert_eq!(
+            error(
+                r#"Scene(nodes: {"a": Union(lhs: Ref("a"), rhs: Sphere(radius: 1))}, root: None)"#
+            ),
+            "the node `a` contains itself"
+        );
+        assert_eq!(
+            error(
+                r#"Scene(root: Some(Filled(fill: Gyroid(scale: 1, thickness: 1), shape: Ref("a"))))"#
+            ),
+            "root.shape: only a shape can be filled"
+        );
+        assert_eq!(
+            error(
+                r#"Scene(root: Some(Filled(fill: Bcc(cell_size: 1, strut_radius: "0.5 *"), shape: Sphere(radius: 1))))"#
+            ),
+            "root.fill.strut_radius: column 6: unexpected end of expression in `0.5 *`"
+        );
+    }
+
+    #[test]
+    fn strut_fills() {
+        let source = r#"
+            Scene(root: Some(Filled(
+                fill: Kelvin(cell_size: "5 mm", strut_radius: 0.4),
+                shape: Sphere(radius: 20),
+            )))
+        "#;
+        let tree = CsgTree::from_ron(source).unwrap();
+        assert_eq!(
+            tree.to_string(),
+            "sphere, r = 20, fill = kelvin(cell = 5 mm, r = 0.4)\n"
+        );
+        let reloaded = CsgTree::from_ron(&tree.to_ron()).unwrap();
+        assert_eq!(reloaded.to_string(), tree.to_string());
+    }
+
+    #[test]
+    fn graded_fills() {
+        let source = r#"
+            Scene(root: Some(Filled(
+                fill: Gyroid(scale: "0.2 + x/100", thickness: "0.05 * (1 + sin(y))"),
+                shape: Sphere(radius: 20),
+            )))
+        "#;
+        let tree = CsgTree::from_ron(source).unwrap();
+        assert_eq!(
+            tree.to_string(),
+            "sphere, r = 20, fill = gyroid(s = 0.2 + x/100, t = 0.05*(1 + sin(y)))\n"
+        );
+        let reloaded = CsgTree::from_ron(&tree.to_ron()).unwrap();
+        assert_eq!(reloaded.to_string(), tree.to_string());
+        assert!(tree.compile().is_ok());
+    }
+
+    #[test]
+    fn foam_fills() {
+        let source = r#"
+            Scene(root: Some(Filled(
+                fill: Voronoi(cell_size: "4 mm", thickness: 0.2, seed: 7),
+                shape: Sphere(radius: 20),
+            )))
+        "#;
+        let tree = CsgTree::from_ron(source).unwrap();
+        assert_eq!(
+            tree.to_string(),
+            "sphere, r = 20, fill = voronoi(cell = 4 mm, t = 0.2, seed = 7)\n"
+        );
+        let reloaded = CsgTree::from_ron(&tree.to_ron()).unwrap();
+        assert_eq!(reloaded.to_string(), tree.to_string());
+
+        // Foams without a seed use the first one.
+        let source = r#"Scene(root: Some(Filled(
+            fill: Voronoi(cell_size: 4, thickness: 0.2),
+            shape: Sphere(radius: 20),
+        )))"#;
+        let tree = CsgTree::from_ron(source).unwrap();
+        assert!(tree.to_string().ends_with("seed = 0)\n"));
+    }
+
+    #[test]
+    fn infill() {
+        let source = r#"
+            Scene(root: Some(Infill(
+                fill: Octet(cell_size: 5, strut_radius: 0.6),
+                shape: Box(side_x: 40, side_y: 30, side_z: 20),
+                wall_thickness: "1.2 mm",
+                drains: [(x: 0, y: 0, z: -10, radius: 2.5)],
+            )))
+        "#;
+        let tree = CsgTree::from_ron(source).unwrap();
+        assert_eq!(
+            tree.to_string(),
+            "box, sides = ⟨40, 30, 20⟩, wall = 1.2 mm, infill = octet(cell = 5, r = 0.6), \
+             drain at ⟨0, 0, -10⟩, r = 2.5\n"
+        );
+        let reloaded = CsgTree::from_ron(&tree.to_ron()).unwrap();
+        assert_eq!(reloaded.to_string(), tree.to_string());
+
+        // Parts without drains are sealed.
+        let source = r#"Scene(root: Some(Infill(
+            fill: Gyroid(scale: 3, thickness: 0.4),
+            shape: Sphere(radius: 20),
+            wall_thickness: 1,
+        )))"#;
+        let tree = CsgTree::from_ron(source).unwrap();
+        assert!(!tree.to_ron().contains("drains"));
+
+        let error = CsgTree::from_ron(
+            r#"Scene(root: Some(Infill(
+                fill: Gyroid(scale: 3, thickness: 0.4),
+                shape: Sphere(radius: 20),
+                wall_thickness: 1,
+                drains: [(x: 20, y: 0, z: 0, radius: "2 +")],
+            )))"#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "root.drains[0].radius: column 4: unexpected end of expression in `2 +`"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Units for the values in a CSG tree.
+//!
+//! Lengths are normalized to millimetres and angles to radians when the tree
+//! is compiled, so one unit of the model is one millimetre.
+
+use std::{f32::consts::PI, fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    Length,
+    Angle,
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dimension::Length => write!(f, "length"),
+            Dimension::Angle => write!(f, "angle"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Millimeter,
+    Centimeter,
+    Meter,
+    Inch,
+    Degree,
+    Radian,
+}
+
+impl Unit {
+    pub fn dimension(self) -> Dimension {
+        match self {
+            Unit::Millimeter | Unit::Centimeter | Unit::Meter | Unit::Inch => Dimension::Length,
+            Unit::Degree | Unit::Radian => Dimension::Angle,
+        }
+    }
+
+    /// Converts a value in this unit to millimetres or radians.
+    pub fn normalize(self, value: f32) -> f32 {
+        match self {
+            Unit::Millimeter | Unit::Radian => value,
+            Unit::Centimeter => value * 10.0,
+            Unit::Meter => value * 1000.0,
+            Unit::Inch => value * 25.4,
+            Unit::Degree => value * (PI / 180.0),
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::Millimeter => "mm",
+            Unit::Centimeter => "cm",
+            Unit::Meter => "m",
+            Unit::Inch => "in",
+            Unit::Degree => "deg",
+            Unit::Radian => "rad",
+        }
+    }
+}
+
+impl FromStr for Unit {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Ok(match s {
+            "mm" => Unit::Millimeter,
+            "cm" => Unit::Centimeter,
+            "m" => Unit::Meter,
+            "in" => Unit::Inch,
+            "deg" | "°" => Unit::Degree,
+            "rad" => Unit::Radian,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::ConstantOrExpr;
+
+    #[test]
+    fn normalize() {
+        assert_eq!(Unit::Millimeter.normalize(10.0), 10.0);
+        assert_eq!(Unit::Centimeter.normalize(1.5), 15.0);
+        assert_eq!(Unit::Meter.normalize(0.25), 250.0);
+        assert_eq!(Unit::Inch.normalize(2.0), 50.8);
+        assert_eq!(Unit::Degree.normalize(180.0), PI);
+        assert_eq!(Unit::Radian.normalize(1.0), 1.0);
+
+        for unit in &[
+            Unit::Millimeter,
+            Unit::Centimeter,
+            Unit::Meter,
+            Unit::Inch,
+            Unit::Degree,
+            Unit::Radian,
+        ] {
+            assert_eq!(unit.symbol().parse(), Ok(*unit));
+        }
+        assert_eq!("°".parse(), Ok(Unit::Degree));
+        assert_eq!("ft".parse::<Unit>(), Err(()));
+    }
+
+    #[test]
+    fn parse_units() {
+        let display = |source| ConstantOrExpr::parse(source).unwrap().to_string();
+        assert_eq!(display("10mm"), "10 mm");
+        assert_eq!(display("2 * r in"), "(2*r) in");
+        assert_eq!(display("r rad"), "r rad");
+        // Without a number, space or parenthesis before it, a unit is a parameter.
+        assert_eq!(display("2*m"), "2*m");
+        assert_eq!(display("m"), "m");
+        assert_eq!(ConstantOrExpr::parse("m").unwrap().unit(), None);
+        // Nor is it after an operator.
+        assert_eq!(display("r + m"), "r + m");
+        assert_eq!(display("2 * in"), "2*in");
+        assert_eq!(display("x - deg"), "x - deg");
+        assert_eq!(ConstantOrExpr::parse("2 * in").unwrap().unit(), None);
+    }
+}
@@ -0,0 +1,98 @@
+//! Watches a scene file, so the viewer can show edits to it without restarting.
+//!
+//! The file's modification time and length are polled from the event loop,
+//! which also sees the renames that editors use to save atomically.
+
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::tree::{CsgTree, Tape};
+
+/// How often the file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct SceneWatcher {
+    path: PathBuf,
+    /// The modification time and length of the file when it was last loaded.
+    stamp: Option<(SystemTime, u64)>,
+    last_poll: Instant,
+}
+
+impl SceneWatcher {
+    /// Starts watching `path`. Make this before loading the scene the first
+    /// time, so that changes made while it is loading aren't missed.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            stamp: stamp(&path),
+            path,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Reloads and compiles the scene if the file has changed since it was
+    /// last loaded. A scene with errors isn't loaded again until it changes.
+    pub fn poll(&mut self) -> Option<Result<(CsgTree, Tape), Box<dyn Error>>> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+        self.reload_if_changed()
+    }
+
+    fn reload_if_changed(&mut self) -> Option<Result<(CsgTree, Tape), Box<dyn Error>>> {
+        // The file can be missing for a moment while it is being saved.
+        let stamp = stamp(&self.path)?;
+        if self.stamp == Some(stamp) {
+            return None;
+        }
+        self.stamp = Some(stamp);
+
+        Some(load(&self.path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn load(path: &Path) -> Result<(CsgTree, Tape), Box<dyn Error>> {
+    let tree = CsgTree::load(path)?;
+    let tape = tree.compile()?;
+    Ok((tree, tape))
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reload() {
+        let path = std::env::temp_dir().join(format!("sdf-watch-{}.ron", std::process::id()));
+        fs::write(&path, "Scene(root: Some(Sphere(radius: 1)))").unwrap();
+
+        let mut watcher = SceneWatcher::new(path.clone());
+        assert!(watcher.reload_if_changed().is_none());
+
+        // The lengths differ, so this is a change even if the modification time isn't.
+        fs::write(&path, "Scene(root: Some(Sphere(radius: \"2 *\")))").unwrap();
+        assert!(matches!(watcher.reload_if_changed(), Some(Err(_))));
+        assert!(watcher.reload_if_changed().is_none());
+
+        fs::write(&path, "Scene(root: Some(Sphere(radius: \"2 mm\")))").unwrap();
+        let (tree, tape) = watcher.reload_if_changed().unwrap().unwrap();
+        assert_eq!(tree.to_string(), "sphere, r = 2 mm\n");
+        assert_eq!(tape.insts.len(), 2);
+
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.reload_if_changed().is_none());
+    }
+}